@@ -0,0 +1,75 @@
+//! Redirect-style lookups from several threads at once.
+//!
+//! Compares the `InMemoryLinkStore` as is against the same store behind one
+//! `Mutex`, which is how every lookup used to be serialized. The latter is
+//! still what redirects through `Server` measure up to: it keeps its store
+//! behind such a `Mutex`, as counting their hits needs `&mut` access.
+//!
+//! Run with `cargo bench --bench concurrent_reads`.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use url_manager::{InMemoryLinkStore, Link, LinkStore};
+
+const LINKS: u64 = 10_000;
+const THREADS: usize = 8;
+const LOOKUPS: u64 = 200_000;
+
+fn store() -> InMemoryLinkStore {
+    let mut store = InMemoryLinkStore::new();
+    for id in 0..LINKS {
+        let link = Link::builder()
+            .id(id)
+            .target(format!("https://example.com/{id}").as_str())
+            .build()
+            .unwrap();
+        store.create(link).unwrap();
+    }
+    store
+}
+
+fn run(lookup: impl Fn(&str) + Sync) -> Duration {
+    let shortcuts: Vec<String> = (0..LINKS)
+        .map(|id| url_manager::Base62::new().encode(id))
+        .collect();
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for thread in 0..THREADS as u64 {
+            let (lookup, shortcuts) = (&lookup, &shortcuts);
+            scope.spawn(move || {
+                for i in 0..LOOKUPS {
+                    lookup(&shortcuts[((i * 7919 + thread) % LINKS) as usize]);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let per_second = (THREADS as u64 * LOOKUPS) as f64 / elapsed.as_secs_f64();
+    println!("{name:<8} {elapsed:>10.2?} {per_second:>14.0} lookups/s");
+}
+
+fn main() {
+    let shared = Arc::new(store());
+    let rwlock = run(|shortcut| {
+        assert!(shared.get_by_shortcut(shortcut).unwrap().is_some());
+    });
+
+    let serialized = Arc::new(Mutex::new(store()));
+    let mutex = run(|shortcut| {
+        let store = serialized.lock().unwrap();
+        assert!(store.get_by_shortcut(shortcut).unwrap().is_some());
+    });
+
+    println!("{THREADS} threads, {LOOKUPS} lookups each");
+    report("rwlock", rwlock);
+    report("mutex", mutex);
+    println!(
+        "speedup  {:>10.2}x",
+        mutex.as_secs_f64() / rwlock.as_secs_f64()
+    );
+}
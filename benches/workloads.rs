@@ -0,0 +1,122 @@
+//! Resolution, creation and mixed workloads against each store.
+//!
+//! Every workload runs on the `InMemoryLinkStore`, the same store behind a
+//! `CachedLinkStore`, and a `FileLinkStore` in the temp directory, on one
+//! thread; `concurrent_reads` covers contention. Over an in-memory store
+//! the cache can only add overhead; that overhead is what it costs against
+//! a backend with a network round trip.
+//!
+//! The crate doesn't depend on criterion, so like `concurrent_reads` this
+//! is a plain `harness = false` binary timing each workload once.
+//!
+//! Run with `cargo bench --bench workloads`, or pass a workload name
+//! (`resolve`, `create`, `mixed`) to run only that one.
+
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use url_manager::{Base62, CachedLinkStore, FileLinkStore, InMemoryLinkStore, Link, LinkStore};
+
+const LINKS: u64 = 10_000;
+const RESOLVES: u64 = 200_000;
+const CREATES: u64 = 20_000;
+const MIXED: u64 = 100_000;
+// One write in this many operations of the mixed workload.
+const WRITE_EVERY: u64 = 10;
+
+fn link(id: u64) -> Link {
+    Link::builder()
+        .id(id)
+        .target(format!("https://example.com/{id}").as_str())
+        .build()
+        .unwrap()
+}
+
+fn fill(store: &mut impl LinkStore) {
+    for id in 0..LINKS {
+        store.create(link(id)).unwrap();
+    }
+}
+
+fn shortcut(i: u64) -> String {
+    Base62::new().encode((i * 7919) % LINKS)
+}
+
+fn resolve(store: &mut impl LinkStore) -> (u64, Duration) {
+    fill(store);
+    let shortcuts: Vec<String> = (0..LINKS).map(shortcut).collect();
+    let start = Instant::now();
+    for i in 0..RESOLVES {
+        black_box(store.resolve(&shortcuts[(i % LINKS) as usize]).unwrap());
+    }
+    (RESOLVES, start.elapsed())
+}
+
+fn create(store: &mut impl LinkStore) -> (u64, Duration) {
+    let links: Vec<Link> = (0..CREATES).map(link).collect();
+    let start = Instant::now();
+    for link in links {
+        store.create(link).unwrap();
+    }
+    (CREATES, start.elapsed())
+}
+
+// Mostly hits on existing links, with a new link every `WRITE_EVERY` ops.
+fn mixed(store: &mut impl LinkStore) -> (u64, Duration) {
+    fill(store);
+    let shortcuts: Vec<String> = (0..LINKS).map(shortcut).collect();
+    let start = Instant::now();
+    for i in 0..MIXED {
+        if i % WRITE_EVERY == 0 {
+            store.create(link(LINKS + i)).unwrap();
+        } else {
+            black_box(store.record_hit(&shortcuts[(i % LINKS) as usize]).unwrap());
+        }
+    }
+    (MIXED, start.elapsed())
+}
+
+fn temp_log(name: &str) -> PathBuf {
+    env::temp_dir().join(format!(
+        "url-manager-bench-{}-{name}.jsonl",
+        std::process::id()
+    ))
+}
+
+fn report(workload: &str, store: &str, (ops, elapsed): (u64, Duration)) {
+    let per_second = ops as f64 / elapsed.as_secs_f64();
+    println!("{workload:<8} {store:<8} {elapsed:>10.2?} {per_second:>14.0} ops/s");
+}
+
+// Runs `$run` on a fresh store of each kind.
+macro_rules! on_each_store {
+    ($workload:literal, $run:ident) => {{
+        report($workload, "memory", $run(&mut InMemoryLinkStore::new()));
+        let mut cached = CachedLinkStore::new(InMemoryLinkStore::new()).capacity(LINKS as usize);
+        report($workload, "cached", $run(&mut cached));
+        let path = temp_log($workload);
+        report(
+            $workload,
+            "file",
+            $run(&mut FileLinkStore::open(&path).unwrap()),
+        );
+        let _ = fs::remove_file(&path);
+    }};
+}
+
+fn main() {
+    let only = env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let selected = |workload: &str| only.as_deref().is_none_or(|only| only == workload);
+    if selected("resolve") {
+        on_each_store!("resolve", resolve);
+    }
+    if selected("create") {
+        on_each_store!("create", create);
+    }
+    if selected("mixed") {
+        on_each_store!("mixed", mixed);
+    }
+}
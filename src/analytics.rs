@@ -0,0 +1,493 @@
+//! Aggregated views over recorded [`Click`]s, and a [`PrivateClickRecorder`]
+//! limiting what is kept of them.
+//!
+//! Together with `server::Server::honor_do_not_track`, which records the clicks of clients sending `DNT: 1` or `Sec-GPC: 1`
+//! without referrer, user agent, country or address, it keeps analytics
+//! free of personal data:
+//!
+//! ```
+//! # use std::sync::Arc;
+//! # use std::time::Duration;
+//! # use url_manager::analytics::PrivateClickRecorder;
+//! # use url_manager::InMemoryClickRecorder;
+//! let clicks = Arc::new(InMemoryClickRecorder::new());
+//! let recorder = PrivateClickRecorder::new(Arc::clone(&clicks))
+//!     .anonymize_ips()
+//!     .retention(Duration::from_secs(90 * 24 * 60 * 60));
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::net::IpAddr;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::link::{from_unix_millis, now, unix_millis};
+use crate::store::spawn_every;
+use crate::transfer::{self, Format};
+use crate::{sync, Click, ClickRecorder, InMemoryClickRecorder, Purger, Result, UrlManagerError};
+
+/// Bucket width for per-day figures.
+pub const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Hits within `[start, start + width)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeBucket {
+    pub start: SystemTime,
+    pub hits: u64,
+}
+
+/// How often one referrer, user agent or country was seen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Count {
+    pub value: String,
+    pub hits: u64,
+}
+
+/// Everything a dashboard needs about one link, see [`Analytics::summary`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub total: u64,
+    pub buckets: Vec<TimeBucket>,
+    pub top_referrers: Vec<Count>,
+    pub top_user_agents: Vec<Count>,
+    pub top_countries: Vec<Count>,
+}
+
+/// Aggregates the clicks of a link.
+///
+/// Backends that can aggregate natively (e.g. in SQL) implement the four
+/// queries themselves; ones that hold raw clicks can use [`buckets`] and
+/// [`top`]. Buckets are aligned to the unix epoch and only non-empty ones are
+/// returned, oldest first. Top lists are ordered by hits, then value.
+pub trait Analytics {
+    fn hits_per_bucket(&self, link_id: u64, width: Duration) -> Result<Vec<TimeBucket>>;
+
+    fn top_referrers(&self, link_id: u64, limit: usize) -> Result<Vec<Count>>;
+
+    fn top_user_agents(&self, link_id: u64, limit: usize) -> Result<Vec<Count>>;
+
+    fn top_countries(&self, link_id: u64, limit: usize) -> Result<Vec<Count>>;
+
+    /// The clicks on any link within `range`, oldest first. Days rolled up
+    /// by [`ClickRecorder::roll_up`] are left out. Backends that only keep
+    /// aggregates fail with `StorageBackend`, the default.
+    fn clicks_between(&self, range: Range<SystemTime>) -> Result<Vec<Click>> {
+        let _ = range;
+        Err(UrlManagerError::backend(
+            "these analytics can't list clicks",
+        ))
+    }
+
+    /// Writes [`Analytics::clicks_between`] to `writer` in `format`, for
+    /// spreadsheets and data warehouses, returning how many were written.
+    /// CSV has the columns in [`CLICK_COLUMNS`](crate::transfer::CLICK_COLUMNS).
+    fn export(
+        &self,
+        range: Range<SystemTime>,
+        mut writer: impl Write,
+        format: Format,
+    ) -> Result<usize>
+    where
+        Self: Sized,
+    {
+        let clicks = self.clicks_between(range)?;
+        transfer::write_clicks(&mut writer, format, &clicks)?;
+        writer.flush()?;
+        Ok(clicks.len())
+    }
+
+    fn summary(&self, link_id: u64, width: Duration, limit: usize) -> Result<Summary> {
+        let buckets = self.hits_per_bucket(link_id, width)?;
+        Ok(Summary {
+            total: buckets.iter().map(|bucket| bucket.hits).sum(),
+            buckets,
+            top_referrers: self.top_referrers(link_id, limit)?,
+            top_user_agents: self.top_user_agents(link_id, limit)?,
+            top_countries: self.top_countries(link_id, limit)?,
+        })
+    }
+}
+
+impl<T: Analytics + ?Sized> Analytics for Arc<T> {
+    fn hits_per_bucket(&self, link_id: u64, width: Duration) -> Result<Vec<TimeBucket>> {
+        (**self).hits_per_bucket(link_id, width)
+    }
+
+    fn top_referrers(&self, link_id: u64, limit: usize) -> Result<Vec<Count>> {
+        (**self).top_referrers(link_id, limit)
+    }
+
+    fn top_user_agents(&self, link_id: u64, limit: usize) -> Result<Vec<Count>> {
+        (**self).top_user_agents(link_id, limit)
+    }
+
+    fn top_countries(&self, link_id: u64, limit: usize) -> Result<Vec<Count>> {
+        (**self).top_countries(link_id, limit)
+    }
+
+    fn clicks_between(&self, range: Range<SystemTime>) -> Result<Vec<Click>> {
+        (**self).clicks_between(range)
+    }
+}
+
+/// Counts `clicks` into buckets of `width`.
+pub fn buckets<'a>(
+    clicks: impl IntoIterator<Item = &'a Click>,
+    width: Duration,
+) -> Result<Vec<TimeBucket>> {
+    let hits = clicks.into_iter().map(|click| TimeBucket {
+        start: click.at,
+        hits: 1,
+    });
+    rebucket(hits, width)
+}
+
+// Sums `hits` into buckets of `width`, each counted in the bucket its
+// start falls into.
+fn rebucket(
+    hits: impl IntoIterator<Item = TimeBucket>,
+    width: Duration,
+) -> Result<Vec<TimeBucket>> {
+    let width = u64::try_from(width.as_millis())
+        .ok()
+        .filter(|&width| width > 0)
+        .ok_or_else(|| UrlManagerError::InvalidConfig("invalid bucket width".to_string()))?;
+    let mut counts = BTreeMap::new();
+    for bucket in hits {
+        let millis = unix_millis(bucket.start);
+        *counts.entry(millis - millis % width).or_insert(0) += bucket.hits;
+    }
+    Ok(counts
+        .into_iter()
+        .map(|(start, hits)| TimeBucket {
+            start: from_unix_millis(start),
+            hits,
+        })
+        .collect())
+}
+
+/// The `limit` most common of `values`.
+pub fn top<'a>(values: impl IntoIterator<Item = &'a str>, limit: usize) -> Vec<Count> {
+    let mut counts: HashMap<&str, u64> = HashMap::new();
+    for value in values {
+        *counts.entry(value).or_insert(0) += 1;
+    }
+    let mut counts: Vec<Count> = counts
+        .into_iter()
+        .map(|(value, hits)| Count {
+            value: value.to_string(),
+            hits,
+        })
+        .collect();
+    counts.sort_by(|a, b| b.hits.cmp(&a.hits).then_with(|| a.value.cmp(&b.value)));
+    counts.truncate(limit);
+    counts
+}
+
+/// Rolled-up days count in the bucket they start in; the top lists only
+/// see the clicks that weren't rolled up.
+impl Analytics for InMemoryClickRecorder {
+    fn hits_per_bucket(&self, link_id: u64, width: Duration) -> Result<Vec<TimeBucket>> {
+        let clicks = self.clicks(link_id);
+        let raw = clicks.iter().map(|click| TimeBucket {
+            start: click.at,
+            hits: 1,
+        });
+        rebucket(self.daily_hits(link_id).into_iter().chain(raw), width)
+    }
+
+    fn top_referrers(&self, link_id: u64, limit: usize) -> Result<Vec<Count>> {
+        let clicks = self.clicks(link_id);
+        Ok(top(
+            clicks.iter().filter_map(|c| c.referrer.as_deref()),
+            limit,
+        ))
+    }
+
+    fn top_user_agents(&self, link_id: u64, limit: usize) -> Result<Vec<Count>> {
+        let clicks = self.clicks(link_id);
+        Ok(top(
+            clicks.iter().filter_map(|c| c.user_agent.as_deref()),
+            limit,
+        ))
+    }
+
+    fn top_countries(&self, link_id: u64, limit: usize) -> Result<Vec<Count>> {
+        let clicks = self.clicks(link_id);
+        Ok(top(
+            clicks.iter().filter_map(|c| c.country.as_deref()),
+            limit,
+        ))
+    }
+
+    fn clicks_between(&self, range: Range<SystemTime>) -> Result<Vec<Click>> {
+        let mut clicks = self.all_clicks();
+        clicks.retain(|click| range.contains(&click.at));
+        clicks.sort_by_key(|click| click.at);
+        Ok(clicks)
+    }
+}
+
+/// Hands clicks on to another recorder with less in them: addresses in
+/// [`Click::visitor`] cut down with [`anonymize_ip`] before they are
+/// stored, and clicks older than the retention pruned from the recorder
+/// as new ones come in, at most once per hour.
+#[derive(Debug)]
+pub struct PrivateClickRecorder<R> {
+    inner: R,
+    anonymize_ips: bool,
+    retention: Option<Duration>,
+    last_pruned: Mutex<Option<SystemTime>>,
+}
+
+impl<R: ClickRecorder> PrivateClickRecorder<R> {
+    /// Passes clicks on unchanged until configured otherwise.
+    pub fn new(inner: R) -> Self {
+        PrivateClickRecorder {
+            inner,
+            anonymize_ips: false,
+            retention: None,
+            last_pruned: Mutex::default(),
+        }
+    }
+
+    /// Stores only the network of each client address, see [`anonymize_ip`].
+    /// Visitors that aren't addresses, e.g. cookie values, are kept.
+    pub fn anonymize_ips(mut self) -> Self {
+        self.anonymize_ips = true;
+        self
+    }
+
+    /// Keeps clicks for `retention`, see [`ClickRecorder::prune`]; older
+    /// ones aren't recorded at all.
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Prunes the recorder if the retention calls for it and it wasn't
+    // pruned within the last hour or retention, whichever is shorter.
+    fn prune_due(&self, now: SystemTime) -> Result<()> {
+        const PRUNE_EVERY: Duration = Duration::from_secs(60 * 60);
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+        let mut last_pruned = sync::lock(&self.last_pruned);
+        let due = last_pruned.is_none_or(|last| {
+            now.duration_since(last)
+                .is_ok_and(|since| since >= PRUNE_EVERY.min(retention))
+        });
+        if due {
+            let cutoff = now.checked_sub(retention).unwrap_or(SystemTime::UNIX_EPOCH);
+            self.inner.prune(cutoff)?;
+            *last_pruned = Some(now);
+        }
+        Ok(())
+    }
+}
+
+impl<R: ClickRecorder> ClickRecorder for PrivateClickRecorder<R> {
+    fn record(&self, mut click: Click) -> Result<()> {
+        let now = now();
+        self.prune_due(now)?;
+        if let Some(retention) = self.retention {
+            if now
+                .duration_since(click.at)
+                .is_ok_and(|age| age > retention)
+            {
+                return Ok(());
+            }
+        }
+        if self.anonymize_ips {
+            if let Some(ip) = click.visitor.as_deref().and_then(|v| v.parse().ok()) {
+                click.visitor = Some(anonymize_ip(ip).to_string());
+            }
+        }
+        self.inner.record(click)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn prune(&self, cutoff: SystemTime) -> Result<usize> {
+        self.inner.prune(cutoff)
+    }
+
+    fn roll_up(&self, cutoff: SystemTime) -> Result<usize> {
+        self.inner.roll_up(cutoff)
+    }
+
+    fn forget(&self, link_id: u64) -> Result<usize> {
+        self.inner.forget(link_id)
+    }
+}
+
+/// Calls [`ClickRecorder::roll_up`] on `recorder` every `interval`, rolling
+/// up the clicks older than `keep`, until the returned [`Purger`] is
+/// dropped.
+///
+/// Errors are ignored; the next run tries again.
+pub fn spawn_rollup<R>(recorder: Arc<R>, interval: Duration, keep: Duration) -> Purger
+where
+    R: ClickRecorder + Send + Sync + ?Sized + 'static,
+{
+    spawn_every(interval, move || {
+        let cutoff = now().checked_sub(keep).unwrap_or(SystemTime::UNIX_EPOCH);
+        let _ = recorder.roll_up(cutoff);
+    })
+}
+
+/// `ip` with the host part zeroed: the last octet of IPv4 addresses and
+/// all but the first 48 bits of IPv6 ones, as commonly done before
+/// storing addresses for analytics.
+pub fn anonymize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::from([a, b, c, 0])
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, c, ..] = ip.segments();
+            IpAddr::from([a, b, c, 0, 0, 0, 0, 0])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(day: u64, referrer: &str, country: Option<&str>) -> Click {
+        Click {
+            link_id: 1,
+            at: from_unix_millis(day * 86_400_000 + 1_000),
+            referrer: Some(referrer.to_string()),
+            user_agent: None,
+            country: country.map(str::to_string),
+            visitor: None,
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let recorder = InMemoryClickRecorder::new();
+        recorder.record(click(1, "b.example", Some("DE"))).unwrap();
+        recorder.record(click(1, "a.example", None)).unwrap();
+        recorder.record(click(3, "b.example", Some("DE"))).unwrap();
+        recorder
+            .record(Click {
+                link_id: 2,
+                ..click(3, "c.example", None)
+            })
+            .unwrap();
+
+        let summary = recorder.summary(1, DAY, 1).unwrap();
+        assert_eq!(summary.total, 3);
+        assert_eq!(
+            summary.buckets,
+            [
+                TimeBucket {
+                    start: from_unix_millis(86_400_000),
+                    hits: 2
+                },
+                TimeBucket {
+                    start: from_unix_millis(3 * 86_400_000),
+                    hits: 1
+                },
+            ]
+        );
+        assert_eq!(
+            summary.top_referrers,
+            [Count {
+                value: "b.example".to_string(),
+                hits: 2
+            }]
+        );
+        assert!(summary.top_user_agents.is_empty());
+        assert_eq!(summary.top_countries[0].value, "DE");
+
+        assert!(recorder.hits_per_bucket(1, Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn test_export() {
+        let recorder = InMemoryClickRecorder::new();
+        recorder.record(click(3, "b.example", None)).unwrap();
+        recorder
+            .record(click(1, "a.example, \"the\" site", Some("DE")))
+            .unwrap();
+        recorder.record(click(5, "c.example", None)).unwrap();
+        let range = from_unix_millis(86_400_000)..from_unix_millis(5 * 86_400_000);
+
+        let mut csv = Vec::new();
+        assert_eq!(
+            recorder
+                .export(range.clone(), &mut csv, Format::Csv)
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "link_id,at,referrer,user_agent,country,visitor\n\
+             1,86401000,\"a.example, \"\"the\"\" site\",,DE,\n\
+             1,259201000,b.example,,,\n"
+        );
+
+        let mut json = Vec::new();
+        recorder.export(range, &mut json, Format::Json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        let first = crate::json::parse(json.lines().next().unwrap()).unwrap();
+        assert_eq!(
+            Click::from_json(&first).unwrap().country.as_deref(),
+            Some("DE")
+        );
+        assert_eq!(json.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_private_recorder() {
+        assert_eq!(
+            anonymize_ip("192.0.2.77".parse().unwrap()).to_string(),
+            "192.0.2.0"
+        );
+        assert_eq!(
+            anonymize_ip("2001:db8:1:2:3::4".parse().unwrap()).to_string(),
+            "2001:db8:1::"
+        );
+
+        let clicks = Arc::new(InMemoryClickRecorder::new());
+        clicks.record(click(1, "old.example", None)).unwrap();
+        let recorder = PrivateClickRecorder::new(Arc::clone(&clicks))
+            .anonymize_ips()
+            .retention(DAY);
+        let recent = |visitor: &str| Click {
+            at: now(),
+            visitor: Some(visitor.to_string()),
+            ..click(0, "new.example", None)
+        };
+        recorder.record(recent("192.0.2.77")).unwrap();
+        recorder.record(recent("cookie-42")).unwrap();
+        recorder.record(click(2, "old.example", None)).unwrap();
+
+        let visitors: Vec<Option<String>> = clicks
+            .all_clicks()
+            .into_iter()
+            .map(|click| click.visitor)
+            .collect();
+        assert_eq!(
+            visitors,
+            [Some("192.0.2.0".to_string()), Some("cookie-42".to_string())]
+        );
+    }
+}
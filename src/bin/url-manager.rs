@@ -0,0 +1,470 @@
+//! Command line front end for a file-backed link store.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use url_manager::config::Config;
+use url_manager::events::PlainHttp;
+use url_manager::healthcheck::check_links;
+use url_manager::manifest::Manifest;
+use url_manager::transfer::Format;
+use url_manager::{
+    migrate, ClickRecorder, Conflict, FileClickRecorder, FileLinkStore, Link, LinkStore,
+    MigrateOptions, Namespace, Result, UrlManagerError, UrlType,
+};
+
+const USAGE: &str = "\
+usage: url-manager [--config PATH] [--store PATH] <command>
+
+commands:
+  add <target> [--slug SLUG] [--namespace NS]
+                               create a link
+  get <id>                     show a link
+  list [--offset N] [--limit N] show links, oldest first
+  delete <id>                  move a link to the trash
+  restore <id>                 take a link out of the trash
+  restore --snapshot <file>    put back the links of a backup, - for stdin
+  purge [--older-than SECONDS] permanently delete links trashed that long ago
+  rollup [--older-than DAYS] [--clicks PATH]
+                               fold clicks that many days old into daily hits
+  check [--older-than SECONDS] HEAD-request http:// targets not checked that
+                               long ago, then show the broken links
+  resolve <shortcut> [--namespace NS]
+                               print the target of a shortcut
+  import <file> [--format csv|json]
+                               add the links in a file, - for stdin
+  export [--format csv|json]   write all links to stdout
+  manifest [--format json|sitemap] [--base-url URL]
+                               list the public shortcuts with their targets
+  qr <shortcut> [--namespace NS] [--base-url URL] [--out FILE]
+                               draw the QR code of a short URL, as PNG or, for
+                               *.svg and stdout, SVG (needs the `qr` feature)
+  export-static <dir> [--base-url URL]
+                               write a redirect page per public shortcut, to
+                               host the links on any static file server
+  backup [--out FILE]          write a checksummed snapshot of all links,
+                               trashed ones included, to stdout or FILE
+  migrate --to PATH [--from PATH] [--on-conflict skip|overwrite|error]
+                               copy all links into another store
+  serve [--bind ADDR]          run the redirect server (needs the `server` feature)
+
+Settings are read from the TOML or YAML file given with --config or in
+$URL_MANAGER_CONFIG, then from URL_MANAGER_* variables, e.g. $URL_MANAGER_STORE;
+see the url_manager::config docs for the keys. --store and --bind override both.
+The store defaults to links.jsonl in the current directory.
+add generates codes over $URL_MANAGER_ALPHABET if set, e.g. without look-alikes like 0/O and 1/l/I.
+serve records clicks in $URL_MANAGER_CLICKS if set, rolling them up after
+$URL_MANAGER_ROLLUP_AFTER_DAYS if that is set too.
+If $URL_MANAGER_API_KEY is set, serve requires it as an admin key for the /api routes.";
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Usage(message)) => {
+            eprintln!("{message}\n\n{USAGE}");
+            ExitCode::from(2)
+        }
+        Err(Failed(e)) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+enum CliError {
+    Usage(String),
+    Failed(UrlManagerError),
+}
+
+use CliError::{Failed, Usage};
+
+impl From<UrlManagerError> for CliError {
+    fn from(value: UrlManagerError) -> Self {
+        Failed(value)
+    }
+}
+
+// Removes `--name value` from `args`.
+fn take_option(
+    args: &mut Vec<String>,
+    name: &str,
+) -> std::result::Result<Option<String>, CliError> {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) if i + 1 < args.len() => {
+            let value = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(value))
+        }
+        Some(_) => Err(Usage(format!("{name} needs a value"))),
+        None => Ok(None),
+    }
+}
+
+fn parse_number(
+    value: Option<String>,
+    name: &str,
+    default: usize,
+) -> std::result::Result<usize, CliError> {
+    match value {
+        Some(value) => value
+            .parse()
+            .map_err(|_| Usage(format!("{name} must be a number"))),
+        None => Ok(default),
+    }
+}
+
+fn take_namespace(args: &mut Vec<String>) -> std::result::Result<Option<Namespace>, CliError> {
+    Ok(take_option(args, "--namespace")?
+        .map(Namespace::new)
+        .transpose()?)
+}
+
+fn take_format(args: &mut Vec<String>) -> std::result::Result<Format, CliError> {
+    match take_option(args, "--format")? {
+        Some(format) => format
+            .parse()
+            .map_err(|e: UrlManagerError| Usage(e.to_string())),
+        None => Ok(Format::Json),
+    }
+}
+
+// The `--base-url` option, or else the configured base URL.
+fn take_base_url(
+    args: &mut Vec<String>,
+    config: &Config,
+) -> std::result::Result<UrlType, CliError> {
+    match take_option(args, "--base-url")? {
+        Some(base_url) => {
+            UrlType::parse(&base_url).map_err(|e| Usage(format!("invalid --base-url: {e}")))
+        }
+        None => config
+            .get_base_url()
+            .cloned()
+            .ok_or_else(|| Usage("missing --base-url".to_string())),
+    }
+}
+
+fn parse_id(arg: Option<&String>) -> std::result::Result<u64, CliError> {
+    arg.ok_or_else(|| Usage("missing id".to_string()))?
+        .parse()
+        .map_err(|_| Usage("id must be a number".to_string()))
+}
+
+fn print_link(link: &Link) {
+    println!(
+        "{}\t{}\t{}",
+        link.id(),
+        link.shortcut().unwrap_or("-"),
+        link.target()
+    );
+}
+
+fn open(path: &str) -> Result<FileLinkStore> {
+    Config::new().store(path)?.open_store()
+}
+
+fn load_config(args: &mut Vec<String>) -> std::result::Result<Config, CliError> {
+    let config =
+        match take_option(args, "--config")?.or_else(|| env::var("URL_MANAGER_CONFIG").ok()) {
+            Some(path) => Config::load(path)?,
+            None => Config::new(),
+        };
+    let mut config = config.apply_env()?;
+    if let Some(path) = take_option(args, "--store")? {
+        config = config.store(path)?;
+    }
+    Ok(config)
+}
+
+fn run(mut args: Vec<String>) -> std::result::Result<(), CliError> {
+    let config = load_config(&mut args)?;
+    let Some(command) = args.first().cloned() else {
+        return Err(Usage("missing command".to_string()));
+    };
+    let rest = &mut args.split_off(1);
+
+    match command.as_str() {
+        "add" => {
+            let slug = take_option(rest, "--slug")?;
+            let namespace = take_namespace(rest)?;
+            let target = rest
+                .first()
+                .ok_or_else(|| Usage("missing target".to_string()))?;
+            let mut service = config.link_service(config.open_store()?);
+            if let Some(namespace) = namespace {
+                service = service.namespace(namespace);
+            }
+            let short = match slug {
+                Some(slug) => service.shorten_with_slug(target.as_str(), &slug)?,
+                None => service.shorten(target.as_str())?,
+            };
+            print_link(&short.link);
+        }
+        "get" => {
+            let id = parse_id(rest.first())?;
+            let link = config
+                .open_store()?
+                .get(id)?
+                .filter(|link| !link.is_deleted())
+                .ok_or(UrlManagerError::NotFound)?;
+            print_link(&link);
+        }
+        "list" => {
+            let offset = parse_number(take_option(rest, "--offset")?, "--offset", 0)?;
+            let limit = parse_number(take_option(rest, "--limit")?, "--limit", usize::MAX)?;
+            for link in config.open_store()?.list(offset, limit)? {
+                print_link(&link);
+            }
+        }
+        "delete" => {
+            let id = parse_id(rest.first())?;
+            config.open_store()?.soft_delete(id)?;
+        }
+        "restore" => {
+            if let Some(path) = take_option(rest, "--snapshot")? {
+                let mut store = config.open_store()?;
+                let snapshot = if path == "-" {
+                    store.restore_snapshot(io::stdin().lock())?
+                } else {
+                    store.restore_snapshot(io::BufReader::new(
+                        fs::File::open(&path).map_err(UrlManagerError::from)?,
+                    ))?
+                };
+                println!("restored {} links", snapshot.links().len());
+                return Ok(());
+            }
+            let id = parse_id(rest.first())?;
+            config.open_store()?.restore(id)?;
+        }
+        "backup" => {
+            let store = config.open_store()?;
+            let links = match take_option(rest, "--out")?.filter(|path| path != "-") {
+                Some(path) => {
+                    let mut file = fs::File::create(&path).map_err(UrlManagerError::from)?;
+                    let links = store.snapshot(io::BufWriter::new(&mut file))?;
+                    file.sync_all().map_err(UrlManagerError::from)?;
+                    links
+                }
+                None => store.snapshot(io::stdout().lock())?,
+            };
+            eprintln!("backed up {links} links");
+        }
+        "purge" => {
+            let older_than = parse_number(take_option(rest, "--older-than")?, "--older-than", 0)?;
+            let purged = config
+                .open_store()?
+                .purge(Duration::from_secs(older_than as u64))?;
+            println!("purged {purged} links");
+        }
+        "rollup" => {
+            let clicks = take_option(rest, "--clicks")?
+                .or_else(|| config.get_clicks().map(str::to_string))
+                .ok_or_else(|| Usage("missing --clicks".to_string()))?;
+            let older_than = match take_option(rest, "--older-than")? {
+                Some(days) => Duration::from_secs(
+                    parse_number(Some(days), "--older-than", 0)? as u64 * DAY_SECS,
+                ),
+                None => config.get_rollup_after().unwrap_or(Duration::ZERO),
+            };
+            let cutoff = SystemTime::now()
+                .checked_sub(older_than)
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let rolled_up = FileClickRecorder::open(clicks)?.roll_up(cutoff)?;
+            println!("rolled up {rolled_up} clicks");
+        }
+        "check" => {
+            let older_than = parse_number(take_option(rest, "--older-than")?, "--older-than", 0)?;
+            let store = Mutex::new(config.open_store()?);
+            let checked = check_links(
+                &store,
+                &PlainHttp::default(),
+                Duration::from_secs(older_than as u64),
+            )?;
+            eprintln!("checked {checked} links");
+            let store = store.into_inner().unwrap_or_else(|e| e.into_inner());
+            for link in store.find_broken()? {
+                print_link(&link);
+            }
+        }
+        "resolve" => {
+            let namespace = take_namespace(rest)?;
+            let shortcut = rest
+                .first()
+                .ok_or_else(|| Usage("missing shortcut".to_string()))?;
+            let link = config
+                .open_store()?
+                .resolve_in(namespace.as_ref(), shortcut)?;
+            println!("{}", link.target());
+        }
+        "import" => {
+            let format = take_format(rest)?;
+            let path = rest
+                .first()
+                .ok_or_else(|| Usage("missing file".to_string()))?;
+            let mut store = config.open_store()?;
+            let report = if path == "-" {
+                store.import(io::stdin().lock(), format)?
+            } else {
+                store.import(
+                    io::BufReader::new(fs::File::open(path).map_err(UrlManagerError::from)?),
+                    format,
+                )?
+            };
+            for error in &report.errors {
+                eprintln!("{path}: {error}");
+            }
+            println!("imported {} links", report.imported);
+            if !report.errors.is_empty() {
+                return Err(Failed(UrlManagerError::InvalidLink(format!(
+                    "{} records were rejected",
+                    report.errors.len()
+                ))));
+            }
+        }
+        "manifest" => {
+            let base_url = take_base_url(rest, &config)?;
+            let manifest = Manifest::of(&config.open_store()?, &base_url)?;
+            match take_option(rest, "--format")?.as_deref() {
+                None | Some("json") => manifest.write_json(io::stdout().lock())?,
+                Some("sitemap") => {
+                    manifest.write_sitemap(io::stdout().lock())?;
+                }
+                Some(other) => {
+                    return Err(Usage(format!(
+                        "unknown format '{other}', expected json or sitemap"
+                    )))
+                }
+            }
+        }
+        "qr" => {
+            let base_url = take_base_url(rest, &config)?;
+            let namespace = take_namespace(rest)?;
+            let out = take_option(rest, "--out")?.filter(|path| path != "-");
+            let shortcut = rest
+                .first()
+                .ok_or_else(|| Usage("missing shortcut".to_string()))?;
+            let link = config
+                .open_store()?
+                .get_by_shortcut_in(namespace.as_ref(), shortcut)?
+                .filter(|link| !link.is_deleted())
+                .ok_or(UrlManagerError::NotFound)?;
+            qr(&link, &base_url, out.as_deref())?;
+        }
+        "export-static" => {
+            let base_url = take_base_url(rest, &config)?;
+            let dir = rest
+                .first()
+                .ok_or_else(|| Usage("missing directory".to_string()))?;
+            let written = Manifest::of(&config.open_store()?, &base_url)?.export_static(dir)?;
+            println!("wrote {written} pages");
+        }
+        "export" => {
+            let format = take_format(rest)?;
+            config.open_store()?.export(io::stdout().lock(), format)?;
+        }
+        "migrate" => {
+            let to = take_option(rest, "--to")?.ok_or_else(|| Usage("missing --to".to_string()))?;
+            let from = take_option(rest, "--from")?;
+            let conflict = match take_option(rest, "--on-conflict")? {
+                Some(conflict) => conflict
+                    .parse()
+                    .map_err(|e: UrlManagerError| Usage(e.to_string()))?,
+                None => Conflict::Skip,
+            };
+            let source = match from {
+                Some(from) => open(&from)?,
+                None => config.open_store()?,
+            };
+            let mut destination = open(&to)?;
+            let report = migrate(
+                &source,
+                &mut destination,
+                MigrateOptions::new()
+                    .on_conflict(conflict)
+                    .progress(|report| {
+                        eprintln!(
+                            "{} of {} links",
+                            report.copied + report.skipped,
+                            report.total
+                        )
+                    }),
+            )?;
+            println!(
+                "copied {} links ({} overwritten), skipped {}",
+                report.copied, report.overwritten, report.skipped
+            );
+        }
+        "serve" => {
+            let config = match take_option(rest, "--bind")? {
+                Some(bind) => config.bind(bind),
+                None => config,
+            };
+            serve(&config)?
+        }
+        "help" | "--help" | "-h" => println!("{USAGE}"),
+        other => return Err(Usage(format!("unknown command '{other}'"))),
+    }
+    Ok(())
+}
+
+#[cfg(feature = "qr")]
+fn qr(link: &Link, base_url: &UrlType, out: Option<&str>) -> Result<()> {
+    let code = link.qr_code(base_url)?;
+    let Some(path) = out else {
+        return code.write_svg(io::stdout().lock());
+    };
+    let file = io::BufWriter::new(fs::File::create(path).map_err(UrlManagerError::from)?);
+    if path.ends_with(".svg") {
+        code.write_svg(file)
+    } else {
+        // 8 pixels a module prints at about 2.5cm for a short URL
+        code.write_png(file, 8)
+    }
+}
+
+#[cfg(not(feature = "qr"))]
+fn qr(_link: &Link, _base_url: &UrlType, _out: Option<&str>) -> Result<()> {
+    Err(UrlManagerError::InvalidConfig(
+        "url-manager was built without the `qr` feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "server")]
+fn serve(config: &Config) -> Result<()> {
+    let store = config.open_store()?;
+    let bind = config.get_bind();
+    let listener = std::net::TcpListener::bind(bind)?;
+    eprintln!("serving {} on http://{bind}", store.path().display());
+    // Ctrl-C and SIGTERM finish the requests in flight and close the store
+    let mut server = config
+        .server(store)?
+        .shutdown(url_manager::server::Shutdown::on_signals()?);
+    let mut _rollup = None;
+    if let Some(clicks) = config.open_clicks()? {
+        let clicks = std::sync::Arc::new(clicks);
+        server = server
+            .click_recorder(clicks.clone())
+            .analytics(clicks.clone());
+        if let Some(age) = config.get_rollup_after() {
+            let hourly = Duration::from_secs(60 * 60);
+            _rollup = Some(url_manager::analytics::spawn_rollup(clicks, hourly, age));
+        }
+    }
+    let _purger = server.spawn_purger(config.get_purge_interval());
+    server.serve(listener)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_config: &Config) -> Result<()> {
+    Err(UrlManagerError::InvalidConfig(
+        "url-manager was built without the `server` feature".to_string(),
+    ))
+}
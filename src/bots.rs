@@ -0,0 +1,118 @@
+use crate::HitMetadata;
+
+/// Tells crawlers and link preview bots apart from people, see
+/// [`HitMetadata::bot`].
+pub trait BotDetector {
+    fn is_bot(&self, metadata: &HitMetadata) -> bool;
+}
+
+impl<F: Fn(&HitMetadata) -> bool> BotDetector for F {
+    fn is_bot(&self, metadata: &HitMetadata) -> bool {
+        self(metadata)
+    }
+}
+
+/// Recognizes bots by their user agent: search engine crawlers, the
+/// preview fetchers of chat apps and social networks, and anything calling
+/// itself a bot, crawler or spider.
+///
+/// ```
+/// # use url_manager::{BotDetector, HitMetadata, KnownBots};
+/// let slack = HitMetadata {
+///     user_agent: Some("Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)".to_string()),
+///     ..HitMetadata::default()
+/// };
+/// assert!(KnownBots::new().is_bot(&slack));
+/// assert!(!KnownBots::new().is_bot(&HitMetadata::default()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct KnownBots {
+    patterns: Vec<String>,
+}
+
+impl KnownBots {
+    /// The user agent fragments recognized by default, compared
+    /// case-insensitively.
+    pub const PATTERNS: [&'static str; 12] = [
+        "bot",
+        "crawler",
+        "spider",
+        "slurp",
+        "facebookexternalhit",
+        "facebookcatalog",
+        "embedly",
+        "whatsapp",
+        "skypeuripreview",
+        "vkshare",
+        "pinterest",
+        "headlesschrome",
+    ];
+
+    pub fn new() -> Self {
+        KnownBots {
+            patterns: Self::PATTERNS.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    /// Also recognizes user agents containing `pattern`.
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.patterns.push(pattern.into().to_ascii_lowercase());
+        self
+    }
+}
+
+impl Default for KnownBots {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BotDetector for KnownBots {
+    fn is_bot(&self, metadata: &HitMetadata) -> bool {
+        let Some(user_agent) = &metadata.user_agent else {
+            return false;
+        };
+        let user_agent = user_agent.to_ascii_lowercase();
+        self.patterns
+            .iter()
+            .any(|pattern| user_agent.contains(pattern.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(user_agent: &str) -> HitMetadata {
+        HitMetadata {
+            user_agent: Some(user_agent.to_string()),
+            ..HitMetadata::default()
+        }
+    }
+
+    #[test]
+    fn test_known_bots() {
+        let bots = KnownBots::new();
+        for user_agent in [
+            "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)",
+            "Twitterbot/1.0",
+            "facebookexternalhit/1.1 (+http://www.facebook.com/externalhit_uatext.php)",
+            "Mozilla/5.0 (compatible; Discordbot/2.0; +https://discordapp.com)",
+            "WhatsApp/2.23.20.0",
+        ] {
+            assert!(bots.is_bot(&agent(user_agent)), "{user_agent}");
+        }
+        let firefox =
+            agent("Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0");
+        assert!(!bots.is_bot(&firefox));
+        assert!(bots.pattern("Firefox").is_bot(&firefox));
+
+        let curl = |metadata: &HitMetadata| {
+            metadata
+                .user_agent
+                .as_deref()
+                .is_some_and(|ua| ua.starts_with("curl/"))
+        };
+        assert!(curl.is_bot(&agent("curl/8.5.0")));
+    }
+}
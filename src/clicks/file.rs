@@ -0,0 +1,237 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use super::{Click, ClickRecorder, InMemoryClickRecorder};
+use crate::analytics::{Analytics, Count, TimeBucket};
+use crate::json::{self, Value};
+use crate::{sync, Result, UrlManagerError};
+
+/// Keeps clicks in a JSON-lines file, one `click` record per line, and
+/// the days rolled up by [`ClickRecorder::roll_up`] as `day` records.
+/// They are held in an [`InMemoryClickRecorder`] as well, which answers
+/// [`Analytics`] queries.
+///
+/// ```text
+/// {"click":{"link_id":7,"at":1700000000000,"referrer":null,…}}
+/// {"day":{"link_id":7,"start":1699920000000,"hits":12}}
+/// ```
+///
+/// Clicks are appended as they come in; pruning and rolling up rewrite
+/// the file.
+#[derive(Debug)]
+pub struct FileClickRecorder {
+    path: PathBuf,
+    log: Mutex<File>,
+    clicks: InMemoryClickRecorder,
+}
+
+impl FileClickRecorder {
+    /// Opens the file at `path`, creating it if it doesn't exist, and reads
+    /// it. A torn record left at the end by a crash is dropped.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        // a leftover from a rewrite interrupted before the rename
+        let _ = fs::remove_file(rewrite_path(&path));
+
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut contents = String::new();
+        log.read_to_string(&mut contents)?;
+
+        let clicks = InMemoryClickRecorder::new();
+        let mut valid_len = 0;
+        for line in contents.split_inclusive('\n') {
+            if !line.ends_with('\n') {
+                break;
+            }
+            let record = json::parse(line).map_err(UrlManagerError::backend)?;
+            apply(&clicks, &record)?;
+            valid_len += line.len();
+        }
+        if valid_len < contents.len() {
+            log.set_len(valid_len as u64)?;
+        }
+
+        Ok(FileClickRecorder {
+            path,
+            log: Mutex::new(log),
+            clicks,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The clicks and rolled-up days as read and recorded so far.
+    pub fn get_ref(&self) -> &InMemoryClickRecorder {
+        &self.clicks
+    }
+
+    // Rewrites the file from the clicks and days in memory.
+    fn rewrite(&self, log: &mut File) -> Result<()> {
+        let tmp = rewrite_path(&self.path);
+        let mut file = File::create(&tmp)?;
+        let mut buffer = String::new();
+        for (&(link_id, start), &hits) in sync::lock(&self.clicks.days).iter() {
+            let day = Value::object([
+                ("link_id", Value::from(link_id)),
+                ("start", Value::from(start)),
+                ("hits", Value::from(hits)),
+            ]);
+            buffer.push_str(&Value::object([("day", day)]).to_string());
+            buffer.push('\n');
+        }
+        for click in sync::lock(&self.clicks.clicks).iter() {
+            buffer.push_str(&click_record(click).to_string());
+            buffer.push('\n');
+        }
+        file.write_all(buffer.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+
+        *log = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl ClickRecorder for FileClickRecorder {
+    fn record(&self, click: Click) -> Result<()> {
+        let mut log = sync::lock(&self.log);
+        log.write_all(format!("{}\n", click_record(&click)).as_bytes())?;
+        log.flush()?;
+        self.clicks.record(click)
+    }
+
+    fn flush(&self) -> Result<()> {
+        sync::lock(&self.log).sync_all()?;
+        Ok(())
+    }
+
+    fn prune(&self, cutoff: SystemTime) -> Result<usize> {
+        let mut log = sync::lock(&self.log);
+        let pruned = self.clicks.prune(cutoff)?;
+        if pruned > 0 {
+            self.rewrite(&mut log)?;
+        }
+        Ok(pruned)
+    }
+
+    fn roll_up(&self, cutoff: SystemTime) -> Result<usize> {
+        let mut log = sync::lock(&self.log);
+        let rolled_up = self.clicks.roll_up(cutoff)?;
+        if rolled_up > 0 {
+            self.rewrite(&mut log)?;
+        }
+        Ok(rolled_up)
+    }
+
+    fn forget(&self, link_id: u64) -> Result<usize> {
+        let mut log = sync::lock(&self.log);
+        let forgotten = self.clicks.forget(link_id)?;
+        if forgotten > 0 {
+            self.rewrite(&mut log)?;
+        }
+        Ok(forgotten)
+    }
+}
+
+impl Analytics for FileClickRecorder {
+    fn hits_per_bucket(&self, link_id: u64, width: Duration) -> Result<Vec<TimeBucket>> {
+        self.clicks.hits_per_bucket(link_id, width)
+    }
+
+    fn top_referrers(&self, link_id: u64, limit: usize) -> Result<Vec<Count>> {
+        self.clicks.top_referrers(link_id, limit)
+    }
+
+    fn top_user_agents(&self, link_id: u64, limit: usize) -> Result<Vec<Count>> {
+        self.clicks.top_user_agents(link_id, limit)
+    }
+
+    fn top_countries(&self, link_id: u64, limit: usize) -> Result<Vec<Count>> {
+        self.clicks.top_countries(link_id, limit)
+    }
+
+    fn clicks_between(&self, range: Range<SystemTime>) -> Result<Vec<Click>> {
+        self.clicks.clicks_between(range)
+    }
+}
+
+fn rewrite_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".rewrite");
+    path.with_file_name(name)
+}
+
+fn click_record(click: &Click) -> Value {
+    Value::object([("click", click.to_json())])
+}
+
+fn apply(clicks: &InMemoryClickRecorder, record: &Value) -> Result<()> {
+    let invalid = || UrlManagerError::backend(format!("invalid click record: {record}"));
+    if let Some(click) = record.get("click") {
+        return clicks.record(Click::from_json(click)?);
+    }
+    let day = record.get("day").ok_or_else(invalid)?;
+    let number = |name: &str| day.get(name).and_then(Value::as_u64).ok_or_else(invalid);
+    let key = (number("link_id")?, number("start")?);
+    *sync::lock(&clicks.days).entry(key).or_insert(0) += number("hits")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::DAY;
+    use crate::link::from_unix_millis;
+
+    #[test]
+    fn test_roll_up() {
+        let path = std::env::temp_dir().join(format!("clicks-{}.jsonl", rand::random::<u64>()));
+        let day = |n: u64, link_id: u64| Click {
+            link_id,
+            at: from_unix_millis(n * DAY.as_millis() as u64 + 1_000),
+            referrer: Some("https://news.example/".to_string()),
+            user_agent: None,
+            country: None,
+            visitor: Some("192.0.2.1".to_string()),
+        };
+        let recorder = FileClickRecorder::open(&path).unwrap();
+        for click in [day(1, 1), day(1, 1), day(1, 2), day(2, 1), day(3, 1)] {
+            recorder.record(click).unwrap();
+        }
+        // the cutoff falls on day 3, which is kept whole
+        let cutoff = from_unix_millis(3 * DAY.as_millis() as u64 + 5_000);
+        assert_eq!(recorder.roll_up(cutoff).unwrap(), 4);
+        assert_eq!(recorder.roll_up(cutoff).unwrap(), 0);
+        recorder.record(day(4, 1)).unwrap();
+        drop(recorder);
+
+        let recorder = FileClickRecorder::open(&path).unwrap();
+        assert_eq!(recorder.get_ref().all_clicks().len(), 2);
+        let hits = |link_id| -> Vec<u64> {
+            recorder
+                .hits_per_bucket(link_id, DAY)
+                .unwrap()
+                .iter()
+                .map(|bucket| bucket.hits)
+                .collect()
+        };
+        assert_eq!(hits(1), [2, 1, 1, 1]);
+        assert_eq!(hits(2), [1]);
+        assert_eq!(recorder.summary(1, DAY * 7, 5).unwrap().total, 5);
+        assert_eq!(recorder.top_referrers(1, 5).unwrap()[0].hits, 2);
+        let stored = fs::read_to_string(&path).unwrap();
+        assert_eq!(stored.lines().count(), 5);
+        assert_eq!(stored.matches("192.0.2.1").count(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,597 @@
+mod file;
+
+pub use file::FileClickRecorder;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::analytics::{TimeBucket, DAY};
+use crate::json::Value;
+use crate::link::{from_unix_millis, unix_millis};
+use crate::{metrics, sync, Link, LinkStore, Namespace, Result, UrlManagerError};
+
+/// What is known about a request that resolved a link.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HitMetadata {
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    /// Country code, if the caller resolved one from the client address.
+    pub country: Option<String>,
+    /// A stable key for the client, e.g. from a cookie or its address, so
+    /// an A/B tested link shows it the same variant every time.
+    pub visitor: Option<String>,
+    /// Whether the client is a crawler or preview bot, e.g. as told by a
+    /// [`BotDetector`](crate::BotDetector). Bot hits aren't counted.
+    pub bot: bool,
+    /// Whether the client asked not to be tracked, e.g. with `DNT: 1`. Its
+    /// hit is counted, but its click is recorded without referrer, user
+    /// agent, country or visitor.
+    pub do_not_track: bool,
+}
+
+/// One resolution of a link, as handed to a [`ClickRecorder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Click {
+    pub link_id: u64,
+    pub at: SystemTime,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub country: Option<String>,
+    /// The [`HitMetadata::visitor`] of the request, e.g. the client address.
+    pub visitor: Option<String>,
+}
+
+/// Keeps individual clicks; the per-link counter lives in the [`LinkStore`].
+pub trait ClickRecorder {
+    fn record(&self, click: Click) -> Result<()>;
+
+    /// Waits until every click recorded so far is stored, for recorders
+    /// that write them out in the background. The default does nothing.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Drops the clicks from before `cutoff`, returning how many, for
+    /// recorders that keep them. The default keeps everything.
+    fn prune(&self, cutoff: SystemTime) -> Result<usize> {
+        let _ = cutoff;
+        Ok(0)
+    }
+
+    /// Replaces the clicks from days before the one `cutoff` falls on with
+    /// their hits per link and day, returning how many clicks were rolled
+    /// up, so storage stays bounded while the trend is kept. Days are UTC,
+    /// like the buckets of [`Analytics`](crate::analytics::Analytics). The
+    /// default keeps clicks as they are.
+    fn roll_up(&self, cutoff: SystemTime) -> Result<usize> {
+        let _ = cutoff;
+        Ok(0)
+    }
+
+    /// Drops the clicks on `link_id`, returning how many, e.g. once they
+    /// are [archived](crate::ArchiveStore). Days rolled up by
+    /// [`ClickRecorder::roll_up`] are kept. The default keeps everything.
+    fn forget(&self, link_id: u64) -> Result<usize> {
+        let _ = link_id;
+        Ok(0)
+    }
+}
+
+/// Lets one recorder be shared, e.g. with a [`Server`](crate::server::Server)
+/// recording clicks and answering [`Analytics`](crate::analytics::Analytics)
+/// queries.
+impl<T: ClickRecorder + ?Sized> ClickRecorder for Arc<T> {
+    fn record(&self, click: Click) -> Result<()> {
+        (**self).record(click)
+    }
+
+    fn flush(&self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn prune(&self, cutoff: SystemTime) -> Result<usize> {
+        (**self).prune(cutoff)
+    }
+
+    fn roll_up(&self, cutoff: SystemTime) -> Result<usize> {
+        (**self).roll_up(cutoff)
+    }
+
+    fn forget(&self, link_id: u64) -> Result<usize> {
+        (**self).forget(link_id)
+    }
+}
+
+/// Keeps clicks in a `Vec`, mostly useful for tests and small deployments.
+#[derive(Debug, Default)]
+pub struct InMemoryClickRecorder {
+    clicks: Mutex<Vec<Click>>,
+    // rolled-up hits by link id and start of the day in unix millis
+    days: Mutex<BTreeMap<(u64, u64), u64>>,
+}
+
+impl InMemoryClickRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The clicks on `link_id`, oldest first.
+    pub fn clicks(&self, link_id: u64) -> Vec<Click> {
+        let clicks = sync::lock(&self.clicks);
+        clicks
+            .iter()
+            .filter(|click| click.link_id == link_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Every click, oldest first.
+    pub fn all_clicks(&self) -> Vec<Click> {
+        sync::lock(&self.clicks).clone()
+    }
+
+    /// The hits on `link_id` per day rolled up by [`ClickRecorder::roll_up`],
+    /// oldest first.
+    pub fn daily_hits(&self, link_id: u64) -> Vec<TimeBucket> {
+        sync::lock(&self.days)
+            .range((link_id, 0)..=(link_id, u64::MAX))
+            .map(|(&(_, start), &hits)| TimeBucket {
+                start: from_unix_millis(start),
+                hits,
+            })
+            .collect()
+    }
+}
+
+impl ClickRecorder for InMemoryClickRecorder {
+    fn record(&self, click: Click) -> Result<()> {
+        sync::lock(&self.clicks).push(click);
+        Ok(())
+    }
+
+    fn prune(&self, cutoff: SystemTime) -> Result<usize> {
+        let mut clicks = sync::lock(&self.clicks);
+        let before = clicks.len();
+        clicks.retain(|click| click.at >= cutoff);
+        Ok(before - clicks.len())
+    }
+
+    fn roll_up(&self, cutoff: SystemTime) -> Result<usize> {
+        let cutoff = start_of_day(cutoff);
+        let mut clicks = sync::lock(&self.clicks);
+        let mut days = sync::lock(&self.days);
+        let before = clicks.len();
+        clicks.retain(|click| {
+            let day = start_of_day(click.at);
+            if day >= cutoff {
+                return true;
+            }
+            *days.entry((click.link_id, day)).or_insert(0) += 1;
+            false
+        });
+        Ok(before - clicks.len())
+    }
+
+    fn forget(&self, link_id: u64) -> Result<usize> {
+        let mut clicks = sync::lock(&self.clicks);
+        let before = clicks.len();
+        clicks.retain(|click| click.link_id != link_id);
+        Ok(before - clicks.len())
+    }
+}
+
+// The start of the UTC day `time` falls on, in unix millis.
+fn start_of_day(time: SystemTime) -> u64 {
+    let millis = unix_millis(time);
+    millis - millis % DAY.as_millis() as u64
+}
+
+impl Click {
+    pub(crate) fn to_json(&self) -> Value {
+        Value::object([
+            ("link_id", Value::from(self.link_id)),
+            ("at", Value::from(unix_millis(self.at))),
+            ("referrer", Value::from(self.referrer.clone())),
+            ("user_agent", Value::from(self.user_agent.clone())),
+            ("country", Value::from(self.country.clone())),
+            ("visitor", Value::from(self.visitor.clone())),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Click> {
+        let number = |name: &str| {
+            value
+                .get(name)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| UrlManagerError::InvalidLink(format!("'{name}' is not a number")))
+        };
+        let optional_string =
+            |name: &str| value.get(name).and_then(Value::as_str).map(str::to_string);
+        Ok(Click {
+            link_id: number("link_id")?,
+            at: from_unix_millis(number("at")?),
+            referrer: optional_string("referrer"),
+            user_agent: optional_string("user_agent"),
+            country: optional_string("country"),
+            visitor: optional_string("visitor"),
+        })
+    }
+}
+
+/// Hands clicks on to another recorder, dropping repeats: a click on a
+/// link by the same [`Click::visitor`] with the same user agent within
+/// `window` of the last one passed on counts once, so double-clicks and
+/// prefetchers don't inflate analytics. Clicks without a visitor are
+/// always passed on. The hit counter in the [`LinkStore`] still counts
+/// every hit.
+///
+/// The clicks passed on are remembered for `window` in memory, keyed by
+/// link, visitor and user agent.
+#[derive(Debug)]
+pub struct DedupClickRecorder<R> {
+    inner: R,
+    window: Duration,
+    seen: Mutex<Seen>,
+}
+
+type ClickKey = (u64, String, Option<String>);
+
+#[derive(Debug, Default)]
+struct Seen {
+    last: HashMap<ClickKey, SystemTime>,
+    // the keys in `last` in the order they were passed on, for expiring them
+    order: VecDeque<(SystemTime, ClickKey)>,
+}
+
+impl<R: ClickRecorder> DedupClickRecorder<R> {
+    pub fn new(inner: R, window: Duration) -> Self {
+        DedupClickRecorder {
+            inner,
+            window,
+            seen: Mutex::default(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Whether `click` repeats one passed on within the window, remembering
+    // it if not.
+    fn is_repeat(&self, click: &Click) -> bool {
+        let Some(visitor) = &click.visitor else {
+            return false;
+        };
+        let mut seen = sync::lock(&self.seen);
+        let Seen { last, order } = &mut *seen;
+        while let Some((at, key)) = order.front() {
+            if at
+                .checked_add(self.window)
+                .is_some_and(|end| end > click.at)
+            {
+                break;
+            }
+            if last.get(key) == Some(at) {
+                last.remove(key);
+            }
+            order.pop_front();
+        }
+        let key = (click.link_id, visitor.clone(), click.user_agent.clone());
+        let repeat = last.get(&key).is_some_and(|&at| {
+            click
+                .at
+                .duration_since(at)
+                .map_or(true, |since| since < self.window)
+        });
+        if !repeat {
+            last.insert(key.clone(), click.at);
+            order.push_back((click.at, key));
+        }
+        repeat
+    }
+}
+
+impl<R: ClickRecorder> ClickRecorder for DedupClickRecorder<R> {
+    fn record(&self, click: Click) -> Result<()> {
+        if self.is_repeat(&click) {
+            return Ok(());
+        }
+        self.inner.record(click)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn prune(&self, cutoff: SystemTime) -> Result<usize> {
+        self.inner.prune(cutoff)
+    }
+
+    fn roll_up(&self, cutoff: SystemTime) -> Result<usize> {
+        self.inner.roll_up(cutoff)
+    }
+
+    fn forget(&self, link_id: u64) -> Result<usize> {
+        self.inner.forget(link_id)
+    }
+}
+
+/// Resolves `slug`, counts the hit in `store` and hands the click to
+/// `recorder`, if any. The returned link's [`Link::target`] is where the
+/// visitor `metadata` describes should go: the first of its [`Link::rules`]
+/// matching it, or else one of its [`Link::variants`], picked for
+/// `metadata.visitor` and counted too.
+pub fn record_hit<S: LinkStore + ?Sized>(
+    store: &mut S,
+    recorder: Option<&dyn ClickRecorder>,
+    slug: &str,
+    metadata: HitMetadata,
+) -> Result<Link> {
+    record_hit_with_password(store, recorder, slug, None, metadata)
+}
+
+/// [`record_hit`] giving `password` for a protected link.
+///
+/// Hits by a [`HitMetadata::bot`] resolve the same way but are neither
+/// counted nor handed to `recorder`.
+pub fn record_hit_with_password<S: LinkStore + ?Sized>(
+    store: &mut S,
+    recorder: Option<&dyn ClickRecorder>,
+    slug: &str,
+    password: Option<&str>,
+    metadata: HitMetadata,
+) -> Result<Link> {
+    record_hit_with_password_in(store, recorder, None, slug, password, metadata)
+}
+
+/// [`record_hit_with_password`] for `slug` in `namespace`.
+pub fn record_hit_with_password_in<S: LinkStore + ?Sized>(
+    store: &mut S,
+    recorder: Option<&dyn ClickRecorder>,
+    namespace: Option<&Namespace>,
+    slug: &str,
+    password: Option<&str>,
+    metadata: HitMetadata,
+) -> Result<Link> {
+    let link = metrics::resolution(|| {
+        if metadata.bot {
+            store.resolve_with_password_in(namespace, slug, password)
+        } else {
+            store.record_hit_with_password_in(namespace, slug, password)
+        }
+    })?;
+    follow_hit(store, recorder, link, metadata)
+}
+
+// The rest of `record_hit_with_password_in` once `link` is resolved, and
+// counted unless `metadata.bot`: applies its rules or variants and hands
+// the click to `recorder`.
+pub(crate) fn follow_hit<S: LinkStore + ?Sized>(
+    store: &mut S,
+    recorder: Option<&dyn ClickRecorder>,
+    link: Link,
+    metadata: HitMetadata,
+) -> Result<Link> {
+    let visitor = metadata.visitor.as_deref();
+    let link = match link.match_rule(&metadata) {
+        Some(rule) => link.matched_rule(rule),
+        None if metadata.bot => match link.pick_variant(visitor) {
+            Some(variant) => link.resolved_to(variant),
+            None => link,
+        },
+        None => pick_variant(store, link, visitor)?,
+    };
+    if metadata.bot {
+        return Ok(link);
+    }
+    if let Some(recorder) = recorder {
+        let at = link.last_hit_at().unwrap_or_else(crate::link::now);
+        recorder.record(if metadata.do_not_track {
+            Click {
+                link_id: link.id(),
+                at,
+                referrer: None,
+                user_agent: None,
+                country: None,
+                visitor: None,
+            }
+        } else {
+            Click {
+                link_id: link.id(),
+                at,
+                referrer: metadata.referrer,
+                user_agent: metadata.user_agent,
+                country: metadata.country,
+                visitor: metadata.visitor,
+            }
+        })?;
+    }
+    Ok(link)
+}
+
+// Sends a resolution of `link` to one of its variants, if it has any.
+pub(crate) fn pick_variant<S: LinkStore + ?Sized>(
+    store: &mut S,
+    link: Link,
+    visitor: Option<&str>,
+) -> Result<Link> {
+    match link.pick_variant(visitor) {
+        Some(variant) => store.record_variant_hit(link.id(), variant),
+        None => Ok(link),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryLinkStore, Platform, RedirectRule, UrlManagerError, UrlType};
+
+    #[test]
+    fn test_record_hit() {
+        let mut store = InMemoryLinkStore::new();
+        let link = store
+            .create_with_slug("docs", UrlType::parse("https://example.com").unwrap())
+            .unwrap();
+        let recorder = InMemoryClickRecorder::new();
+
+        let metadata = HitMetadata {
+            referrer: Some("https://search.example".to_string()),
+            ..HitMetadata::default()
+        };
+        record_hit(&mut store, Some(&recorder), "docs", metadata).unwrap();
+        let hit = record_hit(&mut store, None, "docs", HitMetadata::default()).unwrap();
+        assert_eq!(hit.hit_count(), 2);
+        assert!(record_hit(&mut store, None, "nope", HitMetadata::default()).is_err());
+
+        let stats = store.stats(link.id()).unwrap();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.last_hit_at, hit.last_hit_at());
+        // counting hits is not an edit
+        assert_eq!(
+            store.get(link.id()).unwrap().unwrap().updated_at(),
+            link.updated_at()
+        );
+
+        let clicks = recorder.clicks(link.id());
+        assert_eq!(clicks.len(), 1);
+        assert_eq!(
+            clicks[0].referrer.as_deref(),
+            Some("https://search.example")
+        );
+    }
+
+    #[test]
+    fn test_dedup() {
+        let recorder =
+            DedupClickRecorder::new(InMemoryClickRecorder::new(), Duration::from_secs(10));
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let click = |link_id: u64, secs: u64, visitor: Option<&str>, user_agent: &str| Click {
+            link_id,
+            at: start + Duration::from_secs(secs),
+            referrer: None,
+            user_agent: Some(user_agent.to_string()),
+            country: None,
+            visitor: visitor.map(str::to_string),
+        };
+        let alice = Some("192.0.2.1");
+        for click in [
+            click(1, 0, alice, "Firefox"),
+            click(1, 2, alice, "Firefox"),
+            click(1, 3, alice, "Safari"),
+            click(1, 4, Some("192.0.2.2"), "Firefox"),
+            click(2, 5, alice, "Firefox"),
+            click(1, 6, None, "Firefox"),
+            click(1, 7, None, "Firefox"),
+            click(1, 9, alice, "Firefox"),
+            click(1, 10, alice, "Firefox"),
+            click(1, 15, alice, "Firefox"),
+        ] {
+            recorder.record(click).unwrap();
+        }
+        let seconds: Vec<u64> = recorder
+            .get_ref()
+            .clicks(1)
+            .iter()
+            .map(|click| click.at.duration_since(start).unwrap().as_secs())
+            .collect();
+        assert_eq!(seconds, [0, 3, 4, 6, 7, 10]);
+        assert_eq!(recorder.get_ref().clicks(2).len(), 1);
+        assert!(sync::lock(&recorder.seen).order.len() <= 5);
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut store = InMemoryLinkStore::new();
+        let link = Link::builder()
+            .target("https://example.com/app")
+            .slug("app")
+            .rule(
+                RedirectRule::new(UrlType::parse("https://apps.apple.com/app/id1").unwrap())
+                    .platform(Platform::Ios),
+            )
+            .rule(
+                RedirectRule::new(UrlType::parse("https://example.com/de/app").unwrap())
+                    .countries(["DE"]),
+            )
+            .variant("https://example.com/app-b", 1)
+            .build()
+            .unwrap();
+        store.create(link).unwrap();
+
+        let iphone = HitMetadata {
+            user_agent: Some("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0)".to_string()),
+            country: Some("DE".to_string()),
+            ..HitMetadata::default()
+        };
+        let hit = record_hit(&mut store, None, "app", iphone).unwrap();
+        assert_eq!(hit.rule(), Some(0));
+        assert_eq!(hit.target().as_str(), "https://apps.apple.com/app/id1");
+
+        let german = HitMetadata {
+            country: Some("de".to_string()),
+            ..HitMetadata::default()
+        };
+        let hit = record_hit(&mut store, None, "app", german).unwrap();
+        assert_eq!(hit.target().as_str(), "https://example.com/de/app");
+
+        let hit = record_hit(&mut store, None, "app", HitMetadata::default()).unwrap();
+        assert_eq!(hit.rule(), None);
+        assert_eq!(hit.target().as_str(), "https://example.com/app-b");
+    }
+
+    #[test]
+    fn test_variants() {
+        let mut store = InMemoryLinkStore::new();
+        let link = Link::builder()
+            .target("https://example.com")
+            .slug("launch")
+            .variant("https://example.com/a", 1)
+            .variant("https://example.com/b", 1)
+            .build()
+            .unwrap();
+        store.create(link.clone()).unwrap();
+
+        let visitor = |key: &str| HitMetadata {
+            visitor: Some(key.to_string()),
+            ..HitMetadata::default()
+        };
+        let first = record_hit(&mut store, None, "launch", visitor("alice")).unwrap();
+        let again = record_hit(&mut store, None, "launch", visitor("alice")).unwrap();
+        assert_eq!(first.variant(), again.variant());
+        assert_eq!(first.target(), again.target());
+        assert_ne!(first.target().as_str(), "https://example.com/");
+        for _ in 0..8 {
+            record_hit(&mut store, None, "launch", HitMetadata::default()).unwrap();
+        }
+
+        let stored = store.get(link.id()).unwrap().unwrap();
+        assert_eq!(stored.hit_count(), 10);
+        let per_variant: Vec<u64> = stored.variants().iter().map(|v| v.hit_count()).collect();
+        assert_eq!(per_variant.iter().sum::<u64>(), 10);
+        assert!(per_variant[first.variant().unwrap()] >= 2);
+        assert_eq!(stored.variant(), None);
+        assert_eq!(stored.target().as_str(), "https://example.com/");
+        assert!(matches!(
+            store.record_variant_hit(link.id(), 2),
+            Err(UrlManagerError::InvalidLink(_))
+        ));
+
+        let bot = HitMetadata {
+            bot: true,
+            ..visitor("alice")
+        };
+        let recorder = InMemoryClickRecorder::new();
+        let crawled = record_hit(&mut store, Some(&recorder), "launch", bot).unwrap();
+        assert_eq!(crawled.target(), first.target());
+        let stored = store.get(link.id()).unwrap().unwrap();
+        assert_eq!(stored.hit_count(), 10);
+        let per_bot: Vec<u64> = stored.variants().iter().map(|v| v.hit_count()).collect();
+        assert_eq!(per_bot, per_variant);
+        assert!(recorder.clicks(link.id()).is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+pub use crate::pure::{Base62, CodeGenerator, MIN_ALPHABET_SIZE, UNAMBIGUOUS_ALPHABET};
+use crate::{Result, UrlManagerError};
+
+/// How many codes [`unique_code`] tries before giving up.
+pub const MAX_ATTEMPTS: u32 = 16;
+
+/// Asks `generator` for codes until `is_taken` accepts one.
+///
+/// `is_taken` is where the store gets consulted; after [`MAX_ATTEMPTS`]
+/// collisions the last code is returned in a `ShortcutCollision` error.
+pub fn unique_code<G, F>(generator: &G, id: u64, mut is_taken: F) -> Result<String>
+where
+    G: CodeGenerator + ?Sized,
+    F: FnMut(&str) -> Result<bool>,
+{
+    let mut code = String::new();
+    for attempt in 0..MAX_ATTEMPTS {
+        code = generator.generate(id, attempt);
+        if !is_taken(&code)? {
+            return Ok(code);
+        }
+    }
+    Err(UrlManagerError::ShortcutCollision(code))
+}
+
+impl Base62 {
+    /// Uses `alphabet` instead of `[0-9A-Za-z]`.
+    ///
+    /// The alphabet needs at least two characters and no duplicates.
+    pub fn with_alphabet(alphabet: &str) -> Result<Self> {
+        Ok(Base62::from_alphabet(alphabet)?)
+    }
+
+    /// [`Base62::with_alphabet`] for alphabets supplied by an admin: they
+    /// also need at least [`MIN_ALPHABET_SIZE`] characters, all of them
+    /// usable in a slug unescaped, i.e. ASCII letters, digits, `-` or `_`.
+    pub fn with_checked_alphabet(alphabet: &str) -> Result<Self> {
+        Ok(Base62::from_checked_alphabet(alphabet)?)
+    }
+
+    /// The entropy of one character of a code, in bits.
+    pub fn bits_per_char(&self) -> f64 {
+        (self.chars().len() as f64).log2()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base62_round_trip() {
+        let base62 = Base62::new();
+        assert_eq!(base62.encode(0), "0");
+        assert_eq!(base62.encode(61), "z");
+        assert_eq!(base62.encode(62), "10");
+        for value in [0, 1, 3844, 987_654_321, u64::MAX] {
+            assert_eq!(base62.decode(&base62.encode(value)), Some(value));
+        }
+        assert_eq!(base62.decode("not-base62"), None);
+    }
+
+    #[test]
+    fn test_custom_alphabet() {
+        let binary = Base62::with_alphabet("ab").unwrap().min_length(4);
+        assert_eq!(binary.encode(2), "aaba");
+        assert_eq!(binary.decode("aaba"), Some(2));
+
+        assert!(matches!(
+            Base62::with_alphabet("a"),
+            Err(UrlManagerError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            Base62::with_alphabet("abca"),
+            Err(UrlManagerError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_checked_alphabet() {
+        let unambiguous = Base62::unambiguous();
+        assert_eq!(unambiguous.alphabet().len(), 56);
+        for c in ['0', 'O', 'o', '1', 'I', 'l'] {
+            assert!(!unambiguous.encode(u64::MAX).contains(c));
+        }
+        assert!(unambiguous.bits_per_char() > 5.8);
+
+        assert!(Base62::with_checked_alphabet(UNAMBIGUOUS_ALPHABET).is_ok());
+        assert!(Base62::with_checked_alphabet("0123456789abcdef").is_ok());
+        for alphabet in ["0123456789abcde", "0123456789abcde/", "0123456789abcdeé"] {
+            assert!(
+                matches!(
+                    Base62::with_checked_alphabet(alphabet),
+                    Err(UrlManagerError::InvalidConfig(_))
+                ),
+                "{alphabet} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_unique_code() {
+        let base62 = Base62::new().min_length(5);
+        let taken = base62.generate(42, 0);
+
+        let code = unique_code(&base62, 42, |code| Ok(code == taken)).unwrap();
+        assert_ne!(code, taken);
+        assert!(code.len() >= 5);
+
+        let result = unique_code(&base62, 42, |_| Ok(true));
+        assert!(matches!(result, Err(UrlManagerError::ShortcutCollision(_))));
+    }
+
+    #[test]
+    fn test_random_round_trips() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x5eed);
+        for _ in 0..500 {
+            let mut chars: Vec<char> = "0123456789abcdefghijklmnopqrstuvwxyzüß-_".chars().collect();
+            let len = rng.gen_range(2..=chars.len());
+            rand::seq::SliceRandom::shuffle(chars.as_mut_slice(), &mut rng);
+            let alphabet: String = chars[..len].iter().collect();
+            let generator = Base62::with_alphabet(&alphabet)
+                .unwrap()
+                .min_length(rng.gen_range(0..12));
+            let value = match rng.gen_range(0..3) {
+                0 => rng.gen_range(0..1000),
+                1 => u64::MAX - rng.gen_range(0..1000),
+                _ => rng.gen(),
+            };
+            let code = generator.encode(value);
+            assert_eq!(generator.decode(&code), Some(value), "{alphabet}: {value}");
+            let retry = generator.generate(value, rng.gen());
+            assert!(generator.decode(&retry).is_some());
+        }
+    }
+}
@@ -0,0 +1,711 @@
+//! Settings of a deployment, read from a file and the environment.
+//!
+//! A [`Config`] starts from defaults, takes the keys of a TOML or YAML file
+//! and then `URL_MANAGER_*` environment variables, each later source
+//! overriding the earlier ones:
+//!
+//! ```toml
+//! store = "links.jsonl"
+//! bind = "0.0.0.0:8080"
+//! base_url = "https://sho.rt/"
+//! code_length = 6
+//! redirect_status = 301
+//!
+//! [rate_limits]
+//! create_burst = 10
+//! create_refill_secs = 6
+//! ```
+//!
+//! The environment variable for a key is its name in upper case, prefixed
+//! with `URL_MANAGER_` and with sections joined by `_`, e.g.
+//! `URL_MANAGER_BASE_URL` or `URL_MANAGER_RATE_LIMITS_CREATE_BURST`.
+//!
+//! | Key | Default | |
+//! | --- | ------- | --- |
+//! | `store` | `links.jsonl` | path of the [`FileLinkStore`] |
+//! | `bind` | `127.0.0.1:8080` | address the server listens on |
+//! | `base_url` | none | where shortcuts are served, see [`LinkService::base_url`] |
+//! | `code_length` | `0` | minimum length of generated codes, see [`Base62::min_length`] |
+//! | `alphabet` | Base62 | characters of generated codes, see [`Base62::with_checked_alphabet`] |
+//! | `api_key` | none | an admin key the `/api` routes then require |
+//! | `redirect_status` | `302` | 301, 302, 307 or 308 |
+//! | `case_insensitive` | `false` | resolve shortcuts regardless of case |
+//! | `interstitial_for_all` | `false` | show every link's target before redirecting |
+//! | `honor_do_not_track` | `false` | record clicks of clients sending `DNT: 1` without personal data |
+//! | `purge_interval_secs` | `60` | how often the server purges expired links |
+//! | `clicks` | none | path of the [`FileClickRecorder`] the server records clicks in |
+//! | `rollup_after_days` | none | days after which clicks are rolled up into daily hits, see [`ClickRecorder::roll_up`](crate::ClickRecorder::roll_up) |
+//! | `rate_limits.create_burst`, `rate_limits.resolve_burst` | none | requests a client may make at once |
+//! | `rate_limits.create_refill_secs`, `rate_limits.resolve_refill_secs` | `1` | seconds until a client may make another |
+//!
+//! Files are read as the subset of TOML and YAML these keys need: one
+//! level of sections, strings, integers and booleans.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{
+    Base62, FileClickRecorder, FileLinkStore, LinkService, LinkStore, RedirectStatus, Result,
+    UrlManagerError, UrlType, MAX_SLUG_LENGTH,
+};
+
+const ENV_PREFIX: &str = "URL_MANAGER_";
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// How many requests a client may make at once, and how often it regains one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Limit {
+    burst: Option<u32>,
+    refill: Duration,
+}
+
+impl Default for Limit {
+    fn default() -> Self {
+        Limit {
+            burst: None,
+            refill: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Limit {
+    #[cfg(feature = "server")]
+    fn limiter(&self) -> Result<Option<crate::RateLimiter>> {
+        self.burst
+            .map(|burst| crate::RateLimiter::new(burst, self.refill))
+            .transpose()
+    }
+}
+
+/// Settings for the command line and the server, see the [module
+/// docs](self) for the keys and their defaults.
+///
+/// ```
+/// # use url_manager::config::Config;
+/// let config = Config::from_toml(
+///     r#"
+///     base_url = "https://sho.rt/"
+///     code_length = 6
+///
+///     [rate_limits]
+///     create_burst = 10
+///     "#,
+/// )?
+/// .apply_vars([("URL_MANAGER_CODE_LENGTH", "8")])?;
+/// assert_eq!(config.get_code_length(), 8);
+/// assert_eq!(config.get_base_url().unwrap().as_str(), "https://sho.rt/");
+/// # Ok::<(), url_manager::UrlManagerError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    store: String,
+    bind: String,
+    base_url: Option<UrlType>,
+    code_length: usize,
+    alphabet: Option<String>,
+    api_key: Option<String>,
+    redirect_status: RedirectStatus,
+    case_insensitive: bool,
+    interstitial_for_all: bool,
+    honor_do_not_track: bool,
+    purge_interval: Duration,
+    clicks: Option<String>,
+    rollup_after: Option<Duration>,
+    create_limit: Limit,
+    resolve_limit: Limit,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            store: "links.jsonl".to_string(),
+            bind: "127.0.0.1:8080".to_string(),
+            base_url: None,
+            code_length: 0,
+            alphabet: None,
+            api_key: None,
+            redirect_status: RedirectStatus::default(),
+            case_insensitive: false,
+            interstitial_for_all: false,
+            honor_do_not_track: false,
+            purge_interval: Duration::from_secs(60),
+            clicks: None,
+            rollup_after: None,
+            create_limit: Limit::default(),
+            resolve_limit: Limit::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads `path` as YAML if it ends in `.yaml` or `.yml`, as TOML
+    /// otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let yaml = path
+            .extension()
+            .is_some_and(|extension| extension == "yaml" || extension == "yml");
+        let config = if yaml {
+            Self::from_yaml(&text)
+        } else {
+            Self::from_toml(&text)
+        };
+        config.map_err(|e| match e {
+            UrlManagerError::InvalidConfig(reason) => {
+                UrlManagerError::InvalidConfig(format!("{}: {reason}", path.display()))
+            }
+            e => e,
+        })
+    }
+
+    /// The defaults overridden by the keys of a TOML document.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let mut config = Config::new();
+        let mut section = String::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                UrlManagerError::InvalidConfig(format!("line {}: expected key = value", number + 1))
+            })?;
+            config = config.set(&qualified(&section, key.trim()), &unquote(value.trim())?)?;
+        }
+        Ok(config)
+    }
+
+    /// The defaults overridden by the keys of a YAML document.
+    pub fn from_yaml(text: &str) -> Result<Self> {
+        let mut config = Config::new();
+        let mut section = String::new();
+        for (number, line) in text.lines().enumerate() {
+            let content = strip_comment(line).trim_end();
+            if content.trim().is_empty() || content == "---" {
+                continue;
+            }
+            let (key, value) = content.split_once(':').ok_or_else(|| {
+                UrlManagerError::InvalidConfig(format!("line {}: expected key: value", number + 1))
+            })?;
+            let nested = content.starts_with([' ', '\t']);
+            let (key, value) = (key.trim(), value.trim());
+            if !nested {
+                section.clear();
+            }
+            if value.is_empty() && !nested {
+                section = key.to_string();
+                continue;
+            }
+            config = config.set(&qualified(&section, key), &unquote(value)?)?;
+        }
+        Ok(config)
+    }
+
+    /// Overrides keys with the process's `URL_MANAGER_*` environment
+    /// variables.
+    pub fn apply_env(self) -> Result<Self> {
+        self.apply_vars(env::vars())
+    }
+
+    /// Overrides keys with the `URL_MANAGER_*` variables among `vars`;
+    /// others, and `URL_MANAGER_*` ones that aren't keys, are ignored.
+    pub fn apply_vars<K, V>(mut self, vars: impl IntoIterator<Item = (K, V)>) -> Result<Self>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        for (name, value) in vars {
+            let Some(name) = name.as_ref().strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let Some(key) = KEYS
+                .iter()
+                .find(|key| key.replace('.', "_").eq_ignore_ascii_case(name))
+            else {
+                continue;
+            };
+            self = self.set(key, value.as_ref()).map_err(|e| match e {
+                UrlManagerError::InvalidConfig(reason) => {
+                    UrlManagerError::InvalidConfig(format!("{ENV_PREFIX}{name}: {reason}"))
+                }
+                e => e,
+            })?;
+        }
+        Ok(self)
+    }
+
+    // Sets `key`, from a file or the environment, to `value`.
+    fn set(self, key: &str, value: &str) -> Result<Self> {
+        let invalid = |expected: &str| {
+            UrlManagerError::InvalidConfig(format!("{key} must be {expected}, not '{value}'"))
+        };
+        let number = || value.parse::<u64>().map_err(|_| invalid("a number"));
+        let flag = || value.parse::<bool>().map_err(|_| invalid("true or false"));
+        let burst = || value.parse::<u32>().map_err(|_| invalid("a number"));
+        let secs = || number().map(Duration::from_secs);
+        match key {
+            "store" => self.store(value),
+            "bind" => Ok(self.bind(value)),
+            "base_url" => self.base_url(value),
+            "code_length" => self.code_length(number()? as usize),
+            "alphabet" => self.alphabet(value),
+            "api_key" => Ok(self.api_key(value)),
+            "redirect_status" => Ok(self.redirect_status(value.parse()?)),
+            "case_insensitive" => Ok(self.case_insensitive(flag()?)),
+            "interstitial_for_all" => Ok(self.interstitial_for_all(flag()?)),
+            "honor_do_not_track" => Ok(self.honor_do_not_track(flag()?)),
+            "purge_interval_secs" => self.purge_interval(secs()?),
+            "clicks" => Ok(self.clicks(value)),
+            "rollup_after_days" => Ok(self.rollup_after(Duration::from_secs(number()? * DAY_SECS))),
+            "rate_limits.create_burst" => {
+                let refill = self.create_limit.refill;
+                self.create_rate_limit(burst()?, refill)
+            }
+            "rate_limits.create_refill_secs" => {
+                let burst = self.create_limit.burst.unwrap_or(0);
+                self.create_rate_limit(burst, secs()?)
+            }
+            "rate_limits.resolve_burst" => {
+                let refill = self.resolve_limit.refill;
+                self.resolve_rate_limit(burst()?, refill)
+            }
+            "rate_limits.resolve_refill_secs" => {
+                let burst = self.resolve_limit.burst.unwrap_or(0);
+                self.resolve_rate_limit(burst, secs()?)
+            }
+            _ => Err(UrlManagerError::InvalidConfig(format!(
+                "unknown key '{key}'"
+            ))),
+        }
+    }
+
+    /// Keeps links in the JSON-lines file at `path`; database URLs like
+    /// `sqlite://…` fail with `InvalidConfig` since there are no such
+    /// backends yet.
+    pub fn store(mut self, path: impl Into<String>) -> Result<Self> {
+        let path = path.into();
+        if let Some((scheme, _)) = path.split_once("://") {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "{scheme} stores are not supported yet, only JSON-lines files"
+            )));
+        }
+        self.store = path;
+        Ok(self)
+    }
+
+    pub fn bind(mut self, address: impl Into<String>) -> Self {
+        self.bind = address.into();
+        self
+    }
+
+    /// See [`LinkService::base_url`].
+    pub fn base_url<T>(mut self, base_url: T) -> Result<Self>
+    where
+        T: TryInto<UrlType>,
+        T::Error: Into<UrlManagerError>,
+    {
+        let base_url = base_url.try_into().map_err(Into::into)?;
+        if base_url.cannot_be_a_base() {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "'{base_url}' can't be a base URL"
+            )));
+        }
+        self.base_url = Some(base_url);
+        Ok(self)
+    }
+
+    /// Pads generated codes to `length`; up to [`MAX_SLUG_LENGTH`].
+    pub fn code_length(mut self, length: usize) -> Result<Self> {
+        if length > MAX_SLUG_LENGTH {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "code_length must be at most {MAX_SLUG_LENGTH}, not {length}"
+            )));
+        }
+        self.code_length = length;
+        Ok(self)
+    }
+
+    /// Generates codes over `alphabet`, checked like
+    /// [`Base62::with_checked_alphabet`].
+    pub fn alphabet(mut self, alphabet: impl Into<String>) -> Result<Self> {
+        let alphabet = alphabet.into();
+        Base62::with_checked_alphabet(&alphabet)?;
+        self.alphabet = Some(alphabet);
+        Ok(self)
+    }
+
+    /// Requires `key`, with the admin scope, on the server's `/api` routes.
+    pub fn api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    pub fn redirect_status(mut self, status: RedirectStatus) -> Self {
+        self.redirect_status = status;
+        self
+    }
+
+    pub fn case_insensitive(mut self, enabled: bool) -> Self {
+        self.case_insensitive = enabled;
+        self
+    }
+
+    pub fn interstitial_for_all(mut self, enabled: bool) -> Self {
+        self.interstitial_for_all = enabled;
+        self
+    }
+
+    pub fn honor_do_not_track(mut self, enabled: bool) -> Self {
+        self.honor_do_not_track = enabled;
+        self
+    }
+
+    /// How often the server purges expired links.
+    pub fn purge_interval(mut self, interval: Duration) -> Result<Self> {
+        if interval.is_zero() {
+            return Err(UrlManagerError::InvalidConfig(
+                "purge_interval_secs must be above zero".to_string(),
+            ));
+        }
+        self.purge_interval = interval;
+        Ok(self)
+    }
+
+    /// Records clicks in the [`FileClickRecorder`] at `path`.
+    pub fn clicks(mut self, path: impl Into<String>) -> Self {
+        self.clicks = Some(path.into());
+        self
+    }
+
+    /// Rolls up clicks once they are `age` old, see
+    /// [`spawn_rollup`](crate::analytics::spawn_rollup).
+    pub fn rollup_after(mut self, age: Duration) -> Self {
+        self.rollup_after = Some(age);
+        self
+    }
+
+    /// Rate limits creating links per client, see [`RateLimiter::new`](crate::RateLimiter::new).
+    pub fn create_rate_limit(mut self, burst: u32, refill: Duration) -> Result<Self> {
+        self.create_limit = checked_limit(burst, refill)?;
+        Ok(self)
+    }
+
+    /// Rate limits resolving shortcuts per client, see [`RateLimiter::new`](crate::RateLimiter::new).
+    pub fn resolve_rate_limit(mut self, burst: u32, refill: Duration) -> Result<Self> {
+        self.resolve_limit = checked_limit(burst, refill)?;
+        Ok(self)
+    }
+
+    pub fn get_store(&self) -> &str {
+        &self.store
+    }
+
+    pub fn get_bind(&self) -> &str {
+        &self.bind
+    }
+
+    pub fn get_base_url(&self) -> Option<&UrlType> {
+        self.base_url.as_ref()
+    }
+
+    pub fn get_code_length(&self) -> usize {
+        self.code_length
+    }
+
+    pub fn get_api_key(&self) -> Option<&str> {
+        self.api_key.as_deref()
+    }
+
+    pub fn get_purge_interval(&self) -> Duration {
+        self.purge_interval
+    }
+
+    pub fn get_clicks(&self) -> Option<&str> {
+        self.clicks.as_deref()
+    }
+
+    pub fn get_rollup_after(&self) -> Option<Duration> {
+        self.rollup_after
+    }
+
+    /// Opens the configured [`FileClickRecorder`], if any.
+    pub fn open_clicks(&self) -> Result<Option<FileClickRecorder>> {
+        self.clicks
+            .as_ref()
+            .map(FileClickRecorder::open)
+            .transpose()
+    }
+
+    /// Opens the configured [`FileLinkStore`].
+    pub fn open_store(&self) -> Result<FileLinkStore> {
+        FileLinkStore::open(&self.store)
+    }
+
+    /// The code generator for the configured alphabet and code length.
+    pub fn generator(&self) -> Base62 {
+        let generator = match &self.alphabet {
+            // checked when it was set
+            Some(alphabet) => Base62::with_checked_alphabet(alphabet).unwrap_or_default(),
+            None => Base62::new(),
+        };
+        generator.min_length(self.code_length)
+    }
+
+    /// A [`LinkService`] on `store` with the configured generator, base URL,
+    /// redirect status and case sensitivity.
+    pub fn link_service<S: LinkStore>(&self, store: S) -> LinkService<S> {
+        let mut service = LinkService::new(store, self.generator())
+            .redirect_status(self.redirect_status)
+            .case_insensitive(self.case_insensitive);
+        if let Some(base_url) = &self.base_url {
+            service = service
+                .base_url(base_url.clone())
+                .expect("checked when it was set");
+        }
+        service
+    }
+
+    /// A [`Server`](crate::server::Server) on `store` with the configured
+    /// API key, rate limits, redirect status and flags.
+    #[cfg(feature = "server")]
+    pub fn server<S: LinkStore>(&self, store: S) -> Result<crate::server::Server<S>> {
+        use crate::server::{ApiKey, ApiKeyStore, Scope, Server};
+
+        let mut server = Server::new(store)
+            .redirect_status(self.redirect_status.code())?
+            .case_insensitive(self.case_insensitive)
+            .interstitial_for_all(self.interstitial_for_all)
+            .honor_do_not_track(self.honor_do_not_track);
+        if let Some(key) = &self.api_key {
+            let keys = ApiKeyStore::new();
+            keys.insert(key, ApiKey::new("api_key", Scope::Admin));
+            server = server.api_keys(keys);
+        }
+        if let Some(limiter) = self.create_limit.limiter()? {
+            server = server.create_rate_limit(limiter);
+        }
+        if let Some(limiter) = self.resolve_limit.limiter()? {
+            server = server.resolve_rate_limit(limiter);
+        }
+        Ok(server)
+    }
+}
+
+// Every key, for matching environment variables.
+const KEYS: [&str; 17] = [
+    "store",
+    "bind",
+    "base_url",
+    "code_length",
+    "alphabet",
+    "api_key",
+    "redirect_status",
+    "case_insensitive",
+    "interstitial_for_all",
+    "honor_do_not_track",
+    "purge_interval_secs",
+    "clicks",
+    "rollup_after_days",
+    "rate_limits.create_burst",
+    "rate_limits.create_refill_secs",
+    "rate_limits.resolve_burst",
+    "rate_limits.resolve_refill_secs",
+];
+
+// A refill interval may be set before the burst, which is then still 0.
+fn checked_limit(burst: u32, refill: Duration) -> Result<Limit> {
+    if refill.is_zero() {
+        return Err(UrlManagerError::InvalidConfig(
+            "rate limits need a refill interval above zero".to_string(),
+        ));
+    }
+    Ok(Limit {
+        burst: (burst > 0).then_some(burst),
+        refill,
+    })
+}
+
+fn qualified(section: &str, key: &str) -> String {
+    if section.is_empty() {
+        key.to_string()
+    } else {
+        format!("{section}.{key}")
+    }
+}
+
+// `line` up to a `#` outside of quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '#') => return &line[..i],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+// The text of a scalar: quoted strings without their quotes, with `\"` and
+// `\\` unescaped in double-quoted ones, anything else as written.
+fn unquote(value: &str) -> Result<String> {
+    let unterminated = || UrlManagerError::InvalidConfig(format!("unterminated string {value}"));
+    if let Some(rest) = value.strip_prefix('\'') {
+        return rest
+            .strip_suffix('\'')
+            .map(str::to_string)
+            .ok_or_else(unterminated);
+    }
+    let Some(rest) = value.strip_prefix('"') else {
+        return Ok(value.to_string());
+    };
+    let rest = rest.strip_suffix('"').ok_or_else(unterminated)?;
+    let mut text = String::with_capacity(rest.len());
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some(c @ ('"' | '\\')) => text.push(c),
+                _ => return Err(unterminated()),
+            },
+            c => text.push(c),
+        }
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources() {
+        let toml = r#"
+            # where links live
+            store = "data/links.jsonl"
+            base_url = 'https://sho.rt/'
+            code_length = 6
+            case_insensitive = true
+            alphabet = "abcdefghijkmnpqrstuvwxyz23456789" # no look-alikes
+
+            [rate_limits]
+            create_burst = 10
+            create_refill_secs = 6
+        "#;
+        let yaml = "
+---
+store: data/links.jsonl
+base_url: \"https://sho.rt/\"
+code_length: 6
+case_insensitive: true
+alphabet: abcdefghijkmnpqrstuvwxyz23456789
+rate_limits:
+  create_refill_secs: 6
+  create_burst: 10
+";
+        let config = Config::from_toml(toml).unwrap();
+        assert_eq!(Config::from_yaml(yaml).unwrap(), config);
+        assert_eq!(config.get_store(), "data/links.jsonl");
+        assert_eq!(config.get_bind(), "127.0.0.1:8080");
+        assert_eq!(config.get_code_length(), 6);
+        assert_eq!(
+            config.create_limit,
+            Limit {
+                burst: Some(10),
+                refill: Duration::from_secs(6)
+            }
+        );
+        assert_eq!(config.resolve_limit.burst, None);
+        assert_eq!(config.generator().encode(0), "aaaaaa");
+
+        let config = config
+            .apply_vars([
+                ("URL_MANAGER_BIND", "0.0.0.0:80"),
+                ("URL_MANAGER_RATE_LIMITS_CREATE_BURST", "3"),
+                ("URL_MANAGER_HONOR_DO_NOT_TRACK", "true"),
+                ("URL_MANAGER_ROLLUP_AFTER_DAYS", "30"),
+                ("URL_MANAGER_UNRELATED", "x"),
+                ("HOME", "/root"),
+            ])
+            .unwrap();
+        assert_eq!(config.get_bind(), "0.0.0.0:80");
+        assert_eq!(config.create_limit.burst, Some(3));
+        assert_eq!(config.create_limit.refill, Duration::from_secs(6));
+        assert!(config.honor_do_not_track);
+        assert_eq!(
+            config.get_rollup_after(),
+            Some(Duration::from_secs(30 * DAY_SECS))
+        );
+        assert_eq!(config.get_clicks(), None);
+
+        let short = config
+            .link_service(crate::InMemoryLinkStore::new())
+            .shorten("https://example.com/")
+            .unwrap();
+        assert!(short.url.unwrap().as_str().starts_with("https://sho.rt/"));
+    }
+
+    #[test]
+    fn test_validation() {
+        let invalid = |text: &str| match Config::from_toml(text) {
+            Err(UrlManagerError::InvalidConfig(reason)) => reason,
+            other => panic!("{text}: {other:?}"),
+        };
+        assert!(invalid("colour = \"blue\"").contains("unknown key 'colour'"));
+        assert!(invalid("[limits]\ncreate_burst = 1").contains("limits.create_burst"));
+        assert!(invalid("code_length = six").contains("must be a number"));
+        assert!(invalid("code_length = 1000").contains("at most"));
+        assert!(invalid("case_insensitive = yes").contains("true or false"));
+        assert!(invalid("store = \"sqlite://links.db\"").contains("sqlite"));
+        assert!(invalid("redirect_status = 200").contains("not a redirect status"));
+        assert!(invalid("purge_interval_secs = 0").contains("above zero"));
+        assert!(invalid("base_url = \"https://sho.rt/").contains("unterminated"));
+        assert!(invalid("just words").contains("line 1"));
+        assert!(Config::from_toml("alphabet = \"ab\"").is_err());
+        assert!(Config::from_toml("base_url = \"mailto:a@b.c\"").is_err());
+
+        let reason = match Config::new().apply_vars([("URL_MANAGER_CODE_LENGTH", "x")]) {
+            Err(UrlManagerError::InvalidConfig(reason)) => reason,
+            other => panic!("{other:?}"),
+        };
+        assert!(reason.starts_with("URL_MANAGER_CODE_LENGTH"), "{reason}");
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_server() {
+        use crate::server::Request;
+        use crate::InMemoryLinkStore;
+
+        let server = Config::from_toml("api_key = \"secret\"\nredirect_status = 308")
+            .unwrap()
+            .server(InMemoryLinkStore::new())
+            .unwrap();
+        let create = |key: &str| {
+            let request = Request::new("POST", "/api/links")
+                .header("Authorization", &format!("Bearer {key}"))
+                .body(r#"{"target": "https://example.com/", "slug": "docs"}"#);
+            server.handle(&request).status
+        };
+        assert_eq!(create("wrong"), 401);
+        assert_eq!(create("secret"), 201);
+        assert_eq!(server.handle(&Request::new("GET", "/docs")).status, 308);
+    }
+}
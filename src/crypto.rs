@@ -0,0 +1,370 @@
+//! The few cryptographic primitives the crate needs, on std alone:
+//! SHA-256, HMAC-SHA256, PBKDF2 and base64.
+//!
+//! Passwords are stored as PHC strings, `$pbkdf2-sha256$i=<rounds>,l=32$<salt>$<hash>`,
+//! so other schemes can be told apart once the crate supports them.
+
+use rand::RngCore;
+
+use crate::{Result, UrlManagerError};
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// PBKDF2 rounds for new password hashes, OWASP's recommendation for
+/// HMAC-SHA256. Tests use fewer to stay fast in debug builds.
+const PBKDF2_ROUNDS: u32 = if cfg!(test) { 1_000 } else { 600_000 };
+/// The most rounds and bytes of output a stored hash may ask for; hashes
+/// come from stores and imports, and shouldn't tie up a thread for long.
+const MAX_PBKDF2_ROUNDS: u32 = 2_000_000;
+const MAX_HASH_LENGTH: usize = 64;
+const PHC_ID: &str = "pbkdf2-sha256";
+
+#[derive(Clone)]
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Sha256 {
+    pub(crate) fn new() -> Self {
+        Sha256 {
+            state: H0,
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if !self.buffer.is_empty() {
+            let take = data.len().min(64 - self.buffer.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() < 64 {
+                return;
+            }
+            let block = std::mem::take(&mut self.buffer);
+            self.compress(&block);
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.compress(block);
+        }
+        self.buffer.extend_from_slice(blocks.remainder());
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 32] {
+        let bits = self.length.wrapping_mul(8);
+        let mut tail = std::mem::take(&mut self.buffer);
+        tail.push(0x80);
+        while tail.len() % 64 != 56 {
+            tail.push(0);
+        }
+        tail.extend_from_slice(&bits.to_be_bytes());
+        for block in tail.chunks_exact(64) {
+            self.compress(block);
+        }
+        let mut out = [0; 32];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// HMAC-SHA256 with the key schedule done once, for repeated use in PBKDF2.
+#[derive(Clone)]
+pub(crate) struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    pub(crate) fn new(key: &[u8]) -> Self {
+        let mut block = [0u8; 64];
+        if key.len() > 64 {
+            block[..32].copy_from_slice(&sha256(key));
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let mut inner = Sha256::new();
+        inner.update(&block.map(|b| b ^ 0x36));
+        let mut outer = Sha256::new();
+        outer.update(&block.map(|b| b ^ 0x5c));
+        HmacSha256 { inner, outer }
+    }
+
+    pub(crate) fn mac(&self, data: &[u8]) -> [u8; 32] {
+        let mut inner = self.inner.clone();
+        inner.update(data);
+        let mut outer = self.outer.clone();
+        outer.update(&inner.finish());
+        outer.finish()
+    }
+}
+
+/// PBKDF2 (RFC 8018) with HMAC-SHA256, filling `out`.
+pub(crate) fn pbkdf2_sha256(password: &[u8], salt: &[u8], rounds: u32, out: &mut [u8]) {
+    let prf = HmacSha256::new(password);
+    for (index, chunk) in out.chunks_mut(32).enumerate() {
+        let mut input = salt.to_vec();
+        input.extend_from_slice(&(index as u32 + 1).to_be_bytes());
+        let mut u = prf.mac(&input);
+        let mut block = u;
+        for _ in 1..rounds {
+            u = prf.mac(&u);
+            for (b, x) in block.iter_mut().zip(u) {
+                *b ^= x;
+            }
+        }
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
+
+/// Compares in time depending only on the lengths, so secrets can't be
+/// guessed byte by byte from how long a mismatch takes.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Hashes `password` with a fresh random salt into a PHC string.
+pub(crate) fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let mut hash = [0u8; 32];
+    pbkdf2_sha256(password.as_bytes(), &salt, PBKDF2_ROUNDS, &mut hash);
+    format!(
+        "${PHC_ID}$i={PBKDF2_ROUNDS},l=32${}${}",
+        base64_encode(&salt, STANDARD),
+        base64_encode(&hash, STANDARD)
+    )
+}
+
+/// Whether `password` matches the PHC string `hash`, failing with
+/// `InvalidLink` if `hash` isn't one this crate can check.
+pub(crate) fn verify_password(hash: &str, password: &str) -> Result<bool> {
+    let invalid = || UrlManagerError::InvalidLink("unsupported password hash".to_string());
+    let mut parts = hash.strip_prefix('$').ok_or_else(invalid)?.split('$');
+    let (Some(PHC_ID), Some(params), Some(salt), Some(expected), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return Err(invalid());
+    };
+    let rounds = params
+        .split(',')
+        .find_map(|param| param.strip_prefix("i="))
+        .and_then(|rounds| rounds.parse().ok())
+        .filter(|rounds| (1..=MAX_PBKDF2_ROUNDS).contains(rounds))
+        .ok_or_else(invalid)?;
+    let salt = base64_decode(salt, STANDARD).ok_or_else(invalid)?;
+    let expected = base64_decode(expected, STANDARD).ok_or_else(invalid)?;
+    if expected.is_empty() || expected.len() > MAX_HASH_LENGTH {
+        return Err(invalid());
+    }
+    let mut actual = vec![0; expected.len()];
+    pbkdf2_sha256(password.as_bytes(), &salt, rounds, &mut actual);
+    Ok(constant_time_eq(&actual, &expected))
+}
+
+pub(crate) const STANDARD: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+pub(crate) const URL_SAFE: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Base64 without padding in `alphabet`.
+pub(crate) fn base64_encode(data: &[u8], alphabet: &[u8; 64]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | u32::from(b) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(alphabet[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    out
+}
+
+/// Decodes unpadded base64 in `alphabet`, `None` if `text` isn't any or
+/// sets the unused bits of its last character, so every byte string has
+/// exactly one encoding.
+pub(crate) fn base64_decode(text: &str, alphabet: &[u8; 64]) -> Option<Vec<u8>> {
+    if text.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.as_bytes().chunks(4) {
+        let mut bits = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = alphabet.iter().position(|a| a == c)? as u32;
+            bits |= value << (18 - 6 * i);
+        }
+        if bits & ((1 << (32 - 8 * chunk.len())) - 1) != 0 {
+            return None;
+        }
+        for i in 0..chunk.len() - 1 {
+            out.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn test_sha256_and_hmac() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        let mut hasher = Sha256::new();
+        for _ in 0..1000 {
+            hasher.update(&[b'a'; 1000]);
+        }
+        assert_eq!(
+            hex(&hasher.finish()),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+        // RFC 4231 test cases 2 and 6
+        assert_eq!(
+            hex(&HmacSha256::new(b"Jefe").mac(b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&HmacSha256::new(&[0xaa; 131])
+                .mac(b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_pbkdf2() {
+        // RFC 7914 section 11
+        let mut out = [0u8; 64];
+        pbkdf2_sha256(b"passwd", b"salt", 1, &mut out);
+        assert_eq!(
+            hex(&out),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+             49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+        );
+    }
+
+    #[test]
+    fn test_password_hash() {
+        let hash = hash_password("hunter2");
+        assert!(hash.starts_with("$pbkdf2-sha256$i="));
+        assert!(verify_password(&hash, "hunter2").unwrap());
+        assert!(!verify_password(&hash, "hunter3").unwrap());
+        assert_ne!(hash, hash_password("hunter2"), "salts should differ");
+        assert!(verify_password("$argon2id$v=19$m=65536,t=3,p=4$c2FsdA$aGFzaA", "x").is_err());
+        assert!(verify_password("plain", "plain").is_err());
+
+        // refused before hashing, rather than hashing for minutes
+        let costly = |rounds: u32, length: usize| {
+            format!(
+                "${PHC_ID}$i={rounds}$c2FsdA${}",
+                base64_encode(&vec![0; length], STANDARD)
+            )
+        };
+        assert!(verify_password(&costly(u32::MAX, 32), "x").is_err());
+        assert!(verify_password(&costly(1, 1 << 20), "x").is_err());
+        assert!(!verify_password(&costly(1, 32), "x").unwrap());
+    }
+
+    #[test]
+    fn test_base64() {
+        for (data, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg"),
+            (b"fo", "Zm8"),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg"),
+            (b"\xfb\xff", "+/8"),
+        ] {
+            assert_eq!(base64_encode(data, STANDARD), encoded);
+            assert_eq!(base64_decode(encoded, STANDARD).unwrap(), data);
+        }
+        assert_eq!(base64_encode(b"\xfb\xff", URL_SAFE), "-_8");
+        assert!(base64_decode("Zm9v!", STANDARD).is_none());
+        assert!(base64_decode("Z", STANDARD).is_none());
+        // "Zg" and "Zm8" with the unused bits of their last character set
+        assert!(base64_decode("Zh", STANDARD).is_none());
+        assert!(base64_decode("Zm9", STANDARD).is_none());
+    }
+}
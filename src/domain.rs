@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use url::Host;
+
+use crate::{idn, sync, Namespace, Result, UrlManagerError};
+
+/// A hostname serving the links of one namespace, e.g. `go.acme.com` for
+/// the namespace `acme`.
+///
+/// Hosts are kept lowercase in their ASCII form, without a trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Domain {
+    host: String,
+    namespace: Namespace,
+}
+
+impl Domain {
+    /// Fails with `InvalidConfig` if `host` isn't a domain name.
+    pub fn new(host: &str, namespace: Namespace) -> Result<Self> {
+        Ok(Domain {
+            host: canonical_host(host)?,
+            namespace,
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+}
+
+/// The [`Domain`]s a [`Server`](crate::server::Server) answers for, each
+/// host claimed by one namespace at most.
+///
+/// ```
+/// # use url_manager::{Domain, DomainRegistry, Namespace};
+/// let domains = DomainRegistry::new();
+/// domains.claim(Domain::new("go.acme.com", Namespace::new("acme")?)?)?;
+/// domains.claim(Domain::new("lnk.beta.io", Namespace::new("beta")?)?)?;
+/// assert!(domains.claim(Domain::new("GO.acme.com", Namespace::new("beta")?)?).is_err());
+/// assert_eq!(domains.namespace_for("go.acme.com:443").unwrap().as_str(), "acme");
+/// # Ok::<(), url_manager::UrlManagerError>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct DomainRegistry {
+    domains: RwLock<BTreeMap<String, Namespace>>,
+}
+
+impl DomainRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves the namespace of `domain` on its host, failing with
+    /// `InvalidConfig` if another namespace has claimed the host already.
+    /// Claiming a host again for the same namespace does nothing.
+    pub fn claim(&self, domain: Domain) -> Result<()> {
+        let mut domains = sync::write(&self.domains);
+        match domains.get(&domain.host) {
+            Some(namespace) if *namespace != domain.namespace => {
+                Err(UrlManagerError::InvalidConfig(format!(
+                    "'{}' is already claimed by namespace '{namespace}'",
+                    domain.host
+                )))
+            }
+            _ => {
+                domains.insert(domain.host, domain.namespace);
+                Ok(())
+            }
+        }
+    }
+
+    /// Gives up `host`, returning whether it was claimed.
+    pub fn release(&self, host: &str) -> bool {
+        canonical_host(host).is_ok_and(|host| sync::write(&self.domains).remove(&host).is_some())
+    }
+
+    /// The namespace served on `host`, which may carry a port like a
+    /// `Host` header does.
+    pub fn namespace_for(&self, host: &str) -> Option<Namespace> {
+        let host = strip_port(host);
+        let host = canonical_host(host).ok()?;
+        sync::read(&self.domains).get(&host).cloned()
+    }
+
+    /// Every claimed domain, ordered by host.
+    pub fn domains(&self) -> Vec<Domain> {
+        sync::read(&self.domains)
+            .iter()
+            .map(|(host, namespace)| Domain {
+                host: host.clone(),
+                namespace: namespace.clone(),
+            })
+            .collect()
+    }
+}
+
+fn canonical_host(host: &str) -> Result<String> {
+    let host = idn::ascii_host(host.trim().trim_end_matches('.'));
+    match Host::parse(&host) {
+        Ok(Host::Domain(_)) if !host.is_empty() => Ok(host),
+        _ => Err(UrlManagerError::InvalidConfig(format!(
+            "'{host}' is not a domain name"
+        ))),
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domain(host: &str, namespace: &str) -> Domain {
+        Domain::new(host, Namespace::new(namespace).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_claims() {
+        let domains = DomainRegistry::new();
+        domains.claim(domain("go.acme.com.", "acme")).unwrap();
+        domains.claim(domain("Go.Acme.com", "acme")).unwrap();
+        assert!(matches!(
+            domains.claim(domain("go.acme.com", "beta")),
+            Err(UrlManagerError::InvalidConfig(_))
+        ));
+        domains.claim(domain("bücher.example", "books")).unwrap();
+        assert_eq!(
+            domains.namespace_for("xn--bcher-kva.example"),
+            Some(Namespace::new("books").unwrap())
+        );
+        assert_eq!(
+            domains.namespace_for("GO.ACME.COM:8080"),
+            Some(Namespace::new("acme").unwrap())
+        );
+        assert_eq!(domains.namespace_for("sho.rt"), None);
+        assert_eq!(domains.domains().len(), 2);
+
+        assert!(domains.release("go.acme.com"));
+        assert!(!domains.release("go.acme.com"));
+        domains.claim(domain("go.acme.com", "beta")).unwrap();
+
+        for host in ["", "127.0.0.1", "[::1]", "a b.com"] {
+            assert!(
+                Domain::new(host, Namespace::new("acme").unwrap()).is_err(),
+                "{host}"
+            );
+        }
+    }
+}
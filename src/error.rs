@@ -0,0 +1,154 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use url::ParseError;
+
+use crate::pure;
+
+/// Errors returned by the url-manager types and stores.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum UrlManagerError {
+    /// No link exists for the requested id or shortcut.
+    NotFound,
+    /// A link with this id is already stored.
+    DuplicateId(u64),
+    /// The link exists but its `expires_at` has passed.
+    Expired,
+    /// The link has been resolved as often as its `max_uses` allows.
+    UsesExhausted,
+    /// The link is password-protected and no or the wrong password was given.
+    PasswordRequired,
+    /// The shortcut is already taken by another link.
+    ShortcutCollision(String),
+    /// The link was changed since the version the caller expected, see
+    /// [`LinkStore::update_if_version`](crate::LinkStore::update_if_version);
+    /// this is its current version.
+    Conflict(u64),
+    /// The slug has invalid characters or length, or is reserved.
+    InvalidSlug(String),
+    /// The URL could not be parsed.
+    InvalidUrl(ParseError),
+    /// The link is incomplete or violates a constraint.
+    InvalidLink(String),
+    /// No valid credentials were given, e.g. a missing or unknown API key.
+    Unauthorized(String),
+    /// The target is not allowed by a [`UrlPolicy`](crate::UrlPolicy), or
+    /// the caller may not do what they asked.
+    Forbidden(String),
+    /// Too many requests from one client; the duration is how long until
+    /// the next one is allowed.
+    RateLimited(Duration),
+    /// A setting or component was configured with unusable values.
+    InvalidConfig(String),
+    /// The underlying storage failed.
+    StorageBackend(Box<dyn Error + Send + Sync>),
+}
+
+/// Shorthand for results carrying a [`UrlManagerError`].
+pub type Result<T, E = UrlManagerError> = std::result::Result<T, E>;
+
+impl UrlManagerError {
+    /// Wraps any backend failure into [`UrlManagerError::StorageBackend`].
+    pub fn backend<E>(error: E) -> Self
+    where
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        UrlManagerError::StorageBackend(error.into())
+    }
+}
+
+impl fmt::Display for UrlManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlManagerError::NotFound => write!(f, "Link not found"),
+            UrlManagerError::Expired => write!(f, "Link expired"),
+            UrlManagerError::UsesExhausted => write!(f, "Link has no uses left"),
+            UrlManagerError::PasswordRequired => write!(f, "Link requires a password"),
+            UrlManagerError::DuplicateId(id) => write!(f, "Link {id} already exists"),
+            UrlManagerError::ShortcutCollision(shortcut) => {
+                write!(f, "Shortcut '{shortcut}' is already taken")
+            }
+            UrlManagerError::Conflict(version) => {
+                write!(
+                    f,
+                    "Link was changed meanwhile, it is at version {version} now"
+                )
+            }
+            UrlManagerError::InvalidSlug(reason) => write!(f, "Invalid slug: {reason}"),
+            UrlManagerError::InvalidUrl(e) => write!(f, "Invalid URL: {e}"),
+            UrlManagerError::InvalidLink(reason) => write!(f, "Invalid link: {reason}"),
+            UrlManagerError::Unauthorized(reason) => write!(f, "Unauthorized: {reason}"),
+            UrlManagerError::Forbidden(reason) => write!(f, "Not allowed: {reason}"),
+            UrlManagerError::RateLimited(retry_after) => write!(
+                f,
+                "Rate limit exceeded, retry in {:.1}s",
+                retry_after.as_secs_f64()
+            ),
+            UrlManagerError::InvalidConfig(reason) => write!(f, "Invalid configuration: {reason}"),
+            UrlManagerError::StorageBackend(e) => write!(f, "Storage backend error: {e}"),
+        }
+    }
+}
+
+impl Error for UrlManagerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UrlManagerError::InvalidUrl(e) => Some(e),
+            UrlManagerError::StorageBackend(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::convert::Infallible> for UrlManagerError {
+    fn from(value: std::convert::Infallible) -> Self {
+        match value {}
+    }
+}
+
+impl From<ParseError> for UrlManagerError {
+    fn from(value: ParseError) -> Self {
+        UrlManagerError::InvalidUrl(value)
+    }
+}
+
+impl From<pure::Error> for UrlManagerError {
+    fn from(value: pure::Error) -> Self {
+        match value {
+            pure::Error::InvalidAlphabet(reason) => UrlManagerError::InvalidConfig(reason),
+            pure::Error::InvalidSlug(reason) => UrlManagerError::InvalidSlug(reason),
+            pure::Error::Expired => UrlManagerError::Expired,
+            pure::Error::UsesExhausted => UrlManagerError::UsesExhausted,
+        }
+    }
+}
+
+impl From<io::Error> for UrlManagerError {
+    fn from(value: io::Error) -> Self {
+        UrlManagerError::StorageBackend(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_source() {
+        let error = UrlManagerError::from(ParseError::EmptyHost);
+        assert!(matches!(
+            error,
+            UrlManagerError::InvalidUrl(ParseError::EmptyHost)
+        ));
+        assert!(error.source().is_some());
+        assert_eq!(error.to_string(), "Invalid URL: empty host");
+
+        let error = UrlManagerError::from(io::Error::other("disk on fire"));
+        assert!(matches!(error, UrlManagerError::StorageBackend(_)));
+        assert_eq!(error.to_string(), "Storage backend error: disk on fire");
+
+        assert!(UrlManagerError::NotFound.source().is_none());
+    }
+}
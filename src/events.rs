@@ -0,0 +1,652 @@
+//! Notifications about what happens to links.
+//!
+//! [`LinkService`](crate::LinkService) and the server publish an [`Event`]
+//! on an [`EventBus`] whenever a link is created, resolved, expired or
+//! deleted. Anything implementing [`EventSink`], including closures, can
+//! subscribe: [`Webhooks`] delivers events to HTTP endpoints and
+//! [`JsonLines`] writes them to stdout or a file, e.g. for a log shipper
+//! feeding an analytics pipeline.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::crypto::HmacSha256;
+use crate::json::Value;
+use crate::link::{now, unix_millis};
+use crate::{sync, Link, Result, UrlManagerError, UrlType};
+
+/// What kind of thing happened, see [`Event::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    LinkCreated,
+    LinkResolved,
+    LinkExpired,
+    LinkDeleted,
+}
+
+impl EventKind {
+    /// The name used in payloads and the `X-Webhook-Event` header, e.g.
+    /// `link.created`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::LinkCreated => "link.created",
+            EventKind::LinkResolved => "link.resolved",
+            EventKind::LinkExpired => "link.expired",
+            EventKind::LinkDeleted => "link.deleted",
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Something that happened to a link.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Event {
+    /// The link was stored.
+    LinkCreated(Link),
+    /// The link was followed; it carries the hit just counted.
+    LinkResolved(Link),
+    /// The link was made to expire.
+    LinkExpired(Link),
+    /// The link with this id was deleted.
+    LinkDeleted(u64),
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::LinkCreated(_) => EventKind::LinkCreated,
+            Event::LinkResolved(_) => EventKind::LinkResolved,
+            Event::LinkExpired(_) => EventKind::LinkExpired,
+            Event::LinkDeleted(_) => EventKind::LinkDeleted,
+        }
+    }
+
+    /// The link the event is about, unless it was deleted.
+    pub fn link(&self) -> Option<&Link> {
+        match self {
+            Event::LinkCreated(link) | Event::LinkResolved(link) | Event::LinkExpired(link) => {
+                Some(link)
+            }
+            Event::LinkDeleted(_) => None,
+        }
+    }
+
+    /// The JSON payload webhooks receive: `event`, `at` (unix
+    /// milliseconds) and the `link` as the API shows it, or its `id` for
+    /// deletions.
+    pub fn to_json(&self) -> String {
+        let detail = match self {
+            Event::LinkDeleted(id) => ("id", Value::from(*id)),
+            _ => (
+                "link",
+                self.link()
+                    .map_or(Value::Null, |link| link.to_public_json()),
+            ),
+        };
+        Value::object([
+            ("event", Value::from(self.kind().as_str())),
+            ("at", Value::from(unix_millis(now()))),
+            detail,
+        ])
+        .to_string()
+    }
+}
+
+/// Receives the events published on an [`EventBus`].
+///
+/// Sinks run on the publishing thread, so slow work such as network calls
+/// belongs on a thread of its own, the way [`Webhooks`] does it. Errors
+/// don't reach whoever published the event; the bus only counts them, see
+/// [`EventBus::failures`].
+pub trait EventSink: Send + Sync {
+    fn send(&self, event: &Event) -> Result<()>;
+
+    /// Waits until every event sent so far is delivered, for sinks that
+    /// deliver in the background. The default does nothing.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: Fn(&Event) + Send + Sync> EventSink for F {
+    fn send(&self, event: &Event) -> Result<()> {
+        self(event);
+        Ok(())
+    }
+}
+
+impl<T: EventSink + ?Sized> EventSink for Arc<T> {
+    fn send(&self, event: &Event) -> Result<()> {
+        (**self).send(event)
+    }
+
+    fn flush(&self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+/// An [`EventSink`] writing each event's [`Event::to_json`] as one line.
+pub struct JsonLines<W> {
+    writer: Mutex<W>,
+}
+
+impl<W> fmt::Debug for JsonLines<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonLines").finish_non_exhaustive()
+    }
+}
+
+impl JsonLines<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    /// Writes to `writer`, flushing after every event.
+    pub fn new(writer: W) -> Self {
+        JsonLines {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<W: Write + Send> EventSink for JsonLines<W> {
+    fn send(&self, event: &Event) -> Result<()> {
+        let mut writer = sync::lock(&self.writer);
+        writeln!(writer, "{}", event.to_json())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Hands every published [`Event`] to the subscribed sinks, in the order
+/// they subscribed.
+///
+/// Clones share their subscribers.
+///
+/// ```
+/// # use std::sync::{Arc, Mutex};
+/// # use url_manager::events::{Event, EventBus};
+/// # use url_manager::{Base62, InMemoryLinkStore, LinkService};
+/// let created = Arc::new(Mutex::new(Vec::new()));
+/// let bus = EventBus::new();
+/// let seen = Arc::clone(&created);
+/// bus.subscribe(move |event: &Event| {
+///     if let Event::LinkCreated(link) = event {
+///         seen.lock().unwrap().push(link.id());
+///     }
+/// });
+///
+/// let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new()).events(bus);
+/// let short = service.shorten("https://example.com")?;
+/// assert_eq!(*created.lock().unwrap(), [short.link.id()]);
+/// # Ok::<(), url_manager::UrlManagerError>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct EventBus {
+    sinks: Arc<RwLock<Vec<Arc<dyn EventSink>>>>,
+    failures: Arc<AtomicU64>,
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("sinks", &sync::read(&self.sinks).len())
+            .field("failures", &self.failures())
+            .finish()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, sink: impl EventSink + 'static) {
+        sync::write(&self.sinks).push(Arc::new(sink));
+    }
+
+    pub fn publish(&self, event: &Event) {
+        let sinks = sync::read(&self.sinks).clone();
+        for sink in sinks {
+            if sink.send(event).is_err() {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// How many times a sink failed to take an event.
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Waits until every sink has delivered the events published so far,
+    /// see [`EventSink::flush`]; fails with the first sink's error.
+    pub fn flush(&self) -> Result<()> {
+        let sinks = sync::read(&self.sinks).clone();
+        let mut result = Ok(());
+        for sink in sinks {
+            result = result.and(sink.flush());
+        }
+        result
+    }
+}
+
+/// Publishes `event` on `bus`, if there is one.
+pub(crate) fn publish(bus: &Option<EventBus>, event: impl FnOnce() -> Event) {
+    if let Some(bus) = bus {
+        bus.publish(&event());
+    }
+}
+
+/// Sends webhook requests for [`Webhooks`].
+///
+/// [`PlainHttp`] covers `http://` endpoints; for `https://` pass a client
+/// of your own. Non-2xx responses should be errors so they are retried.
+pub trait WebhookTransport: Send + 'static {
+    fn post(&self, url: &UrlType, headers: &[(&str, String)], body: &str) -> Result<()>;
+}
+
+/// A [`WebhookTransport`] speaking HTTP/1.1 over a plain TCP connection;
+/// it refuses `https://` URLs since the crate has no TLS.
+#[derive(Debug, Clone, Copy)]
+pub struct PlainHttp {
+    pub timeout: Duration,
+}
+
+impl Default for PlainHttp {
+    fn default() -> Self {
+        PlainHttp {
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl WebhookTransport for PlainHttp {
+    fn post(&self, url: &UrlType, headers: &[(&str, String)], body: &str) -> Result<()> {
+        if url.scheme() != "http" {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "PlainHttp can't reach '{url}', only http:// URLs"
+            )));
+        }
+        let addrs = url.socket_addrs(|| None)?;
+        let mut stream = TcpStream::connect(&*addrs)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let host = &url[url::Position::BeforeHost..url::Position::AfterPort];
+        let path = &url[url::Position::BeforePath..url::Position::AfterQuery];
+        let mut request = format!(
+            "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n",
+            body.len()
+        );
+        for (name, value) in headers {
+            request += &format!("{name}: {value}\r\n");
+        }
+        request += "\r\n";
+        request += body;
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = String::new();
+        stream.take(64 * 1024).read_to_string(&mut response)?;
+        let status = response
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok());
+        match status {
+            Some(200..=299) => Ok(()),
+            Some(status) => Err(UrlManagerError::backend(format!(
+                "webhook '{url}' answered {status}"
+            ))),
+            None => Err(UrlManagerError::backend(format!(
+                "webhook '{url}' sent no HTTP response"
+            ))),
+        }
+    }
+}
+
+/// An endpoint [`Webhooks`] delivers to.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: UrlType,
+    secret: Vec<u8>,
+    kinds: Option<Vec<EventKind>>,
+}
+
+impl Webhook {
+    /// Delivers every event to `url`, signed with `secret`.
+    pub fn new(url: UrlType, secret: impl Into<Vec<u8>>) -> Self {
+        Webhook {
+            url,
+            secret: secret.into(),
+            kinds: None,
+        }
+    }
+
+    /// Only delivers events of `kinds`.
+    pub fn only(mut self, kinds: &[EventKind]) -> Self {
+        self.kinds = Some(kinds.to_vec());
+        self
+    }
+
+    fn wants(&self, kind: EventKind) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&kind))
+    }
+}
+
+/// An [`EventSink`] POSTing events as JSON to [`Webhook`] endpoints.
+///
+/// Requests carry the event name in `X-Webhook-Event` and
+/// `X-Webhook-Signature: sha256=<hex>`, an HMAC-SHA256 of the body with
+/// the endpoint's secret, so receivers can check where they came from.
+/// Deliveries happen in order on a thread of their own; a failed one is
+/// retried with exponential backoff and dropped after the last retry.
+/// Dropping the handler waits for the queued deliveries.
+pub struct Webhooks {
+    queue: Option<Sender<(EventKind, String)>>,
+    thread: Option<JoinHandle<()>>,
+    // events queued and not yet delivered or given up on
+    pending: Arc<AtomicU64>,
+}
+
+/// How [`Webhooks`] retries failed deliveries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// Retries after the first attempt.
+    pub retries: u32,
+    /// The wait before the first retry, doubled before each further one.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            retries: 5,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Retry {
+    fn delay(&self, retry: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+impl fmt::Debug for Webhooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Webhooks").finish_non_exhaustive()
+    }
+}
+
+impl Webhooks {
+    /// Starts delivering to `endpoints` through `transport`.
+    pub fn new(endpoints: Vec<Webhook>, transport: impl WebhookTransport, retry: Retry) -> Self {
+        let (queue, queued) = mpsc::channel::<(EventKind, String)>();
+        let pending = Arc::new(AtomicU64::new(0));
+        let delivered = Arc::clone(&pending);
+        let thread = thread::spawn(move || {
+            for (kind, body) in queued {
+                for endpoint in endpoints.iter().filter(|endpoint| endpoint.wants(kind)) {
+                    let headers = [
+                        ("X-Webhook-Event", kind.as_str().to_string()),
+                        ("X-Webhook-Signature", signature(&endpoint.secret, &body)),
+                    ];
+                    for attempt in 0..=retry.retries {
+                        if attempt > 0 {
+                            thread::sleep(retry.delay(attempt - 1));
+                        }
+                        if transport.post(&endpoint.url, &headers, &body).is_ok() {
+                            break;
+                        }
+                    }
+                }
+                delivered.fetch_sub(1, Ordering::SeqCst);
+            }
+        });
+        Webhooks {
+            queue: Some(queue),
+            thread: Some(thread),
+            pending,
+        }
+    }
+}
+
+impl EventSink for Webhooks {
+    fn send(&self, event: &Event) -> Result<()> {
+        if let Some(queue) = &self.queue {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+            if queue.send((event.kind(), event.to_json())).is_err() {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+                return Err(UrlManagerError::backend("webhook delivery thread stopped"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until every queued event was delivered or ran out of retries.
+    fn flush(&self) -> Result<()> {
+        while self.pending.load(Ordering::SeqCst) > 0 {
+            if self.thread.as_ref().is_none_or(JoinHandle::is_finished) {
+                return Err(UrlManagerError::backend("webhook delivery thread stopped"));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Webhooks {
+    fn drop(&mut self) {
+        drop(self.queue.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The `X-Webhook-Signature` value for `body`.
+fn signature(secret: &[u8], body: &str) -> String {
+    let mac = HmacSha256::new(secret).mac(body.as_bytes());
+    let hex: String = mac.iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256={hex}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+    use std::net::TcpListener;
+
+    fn link() -> Link {
+        Link::builder()
+            .target("https://example.com")
+            .slug("docs")
+            .password("hunter2")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bus_and_payload() {
+        let bus = EventBus::new();
+        let kinds = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&kinds);
+        bus.subscribe(move |event: &Event| seen.lock().unwrap().push(event.kind()));
+        bus.clone().publish(&Event::LinkCreated(link()));
+        bus.publish(&Event::LinkDeleted(7));
+        assert_eq!(
+            *kinds.lock().unwrap(),
+            [EventKind::LinkCreated, EventKind::LinkDeleted]
+        );
+
+        let payload = json::parse(&Event::LinkResolved(link()).to_json()).unwrap();
+        assert_eq!(
+            payload.get("event").and_then(Value::as_str),
+            Some("link.resolved")
+        );
+        let link = payload.get("link").unwrap();
+        assert_eq!(link.get("shortcut").and_then(Value::as_str), Some("docs"));
+        assert!(link.get("password_hash").is_none());
+        let payload = json::parse(&Event::LinkDeleted(7).to_json()).unwrap();
+        assert_eq!(payload.get("id").and_then(Value::as_u64), Some(7));
+    }
+
+    struct Broken;
+
+    impl EventSink for Broken {
+        fn send(&self, _: &Event) -> Result<()> {
+            Err(UrlManagerError::backend("broker unreachable"))
+        }
+    }
+
+    #[test]
+    fn test_sinks() {
+        let bus = EventBus::new();
+        let lines = Arc::new(JsonLines::new(Vec::new()));
+        bus.subscribe(Broken);
+        bus.subscribe(Arc::clone(&lines));
+        bus.publish(&Event::LinkCreated(link()));
+        bus.publish(&Event::LinkDeleted(7));
+        assert_eq!(bus.failures(), 2);
+
+        drop(bus);
+        let written = Arc::into_inner(lines).unwrap().into_inner();
+        let written = String::from_utf8(written).unwrap();
+        let events: Vec<Value> = written
+            .lines()
+            .map(|line| json::parse(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[1].get("event").and_then(Value::as_str),
+            Some("link.deleted")
+        );
+    }
+
+    // url, headers and body of a post
+    type Delivery = (String, Vec<(String, String)>, String);
+
+    // Fails the first `failures` posts, then records the rest.
+    struct Flaky {
+        failures: Mutex<u32>,
+        delivered: Arc<Mutex<Vec<Delivery>>>,
+    }
+
+    impl WebhookTransport for Flaky {
+        fn post(&self, url: &UrlType, headers: &[(&str, String)], body: &str) -> Result<()> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(UrlManagerError::backend("unavailable"));
+            }
+            let headers = headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect();
+            self.delivered
+                .lock()
+                .unwrap()
+                .push((url.to_string(), headers, body.to_string()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_webhooks_sign_and_retry() {
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let transport = Flaky {
+            failures: Mutex::new(2),
+            delivered: Arc::clone(&delivered),
+        };
+        let retry = Retry {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+        };
+        let endpoints = vec![
+            Webhook::new("http://a.example/hook".parse().unwrap(), "secret"),
+            Webhook::new("http://b.example/hook".parse().unwrap(), "other")
+                .only(&[EventKind::LinkDeleted]),
+        ];
+        let bus = EventBus::new();
+        bus.subscribe(Webhooks::new(endpoints, transport, retry));
+        bus.publish(&Event::LinkCreated(link()));
+        bus.publish(&Event::LinkDeleted(7));
+        bus.flush().unwrap();
+
+        let delivered = delivered.lock().unwrap();
+        let urls: Vec<&str> = delivered.iter().map(|(url, ..)| url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "http://a.example/hook",
+                "http://a.example/hook",
+                "http://b.example/hook"
+            ]
+        );
+        let (_, headers, body) = &delivered[0];
+        assert_eq!(
+            headers[0],
+            ("X-Webhook-Event".to_string(), "link.created".to_string())
+        );
+        assert_eq!(headers[1].1, signature(b"secret", body));
+        assert_eq!(retry.delay(10), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_plain_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: UrlType = format!("http://{}/hook?x=1", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![0; 4096];
+            let read = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&request[..read]).into_owned()
+        });
+        PlainHttp::default()
+            .post(&url, &[("X-Test", "1".to_string())], "{}")
+            .unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hook?x=1 HTTP/1.1\r\n"));
+        assert!(request.contains("X-Test: 1\r\n"));
+        assert!(request.ends_with("\r\n\r\n{}"));
+
+        let https = "https://example.com/hook".parse().unwrap();
+        assert!(matches!(
+            PlainHttp::default().post(&https, &[], "{}"),
+            Err(UrlManagerError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,275 @@
+//! Finding links whose targets have gone away.
+//!
+//! [`check_links`] sends a HEAD request to the target of every link through
+//! an [`HttpHead`] client and keeps the answer on the link as its
+//! [`Health`]; [`spawn_health_checker`] does so periodically in the
+//! background. [`LinkStore::find_broken`] then lists the links to fix or
+//! retire.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::events::PlainHttp;
+use crate::json::Value;
+use crate::link::{from_unix_millis, now, unix_millis};
+use crate::{sync, Link, LinkStore, Result, UrlManagerError, UrlType};
+
+/// The outcome of the last check of a link's target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Health {
+    /// The HTTP status the target answered with, `None` if it couldn't be
+    /// reached at all.
+    pub status: Option<u16>,
+    pub checked_at: SystemTime,
+}
+
+impl Health {
+    /// Whether the target was unreachable or answered with a 4xx or 5xx.
+    pub fn is_broken(&self) -> bool {
+        self.status.is_none_or(|status| status >= 400)
+    }
+
+    pub(crate) fn to_json(self) -> Value {
+        Value::object([
+            ("status", Value::from(self.status.map(u64::from))),
+            ("checked_at", Value::from(unix_millis(self.checked_at))),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Health> {
+        let status = match value.get("status") {
+            None | Some(Value::Null) => None,
+            Some(status) => Some(
+                status
+                    .as_u64()
+                    .and_then(|status| u16::try_from(status).ok())
+                    .ok_or_else(|| {
+                        UrlManagerError::InvalidLink("health 'status' is invalid".to_string())
+                    })?,
+            ),
+        };
+        let checked_at = value
+            .get("checked_at")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| {
+                UrlManagerError::InvalidLink("health without 'checked_at'".to_string())
+            })?;
+        Ok(Health {
+            status,
+            checked_at: from_unix_millis(checked_at),
+        })
+    }
+}
+
+/// Sends a HEAD request and returns the response status.
+///
+/// Errors mean the target couldn't be reached, except `InvalidConfig`,
+/// which means the client can't handle the URL and leaves the link
+/// unchecked. [`PlainHttp`] covers `http://` targets, for `https://` pass a
+/// client of your own.
+pub trait HttpHead: Send + 'static {
+    fn head(&self, url: &UrlType) -> Result<u16>;
+}
+
+impl HttpHead for PlainHttp {
+    fn head(&self, url: &UrlType) -> Result<u16> {
+        if url.scheme() != "http" {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "PlainHttp can't reach '{url}', only http:// URLs"
+            )));
+        }
+        let addrs = url.socket_addrs(|| None)?;
+        let mut stream = TcpStream::connect(&*addrs)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let host = &url[url::Position::BeforeHost..url::Position::AfterPort];
+        let path = &url[url::Position::BeforePath..url::Position::AfterQuery];
+        let request = format!("HEAD {path} HTTP/1.0\r\nHost: {host}\r\n\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = String::new();
+        stream.take(16 * 1024).read_to_string(&mut response)?;
+        response
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| UrlManagerError::backend(format!("'{url}' sent no HTTP response")))
+    }
+}
+
+/// Checks the target of every live link last checked at least `older_than`
+/// ago, or never, and returns how many were checked.
+///
+/// The store is only locked to list the links and to record each result,
+/// not while waiting for targets. Links that go away in between are
+/// skipped.
+pub fn check_links<S, H>(store: &Mutex<S>, http: &H, older_than: Duration) -> Result<usize>
+where
+    S: LinkStore,
+    H: HttpHead,
+{
+    let due = now().checked_sub(older_than).unwrap_or(UNIX_EPOCH);
+    let links = sync::lock(store)
+        .iter()
+        .filter(|link| {
+            link.as_ref().map_or(true, |link| {
+                link.health().is_none_or(|health| health.checked_at <= due)
+            })
+        })
+        .collect::<Result<Vec<Link>>>()?;
+    let mut checked = 0;
+    for link in &links {
+        let status = match http.head(link.target()) {
+            Ok(status) => Some(status),
+            Err(UrlManagerError::InvalidConfig(_)) => continue,
+            Err(_) => None,
+        };
+        let health = Health {
+            status,
+            checked_at: now(),
+        };
+        checked += 1;
+        match sync::lock(store).update_with(link.id(), |link| link.set_health(health)) {
+            Ok(_) | Err(UrlManagerError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(checked)
+}
+
+/// Handle of the thread started by [`spawn_health_checker`]; dropping it
+/// stops the thread.
+#[derive(Debug)]
+pub struct HealthChecker {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Runs [`check_links`] on `store` every `interval`, checking the links not
+/// checked within the last `interval`, until the returned [`HealthChecker`]
+/// is dropped.
+///
+/// Errors are ignored; the next run tries again.
+pub fn spawn_health_checker<S, H>(
+    store: Arc<Mutex<S>>,
+    http: H,
+    interval: Duration,
+) -> HealthChecker
+where
+    S: LinkStore + Send + 'static,
+    H: HttpHead,
+{
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let _ = check_links(&store, &http, interval);
+        }
+    });
+    HealthChecker {
+        stop: Some(stop),
+        thread: Some(thread),
+    }
+}
+
+impl Drop for HealthChecker {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryLinkStore;
+    use std::net::TcpListener;
+
+    struct Statuses;
+
+    impl HttpHead for Statuses {
+        fn head(&self, url: &UrlType) -> Result<u16> {
+            match url.path() {
+                "/ok" => Ok(200),
+                "/gone" => Ok(404),
+                "/secure" => Err(UrlManagerError::InvalidConfig("no TLS".to_string())),
+                _ => Err(UrlManagerError::backend("connection refused")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_links() {
+        let mut store = InMemoryLinkStore::new();
+        for path in ["ok", "gone", "down", "secure"] {
+            store
+                .create_with_slug(
+                    path,
+                    UrlType::parse(&format!("https://example.com/{path}")).unwrap(),
+                )
+                .unwrap();
+        }
+        let store = Mutex::new(store);
+        assert!(store.lock().unwrap().find_broken().unwrap().is_empty());
+
+        assert_eq!(check_links(&store, &Statuses, Duration::ZERO).unwrap(), 3);
+        let store = store.into_inner().unwrap();
+        let ok = store.get_by_shortcut("ok").unwrap().unwrap();
+        assert_eq!(ok.health().unwrap().status, Some(200));
+        let secure = store.get_by_shortcut("secure").unwrap().unwrap();
+        assert!(secure.health().is_none());
+        let mut broken: Vec<String> = store
+            .find_broken()
+            .unwrap()
+            .iter()
+            .map(|link| link.shortcut().unwrap().to_string())
+            .collect();
+        broken.sort();
+        assert_eq!(broken, ["down", "gone"]);
+
+        let store = Mutex::new(store);
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(check_links(&store, &Statuses, day).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_health_round_trip() {
+        let health = Health {
+            status: Some(404),
+            checked_at: now(),
+        };
+        assert_eq!(Health::from_json(&health.to_json()).unwrap(), health);
+        let unreachable = Health {
+            status: None,
+            ..health
+        };
+        assert!(unreachable.is_broken());
+        assert!(!Health {
+            status: Some(301),
+            ..health
+        }
+        .is_broken());
+    }
+
+    #[test]
+    fn test_plain_http_head() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let read = stream.read(&mut request).unwrap();
+            stream.write_all(b"HTTP/1.0 410 Gone\r\n\r\n").unwrap();
+            String::from_utf8_lossy(&request[..read]).to_string()
+        });
+        let url = UrlType::parse(&format!("http://{addr}/old")).unwrap();
+        assert_eq!(PlainHttp::default().head(&url).unwrap(), 410);
+        assert!(server.join().unwrap().starts_with("HEAD /old HTTP/1.0\r\n"));
+    }
+}
@@ -0,0 +1,216 @@
+use rand::Rng;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::code::MAX_ATTEMPTS;
+use crate::link::{now, unix_millis};
+use crate::{sync, Result, UrlManagerError};
+
+/// Hands out ids for new links.
+///
+/// Generators are shared between threads, so they take `&self`. Ids only
+/// need to be unlikely to repeat; [`unique_id`] checks them against a store.
+pub trait IdGenerator {
+    fn next_id(&self) -> u64;
+}
+
+/// Asks `generator` for ids until `is_taken` accepts one.
+///
+/// After [`MAX_ATTEMPTS`](crate::code::MAX_ATTEMPTS) taken ids the last one
+/// is returned in a `DuplicateId` error.
+pub fn unique_id<G, F>(generator: &G, mut is_taken: F) -> Result<u64>
+where
+    G: IdGenerator + ?Sized,
+    F: FnMut(u64) -> Result<bool>,
+{
+    let mut id = 0;
+    for _ in 0..MAX_ATTEMPTS {
+        id = generator.next_id();
+        if !is_taken(id)? {
+            return Ok(id);
+        }
+    }
+    Err(UrlManagerError::DuplicateId(id))
+}
+
+/// Random ids, what [`LinkBuilder`](crate::LinkBuilder) uses when no id is set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn next_id(&self) -> u64 {
+        rand::thread_rng().gen()
+    }
+}
+
+/// Counts up from a start value, shared between threads.
+#[derive(Debug, Default)]
+pub struct SequentialIds {
+    next: AtomicU64,
+}
+
+impl SequentialIds {
+    pub fn new(start: u64) -> Self {
+        SequentialIds {
+            next: AtomicU64::new(start),
+        }
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn next_id(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Time-ordered ids in the layout of Twitter's Snowflake.
+///
+/// From the top: 41 bits of milliseconds since the epoch, 10 bits of worker
+/// number and a 12 bit sequence within the millisecond. Workers generating
+/// at the same time need different numbers. Within one generator ids only
+/// grow, even if the clock steps back.
+#[derive(Debug)]
+pub struct Snowflake {
+    epoch: SystemTime,
+    worker: u64,
+    // last millisecond used and the sequence within it
+    state: Mutex<(u64, u64)>,
+}
+
+impl Snowflake {
+    /// 2020-01-01T00:00:00Z, leaving room for ids until 2089.
+    pub const DEFAULT_EPOCH_MILLIS: u64 = 1_577_836_800_000;
+    pub const MAX_WORKER: u16 = (1 << 10) - 1;
+
+    pub fn new(worker: u16) -> Result<Self> {
+        if worker > Self::MAX_WORKER {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "worker {worker} is above {}",
+                Self::MAX_WORKER
+            )));
+        }
+        Ok(Snowflake {
+            epoch: SystemTime::UNIX_EPOCH + Duration::from_millis(Self::DEFAULT_EPOCH_MILLIS),
+            worker: u64::from(worker),
+            state: Mutex::new((0, 0)),
+        })
+    }
+
+    /// Counts milliseconds from `epoch` instead of 2020.
+    pub fn epoch(mut self, epoch: SystemTime) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    fn millis(&self) -> u64 {
+        now()
+            .duration_since(self.epoch)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+}
+
+impl IdGenerator for Snowflake {
+    fn next_id(&self) -> u64 {
+        let mut state = sync::lock(&self.state);
+        let (last, sequence) = *state;
+        let mut millis = self.millis().max(last);
+        let sequence = if millis == last {
+            let next = (sequence + 1) & 0xfff;
+            if next == 0 {
+                // used up this millisecond, wait for the next one
+                while millis <= last {
+                    std::thread::yield_now();
+                    millis = self.millis();
+                }
+            }
+            next
+        } else {
+            0
+        };
+        *state = (millis, sequence);
+        (millis & ((1 << 41) - 1)) << 22 | self.worker << 12 | sequence
+    }
+}
+
+/// UUIDv7s (RFC 9562): a millisecond timestamp followed by random bits.
+///
+/// Link ids are 64 bits, so as an [`IdGenerator`] this yields the upper
+/// half of a fresh UUID: the timestamp, the version and 12 random bits.
+/// Ids sort by time, but more than a few per millisecond will collide and
+/// be retried by [`unique_id`]. [`UuidV7::uuid`] gives the whole UUID.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidV7;
+
+impl UuidV7 {
+    pub fn uuid(&self) -> u128 {
+        let mut rng = rand::thread_rng();
+        let millis = u128::from(unix_millis(now()) & ((1 << 48) - 1));
+        let rand_a = u128::from(rng.gen::<u16>() & 0xfff);
+        let rand_b = rng.gen::<u64>() & ((1 << 62) - 1);
+        millis << 80 | 0x7 << 76 | rand_a << 64 | 0b10 << 62 | u128::from(rand_b)
+    }
+
+    /// Formats `uuid` as `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`.
+    pub fn format(uuid: u128) -> String {
+        let hex = format!("{uuid:032x}");
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    }
+}
+
+impl IdGenerator for UuidV7 {
+    fn next_id(&self) -> u64 {
+        (self.uuid() >> 64) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequential_and_unique_id() {
+        let ids = SequentialIds::new(5);
+        assert_eq!(ids.next_id(), 5);
+        assert_eq!(unique_id(&ids, |id| Ok(id < 8)).unwrap(), 8);
+        assert!(matches!(
+            unique_id(&ids, |_| Ok(true)),
+            Err(UrlManagerError::DuplicateId(_))
+        ));
+    }
+
+    #[test]
+    fn test_snowflake() {
+        let snowflake = Snowflake::new(3).unwrap();
+        let ids: Vec<u64> = (0..10_000).map(|_| snowflake.next_id()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| (id >> 12) & 0x3ff == 3));
+        assert!(Snowflake::new(Snowflake::MAX_WORKER + 1).is_err());
+    }
+
+    #[test]
+    fn test_uuid_v7() {
+        let uuid = UuidV7.uuid();
+        assert_eq!((uuid >> 76) & 0xf, 7, "version");
+        assert_eq!((uuid >> 62) & 0b11, 0b10, "variant");
+        let millis = (uuid >> 80) as u64;
+        assert!(millis.abs_diff(unix_millis(SystemTime::now())) < 60_000);
+
+        let formatted = UuidV7::format(uuid);
+        assert_eq!(formatted.len(), 36);
+        assert_eq!(&formatted[14..15], "7");
+        assert_eq!(
+            UuidV7::format(0x0123_4567_89ab_7def_8123_4567_89ab_cdef),
+            "01234567-89ab-7def-8123-456789abcdef"
+        );
+        assert_eq!(UuidV7.next_id() >> 12 & 0xf, 7);
+    }
+}
@@ -0,0 +1,191 @@
+//! Internationalized domain names: targets keep their hosts in the ASCII
+//! punycode form `UrlType` parses them to, e.g. `xn--bcher-kva.example`,
+//! and are shown with Unicode hosts, e.g. `bücher.example`.
+//!
+//! Unicode hosts can also imitate others, like `аpple.com` with a Cyrillic
+//! `а`; [`homograph_warning`] tells when a host looks like that, and
+//! [`UrlPolicy::block_homographs`](crate::UrlPolicy::block_homographs)
+//! refuses such targets.
+
+use url::Host;
+
+use crate::UrlType;
+
+/// `url` with its host in Unicode, for showing to people; links keep the
+/// punycode form.
+///
+/// ```
+/// # use url_manager::{idn, UrlType};
+/// let url = UrlType::parse("https://Bücher.example/café")?;
+/// assert_eq!(url.as_str(), "https://xn--bcher-kva.example/caf%C3%A9");
+/// assert_eq!(idn::to_display(&url), "https://bücher.example/caf%C3%A9");
+/// # Ok::<(), url_manager::ParseError>(())
+/// ```
+pub fn to_display(url: &UrlType) -> String {
+    match url.host() {
+        Some(Host::Domain(domain)) if domain.contains("xn--") => {
+            format!(
+                "{}{}{}",
+                &url[..url::Position::BeforeHost],
+                unicode_host(domain),
+                &url[url::Position::AfterHost..]
+            )
+        }
+        _ => url.to_string(),
+    }
+}
+
+/// The Unicode form of the punycode `host`, or `host` itself if it doesn't
+/// decode.
+pub fn unicode_host(host: &str) -> String {
+    match idna::domain_to_unicode(host) {
+        (unicode, Ok(())) => unicode,
+        (_, Err(_)) => host.to_string(),
+    }
+}
+
+/// The ASCII form of `host`, lowercased; hosts that aren't valid domain
+/// names are only lowercased.
+pub(crate) fn ascii_host(host: &str) -> String {
+    idna::domain_to_ascii(host).unwrap_or_else(|_| host.to_ascii_lowercase())
+}
+
+/// Why the host of `url` looks like an attempt to imitate another one, if
+/// it does: a label mixing letters of several scripts, like Latin and
+/// Cyrillic, or one written entirely in Cyrillic or Greek letters that
+/// look Latin.
+pub fn homograph_warning(url: &UrlType) -> Option<String> {
+    let Some(Host::Domain(domain)) = url.host() else {
+        return None;
+    };
+    let host = unicode_host(domain);
+    host.split('.').find_map(|label| {
+        let mut scripts: Vec<Script> = Vec::new();
+        for script in label.chars().filter_map(script) {
+            if !scripts.contains(&script) {
+                scripts.push(script);
+            }
+        }
+        match scripts.as_slice() {
+            [] | [Script::Latin] => None,
+            [_] if label.chars().all(|c| LATIN_LOOKALIKES.contains(c)) => Some(format!(
+                "'{label}' in '{host}' is written in {} letters that look Latin",
+                scripts[0].name()
+            )),
+            [_] => None,
+            // Han, Hiragana and Katakana are written together in Japanese
+            _ if scripts.iter().all(|script| script.is_japanese()) => None,
+            _ => Some(format!(
+                "'{label}' in '{host}' mixes {} letters",
+                scripts
+                    .iter()
+                    .map(Script::name)
+                    .collect::<Vec<_>>()
+                    .join(" and ")
+            )),
+        }
+    })
+}
+
+// Cyrillic and Greek letters that pass for Latin ones in most fonts.
+const LATIN_LOOKALIKES: &str = "аеорсухіјѕԁһӏԛԝьвнкмтοαιкνρτυχεΒΕΖΗΙΚΜΝΟΡΤΥΧ-0123456789";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Han,
+    Hiragana,
+    Katakana,
+    Hangul,
+    Other,
+}
+
+impl Script {
+    fn name(&self) -> &'static str {
+        match self {
+            Script::Latin => "Latin",
+            Script::Greek => "Greek",
+            Script::Cyrillic => "Cyrillic",
+            Script::Armenian => "Armenian",
+            Script::Hebrew => "Hebrew",
+            Script::Arabic => "Arabic",
+            Script::Han => "Han",
+            Script::Hiragana => "Hiragana",
+            Script::Katakana => "Katakana",
+            Script::Hangul => "Hangul",
+            Script::Other => "other",
+        }
+    }
+
+    fn is_japanese(&self) -> bool {
+        matches!(self, Script::Han | Script::Hiragana | Script::Katakana)
+    }
+}
+
+// The script of a letter; digits, `-` and other shared characters have none.
+fn script(c: char) -> Option<Script> {
+    let script = match c {
+        '0'..='9' | '-' | '_' => return None,
+        // combining marks and punctuation go with any script
+        '\u{0300}'..='\u{036F}' | '\u{00B7}' | '\u{30FC}' => return None,
+        'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' | '\u{1E00}'..='\u{1EFF}' => Script::Latin,
+        '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}' => Script::Greek,
+        '\u{0400}'..='\u{052F}' => Script::Cyrillic,
+        '\u{0530}'..='\u{058F}' => Script::Armenian,
+        '\u{0590}'..='\u{05FF}' => Script::Hebrew,
+        '\u{0600}'..='\u{06FF}' | '\u{0750}'..='\u{077F}' => Script::Arabic,
+        '\u{3040}'..='\u{309F}' => Script::Hiragana,
+        '\u{30A0}'..='\u{30FF}' => Script::Katakana,
+        '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' => Script::Han,
+        '\u{AC00}'..='\u{D7AF}' | '\u{1100}'..='\u{11FF}' => Script::Hangul,
+        _ => Script::Other,
+    };
+    Some(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warning(url: &str) -> Option<String> {
+        homograph_warning(&UrlType::parse(url).unwrap())
+    }
+
+    #[test]
+    fn test_display() {
+        let url = UrlType::parse("https://user@пример.испытание:8443/путь?q=1").unwrap();
+        assert_eq!(url.host_str(), Some("xn--e1afmkfd.xn--80akhbyknj4f"));
+        assert_eq!(
+            to_display(&url),
+            "https://user@пример.испытание:8443/%D0%BF%D1%83%D1%82%D1%8C?q=1"
+        );
+        let plain = UrlType::parse("https://example.com/").unwrap();
+        assert_eq!(to_display(&plain), "https://example.com/");
+        assert_eq!(ascii_host("Bücher.Example"), "xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn test_homographs() {
+        for url in [
+            "https://example.com/",
+            "https://bücher.example/",
+            "https://пример.рф/",
+            "https://東京タワー.jp/",
+            "https://한국.kr/",
+            "http://127.0.0.1/",
+        ] {
+            assert_eq!(warning(url), None, "{url}");
+        }
+        // Cyrillic а in an otherwise Latin label
+        let mixed = warning("https://\u{0430}pple.com/").unwrap();
+        assert!(mixed.contains("Cyrillic and Latin"), "{mixed}");
+        // all Cyrillic, all look-alikes
+        let whole = warning("https://\u{0430}\u{0440}\u{0440}\u{04CF}\u{0435}.com/").unwrap();
+        assert!(whole.contains("look Latin"), "{whole}");
+    }
+}
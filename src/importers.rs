@@ -0,0 +1,432 @@
+//! Moving links over from other shorteners.
+//!
+//! [`Bitly`] reads the CSV export of the Bitly dashboard and pages of the
+//! Bitly v4 API. Fetching those pages needs an HTTP client, which the crate
+//! doesn't depend on yet, so callers pass in the response bodies themselves.
+
+use std::io::BufRead;
+use std::time::SystemTime;
+
+use crate::json::{self, Value};
+use crate::link::{from_unix_millis, unix_millis};
+use crate::transfer::{self, ImportReport, RowError};
+use crate::{Link, LinkStore, Namespace, Result, UrlManagerError};
+
+/// Reads links exported from Bitly.
+///
+/// Every bitlink becomes a link whose shortcut is the back-half (`3xYz` for
+/// `https://bit.ly/3xYz`) and whose target is the long URL; the creation
+/// time, tags and click count are kept where the source has them. Ids are
+/// generated, since Bitly's ids are not numbers.
+#[derive(Debug, Clone, Default)]
+pub struct Bitly {
+    namespace: Option<Namespace>,
+}
+
+/// Outcome of [`Bitly::import_api_page`].
+#[derive(Debug, Default)]
+pub struct ApiPage {
+    pub report: ImportReport,
+    /// The URL of the next page, if there is one.
+    pub next: Option<String>,
+}
+
+// Accepted spellings of the CSV columns, after lowercasing and turning
+// everything but letters and digits into `_`.
+const TARGET_COLUMNS: [&str; 4] = ["long_url", "long_link", "original_url", "destination"];
+const LINK_COLUMNS: [&str; 4] = ["bitlink", "link", "short_url", "short_link"];
+const CREATED_COLUMNS: [&str; 4] = ["created", "created_at", "created_date", "date_created"];
+const CLICK_COLUMNS: [&str; 4] = ["clicks", "total_clicks", "click_count", "engagements"];
+
+impl Bitly {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts the imported shortcuts into `namespace`, e.g. the custom domain
+    /// they were served under, so they can't collide with existing ones.
+    pub fn namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Reads a CSV export, calling `each` with the line a record starts on
+    /// and the link it maps to.
+    ///
+    /// The header must name a long URL and a bitlink column; created,
+    /// clicks and tags columns are optional. Only I/O errors and a header
+    /// without those columns stop reading.
+    pub fn read_csv(
+        &self,
+        reader: impl BufRead,
+        mut each: impl FnMut(usize, Result<Link>),
+    ) -> Result<()> {
+        let mut columns: Option<CsvColumns> = None;
+        transfer::read_csv(reader, |line, fields| {
+            let Some(columns) = &columns else {
+                columns = Some(CsvColumns::from_header(&fields)?);
+                return Ok(());
+            };
+            each(line, self.csv_link(columns, &fields));
+            Ok(())
+        })
+    }
+
+    /// Creates a link in `store` for every record of a CSV export, see
+    /// [`Bitly::read_csv`]. Rejected records are listed in the report.
+    pub fn import_csv<S: LinkStore + ?Sized>(
+        &self,
+        store: &mut S,
+        reader: impl BufRead,
+    ) -> Result<ImportReport> {
+        let mut report = ImportReport::default();
+        self.read_csv(reader, |line, link| {
+            store_row(store, &mut report, line, link)
+        })?;
+        Ok(report)
+    }
+
+    /// Creates a link in `store` for every bitlink in `body`, a response of
+    /// `GET /v4/groups/{group_guid}/bitlinks`.
+    ///
+    /// Bitlink objects carry no click counts; a `total_clicks` field merged
+    /// in from `/v4/bitlinks/{bitlink}/clicks/summary` is picked up. Errors
+    /// are reported by the bitlink's position in the page, counting from 1.
+    pub fn import_api_page<S: LinkStore + ?Sized>(
+        &self,
+        store: &mut S,
+        body: &str,
+    ) -> Result<ApiPage> {
+        let page = json::parse(body)
+            .map_err(|e| UrlManagerError::InvalidLink(format!("page is not JSON: {e}")))?;
+        let Some(Value::Array(bitlinks)) = page.get("links") else {
+            return Err(UrlManagerError::InvalidLink(
+                "page has no 'links' array".to_string(),
+            ));
+        };
+        let mut report = ImportReport::default();
+        for (i, bitlink) in bitlinks.iter().enumerate() {
+            store_row(store, &mut report, i + 1, self.api_link(bitlink));
+        }
+        let next = page
+            .get("pagination")
+            .and_then(|pagination| pagination.get("next"))
+            .and_then(Value::as_str)
+            .filter(|next| !next.is_empty())
+            .map(str::to_string);
+        Ok(ApiPage { report, next })
+    }
+
+    fn csv_link(&self, columns: &CsvColumns, fields: &[String]) -> Result<Link> {
+        let field = |index: Option<usize>| {
+            index
+                .and_then(|i| fields.get(i))
+                .map(|field| field.trim())
+                .filter(|field| !field.is_empty())
+        };
+        let target = field(Some(columns.target))
+            .ok_or_else(|| UrlManagerError::InvalidLink("long URL is empty".to_string()))?;
+        let bitlink = field(Some(columns.link))
+            .ok_or_else(|| UrlManagerError::InvalidLink("bitlink is empty".to_string()))?;
+        let hits = field(columns.clicks)
+            .map(|clicks| {
+                clicks.replace(',', "").parse::<u64>().map_err(|_| {
+                    UrlManagerError::InvalidLink(format!("'{clicks}' is not a click count"))
+                })
+            })
+            .transpose()?;
+        let tags = field(columns.tags)
+            .map(|tags| {
+                tags.split([',', ';', '|'])
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.link(
+            target,
+            bitlink,
+            field(columns.created).map(parse_timestamp).transpose()?,
+            hits,
+            tags,
+        )
+    }
+
+    fn api_link(&self, bitlink: &Value) -> Result<Link> {
+        let string = |name: &str| bitlink.get(name).and_then(Value::as_str);
+        let target = string("long_url")
+            .ok_or_else(|| UrlManagerError::InvalidLink("'long_url' is missing".to_string()))?;
+        let link = string("link")
+            .or_else(|| string("id"))
+            .ok_or_else(|| UrlManagerError::InvalidLink("'link' is missing".to_string()))?;
+        let tags = match bitlink.get("tags") {
+            Some(Value::Array(tags)) => tags.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        self.link(
+            target,
+            link,
+            string("created_at").map(parse_timestamp).transpose()?,
+            bitlink.get("total_clicks").and_then(Value::as_u64),
+            tags,
+        )
+    }
+
+    fn link(
+        &self,
+        target: &str,
+        bitlink: &str,
+        created_at: Option<SystemTime>,
+        hits: Option<u64>,
+        tags: Vec<&str>,
+    ) -> Result<Link> {
+        let mut fields = vec![
+            ("target", Value::from(target)),
+            ("shortcut", Value::from(back_half(bitlink)?)),
+            (
+                "namespace",
+                Value::from(self.namespace.as_ref().map(Namespace::as_str)),
+            ),
+            ("tags", Value::from(tags)),
+            ("hits", Value::from(hits.unwrap_or(0))),
+        ];
+        if let Some(created_at) = created_at {
+            fields.push(("created_at", Value::from(unix_millis(created_at))));
+        }
+        Link::from_json(&transfer::with_defaults(Value::object(fields)))
+    }
+}
+
+fn store_row<S: LinkStore + ?Sized>(
+    store: &mut S,
+    report: &mut ImportReport,
+    line: usize,
+    link: Result<Link>,
+) {
+    match link.and_then(|link| store.create(link)) {
+        Ok(()) => report.imported += 1,
+        Err(error) => report.errors.push(RowError { line, error }),
+    }
+}
+
+struct CsvColumns {
+    target: usize,
+    link: usize,
+    created: Option<usize>,
+    clicks: Option<usize>,
+    tags: Option<usize>,
+}
+
+impl CsvColumns {
+    fn from_header(header: &[String]) -> Result<Self> {
+        let names: Vec<String> = header
+            .iter()
+            .map(|name| {
+                name.trim()
+                    .chars()
+                    .map(|c| {
+                        if c.is_ascii_alphanumeric() {
+                            c.to_ascii_lowercase()
+                        } else {
+                            '_'
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let find = |aliases: &[&str]| {
+            names
+                .iter()
+                .position(|name| aliases.contains(&name.as_str()))
+        };
+        let required = |aliases: &[&str], what: &str| {
+            find(aliases).ok_or_else(|| {
+                UrlManagerError::InvalidConfig(format!("Bitly CSV header has no {what} column"))
+            })
+        };
+        Ok(CsvColumns {
+            target: required(&TARGET_COLUMNS, "long URL")?,
+            link: required(&LINK_COLUMNS, "bitlink")?,
+            created: find(&CREATED_COLUMNS),
+            clicks: find(&CLICK_COLUMNS),
+            tags: find(&["tags"]),
+        })
+    }
+}
+
+// `bit.ly/3xYz` or `https://bit.ly/3xYz` → `3xYz`
+fn back_half(bitlink: &str) -> Result<String> {
+    let bitlink = bitlink.trim();
+    let rest = bitlink.split_once("://").map_or(bitlink, |(_, rest)| rest);
+    match rest.split_once('/') {
+        Some((host, path)) if !host.is_empty() && !path.trim_matches('/').is_empty() => {
+            Ok(path.trim_matches('/').to_string())
+        }
+        _ => Err(UrlManagerError::InvalidLink(format!(
+            "'{bitlink}' is not a bitlink"
+        ))),
+    }
+}
+
+/// Parses the timestamps Bitly uses, e.g. `2021-03-04T12:34:56+0000`, also
+/// accepting a space instead of `T`, `Z` or `+hh:mm` offsets, fractional
+/// seconds and plain dates.
+fn parse_timestamp(text: &str) -> Result<SystemTime> {
+    let invalid = || UrlManagerError::InvalidLink(format!("'{text}' is not a timestamp"));
+    let number = |digits: &str| -> Result<i64> {
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        digits.parse().map_err(|_| invalid())
+    };
+
+    let trimmed = text.trim();
+    let (date, time) = trimmed.split_once(['T', ' ']).unwrap_or((trimmed, ""));
+    let mut date_parts = date.split('-');
+    let (Some(year), Some(month), Some(day), None) = (
+        date_parts.next(),
+        date_parts.next(),
+        date_parts.next(),
+        date_parts.next(),
+    ) else {
+        return Err(invalid());
+    };
+    let (year, month, day) = (number(year)?, number(month)?, number(day)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    let (clock, offset) = time.split_at(time.find(['Z', '+', '-']).unwrap_or(time.len()));
+    let mut seconds = 0;
+    if !clock.is_empty() {
+        let clock = clock.split_once('.').map_or(clock, |(whole, _)| whole);
+        let mut clock_parts = clock.split(':');
+        let hour = number(clock_parts.next().unwrap_or_default())?;
+        let minute = number(clock_parts.next().unwrap_or_default())?;
+        let second = clock_parts.next().map_or(Ok(0), number)?;
+        if hour > 23 || minute > 59 || second > 60 || clock_parts.next().is_some() {
+            return Err(invalid());
+        }
+        seconds = hour * 3600 + minute * 60 + second;
+    }
+    // east of UTC means earlier in UTC
+    let sign = match offset.get(..1) {
+        None | Some("Z") => 0,
+        Some("+") => -1,
+        _ => 1,
+    };
+    let offset = offset.get(1..).unwrap_or_default().replace(':', "");
+    match (sign, offset.len()) {
+        (0, 0) => {}
+        (_, 2 | 4) if sign != 0 => {
+            let hours = number(&offset[..2])?;
+            let minutes = if offset.len() == 4 {
+                number(&offset[2..])?
+            } else {
+                0
+            };
+            seconds += sign * (hours * 3600 + minutes * 60);
+        }
+        _ => return Err(invalid()),
+    }
+
+    let seconds = days_from_civil(year, month, day) * 86_400 + seconds;
+    let millis = u64::try_from(seconds).map_err(|_| invalid())? * 1000;
+    Ok(from_unix_millis(millis))
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date, after Howard Hinnant.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryLinkStore;
+
+    #[test]
+    fn test_parse_timestamp() {
+        let at = |text: &str| parse_timestamp(text).map(unix_millis).ok();
+        assert_eq!(at("1970-01-01"), Some(0));
+        assert_eq!(at("2021-03-04T12:34:56+0000"), Some(1_614_861_296_000));
+        assert_eq!(at("2021-03-04 12:34:56"), Some(1_614_861_296_000));
+        assert_eq!(at("2021-03-04T13:34:56.789+01:00"), Some(1_614_861_296_000));
+        assert_eq!(at("2021-03-04T07:34:56-05"), Some(1_614_861_296_000));
+        assert_eq!(at("2021-03-04T12:34:56Z"), Some(1_614_861_296_000));
+        for text in [
+            "",
+            "yesterday",
+            "2021-13-01",
+            "2021-03-04T25:00:00",
+            "1969-12-31",
+        ] {
+            assert_eq!(at(text), None, "{text} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_import_csv() {
+        let csv = "\
+Title,Bitlink,Long URL,Created,Clicks,Tags
+Docs,https://bit.ly/3xYz,https://example.com/docs,2021-03-04 12:34:56,\"1,204\",\"docs, spring\"
+Blog,bit.ly/blog,https://example.com/blog,,,
+Broken,bit.ly/,https://example.com/broken,,,
+";
+        let mut store = InMemoryLinkStore::new();
+        let report = Bitly::new().import_csv(&mut store, csv.as_bytes()).unwrap();
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].line, 4);
+
+        let docs = store.get_by_shortcut("3xYz").unwrap().unwrap();
+        assert_eq!(docs.target().as_str(), "https://example.com/docs");
+        assert_eq!(docs.hit_count(), 1204);
+        assert_eq!(unix_millis(docs.created_at()), 1_614_861_296_000);
+        assert_eq!(docs.tags().collect::<Vec<_>>(), ["docs", "spring"]);
+        let blog = store.get_by_shortcut("blog").unwrap().unwrap();
+        assert_eq!(blog.hit_count(), 0);
+
+        assert!(Bitly::new()
+            .import_csv(&mut store, "Title,Clicks\n".as_bytes())
+            .is_err());
+    }
+
+    #[test]
+    fn test_import_api_page() {
+        let body = r#"{
+            "links": [
+                {"link": "https://go.example/docs", "id": "go.example/docs",
+                 "long_url": "https://example.com/docs",
+                 "created_at": "2021-03-04T12:34:56+0000",
+                 "tags": ["docs"], "total_clicks": 7},
+                {"id": "bit.ly/nolong"}
+            ],
+            "pagination": {"next": "https://api-ssl.bitly.com/v4/groups/g/bitlinks?page=2"}
+        }"#;
+        let team = Namespace::new("go.example").unwrap();
+        let mut store = InMemoryLinkStore::new();
+        let page = Bitly::new()
+            .namespace(team.clone())
+            .import_api_page(&mut store, body)
+            .unwrap();
+        assert_eq!(page.report.imported, 1);
+        assert_eq!(page.report.errors[0].line, 2);
+        assert!(page.next.unwrap().ends_with("page=2"));
+
+        let docs = store.resolve_in(Some(&team), "docs").unwrap();
+        assert_eq!(docs.hit_count(), 7);
+        assert!(store.get_by_shortcut("docs").unwrap().is_none());
+
+        let last = Bitly::new()
+            .import_api_page(&mut store, r#"{"links": [], "pagination": {"next": ""}}"#)
+            .unwrap();
+        assert!(last.next.is_none());
+        assert!(Bitly::new().import_api_page(&mut store, "{}").is_err());
+    }
+}
@@ -1,233 +1,181 @@
-use core::panic;
-use rand::Rng;
-use std::collections::HashMap;
-use std::fmt;
-use std::ops::Deref;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
-use url::{ParseError, Url as UrlType};
-
-trait UrlExtension {
-    // UrlExtension should be able to dictate
-    // how the shorten method behaves
-    // basically have an in-memory implementation
-    // and provide a way for other implementations to work in the same way
-    // So we can take advantage of i.e PostgreSQL's domain types to do all the heavy lifting
-    fn shorten(&mut self) -> Result<bool, ParseError>;
-    //fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error>;
-}
-
-impl fmt::Display for Url {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.shortcut.is_empty() {
-            panic!("Not initialized");
-        }
-        write!(f, "{}", self.origin)
-    }
-}
-
-impl From<Url> for String {
-    fn from(value: Url) -> Self {
-        value.origin.to_string()
-    }
-}
-
-impl PartialEq<Url> for &str {
-    fn eq(&self, other: &Url) -> bool {
-        *self == other.origin.to_string().as_str()
-    }
-}
-
-impl PartialEq<Url> for UrlType {
-    fn eq(&self, other: &Url) -> bool {
-        *self == other.origin
-    }
-}
-
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Url {
-    origin: UrlType,
-    shortcut: String,
-}
-
-impl UrlExtension for Url {
-    fn shorten(&mut self) -> Result<bool, ParseError> {
-        self.shortcut = self.origin.host_str().unwrap().to_string();
-        if !self.shortcut.is_empty() {
-            Ok(true)
-        } else {
-            Err(ParseError::RelativeUrlWithoutBase)
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-struct Link {
-    id: u64,
-    origin: UrlType,
-    target: UrlType,
-    created_at: DefaultInstant,
-    updated_at: DefaultInstant,
-}
-
-// Define the LinkStore trait
-trait LinkStore {
-    fn get(&self, id: u64) -> Option<Link>;
-    fn create(&mut self, link: Link) -> Result<(), String>;
-    fn update(&mut self, id: u64, link: Link) -> Result<(), String>;
-    fn delete(&mut self, id: u64) -> Result<(), String>;
-}
-
-// Implement the InMemoryLinkStore
-#[derive(Debug, Default)]
-struct InMemoryLinkStore {
-    links: Arc<Mutex<HashMap<u64, Link>>>,
-}
-
-impl InMemoryLinkStore {
-    fn new() -> Self {
-        InMemoryLinkStore {
-            links: Arc::new(Mutex::new(HashMap::new())),
-        }
-    }
-}
-
-impl LinkStore for InMemoryLinkStore {
-    fn get(&self, id: u64) -> Option<Link> {
-        self.links.lock().unwrap().get(&id).cloned()
-    }
-
-    fn create(&mut self, link: Link) -> Result<(), String> {
-        self.links.lock().unwrap().insert(link.id, link);
-        Ok(())
-    }
-
-    fn update(&mut self, id: u64, link: Link) -> Result<(), String> {
-        if self.links.lock().unwrap().contains_key(&id) {
-            self.links.lock().unwrap().insert(id, link);
-            Ok(())
-        } else {
-            Err("Link not found".to_string())
-        }
-    }
-
-    fn delete(&mut self, id: u64) -> Result<(), String> {
-        if self.links.lock().unwrap().remove(&id).is_some() {
-            Ok(())
-        } else {
-            Err("Link not found".to_string())
-        }
-    }
-}
-
-impl Default for Link {
-    fn default() -> Self {
-        let created_at = DefaultInstant::default();
-        let updated_at = DefaultInstant::default();
-        let id = rand::thread_rng().gen();
-        Link {
-            id,
-            origin: UrlType::parse("https://example.com").unwrap(),
-            target: UrlType::parse("https://example.com").unwrap(),
-            created_at: DefaultInstant::default(),
-            updated_at: DefaultInstant::default(),
-        }
-    }
-}
-
-#[derive(Debug)]
-struct DefaultInstant {
-    instant: Instant,
-}
-
-impl Default for DefaultInstant {
-    fn default() -> Self {
-        DefaultInstant {
-            instant: Instant::now(),
-        }
-    }
-}
-
-impl Clone for DefaultInstant {
-    fn clone(&self) -> DefaultInstant {
-        Self {
-            instant: self.instant,
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::any::{Any, TypeId};
-    use url::Url as UrlType;
-
-    #[test]
-    fn test_type_works() {
-        let myurl = Url {
-            origin: UrlType::parse("https://www.example.com").unwrap(),
-            shortcut: String::from("example"),
-        };
-
-        assert_eq!(myurl.type_id(), TypeId::of::<Url>(), "Type not matched");
-    }
-
-    #[test]
-    fn test_shorten() {
-        let mut myurl = Url {
-            origin: UrlType::parse("https://www.example.com").unwrap(),
-            shortcut: String::from("example"),
-        };
-        let result: bool = match myurl.shorten() {
-            Ok(val) => val,
-            Err(e) => {
-                println!("Error: {:#?}", e);
-                false
-            }
-        };
-
-        assert!(result);
-    }
-
-    #[test]
-    fn test_shorten_new() {
-        let myurl = Url {
-            origin: UrlType::parse("https://www.example.com").unwrap(),
-            shortcut: String::from("example"),
-        };
-        let expect = url::Url::parse("https://www.example.com").unwrap();
-
-        assert!(expect == myurl, "got '{myurl}' instead of '{expect}'");
-
-        let expect = url::Url::parse("https://www.example.co").unwrap();
-
-        assert!(expect == myurl, "got '{myurl}' instead of '{expect}'");
-    }
-
-    #[test]
-    fn test_link_store() {
-        // this can be done and should be done thorugh the factory
-        let mut linkstore = InMemoryLinkStore::new();
-        assert_eq!(
-            TypeId::of::<InMemoryLinkStore>(),
-            linkstore.type_id(),
-            "InMemoryLinkStore type does not match"
-        );
-
-        let link = Link::default();
-        let id = match linkstore.create(link) {
-            Ok(val) => val,
-            Err(e) => {
-                println!("Error: {:#?}", e);
-                ()
-            }
-        };
-        assert!(id != (), "id is not {:#?}, {:#?}", id, linkstore);
-    }
-
-    #[test]
-    fn instant() {
-        let instant = DefaultInstant::default();
-        println!("what? {:#?}", instant);
-    }
-}
+//! Data model and storage for a URL shortener.
+//!
+//! [`Url`] pairs a URL with its shortcut, [`Link`] is the record kept by a
+//! [`LinkStore`], and [`InMemoryLinkStore`] is the built-in store.
+//! [`LinkService`] puts a store and a [`CodeGenerator`] together for the
+//! everyday operations: shortening, resolving and expiring links, and can
+//! publish [`events`] about them, e.g. to webhooks. [`metadata`] keeps
+//! previews of target pages on links, and [`idn`] shows internationalized
+//! hosts in Unicode and spots look-alike ones. [`config`] reads the settings
+//! of a deployment from a file and the environment, [`snapshot`] backs
+//! up a store and [`manifest`] lists its public shortcuts.
+//!
+//! With the `serde` feature, [`Link`] and [`Url`] implement `Serialize` and
+//! `Deserialize`. The `server` feature adds an HTTP redirect server, and
+//! `safe-browsing` a [`scan`] backed by Google Safe Browsing. `metrics`
+//! counts links and resolutions for Prometheus, see `metrics::gather`, `qr`
+//! draws QR codes of short URLs for print, see `qr::QrCode`, `redis` adds
+//! a `RedisLinkStore`, and `testing` a `testing::MockLinkStore` for tests
+//! of code using a store.
+//! `wasm` lets the core build for `wasm32-unknown-unknown`, e.g. for
+//! Cloudflare Workers keeping links in a [`KvLinkStore`], see `set_clock`.
+//!
+//! All of this needs the default `std` feature. Without it the crate is
+//! `no_std` and only has [`pure`]: code generation, slug checks and a
+//! [`LinkRecord`](pure::LinkRecord) on `core` and `alloc`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod pure;
+// `testing` and the other optional features imply `std`
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "std")]
+pub mod analytics;
+#[cfg(feature = "std")]
+mod bots;
+#[cfg(feature = "std")]
+mod clicks;
+#[cfg(feature = "std")]
+mod code;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+mod crypto;
+#[cfg(feature = "std")]
+mod domain;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "std")]
+pub mod healthcheck;
+#[cfg(feature = "std")]
+mod id;
+#[cfg(feature = "std")]
+pub mod idn;
+#[cfg(feature = "std")]
+pub mod importers;
+#[cfg(feature = "std")]
+mod json;
+#[cfg(feature = "std")]
+mod link;
+#[cfg(feature = "std")]
+pub mod manifest;
+#[cfg(feature = "std")]
+pub mod metadata;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(all(feature = "std", not(feature = "metrics")))]
+mod metrics;
+#[cfg(feature = "std")]
+mod namespace;
+#[cfg(feature = "std")]
+mod normalize;
+#[cfg(feature = "std")]
+mod policy;
+#[cfg(feature = "qr")]
+pub mod qr;
+#[cfg(feature = "std")]
+mod ratelimit;
+#[cfg(feature = "std")]
+mod redirect;
+#[cfg(feature = "std")]
+mod rules;
+#[cfg(feature = "std")]
+pub mod scan;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "std")]
+mod service;
+#[cfg(feature = "std")]
+mod share;
+#[cfg(feature = "std")]
+mod shortcut;
+#[cfg(feature = "std")]
+mod signed;
+#[cfg(feature = "std")]
+mod slug;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+mod store;
+#[cfg(feature = "std")]
+mod strategy;
+#[cfg(feature = "std")]
+mod sync;
+#[cfg(feature = "std")]
+pub mod transfer;
+#[cfg(feature = "std")]
+mod users;
+#[cfg(feature = "std")]
+mod validate;
+#[cfg(feature = "std")]
+mod variant;
+
+#[cfg(feature = "std")]
+pub use bots::{BotDetector, KnownBots};
+#[cfg(feature = "std")]
+pub use clicks::{
+    record_hit, record_hit_with_password, record_hit_with_password_in, Click, ClickRecorder,
+    DedupClickRecorder, FileClickRecorder, HitMetadata, InMemoryClickRecorder,
+};
+#[cfg(feature = "std")]
+pub use code::{unique_code, Base62, CodeGenerator, MIN_ALPHABET_SIZE, UNAMBIGUOUS_ALPHABET};
+#[cfg(feature = "std")]
+pub use domain::{Domain, DomainRegistry};
+#[cfg(feature = "std")]
+pub use error::{Result, UrlManagerError};
+#[cfg(feature = "std")]
+pub use id::{unique_id, IdGenerator, RandomIds, SequentialIds, Snowflake, UuidV7};
+#[cfg(feature = "wasm")]
+pub use link::set_clock;
+#[cfg(feature = "std")]
+pub use link::{Link, LinkBuilder};
+#[cfg(feature = "std")]
+pub use namespace::Namespace;
+#[cfg(feature = "std")]
+pub use normalize::{normalize, Normalizer, TrackingParamStripper};
+#[cfg(feature = "std")]
+pub use policy::UrlPolicy;
+#[cfg(feature = "std")]
+pub use ratelimit::RateLimiter;
+#[cfg(feature = "std")]
+pub use redirect::RedirectStatus;
+#[cfg(feature = "std")]
+pub use rules::{GeoIp, Platform, RedirectRule};
+#[cfg(feature = "std")]
+pub use service::{LinkService, NewLink, ShortLink};
+#[cfg(feature = "std")]
+pub use share::{ShareToken, ShareTokens};
+#[cfg(feature = "std")]
+pub use shortcut::{Url, UrlExtension};
+#[cfg(feature = "std")]
+pub use signed::SignedLink;
+#[cfg(feature = "std")]
+pub use slug::{validate_slug, SlugFilter, BLOCKED_WORDS, MAX_SLUG_LENGTH, RESERVED_SLUGS};
+#[cfg(feature = "redis")]
+pub use store::RedisLinkStore;
+#[cfg(feature = "std")]
+pub use store::{
+    migrate, spawn_purger, ArchiveStore, AsyncLinkStore, AuditedLinkStore, CacheStats,
+    CachedLinkStore, Change, Conflict, FileLinkStore, InMemoryKv, InMemoryLinkStore, KvLinkStore,
+    KvPage, KvStore, LinkQuery, LinkStats, LinkStore, LocalObjectStore, MigrateOptions,
+    MigrateReport, ObjectStore, Purger, Revision, ShardedLinkStore, SyncStoreAdapter,
+    TieredLinkStore, Transactional,
+};
+#[cfg(feature = "std")]
+pub use strategy::ShortenStrategy;
+#[cfg(feature = "std")]
+pub use url::{ParseError, Url as UrlType};
+#[cfg(feature = "std")]
+pub use users::{Action, InMemoryUserStore, Role, User, UserStore};
+#[cfg(feature = "std")]
+pub use validate::{HttpProbe, Validation, Validator};
+#[cfg(feature = "std")]
+pub use variant::Variant;
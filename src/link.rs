@@ -0,0 +1,1365 @@
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url as UrlType;
+
+use crate::crypto::{hash_password, verify_password};
+use crate::healthcheck::Health;
+use crate::json::Value;
+use crate::metadata::Preview;
+use crate::pure::LinkRecord;
+use crate::redirect::RedirectStatus;
+use crate::rules::RedirectRule;
+use crate::scan::Verdict;
+use crate::variant::{self, Variant};
+use crate::{
+    validate_slug, HitMetadata, IdGenerator, Namespace, Normalizer, RandomIds, Result,
+    ShortenStrategy, SlugFilter, TrackingParamStripper, UrlManagerError,
+};
+
+/// A stored link from a submitted URL to the URL it resolves to.
+#[derive(Debug, Clone)]
+pub struct Link {
+    id: u64,
+    origin: UrlType,
+    target: UrlType,
+    scheduled_target: Option<(UrlType, SystemTime)>,
+    variants: Vec<Variant>,
+    // the variant picked by the resolution that returned this copy
+    variant: Option<usize>,
+    rules: Vec<RedirectRule>,
+    // the rule matched by the resolution that returned this copy
+    rule: Option<usize>,
+    namespace: Option<Namespace>,
+    owner_id: Option<u64>,
+    shortcut: Option<String>,
+    expires_at: Option<SystemTime>,
+    max_uses: Option<u32>,
+    tags: BTreeSet<String>,
+    hits: u64,
+    last_hit_at: Option<SystemTime>,
+    password_hash: Option<String>,
+    interstitial: bool,
+    redirect_status: Option<RedirectStatus>,
+    verdict: Option<Verdict>,
+    preview: Option<Preview>,
+    health: Option<Health>,
+    deleted_at: Option<SystemTime>,
+    created_at: SystemTime,
+    updated_at: SystemTime,
+    version: u64,
+}
+
+impl Link {
+    /// Starts building a new link, see [`LinkBuilder`].
+    pub fn builder() -> LinkBuilder {
+        LinkBuilder::default()
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The URL as it was submitted.
+    pub fn origin(&self) -> &UrlType {
+        &self.origin
+    }
+
+    /// The URL the link resolves to, taking a due
+    /// [`Link::scheduled_target`] into account. On a link returned by a
+    /// resolution that matched a [`Link::rule`] or picked a
+    /// [`Link::variant`], the target of that rule or variant.
+    pub fn target(&self) -> &UrlType {
+        let rule = self.rule.and_then(|rule| self.rules.get(rule));
+        let variant = self.variant.and_then(|variant| self.variants.get(variant));
+        rule.map(RedirectRule::target)
+            .or(variant.map(Variant::target))
+            .unwrap_or_else(|| self.target_at(now()))
+    }
+
+    /// [`Link::target`] with its host in Unicode, for showing to people;
+    /// the target itself keeps internationalized hosts in punycode.
+    pub fn display_target(&self) -> String {
+        crate::idn::to_display(self.target())
+    }
+
+    /// The URL the link resolves to at `time`.
+    pub fn target_at(&self, time: SystemTime) -> &UrlType {
+        match &self.scheduled_target {
+            Some((target, at)) if *at <= time => target,
+            _ => &self.target,
+        }
+    }
+
+    /// The target the link switches to and when, if a switch is scheduled.
+    ///
+    /// The switch happens when the time comes, checked whenever the target
+    /// is read, so no background task is needed; the next write stores it as
+    /// the plain target.
+    pub fn scheduled_target(&self) -> Option<(&UrlType, SystemTime)> {
+        self.scheduled_target
+            .as_ref()
+            .map(|(target, at)| (target, *at))
+    }
+
+    /// Makes the link resolve to `target` from `at` on, e.g. at a product
+    /// launch, replacing any earlier schedule. Store the change with
+    /// [`LinkStore::update`](crate::LinkStore::update).
+    pub fn schedule_target(&mut self, target: UrlType, at: SystemTime) {
+        self.scheduled_target = Some((target, from_unix_millis(unix_millis(at))));
+    }
+
+    pub fn cancel_scheduled_target(&mut self) {
+        self.scheduled_target = None;
+    }
+
+    /// The targets resolutions are split between by weight, for A/B tests;
+    /// empty for a plain link.
+    pub fn variants(&self) -> &[Variant] {
+        &self.variants
+    }
+
+    /// Sends a `weight` share of resolutions to `target`, see
+    /// [`LinkBuilder::variant`]. Store the change with
+    /// [`LinkStore::update`](crate::LinkStore::update).
+    pub fn add_variant(&mut self, target: UrlType, weight: u32) {
+        self.variants.push(Variant::new(target, weight));
+    }
+
+    /// Turns the link back into a plain one.
+    pub fn clear_variants(&mut self) {
+        self.variants.clear();
+        self.variant = None;
+    }
+
+    /// Picks one of the [`Link::variants`] by weight, `None` if there are
+    /// none or all weights are 0.
+    ///
+    /// With a `visitor` key, e.g. from a cookie, the same visitor always
+    /// gets the same variant; without one the pick is random.
+    pub fn pick_variant(&self, visitor: Option<&str>) -> Option<usize> {
+        variant::pick(&self.variants, self.id, visitor)
+    }
+
+    /// The variant the resolution that returned this link picked, see
+    /// [`LinkStore::record_variant_hit`](crate::LinkStore::record_variant_hit).
+    pub fn variant(&self) -> Option<usize> {
+        self.variant
+    }
+
+    /// Conditional targets, e.g. by platform or country; the first rule
+    /// matching a visitor decides where it goes, ahead of any variants.
+    pub fn rules(&self) -> &[RedirectRule] {
+        &self.rules
+    }
+
+    /// Adds `rule` after the existing ones. Store the change with
+    /// [`LinkStore::update`](crate::LinkStore::update).
+    pub fn add_rule(&mut self, rule: RedirectRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn clear_rules(&mut self) {
+        self.rules.clear();
+        self.rule = None;
+    }
+
+    /// The first of the [`Link::rules`] matching the visitor `metadata`
+    /// describes, if any.
+    pub fn match_rule(&self, metadata: &HitMetadata) -> Option<usize> {
+        self.rules.iter().position(|rule| rule.matches(metadata))
+    }
+
+    /// The rule the resolution that returned this link matched, see
+    /// [`record_hit`](crate::record_hit).
+    pub fn rule(&self) -> Option<usize> {
+        self.rule
+    }
+
+    // This link as returned by a resolution that matched `rule`.
+    pub(crate) fn matched_rule(mut self, rule: usize) -> Link {
+        self.rule = Some(rule);
+        self
+    }
+
+    // Counts a resolution that picked `variant`, failing if there is none.
+    pub(crate) fn hit_variant(&mut self, variant: usize) -> Result<()> {
+        self.variants
+            .get_mut(variant)
+            .ok_or_else(|| UrlManagerError::InvalidLink(format!("no variant {variant}")))?
+            .hit();
+        Ok(())
+    }
+
+    // This link as returned by a resolution that picked `variant`.
+    pub(crate) fn resolved_to(mut self, variant: usize) -> Link {
+        self.variant = Some(variant);
+        self
+    }
+
+    // The stored target and the scheduled one, for indexing by target.
+    pub(crate) fn targets(&self) -> impl Iterator<Item = &UrlType> {
+        std::iter::once(&self.target)
+            .chain(self.scheduled_target.as_ref().map(|(target, _)| target))
+    }
+
+    // Makes a due scheduled target the plain target.
+    fn settle_target(&mut self) {
+        if let Some((_, at)) = &self.scheduled_target {
+            if *at <= now() {
+                self.target = self.scheduled_target.take().unwrap().0;
+            }
+        }
+    }
+
+    /// The namespace the shortcut belongs to, `None` for the shared one.
+    pub fn namespace(&self) -> Option<&Namespace> {
+        self.namespace.as_ref()
+    }
+
+    /// The id of the [`User`](crate::User) the link belongs to, `None` for
+    /// links nobody owns, which only admins manage.
+    pub fn owner_id(&self) -> Option<u64> {
+        self.owner_id
+    }
+
+    pub fn set_owner_id(&mut self, owner_id: Option<u64>) {
+        self.owner_id = owner_id;
+    }
+
+    /// The shortcut the link is reachable under, if one was assigned.
+    pub fn shortcut(&self) -> Option<&str> {
+        self.shortcut.as_deref()
+    }
+
+    /// When the link stops resolving, if it expires at all.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(now())
+    }
+
+    /// Whether the link has expired by `time`.
+    pub fn is_expired_at(&self, time: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= time)
+    }
+
+    /// How often the link may be resolved, if it is limited.
+    pub fn max_uses(&self) -> Option<u32> {
+        self.max_uses
+    }
+
+    /// Whether a use-limited link has been resolved `max_uses` times.
+    pub fn is_exhausted(&self) -> bool {
+        self.max_uses
+            .is_some_and(|max_uses| self.hits >= u64::from(max_uses))
+    }
+
+    // Fails with the reason the link can't be followed anymore, if any.
+    pub(crate) fn check_resolvable(&self) -> Result<()> {
+        self.check_access(None)
+    }
+
+    // Like `check_resolvable`, also checking `password` if the link has one.
+    // That hashes it, which is slow on purpose, so don't hold a lock on the
+    // store meanwhile.
+    pub(crate) fn check_access(&self, password: Option<&str>) -> Result<()> {
+        self.check_live()?;
+        match (&self.password_hash, password) {
+            (None, _) => Ok(()),
+            (Some(hash), Some(password)) if verify_password(hash, password)? => Ok(()),
+            (Some(_), _) => Err(UrlManagerError::PasswordRequired),
+        }
+    }
+
+    // `check_access` for the link read again after `checked`, an earlier
+    // copy of it that passed: only a password changed in between fails.
+    pub(crate) fn check_access_as(&self, checked: &Link) -> Result<()> {
+        self.check_live()?;
+        if self.password_hash == checked.password_hash {
+            Ok(())
+        } else {
+            Err(UrlManagerError::PasswordRequired)
+        }
+    }
+
+    fn check_live(&self) -> Result<()> {
+        if self.is_deleted() {
+            Err(UrlManagerError::NotFound)
+        } else if self.is_expired() {
+            Err(UrlManagerError::Expired)
+        } else if self.is_exhausted() {
+            Err(UrlManagerError::UsesExhausted)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether resolving the link needs a password.
+    pub fn is_protected(&self) -> bool {
+        self.password_hash.is_some()
+    }
+
+    /// Requires `password` to resolve the link; only a salted hash of it is
+    /// kept. Store the change with [`LinkStore::update`](crate::LinkStore::update).
+    pub fn set_password(&mut self, password: &str) {
+        self.password_hash = Some(hash_password(password));
+    }
+
+    pub fn remove_password(&mut self) {
+        self.password_hash = None;
+    }
+
+    /// Whether visitors see a page naming the target before going there,
+    /// instead of being redirected right away.
+    pub fn interstitial(&self) -> bool {
+        self.interstitial
+    }
+
+    pub fn set_interstitial(&mut self, interstitial: bool) {
+        self.interstitial = interstitial;
+    }
+
+    /// The status the link redirects with, `None` to leave it to the
+    /// server.
+    pub fn redirect_status(&self) -> Option<RedirectStatus> {
+        self.redirect_status
+    }
+
+    pub fn set_redirect_status(&mut self, status: Option<RedirectStatus>) {
+        self.redirect_status = status;
+    }
+
+    /// Labels for grouping links, in sorted order.
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.tags.iter().map(String::as_str)
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+
+    /// Adds `tag`, returning whether it was new. Store the change with
+    /// [`LinkStore::update`](crate::LinkStore::update).
+    pub fn add_tag(&mut self, tag: impl Into<String>) -> bool {
+        self.tags.insert(tag.into())
+    }
+
+    /// Removes `tag`, returning whether it was present.
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        self.tags.remove(tag)
+    }
+
+    /// How often the link has been resolved.
+    pub fn hit_count(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn last_hit_at(&self) -> Option<SystemTime> {
+        self.last_hit_at
+    }
+
+    /// How the target was rated when the link was last stored through a
+    /// [`ScanningStore`](crate::scan::ScanningStore), `None` if it never was.
+    pub fn verdict(&self) -> Option<&Verdict> {
+        self.verdict.as_ref()
+    }
+
+    pub(crate) fn set_verdict(&mut self, verdict: Verdict) {
+        self.verdict = Some(verdict);
+    }
+
+    /// What the target page said about itself when the link was last stored
+    /// through a [`PreviewStore`](crate::metadata::PreviewStore), `None` if
+    /// it never was or the page couldn't be fetched.
+    pub fn preview(&self) -> Option<&Preview> {
+        self.preview.as_ref()
+    }
+
+    pub(crate) fn set_preview(&mut self, preview: Preview) {
+        self.preview = Some(preview);
+    }
+
+    /// How the target answered its last [`healthcheck`](crate::healthcheck),
+    /// `None` if it hasn't been checked since it was set.
+    pub fn health(&self) -> Option<&Health> {
+        self.health.as_ref()
+    }
+
+    pub(crate) fn set_health(&mut self, health: Health) {
+        self.health = Some(health);
+    }
+
+    /// When the link was moved to the trash, see
+    /// [`LinkStore::soft_delete`](crate::LinkStore::soft_delete).
+    pub fn deleted_at(&self) -> Option<SystemTime> {
+        self.deleted_at
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Moves the link to the trash.
+    pub(crate) fn trash(&mut self) {
+        self.deleted_at = Some(now());
+    }
+
+    /// Takes the link out of the trash.
+    pub(crate) fn restore(&mut self) {
+        self.deleted_at = None;
+    }
+
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    /// When the link was last changed through [`LinkStore::update`](crate::LinkStore::update).
+    pub fn updated_at(&self) -> SystemTime {
+        self.updated_at
+    }
+
+    /// Starts at 1 and goes up with every change that bumps
+    /// [`Link::updated_at`]; hits don't count. See
+    /// [`LinkStore::update_if_version`](crate::LinkStore::update_if_version).
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Prepares `self` to replace `previous`: the creation time and hit
+    /// counters are kept and the update time bumped. Stores call this from
+    /// `update()`.
+    pub(crate) fn updated_from(mut self, previous: &Link) -> Link {
+        self.created_at = previous.created_at;
+        self.hits = previous.hits;
+        self.last_hit_at = previous.last_hit_at;
+        if self.target == previous.target {
+            self.health = self.health.or(previous.health);
+        }
+        for variant in &mut self.variants {
+            variant.keep_hits(&previous.variants);
+        }
+        // a resolved link passed back in would pin its pick otherwise
+        self.variant = None;
+        self.rule = None;
+        self.updated_at = now();
+        self.version = previous.version + 1;
+        self.settle_target();
+        self
+    }
+
+    /// Names the fields that differ from `previous`, ignoring timestamps
+    /// and usage; the password only by whether its hash changed.
+    pub(crate) fn changes_from(&self, previous: &Link) -> Vec<&'static str> {
+        let fields = [
+            ("origin", self.origin != previous.origin),
+            ("target", self.target != previous.target),
+            (
+                "scheduled_target",
+                self.scheduled_target != previous.scheduled_target,
+            ),
+            (
+                "variants",
+                self.variants.len() != previous.variants.len()
+                    || !self
+                        .variants
+                        .iter()
+                        .zip(&previous.variants)
+                        .all(|(variant, old)| variant.same_as(old)),
+            ),
+            ("rules", self.rules != previous.rules),
+            ("namespace", self.namespace != previous.namespace),
+            ("owner_id", self.owner_id != previous.owner_id),
+            ("shortcut", self.shortcut != previous.shortcut),
+            ("expires_at", self.expires_at != previous.expires_at),
+            ("max_uses", self.max_uses != previous.max_uses),
+            ("tags", self.tags != previous.tags),
+            ("password", self.password_hash != previous.password_hash),
+            ("interstitial", self.interstitial != previous.interstitial),
+            (
+                "redirect_status",
+                self.redirect_status != previous.redirect_status,
+            ),
+            ("verdict", self.verdict != previous.verdict),
+            ("preview", self.preview != previous.preview),
+            ("deleted_at", self.deleted_at != previous.deleted_at),
+        ];
+        fields
+            .into_iter()
+            .filter_map(|(name, changed)| changed.then_some(name))
+            .collect()
+    }
+
+    /// Bumps the update time after an in-place change, see
+    /// [`LinkStore::update_with`](crate::LinkStore::update_with).
+    pub(crate) fn touch(&mut self) {
+        self.updated_at = now();
+        self.version += 1;
+        self.settle_target();
+    }
+
+    /// Makes the link expire now.
+    pub(crate) fn expire(&mut self) {
+        self.expires_at = Some(now());
+    }
+
+    /// Counts one resolution of the link.
+    pub(crate) fn hit(&mut self) {
+        self.hits += 1;
+        self.last_hit_at = Some(now());
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        Value::object([
+            ("id", Value::from(self.id)),
+            ("origin", Value::from(self.origin.as_str())),
+            ("target", Value::from(self.target.as_str())),
+            (
+                "scheduled_target",
+                Value::from(
+                    self.scheduled_target
+                        .as_ref()
+                        .map(|(target, _)| target.as_str()),
+                ),
+            ),
+            (
+                "scheduled_at",
+                Value::from(
+                    self.scheduled_target
+                        .as_ref()
+                        .map(|(_, at)| unix_millis(*at)),
+                ),
+            ),
+            (
+                "variants",
+                Value::Array(self.variants.iter().map(Variant::to_json).collect()),
+            ),
+            (
+                "rules",
+                Value::Array(self.rules.iter().map(RedirectRule::to_json).collect()),
+            ),
+            (
+                "namespace",
+                Value::from(self.namespace.as_ref().map(Namespace::as_str)),
+            ),
+            ("owner_id", Value::from(self.owner_id)),
+            ("shortcut", Value::from(self.shortcut.clone())),
+            ("expires_at", Value::from(self.expires_at.map(unix_millis))),
+            ("max_uses", Value::from(self.max_uses)),
+            (
+                "tags",
+                Value::from(self.tags.iter().map(String::as_str).collect::<Vec<_>>()),
+            ),
+            ("hits", Value::from(self.hits)),
+            (
+                "last_hit_at",
+                Value::from(self.last_hit_at.map(unix_millis)),
+            ),
+            ("password_hash", Value::from(self.password_hash.clone())),
+            ("interstitial", Value::Bool(self.interstitial)),
+            (
+                "redirect_status",
+                Value::from(self.redirect_status.map(|status| u64::from(status.code()))),
+            ),
+            (
+                "verdict",
+                Value::from(self.verdict.as_ref().map(Verdict::to_string)),
+            ),
+            (
+                "preview",
+                self.preview.as_ref().map_or(Value::Null, Preview::to_json),
+            ),
+            ("health", self.health.map_or(Value::Null, Health::to_json)),
+            ("deleted_at", Value::from(self.deleted_at.map(unix_millis))),
+            ("created_at", Value::from(unix_millis(self.created_at))),
+            ("updated_at", Value::from(unix_millis(self.updated_at))),
+            ("version", Value::from(self.version)),
+        ])
+    }
+
+    /// [`Link::to_json`] for API clients: without the password hash, but
+    /// saying whether there is one.
+    pub(crate) fn to_public_json(&self) -> Value {
+        match self.to_json() {
+            Value::Object(mut fields) => {
+                fields.retain(|(key, _)| key != "password_hash");
+                fields.push(("protected".to_string(), Value::Bool(self.is_protected())));
+                Value::Object(fields)
+            }
+            value => value,
+        }
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Link> {
+        let field = |name: &str| {
+            value
+                .get(name)
+                .ok_or_else(|| UrlManagerError::InvalidLink(format!("missing field '{name}'")))
+        };
+        let number = |name: &str| {
+            field(name)?
+                .as_u64()
+                .ok_or_else(|| UrlManagerError::InvalidLink(format!("'{name}' is not a number")))
+        };
+        let url = |name: &str| -> Result<UrlType> {
+            let text = field(name)?
+                .as_str()
+                .ok_or_else(|| UrlManagerError::InvalidLink(format!("'{name}' is not a string")))?;
+            Ok(UrlType::parse(text)?)
+        };
+        let optional_string = |name: &str| match value.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(UrlManagerError::InvalidLink(format!(
+                "'{name}' is not a string"
+            ))),
+        };
+        let optional_time = |name: &str| match value.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(_) => number(name).map(|millis| Some(from_unix_millis(millis))),
+        };
+        Ok(Link {
+            id: number("id")?,
+            origin: url("origin")?,
+            target: url("target")?,
+            scheduled_target: match optional_string("scheduled_target")? {
+                None => None,
+                Some(target) => Some((
+                    UrlType::parse(&target)?,
+                    optional_time("scheduled_at")?.ok_or_else(|| {
+                        UrlManagerError::InvalidLink("missing field 'scheduled_at'".to_string())
+                    })?,
+                )),
+            },
+            variants: match value.get("variants") {
+                None | Some(Value::Null) => Vec::new(),
+                Some(Value::Array(variants)) => variants
+                    .iter()
+                    .map(Variant::from_json)
+                    .collect::<Result<_>>()?,
+                Some(_) => {
+                    return Err(UrlManagerError::InvalidLink(
+                        "'variants' is not an array".to_string(),
+                    ))
+                }
+            },
+            variant: None,
+            rules: match value.get("rules") {
+                None | Some(Value::Null) => Vec::new(),
+                Some(Value::Array(rules)) => rules
+                    .iter()
+                    .map(RedirectRule::from_json)
+                    .collect::<Result<_>>()?,
+                Some(_) => {
+                    return Err(UrlManagerError::InvalidLink(
+                        "'rules' is not an array".to_string(),
+                    ))
+                }
+            },
+            rule: None,
+            namespace: optional_string("namespace")?
+                .map(Namespace::new)
+                .transpose()?,
+            owner_id: match value.get("owner_id") {
+                None | Some(Value::Null) => None,
+                Some(_) => Some(number("owner_id")?),
+            },
+            shortcut: optional_string("shortcut")?,
+            expires_at: optional_time("expires_at")?,
+            max_uses: match value.get("max_uses") {
+                None | Some(Value::Null) => None,
+                Some(_) => Some(u32::try_from(number("max_uses")?).map_err(|_| {
+                    UrlManagerError::InvalidLink("'max_uses' is out of range".to_string())
+                })?),
+            },
+            tags: match value.get("tags") {
+                None | Some(Value::Null) => BTreeSet::new(),
+                Some(Value::Array(tags)) => tags
+                    .iter()
+                    .map(|tag| {
+                        tag.as_str().map(str::to_string).ok_or_else(|| {
+                            UrlManagerError::InvalidLink("'tags' holds a non-string".to_string())
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+                Some(_) => {
+                    return Err(UrlManagerError::InvalidLink(
+                        "'tags' is not an array".to_string(),
+                    ))
+                }
+            },
+            hits: match value.get("hits") {
+                None => 0,
+                Some(_) => number("hits")?,
+            },
+            last_hit_at: optional_time("last_hit_at")?,
+            password_hash: optional_string("password_hash")?,
+            interstitial: match value.get("interstitial") {
+                None | Some(Value::Null) => false,
+                Some(Value::Bool(interstitial)) => *interstitial,
+                Some(_) => {
+                    return Err(UrlManagerError::InvalidLink(
+                        "'interstitial' is not a boolean".to_string(),
+                    ))
+                }
+            },
+            redirect_status: match value.get("redirect_status") {
+                None | Some(Value::Null) => None,
+                Some(_) => Some(
+                    u16::try_from(number("redirect_status")?)
+                        .ok()
+                        .and_then(|code| RedirectStatus::try_from(code).ok())
+                        .ok_or_else(|| {
+                            UrlManagerError::InvalidLink(
+                                "'redirect_status' is not a redirect status".to_string(),
+                            )
+                        })?,
+                ),
+            },
+            verdict: optional_string("verdict")?
+                .map(|verdict| verdict.parse())
+                .transpose()?,
+            preview: match value.get("preview") {
+                None | Some(Value::Null) => None,
+                Some(preview @ Value::Object(_)) => Some(Preview::from_json(preview)?),
+                Some(_) => {
+                    return Err(UrlManagerError::InvalidLink(
+                        "'preview' is not an object".to_string(),
+                    ))
+                }
+            },
+            health: match value.get("health") {
+                None | Some(Value::Null) => None,
+                Some(health) => Some(Health::from_json(health)?),
+            },
+            deleted_at: optional_time("deleted_at")?,
+            created_at: from_unix_millis(number("created_at")?),
+            updated_at: from_unix_millis(number("updated_at")?),
+            // links written before versions were counted
+            version: match value.get("version") {
+                None => 1,
+                Some(_) => number("version")?,
+            },
+        })
+    }
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        let id = RandomIds.next_id();
+        let created_at = now();
+        Link {
+            id,
+            origin: UrlType::parse("https://example.com").unwrap(),
+            target: UrlType::parse("https://example.com").unwrap(),
+            scheduled_target: None,
+            variants: Vec::new(),
+            variant: None,
+            rules: Vec::new(),
+            rule: None,
+            namespace: None,
+            owner_id: None,
+            shortcut: None,
+            expires_at: None,
+            max_uses: None,
+            tags: BTreeSet::new(),
+            hits: 0,
+            last_hit_at: None,
+            password_hash: None,
+            interstitial: false,
+            redirect_status: None,
+            verdict: None,
+            preview: None,
+            health: None,
+            deleted_at: None,
+            created_at,
+            updated_at: created_at,
+            version: 1,
+        }
+    }
+}
+
+/// Builder for [`Link`], obtained through [`Link::builder`].
+///
+/// ```
+/// # use std::time::Duration;
+/// # use url_manager::Link;
+/// let link = Link::builder()
+///     .target("https://www.example.com/docs")
+///     .slug("docs")
+///     .expires_in(Duration::from_secs(30 * 24 * 60 * 60))
+///     .build()?;
+/// assert_eq!(link.shortcut(), Some("docs"));
+/// # Ok::<(), url_manager::UrlManagerError>(())
+/// ```
+///
+/// Only the target is required. The id defaults to a random one, the
+/// origin to the target, and without a slug the shortcut is generated
+/// with the builder's [`ShortenStrategy`]. Nothing is checked against a
+/// store here, that happens when the link is created.
+#[derive(Debug, Default)]
+pub struct LinkBuilder {
+    id: Option<u64>,
+    origin: Option<Result<UrlType>>,
+    target: Option<Result<UrlType>>,
+    namespace: Option<Namespace>,
+    owner_id: Option<u64>,
+    shortcut: Option<String>,
+    slug: Option<String>,
+    strategy: ShortenStrategy,
+    expires_at: Option<Result<SystemTime>>,
+    max_uses: Option<u32>,
+    password_hash: Option<String>,
+    interstitial: bool,
+    redirect_status: Option<RedirectStatus>,
+    tags: BTreeSet<String>,
+    variants: Vec<(Result<UrlType>, u32)>,
+    rules: Vec<RedirectRule>,
+    normalizer: Option<Normalizer>,
+    tracking: Option<TrackingParamStripper>,
+}
+
+impl LinkBuilder {
+    pub fn id(mut self, id: u64) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Takes a parsed URL or a string, which is parsed by [`LinkBuilder::build`].
+    pub fn origin<T>(mut self, origin: T) -> Self
+    where
+        T: TryInto<UrlType>,
+        T::Error: Into<UrlManagerError>,
+    {
+        self.origin = Some(origin.try_into().map_err(Into::into));
+        self
+    }
+
+    /// Takes a parsed URL or a string, which is parsed by [`LinkBuilder::build`].
+    pub fn target<T>(mut self, target: T) -> Self
+    where
+        T: TryInto<UrlType>,
+        T::Error: Into<UrlManagerError>,
+    {
+        self.target = Some(target.try_into().map_err(Into::into));
+        self
+    }
+
+    /// Puts the shortcut into `namespace` instead of the shared one.
+    pub fn namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Makes the link belong to the user `owner_id`, see [`Link::owner_id`].
+    pub fn owner_id(mut self, owner_id: u64) -> Self {
+        self.owner_id = Some(owner_id);
+        self
+    }
+
+    /// Sets the shortcut as is, e.g. one produced by a [`CodeGenerator`](crate::CodeGenerator).
+    pub fn shortcut(mut self, shortcut: impl Into<String>) -> Self {
+        self.shortcut = Some(shortcut.into());
+        self
+    }
+
+    /// Sets a human-chosen shortcut, checked with [`validate_slug`].
+    pub fn slug(mut self, slug: impl Into<String>) -> Self {
+        self.slug = Some(slug.into());
+        self
+    }
+
+    /// How the shortcut is generated when neither a shortcut nor a slug is set.
+    pub fn strategy(mut self, strategy: ShortenStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn expires_at(mut self, expires_at: SystemTime) -> Self {
+        self.expires_at = Some(Ok(expires_at));
+        self
+    }
+
+    /// Expires the link `ttl` from now; [`LinkBuilder::build`] fails with
+    /// `InvalidLink` if that is past what the clock can represent.
+    pub fn expires_in(mut self, ttl: Duration) -> Self {
+        self.expires_at = Some(now().checked_add(ttl).ok_or_else(|| {
+            UrlManagerError::InvalidLink(format!("expiry in {}s is out of range", ttl.as_secs()))
+        }));
+        self
+    }
+
+    /// Stops the link from resolving after `max_uses` hits, e.g. for one-time links.
+    pub fn max_uses(mut self, max_uses: u32) -> Self {
+        self.max_uses = Some(max_uses);
+        self
+    }
+
+    /// Requires `password` to resolve the link, see [`Link::set_password`].
+    pub fn password(mut self, password: &str) -> Self {
+        self.password_hash = Some(hash_password(password));
+        self
+    }
+
+    /// Stores the target in `normalizer`'s canonical form; the origin keeps
+    /// the URL as submitted.
+    pub fn normalize(mut self, normalizer: Normalizer) -> Self {
+        self.normalizer = Some(normalizer);
+        self
+    }
+
+    /// Removes tracking parameters from the target; the origin keeps them.
+    pub fn strip_tracking(mut self, stripper: TrackingParamStripper) -> Self {
+        self.tracking = Some(stripper);
+        self
+    }
+
+    /// Shows visitors a page naming the target first, see
+    /// [`Link::interstitial`].
+    pub fn interstitial(mut self, interstitial: bool) -> Self {
+        self.interstitial = interstitial;
+        self
+    }
+
+    /// Redirects with `status`, see [`Link::redirect_status`].
+    pub fn redirect_status(mut self, status: RedirectStatus) -> Self {
+        self.redirect_status = Some(status);
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.insert(tag.into());
+        self
+    }
+
+    /// Sends the visitors `rule` matches to its target, see
+    /// [`RedirectRule`]; rules are tried in the order they are added.
+    pub fn rule(mut self, rule: RedirectRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Splits resolutions between several targets for an A/B test, each
+    /// getting a `weight` share: `variant(a, 1).variant(b, 1)` sends half
+    /// the visitors to each. The target stays the fallback for when every
+    /// weight is 0. Takes a parsed URL or a string like
+    /// [`LinkBuilder::target`].
+    pub fn variant<T>(mut self, target: T, weight: u32) -> Self
+    where
+        T: TryInto<UrlType>,
+        T::Error: Into<UrlManagerError>,
+    {
+        self.variants
+            .push((target.try_into().map_err(Into::into), weight));
+        self
+    }
+
+    pub fn build(self) -> Result<Link> {
+        let target = self
+            .target
+            .ok_or_else(|| UrlManagerError::InvalidLink("target is required".to_string()))??;
+        check_target(&target)?;
+        for rule in &self.rules {
+            check_target(rule.target())?;
+        }
+        let variants = self
+            .variants
+            .into_iter()
+            .map(|(target, weight)| {
+                let target = target?;
+                check_target(&target)?;
+                Ok(Variant::new(target, weight))
+            })
+            .collect::<Result<_>>()?;
+        let origin = match self.origin {
+            Some(origin) => origin?,
+            None => target.clone(),
+        };
+        let target = match self.tracking {
+            Some(stripper) => stripper.strip(&target),
+            None => target,
+        };
+        let target = match self.normalizer {
+            Some(normalizer) => normalizer.normalize(&target),
+            None => target,
+        };
+        if let Some(slug) = &self.slug {
+            validate_slug(slug)?;
+        }
+        let expires_at = self.expires_at.transpose()?;
+        let created_at = now();
+        if expires_at.is_some_and(|expires_at| expires_at <= created_at) {
+            return Err(UrlManagerError::InvalidLink(
+                "expiry is in the past".to_string(),
+            ));
+        }
+        if self.max_uses == Some(0) {
+            return Err(UrlManagerError::InvalidLink(
+                "max_uses must be at least 1".to_string(),
+            ));
+        }
+
+        let mut link = Link {
+            id: self.id.unwrap_or_else(|| RandomIds.next_id()),
+            origin,
+            target,
+            scheduled_target: None,
+            variants,
+            variant: None,
+            rules: self.rules,
+            rule: None,
+            namespace: self.namespace,
+            owner_id: self.owner_id,
+            shortcut: self.slug.or(self.shortcut),
+            expires_at,
+            max_uses: self.max_uses,
+            tags: self.tags,
+            hits: 0,
+            last_hit_at: None,
+            password_hash: self.password_hash,
+            interstitial: self.interstitial,
+            redirect_status: self.redirect_status,
+            verdict: None,
+            preview: None,
+            health: None,
+            deleted_at: None,
+            created_at,
+            updated_at: created_at,
+            version: 1,
+        };
+        if link.shortcut.is_none() {
+            // generated codes steer clear of reserved slugs and blocked words
+            let filter = SlugFilter::new();
+            link.shortcut = Some(match &self.strategy {
+                ShortenStrategy::Vanity(slug) => {
+                    validate_slug(slug)?;
+                    filter.check(slug)?;
+                    slug.clone()
+                }
+                strategy => strategy.shortcut(&link, |code| Ok(!filter.allows(code)))?,
+            });
+        }
+        Ok(link)
+    }
+}
+
+impl From<&Link> for LinkRecord {
+    fn from(link: &Link) -> Self {
+        LinkRecord {
+            id: link.id,
+            target: link.target.to_string(),
+            namespace: link
+                .namespace
+                .as_ref()
+                .map(|namespace| namespace.as_str().to_string()),
+            shortcut: link.shortcut.clone(),
+            expires_at: link.expires_at.map(unix_millis),
+            max_uses: link.max_uses,
+            hits: link.hits,
+            last_hit_at: link.last_hit_at.map(unix_millis),
+        }
+    }
+}
+
+/// Builds a link from a record, pointing from and to its target and
+/// created now. The target has to parse and the namespace be valid.
+impl TryFrom<LinkRecord> for Link {
+    type Error = UrlManagerError;
+
+    fn try_from(record: LinkRecord) -> Result<Link> {
+        let created_at = Value::from(unix_millis(now()));
+        Link::from_json(&Value::object([
+            ("id", Value::from(record.id)),
+            ("origin", Value::from(record.target.as_str())),
+            ("target", Value::from(record.target.as_str())),
+            ("namespace", Value::from(record.namespace.as_deref())),
+            ("shortcut", Value::from(record.shortcut)),
+            ("expires_at", Value::from(record.expires_at)),
+            ("max_uses", Value::from(record.max_uses)),
+            ("hits", Value::from(record.hits)),
+            ("last_hit_at", Value::from(record.last_hit_at)),
+            ("created_at", created_at.clone()),
+            ("updated_at", created_at),
+        ]))
+    }
+}
+
+// Only web targets: `javascript:` and `data:` URLs would run on the pages
+// showing or redirecting to them.
+pub(crate) fn check_target(target: &UrlType) -> Result<()> {
+    if !matches!(target.scheme(), "http" | "https") {
+        return Err(UrlManagerError::InvalidLink(format!(
+            "target '{target}' is not an http or https URL"
+        )));
+    }
+    if target.host_str().is_none_or(str::is_empty) {
+        return Err(UrlManagerError::InvalidLink(format!(
+            "target '{target}' has no host"
+        )));
+    }
+    Ok(())
+}
+
+// Timestamps are kept at millisecond precision so they survive being
+// persisted as unix milliseconds unchanged.
+pub(crate) fn now() -> SystemTime {
+    from_unix_millis(unix_millis(clock()))
+}
+
+#[cfg(feature = "wasm")]
+static CLOCK: std::sync::OnceLock<fn() -> SystemTime> = std::sync::OnceLock::new();
+
+/// Reads the time from `clock` instead of `SystemTime::now`, which panics
+/// on `wasm32-unknown-unknown`; in a Worker, e.g. from `Date.now()`:
+///
+/// ```ignore
+/// url_manager::set_clock(|| UNIX_EPOCH + Duration::from_millis(js_sys::Date::now() as u64));
+/// ```
+///
+/// The clock can be set once; returns whether this call set it.
+#[cfg(feature = "wasm")]
+pub fn set_clock(clock: fn() -> SystemTime) -> bool {
+    CLOCK.set(clock).is_ok()
+}
+
+#[cfg(feature = "wasm")]
+fn clock() -> SystemTime {
+    CLOCK.get().map_or_else(SystemTime::now, |clock| clock())
+}
+
+#[cfg(not(feature = "wasm"))]
+fn clock() -> SystemTime {
+    SystemTime::now()
+}
+
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+pub(crate) fn from_unix_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instant() {
+        let before = SystemTime::now();
+        let link = Link::default();
+
+        assert!(link.created_at() <= before + Duration::from_millis(1));
+        assert_eq!(link.created_at(), link.updated_at());
+
+        let updated = Link::default().updated_from(&link);
+        assert_eq!(updated.created_at(), link.created_at());
+        assert!(updated.updated_at() >= link.updated_at());
+        assert_eq!((link.version(), updated.version()), (1, 2));
+    }
+
+    #[test]
+    fn test_link_builder() {
+        let target = UrlType::parse("https://www.example.com/docs").unwrap();
+        let link = Link::builder()
+            .id(42)
+            .target(target.clone())
+            .build()
+            .unwrap();
+
+        assert_eq!(link.id(), 42);
+        assert_eq!(link.target(), &target);
+        assert_eq!(
+            link.origin(),
+            &target,
+            "origin should default to the target"
+        );
+
+        let result = Link::builder().id(42).build();
+        assert!(
+            matches!(result, Err(UrlManagerError::InvalidLink(_))),
+            "a link without target should not build"
+        );
+    }
+
+    #[test]
+    fn test_link_builder_normalize() {
+        let link = Link::builder()
+            .target("https://Example.com/a/?b=2&a=1#top")
+            .normalize(Normalizer::new())
+            .build()
+            .unwrap();
+        assert_eq!(link.target().as_str(), "https://example.com/a/?a=1&b=2");
+        assert_eq!(link.origin().as_str(), "https://example.com/a/?b=2&a=1#top");
+
+        let link = Link::builder()
+            .target("https://example.com/?utm_source=mail&id=1")
+            .strip_tracking(TrackingParamStripper::default())
+            .build()
+            .unwrap();
+        assert_eq!(link.target().as_str(), "https://example.com/?id=1");
+    }
+
+    #[test]
+    fn test_link_builder_validation() {
+        let link = Link::builder()
+            .id(62)
+            .target("https://www.example.com/docs")
+            .build()
+            .unwrap();
+        assert_eq!(link.shortcut(), Some("10"), "code should encode the id");
+        assert!(link.expires_at().is_none());
+
+        let link = Link::builder()
+            .target("https://www.example.com/docs")
+            .slug("docs")
+            .expires_in(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        assert_eq!(link.shortcut(), Some("docs"));
+        assert!(link.expires_at().unwrap() > link.created_at());
+
+        let invalid = [
+            Link::builder().target("not a url").build(),
+            Link::builder().target("mailto:someone@example.com").build(),
+            Link::builder()
+                .target("javascript://example.com/%0Aalert(1)")
+                .build(),
+            Link::builder()
+                .target("https://example.com")
+                .slug("a b")
+                .build(),
+            Link::builder()
+                .target("https://example.com")
+                .expires_at(UNIX_EPOCH)
+                .build(),
+            Link::builder()
+                .target("https://example.com")
+                .expires_in(Duration::from_secs(u64::MAX))
+                .build(),
+        ];
+        assert!(matches!(invalid[0], Err(UrlManagerError::InvalidUrl(_))));
+        assert!(matches!(invalid[1], Err(UrlManagerError::InvalidLink(_))));
+        assert!(matches!(invalid[2], Err(UrlManagerError::InvalidLink(_))));
+        assert!(matches!(invalid[3], Err(UrlManagerError::InvalidSlug(_))));
+        assert!(matches!(invalid[4], Err(UrlManagerError::InvalidLink(_))));
+        assert!(matches!(invalid[5], Err(UrlManagerError::InvalidLink(_))));
+
+        // vanity slugs are checked like `slug`, and for blocked words
+        for slug in ["../../admin b", "api", "shithead"] {
+            let built = Link::builder()
+                .target("https://example.com")
+                .strategy(ShortenStrategy::vanity(slug))
+                .build();
+            assert!(
+                matches!(built, Err(UrlManagerError::InvalidSlug(_))),
+                "{slug}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_unicode_target() {
+        let link = Link::builder()
+            .target("https://Bücher.example/neu")
+            .build()
+            .unwrap();
+        assert_eq!(link.target().as_str(), "https://xn--bcher-kva.example/neu");
+        assert_eq!(link.display_target(), "https://bücher.example/neu");
+        let json = link.to_json().to_string();
+        assert!(json.contains("xn--bcher-kva.example"), "{json}");
+    }
+
+    #[test]
+    fn test_scheduled_target() {
+        let mut link = Link::builder()
+            .target("https://example.com/teaser")
+            .build()
+            .unwrap();
+        let launch = SystemTime::now() + Duration::from_secs(60);
+        link.schedule_target(
+            UrlType::parse("https://example.com/product").unwrap(),
+            launch,
+        );
+        assert_eq!(link.target().as_str(), "https://example.com/teaser");
+        assert_eq!(
+            link.target_at(launch).as_str(),
+            "https://example.com/product"
+        );
+        link.touch();
+        assert!(link.scheduled_target().is_some(), "not due yet");
+
+        link.schedule_target(
+            UrlType::parse("https://example.com/now").unwrap(),
+            UNIX_EPOCH,
+        );
+        assert_eq!(link.target().as_str(), "https://example.com/now");
+        link.touch();
+        assert!(link.scheduled_target().is_none());
+        assert_eq!(
+            link.target_at(UNIX_EPOCH).as_str(),
+            "https://example.com/now"
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let link = Link::builder()
+            .id(u64::MAX)
+            .origin(UrlType::parse("https://www.example.com/a?utm_source=x").unwrap())
+            .target(UrlType::parse("https://www.example.com/a").unwrap())
+            .shortcut("docs")
+            .expires_in(Duration::from_secs(60))
+            .max_uses(3)
+            .tag("campaign")
+            .tag("beta")
+            .build()
+            .unwrap();
+        let mut link = link;
+        link.schedule_target(
+            UrlType::parse("https://www.example.com/b").unwrap(),
+            SystemTime::now() + Duration::from_secs(60),
+        );
+        let loaded = Link::from_json(&link.to_json()).unwrap();
+
+        assert_eq!(loaded.id(), link.id());
+        assert_eq!(loaded.origin(), link.origin());
+        assert_eq!(loaded.target(), link.target());
+        assert_eq!(loaded.shortcut(), Some("docs"));
+        assert_eq!(loaded.expires_at(), link.expires_at());
+        assert_eq!(loaded.max_uses(), Some(3));
+        assert_eq!(loaded.tags().collect::<Vec<_>>(), ["beta", "campaign"]);
+        assert_eq!(loaded.scheduled_target(), link.scheduled_target());
+        assert_eq!(loaded.created_at(), link.created_at());
+        assert_eq!(loaded.updated_at(), link.updated_at());
+        assert_eq!(loaded.version(), link.version());
+
+        let broken = crate::json::parse(r#"{"id": 1, "origin": "nope"}"#).unwrap();
+        assert!(Link::from_json(&broken).is_err());
+    }
+
+    #[test]
+    fn test_link_record() {
+        let link = Link::builder()
+            .origin("https://example.com/docs")
+            .target("https://example.com/docs")
+            .shortcut("docs")
+            .max_uses(3)
+            .build()
+            .unwrap();
+        let mut record = LinkRecord::from(&link);
+        assert_eq!(record.target, link.target().as_str());
+        assert_eq!(record.shortcut.as_deref(), Some("docs"));
+        record.hit(unix_millis(now())).unwrap();
+
+        let restored = Link::try_from(record.clone()).unwrap();
+        assert_eq!(restored.id(), link.id());
+        assert_eq!((restored.hit_count(), restored.max_uses()), (1, Some(3)));
+        assert!(restored.last_hit_at().is_some());
+        record.target = "not a url".to_string();
+        assert!(Link::try_from(record).is_err());
+    }
+}
@@ -0,0 +1,424 @@
+//! Listings of the public shortcuts of a store, for static mirrors and
+//! audits.
+//!
+//! A [`Manifest`] holds every link a visitor could follow right now: not
+//! trashed, expired, used up, owned, password-protected or flagged unsafe,
+//! and with a shortcut. It is written as JSON, one object per link under
+//! `links`, as a [sitemap](https://www.sitemaps.org/protocol.html) of
+//! the short URLs, or as a static site of redirect pages, see
+//! [`Manifest::export_static`]:
+//!
+//! ```text
+//! {"generated_at":1700000000000,"links":[{"id":7,"url":"https://sho.rt/docs",
+//!  "shortcut":"docs","namespace":null,"target":"https://example.com/docs",…}]}
+//! ```
+//!
+//! ```
+//! # use url_manager::manifest::Manifest;
+//! # use url_manager::{InMemoryLinkStore, Link, LinkStore, UrlType};
+//! let mut store = InMemoryLinkStore::new();
+//! store.create(Link::builder().target("https://example.com/docs").slug("docs").build()?)?;
+//!
+//! let base_url = UrlType::parse("https://sho.rt/")?;
+//! let manifest = Manifest::of(&store, &base_url)?;
+//! let mut sitemap = Vec::new();
+//! manifest.write_sitemap(&mut sitemap)?;
+//! assert!(String::from_utf8(sitemap)?.contains("<loc>https://sho.rt/docs</loc>"));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::json::Value;
+use crate::link::{check_target, now, unix_millis};
+use crate::scan::Verdict;
+use crate::{DomainRegistry, Link, LinkStore, Result, UrlType};
+
+/// The most URLs a sitemap may list; larger manifests need splitting.
+pub const SITEMAP_MAX_URLS: usize = 50_000;
+
+/// The public shortcuts of a store at one point in time, see the
+/// [module docs](self).
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    generated_at: SystemTime,
+    entries: Vec<Entry>,
+}
+
+/// A link in a [`Manifest`] and the short URL it is served under.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub url: UrlType,
+    pub link: Link,
+}
+
+impl Manifest {
+    /// The public links of `store` in the shared namespace, served under
+    /// `base_url`, ordered by short URL so manifests diff well.
+    pub fn of(store: &dyn LinkStore, base_url: &UrlType) -> Result<Self> {
+        Self::of_domains(store, base_url, &DomainRegistry::new())
+    }
+
+    /// [`Manifest::of`], plus the public links of each namespace claimed in
+    /// `domains`, served on its host with the scheme of `base_url`. Links
+    /// of unclaimed namespaces are left out, having no URL.
+    pub fn of_domains(
+        store: &dyn LinkStore,
+        base_url: &UrlType,
+        domains: &DomainRegistry,
+    ) -> Result<Self> {
+        let generated_at = now();
+        let hosts = domains.domains();
+        let mut entries = Vec::new();
+        for link in store.iter() {
+            let link = link?;
+            if !is_public(&link, generated_at) {
+                continue;
+            }
+            let shortcut = link.shortcut().expect("public links have a shortcut");
+            let base = match link.namespace() {
+                None => Some(base_url.clone()),
+                Some(namespace) => hosts
+                    .iter()
+                    .find(|domain| domain.namespace() == namespace)
+                    .and_then(|domain| {
+                        let base = format!("{}://{}/", base_url.scheme(), domain.host());
+                        UrlType::parse(&base).ok()
+                    }),
+            };
+            if let Some(url) = base.and_then(|base| base.join(shortcut).ok()) {
+                entries.push(Entry { url, link });
+            }
+        }
+        entries.sort_by(|a, b| a.url.as_str().cmp(b.url.as_str()));
+        Ok(Manifest {
+            generated_at,
+            entries,
+        })
+    }
+
+    pub fn generated_at(&self) -> SystemTime {
+        self.generated_at
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        let links = self.entries.iter().map(|entry| {
+            let link = &entry.link;
+            Value::object([
+                ("id", Value::from(link.id())),
+                ("url", Value::from(entry.url.as_str())),
+                ("shortcut", Value::from(link.shortcut())),
+                (
+                    "namespace",
+                    Value::from(link.namespace().map(|ns| ns.as_str())),
+                ),
+                (
+                    "target",
+                    Value::from(link.target_at(self.generated_at).as_str()),
+                ),
+                ("tags", Value::from(link.tags().collect::<Vec<_>>())),
+                (
+                    "title",
+                    Value::from(link.preview().and_then(|preview| preview.title.as_deref())),
+                ),
+                ("interstitial", Value::from(link.interstitial())),
+                ("created_at", Value::from(unix_millis(link.created_at()))),
+                ("updated_at", Value::from(unix_millis(link.updated_at()))),
+                (
+                    "expires_at",
+                    Value::from(link.expires_at().map(unix_millis)),
+                ),
+            ])
+        });
+        Value::object([
+            ("generated_at", Value::from(unix_millis(self.generated_at))),
+            ("links", Value::Array(links.collect())),
+        ])
+    }
+
+    /// Writes the manifest to `writer` as one JSON object: `generated_at`
+    /// and under `links` the id, short URL, shortcut, namespace, target,
+    /// tags, preview title, interstitial flag and times of each link. Times
+    /// are unix milliseconds.
+    pub fn write_json(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "{}", self.to_json())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a sitemap listing the short URLs, each with the day its link
+    /// was last updated. Only the first [`SITEMAP_MAX_URLS`] are written,
+    /// as crawlers ignore the rest; returns how many were.
+    pub fn write_sitemap(&self, mut writer: impl Write) -> Result<usize> {
+        let entries = &self.entries[..self.entries.len().min(SITEMAP_MAX_URLS)];
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+        )?;
+        for entry in entries {
+            writeln!(
+                writer,
+                "  <url><loc>{}</loc><lastmod>{}</lastmod></url>",
+                escape_xml(entry.url.as_str()),
+                date(entry.link.updated_at())
+            )?;
+        }
+        writeln!(writer, "</urlset>")?;
+        writer.flush()?;
+        Ok(entries.len())
+    }
+
+    /// Writes a `<path>/index.html` under `dir` for each link in the
+    /// shared namespace, `<path>` being the path of its short URL, that
+    /// sends visitors on with a meta refresh and JavaScript, so the links
+    /// can be hosted on GitHub Pages, S3 or any static file server. Links
+    /// with [`Link::interstitial`] get a page to click through instead.
+    /// Returns how many pages were written.
+    ///
+    /// Pages point at the target as of [`Manifest::generated_at`]: rules,
+    /// variants, hit counts and later expiry need a server. Existing pages
+    /// are overwritten; pages of links no longer listed are left alone.
+    pub fn export_static(&self, dir: impl AsRef<Path>) -> Result<usize> {
+        let dir = dir.as_ref();
+        let mut written = 0;
+        for entry in &self.entries {
+            if entry.link.namespace().is_some() {
+                continue;
+            }
+            let segments: Vec<&str> = entry
+                .url
+                .path()
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .collect();
+            if segments.is_empty() || segments.iter().any(|s| matches!(*s, "." | "..")) {
+                continue;
+            }
+            let page_dir = segments
+                .iter()
+                .fold(dir.to_path_buf(), |path, s| path.join(s));
+            fs::create_dir_all(&page_dir)?;
+            let target = entry.link.target_at(self.generated_at);
+            fs::write(
+                page_dir.join("index.html"),
+                redirect_page(target, entry.link.interstitial())?,
+            )?;
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+// Fails for targets other than http and https URLs, which would run as
+// scripts from the refresh or the link.
+fn redirect_page(target: &UrlType, interstitial: bool) -> Result<String> {
+    check_target(target)?;
+    let target = target.as_str();
+    let href = escape_xml(target);
+    let redirect = if interstitial {
+        String::new()
+    } else {
+        // a JSON string is a JavaScript string; `</` would end the script
+        let script = Value::from(target).to_string().replace("</", "<\\/");
+        format!(
+            "<meta http-equiv=\"refresh\" content=\"0; url={href}\">\n\
+             <script>location.replace({script});</script>\n"
+        )
+    };
+    Ok(format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"robots\" content=\"noindex\">\n\
+         <link rel=\"canonical\" href=\"{href}\">\n\
+         {redirect}\
+         <title>Redirecting</title>\n\
+         </head>\n\
+         <body>\n\
+         <p>This link leads to <a href=\"{href}\">{href}</a>.</p>\n\
+         </body>\n\
+         </html>\n"
+    ))
+}
+
+fn is_public(link: &Link, time: SystemTime) -> bool {
+    link.shortcut().is_some()
+        && !link.is_deleted()
+        && !link.is_expired_at(time)
+        && !link.is_exhausted()
+        && link.owner_id().is_none()
+        && !link.is_protected()
+        && !matches!(link.verdict(), Some(Verdict::Unsafe(_)))
+        && check_target(link.target_at(time)).is_ok()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// `time` as a UTC date, `YYYY-MM-DD`.
+fn date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / 86_400) as i64;
+    // civil_from_days, see https://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::from_unix_millis;
+    use crate::{Domain, InMemoryLinkStore, Namespace};
+
+    #[test]
+    fn test_public_links() {
+        let mut store = InMemoryLinkStore::new();
+        let link = |slug: &str| {
+            Link::builder()
+                .target(format!("https://example.com/{slug}?a=1&b=2").as_str())
+                .slug(slug)
+        };
+        let acme = Namespace::new("acme").unwrap();
+        let beta = Namespace::new("beta").unwrap();
+        for builder in [
+            link("docs").tag("team"),
+            link("secret").password("hunter2"),
+            link("mine").owner_id(3),
+            link("trashed").id(99),
+            link("acme").namespace(acme.clone()),
+            link("beta").namespace(beta),
+        ] {
+            store.create(builder.build().unwrap()).unwrap();
+        }
+        store.delete(99).unwrap();
+
+        let base_url = UrlType::parse("https://sho.rt/").unwrap();
+        let manifest = Manifest::of(&store, &base_url).unwrap();
+        let urls = |manifest: &Manifest| -> Vec<String> {
+            manifest
+                .entries()
+                .iter()
+                .map(|entry| entry.url.to_string())
+                .collect()
+        };
+        assert_eq!(urls(&manifest), ["https://sho.rt/docs"]);
+
+        let domains = DomainRegistry::new();
+        domains
+            .claim(Domain::new("go.acme.com", acme).unwrap())
+            .unwrap();
+        let manifest = Manifest::of_domains(&store, &base_url, &domains).unwrap();
+        assert_eq!(
+            urls(&manifest),
+            ["https://go.acme.com/acme", "https://sho.rt/docs"]
+        );
+
+        let json = manifest.to_json();
+        let Some(Value::Array(links)) = json.get("links") else {
+            panic!("{json}");
+        };
+        assert_eq!(
+            links[1].get("shortcut").and_then(Value::as_str),
+            Some("docs")
+        );
+        assert_eq!(
+            links[1].get("target").and_then(Value::as_str),
+            Some("https://example.com/docs?a=1&b=2")
+        );
+        assert_eq!(
+            links[0].get("namespace").and_then(Value::as_str),
+            Some("acme")
+        );
+
+        let mut sitemap = Vec::new();
+        assert_eq!(manifest.write_sitemap(&mut sitemap).unwrap(), 2);
+        let sitemap = String::from_utf8(sitemap).unwrap();
+        assert!(sitemap.starts_with("<?xml"), "{sitemap}");
+        assert_eq!(sitemap.matches("<url>").count(), 2);
+        assert!(sitemap.contains("<loc>https://go.acme.com/acme</loc>"));
+    }
+
+    #[test]
+    fn test_export_static() {
+        let mut store = InMemoryLinkStore::new();
+        for builder in [
+            Link::builder()
+                .target("https://example.com/a?x=1&y=</script>")
+                .slug("a"),
+            Link::builder()
+                .target("https://example.com/b")
+                .slug("b")
+                .interstitial(true),
+            Link::builder()
+                .target("https://example.com/c")
+                .slug("c")
+                .namespace(Namespace::new("acme").unwrap()),
+        ] {
+            store.create(builder.build().unwrap()).unwrap();
+        }
+        // stored before targets were checked
+        let mut script = Link::builder()
+            .target("https://example.com/d")
+            .slug("d")
+            .build()
+            .unwrap();
+        let javascript = UrlType::parse("javascript://example.com/%0Aalert(1)").unwrap();
+        script.schedule_target(javascript.clone(), UNIX_EPOCH);
+        store.create(script).unwrap();
+        assert!(redirect_page(&javascript, false).is_err());
+        let base_url = UrlType::parse("https://sho.rt/go/").unwrap();
+        let dir = std::env::temp_dir().join(format!("static-{}", rand::random::<u64>()));
+        let manifest = Manifest::of(&store, &base_url).unwrap();
+        assert_eq!(manifest.export_static(&dir).unwrap(), 2);
+
+        let page = fs::read_to_string(dir.join("go/a/index.html")).unwrap();
+        assert!(
+            page.contains(r#"content="0; url=https://example.com/a?x=1&amp;y=%3C/script%3E""#),
+            "{page}"
+        );
+        assert!(
+            page.contains(r#"location.replace("https://example.com/a?x=1&y=%3C/script%3E")"#),
+            "{page}"
+        );
+        let page = fs::read_to_string(dir.join("go/b/index.html")).unwrap();
+        assert!(
+            !page.contains("refresh") && !page.contains("<script>"),
+            "{page}"
+        );
+        assert!(page.contains(r#"<a href="https://example.com/b">"#));
+        assert!(!dir.join("go/c").exists());
+        assert!(!dir.join("go/d").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_date() {
+        assert_eq!(date(UNIX_EPOCH), "1970-01-01");
+        assert_eq!(date(from_unix_millis(951_782_400_000)), "2000-02-29");
+        assert_eq!(date(from_unix_millis(1_700_000_000_000)), "2023-11-14");
+        assert_eq!(escape_xml("a&b<'c'>"), "a&amp;b&lt;&apos;c&apos;&gt;");
+    }
+}
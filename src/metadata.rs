@@ -0,0 +1,413 @@
+//! Previews of link targets: the title, description and image a page
+//! declares, for rich link lists and API responses.
+//!
+//! [`PreviewStore`] wraps an [`AsyncLinkStore`] and fetches the target page
+//! of every link created or updated through it with an [`HttpGet`] client,
+//! keeping the [`Preview`] on the link. Pages are read up to a size cap;
+//! a page that can't be fetched leaves the link without a preview rather
+//! than failing the write.
+
+use std::future::Future;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use crate::events::PlainHttp;
+use crate::json::Value;
+use crate::{AsyncLinkStore, Link, Namespace, Result, UrlManagerError, UrlType};
+
+/// The size cap [`PreviewStore`] reads pages up to by default, 256 KiB.
+pub const DEFAULT_MAX_BYTES: usize = 256 * 1024;
+
+// Room for the status line and headers on top of the body cap.
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+
+/// What a page says about itself, from its `<title>`, `description` and
+/// Open Graph `og:` meta tags.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preview {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<UrlType>,
+}
+
+impl Preview {
+    /// Reads the preview out of `html`, resolving a relative image URL
+    /// against `base`. Open Graph tags win over `<title>` and `description`.
+    pub fn parse(html: &str, base: &UrlType) -> Preview {
+        let mut preview = Preview::default();
+        let mut title = None;
+        let mut description = None;
+        let mut rest = html;
+        while let Some(start) = find_ignore_case(rest, "<meta") {
+            let tag = &rest[start..];
+            let end = tag.find('>').unwrap_or(tag.len());
+            let attr = |name: &str| attribute(&tag[..end], name);
+            let key = attr("property").or_else(|| attr("name"));
+            if let (Some(key), Some(content)) = (key, attr("content")) {
+                match key.to_ascii_lowercase().as_str() {
+                    "og:title" => preview.title = Some(content),
+                    "og:description" => preview.description = Some(content),
+                    "og:image" => preview.image = base.join(&content).ok(),
+                    "description" => description = Some(content),
+                    _ => {}
+                }
+            }
+            rest = &tag[end..];
+        }
+        if let Some(start) = find_ignore_case(html, "<title") {
+            let tag = &html[start..];
+            if let Some(open) = tag.find('>') {
+                let text = &tag[open + 1..];
+                let close = find_ignore_case(text, "</title").unwrap_or(text.len());
+                title = Some(decode_entities(text[..close].trim()));
+            }
+        }
+        preview.title = preview.title.or(title).filter(|s| !s.is_empty());
+        preview.description = preview
+            .description
+            .or(description)
+            .filter(|s| !s.is_empty());
+        preview
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Preview::default()
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        Value::object([
+            ("title", Value::from(self.title.clone())),
+            ("description", Value::from(self.description.clone())),
+            (
+                "image",
+                Value::from(self.image.as_ref().map(UrlType::as_str)),
+            ),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Preview> {
+        let text = |name: &str| match value.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(UrlManagerError::InvalidLink(format!(
+                "preview '{name}' is not a string"
+            ))),
+        };
+        Ok(Preview {
+            title: text("title")?,
+            description: text("description")?,
+            image: text("image")?
+                .map(|image| UrlType::parse(&image))
+                .transpose()?,
+        })
+    }
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+// The value of attribute `name` in the start tag `tag`, quoted or not.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(at) = find_ignore_case(rest, name) {
+        let before = rest[..at].chars().next_back();
+        let after = rest[at + name.len()..].trim_start();
+        rest = &rest[at + name.len()..];
+        if !before.is_some_and(char::is_whitespace) {
+            continue;
+        }
+        let Some(value) = after.strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let raw = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or(""),
+            _ => value
+                .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                .next()
+                .unwrap_or(""),
+        };
+        return Some(decode_entities(raw.trim()));
+    }
+    None
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Fetches a page and returns its body, read up to `max_bytes`.
+///
+/// The crate has no HTTP client of its own, so [`PreviewStore`] is handed
+/// one through this; [`PlainHttp`] covers `http://` pages. Implementations
+/// should give up after a timeout of their own and fail on non-2xx
+/// responses.
+pub trait HttpGet {
+    fn get(&self, url: &UrlType, max_bytes: usize) -> impl Future<Output = Result<String>> + Send;
+}
+
+/// Blocks the calling task for up to its `timeout` per read; redirects
+/// aren't followed.
+impl HttpGet for PlainHttp {
+    async fn get(&self, url: &UrlType, max_bytes: usize) -> Result<String> {
+        if url.scheme() != "http" {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "PlainHttp can't reach '{url}', only http:// URLs"
+            )));
+        }
+        let addrs = url.socket_addrs(|| None)?;
+        let mut stream = TcpStream::connect(&*addrs)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        // HTTP/1.0 so the body isn't chunked
+        let host = &url[url::Position::BeforeHost..url::Position::AfterPort];
+        let path = &url[url::Position::BeforePath..url::Position::AfterQuery];
+        let request = format!("GET {path} HTTP/1.0\r\nHost: {host}\r\nAccept: text/html\r\n\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = Vec::new();
+        stream
+            .take((MAX_HEADER_BYTES + max_bytes) as u64)
+            .read_to_end(&mut response)?;
+        let response = String::from_utf8_lossy(&response);
+        let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+        let status = head
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok());
+        match status {
+            Some(200..=299) => Ok(body.chars().take(max_bytes).collect()),
+            Some(status) => Err(UrlManagerError::backend(format!(
+                "'{url}' answered {status}"
+            ))),
+            None => Err(UrlManagerError::backend(format!(
+                "'{url}' sent no HTTP response"
+            ))),
+        }
+    }
+}
+
+/// An [`AsyncLinkStore`] that fetches a [`Preview`] of the target of every
+/// link created, upserted or updated through it.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use url_manager::events::PlainHttp;
+/// # use url_manager::metadata::PreviewStore;
+/// # use url_manager::{InMemoryLinkStore, SyncStoreAdapter};
+/// let http = PlainHttp { timeout: Duration::from_secs(3) };
+/// let store = PreviewStore::new(SyncStoreAdapter::new(InMemoryLinkStore::new()), http)
+///     .max_bytes(64 * 1024);
+/// ```
+#[derive(Debug)]
+pub struct PreviewStore<S, H> {
+    inner: S,
+    http: H,
+    max_bytes: usize,
+}
+
+impl<S, H> PreviewStore<S, H>
+where
+    S: AsyncLinkStore,
+    H: HttpGet,
+{
+    pub fn new(inner: S, http: H) -> Self {
+        PreviewStore {
+            inner,
+            http,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+
+    /// Reads at most `max_bytes` of each page; the head, where the tags
+    /// are, usually comes first.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Fetches the preview of `target`.
+    pub async fn fetch(&self, target: &UrlType) -> Result<Preview> {
+        let html = self.http.get(target, self.max_bytes).await?;
+        Ok(Preview::parse(&html, target))
+    }
+
+    async fn attach(&self, link: &mut Link) {
+        if let Ok(preview) = self.fetch(link.target()).await {
+            link.set_preview(preview);
+        }
+    }
+}
+
+impl<S, H> AsyncLinkStore for PreviewStore<S, H>
+where
+    S: AsyncLinkStore + Send + Sync,
+    H: HttpGet + Send + Sync,
+{
+    async fn get(&self, id: u64) -> Result<Option<Link>> {
+        self.inner.get(id).await
+    }
+
+    async fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        self.inner.get_by_shortcut_in(namespace, shortcut).await
+    }
+
+    async fn create(&mut self, mut link: Link) -> Result<()> {
+        self.attach(&mut link).await;
+        self.inner.create(link).await
+    }
+
+    async fn upsert(&mut self, mut link: Link) -> Result<()> {
+        self.attach(&mut link).await;
+        self.inner.upsert(link).await
+    }
+
+    async fn update(&mut self, id: u64, mut link: Link) -> Result<()> {
+        self.attach(&mut link).await;
+        self.inner.update(id, link).await
+    }
+
+    async fn delete(&mut self, id: u64) -> Result<()> {
+        self.inner.delete(id).await
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.inner.list(offset, limit).await
+    }
+
+    async fn count(&self) -> Result<usize> {
+        self.inner.count().await
+    }
+
+    async fn purge_expired(&mut self) -> Result<usize> {
+        self.inner.purge_expired().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::block_on;
+    use crate::{InMemoryLinkStore, SyncStoreAdapter};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    const PAGE: &str = r#"<!doctype html><html><head>
+        <TITLE> Spring &amp; Summer </TITLE>
+        <meta name="description" content="Everything on sale">
+        <meta property='og:image' content="/img/sale.png" />
+        </head><body>...</body></html>"#;
+
+    #[test]
+    fn test_parse() {
+        let base = UrlType::parse("https://shop.example/sale/").unwrap();
+        let preview = Preview::parse(PAGE, &base);
+        assert_eq!(preview.title.as_deref(), Some("Spring & Summer"));
+        assert_eq!(preview.description.as_deref(), Some("Everything on sale"));
+        assert_eq!(
+            preview.image.as_ref().map(UrlType::as_str),
+            Some("https://shop.example/img/sale.png")
+        );
+
+        let og = r#"<title>Plain</title><meta content="Rich" property="og:title">"#;
+        let preview = Preview::parse(og, &base);
+        assert_eq!(preview.title.as_deref(), Some("Rich"));
+        assert!(Preview::parse("not html", &base).is_empty());
+        assert_eq!(
+            Preview::from_json(&Preview::parse(PAGE, &base).to_json()).unwrap(),
+            Preview::parse(PAGE, &base)
+        );
+    }
+
+    struct Pages;
+
+    impl HttpGet for Pages {
+        async fn get(&self, url: &UrlType, max_bytes: usize) -> Result<String> {
+            match url.path() {
+                "/sale" => Ok(PAGE.chars().take(max_bytes).collect()),
+                _ => Err(UrlManagerError::backend("404")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_preview_store() {
+        let mut store = PreviewStore::new(SyncStoreAdapter::new(InMemoryLinkStore::new()), Pages);
+        let sale = Link::builder()
+            .target("https://shop.example/sale")
+            .build()
+            .unwrap();
+        let gone = Link::builder()
+            .target("https://shop.example/gone")
+            .build()
+            .unwrap();
+        block_on(store.create(sale.clone())).unwrap();
+        block_on(store.create(gone.clone())).unwrap();
+
+        let stored = block_on(store.get(sale.id())).unwrap().unwrap();
+        let preview = stored.preview().unwrap();
+        assert_eq!(preview.title.as_deref(), Some("Spring & Summer"));
+        let stored = block_on(store.get(gone.id())).unwrap().unwrap();
+        assert!(stored.preview().is_none());
+
+        let capped =
+            PreviewStore::new(SyncStoreAdapter::new(InMemoryLinkStore::new()), Pages).max_bytes(20);
+        let preview = block_on(capped.fetch(sale.target())).unwrap();
+        assert!(preview.is_empty());
+    }
+
+    #[test]
+    fn test_plain_http_get() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let read = stream.read(&mut request).unwrap();
+            let body = "<title>Local</title>";
+            write!(
+                stream,
+                "HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            String::from_utf8_lossy(&request[..read]).to_string()
+        });
+        let http = PlainHttp {
+            timeout: Duration::from_secs(5),
+        };
+        let url = UrlType::parse(&format!("http://{addr}/page")).unwrap();
+        let body = block_on(http.get(&url, 1024)).unwrap();
+        assert_eq!(body, "<title>Local</title>");
+        assert!(server.join().unwrap().starts_with("GET /page HTTP/1.0\r\n"));
+
+        let https = UrlType::parse("https://example.com").unwrap();
+        assert!(matches!(
+            block_on(http.get(&https, 1024)),
+            Err(UrlManagerError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,236 @@
+//! Prometheus metrics, enabled by the `metrics` feature.
+//!
+//! [`LinkService`](crate::LinkService), [`record_hit`](crate::record_hit)
+//! and the server count created links and resolutions and time their store
+//! calls. [`gather`] renders everything in the Prometheus text format; the
+//! server serves it on `GET /metrics`.
+//!
+//! | Metric | Type |
+//! | ------ | ---- |
+//! | `url_manager_links_created_total` | counter |
+//! | `url_manager_resolutions_total` | counter, successful resolutions |
+//! | `url_manager_not_found_total` | counter, resolutions of unknown shortcuts |
+//! | `url_manager_expired_hits_total` | counter, resolutions of expired or used-up links |
+//! | `url_manager_cache_hits_total` | counter, lookups a [`CachedLinkStore`](crate::CachedLinkStore) answered |
+//! | `url_manager_cache_misses_total` | counter, lookups it passed on |
+//! | `url_manager_store_duration_seconds` | histogram by `operation` |
+//!
+//! Without the feature the hooks compile to nothing.
+
+use crate::Result;
+
+#[cfg(feature = "metrics")]
+use std::collections::BTreeMap;
+#[cfg(feature = "metrics")]
+use std::fmt::Write;
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "metrics")]
+use std::sync::Mutex;
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+#[cfg(feature = "metrics")]
+use crate::{sync, UrlManagerError};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+#[cfg(feature = "metrics")]
+pub const BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+#[cfg(feature = "metrics")]
+static LINKS_CREATED: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "metrics")]
+static RESOLUTIONS: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "metrics")]
+static NOT_FOUND: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "metrics")]
+static EXPIRED_HITS: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "metrics")]
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "metrics")]
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "metrics")]
+static STORE_DURATIONS: Mutex<BTreeMap<&str, Histogram>> = Mutex::new(BTreeMap::new());
+
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+struct Histogram {
+    // per bucket, not cumulative; the last one is +Inf
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+/// Counts a link stored through the service or the server.
+pub(crate) fn link_created() {
+    #[cfg(feature = "metrics")]
+    LINKS_CREATED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a lookup in a cache, answered from it if `hit`.
+pub(crate) fn cache_lookup(hit: bool) {
+    #[cfg(feature = "metrics")]
+    {
+        let counter = if hit { &CACHE_HITS } else { &CACHE_MISSES };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = hit;
+}
+
+/// Runs the store call `resolve`, timing it as `resolve` and counting its
+/// outcome.
+pub(crate) fn resolution<T>(resolve: impl FnOnce() -> Result<T>) -> Result<T> {
+    let result = store_call("resolve", resolve);
+    #[cfg(feature = "metrics")]
+    {
+        let counter = match &result {
+            Ok(_) => &RESOLUTIONS,
+            Err(UrlManagerError::NotFound) => &NOT_FOUND,
+            Err(UrlManagerError::Expired | UrlManagerError::UsesExhausted) => &EXPIRED_HITS,
+            Err(_) => return result,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    result
+}
+
+/// Runs the store call `call`, recording how long it took under `operation`.
+pub(crate) fn store_call<T>(operation: &'static str, call: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "metrics")]
+    {
+        let start = Instant::now();
+        let result = call();
+        observe(operation, start.elapsed().as_secs_f64());
+        result
+    }
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = operation;
+        call()
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn observe(operation: &'static str, seconds: f64) {
+    let mut histograms = sync::lock(&STORE_DURATIONS);
+    let histogram = histograms.entry(operation).or_default();
+    let bucket = BUCKETS
+        .iter()
+        .position(|&bound| seconds <= bound)
+        .unwrap_or(BUCKETS.len());
+    histogram.counts[bucket] += 1;
+    histogram.sum += seconds;
+}
+
+/// Renders all metrics in the Prometheus text exposition format (0.0.4).
+#[cfg(feature = "metrics")]
+pub fn gather() -> String {
+    let mut out = String::new();
+    for (name, help, counter) in [
+        (
+            "url_manager_links_created_total",
+            "Links created.",
+            &LINKS_CREATED,
+        ),
+        (
+            "url_manager_resolutions_total",
+            "Shortcuts resolved to their target.",
+            &RESOLUTIONS,
+        ),
+        (
+            "url_manager_not_found_total",
+            "Resolutions of shortcuts that don't exist.",
+            &NOT_FOUND,
+        ),
+        (
+            "url_manager_expired_hits_total",
+            "Resolutions of expired or used-up links.",
+            &EXPIRED_HITS,
+        ),
+        (
+            "url_manager_cache_hits_total",
+            "Store lookups answered from a cache.",
+            &CACHE_HITS,
+        ),
+        (
+            "url_manager_cache_misses_total",
+            "Store lookups a cache passed on to its store.",
+            &CACHE_MISSES,
+        ),
+    ] {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+        let _ = writeln!(out, "{name} {}", counter.load(Ordering::Relaxed));
+    }
+
+    let name = "url_manager_store_duration_seconds";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Time spent in store calls.\n# TYPE {name} histogram"
+    );
+    for (operation, histogram) in sync::lock(&STORE_DURATIONS).iter() {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{operation=\"{operation}\",le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        cumulative += histogram.counts[BUCKETS.len()];
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{operation=\"{operation}\",le=\"+Inf\"}} {cumulative}"
+        );
+        let _ = writeln!(
+            out,
+            "{name}_sum{{operation=\"{operation}\"}} {}",
+            histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "{name}_count{{operation=\"{operation}\"}} {cumulative}"
+        );
+    }
+    out
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    // The value of the sample line starting with `prefix`.
+    fn sample(prefix: &str) -> f64 {
+        gather()
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix))
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(0.0)
+    }
+
+    #[test]
+    fn test_gather() {
+        let not_found = sample("url_manager_not_found_total ");
+        let resolved = sample("url_manager_resolutions_total ");
+        let _ = resolution(|| -> Result<()> { Err(UrlManagerError::NotFound) });
+        let _ = resolution(|| -> Result<()> { Ok(()) });
+        assert!(sample("url_manager_not_found_total ") > not_found);
+        assert!(sample("url_manager_resolutions_total ") > resolved);
+
+        store_call("test", || ());
+        observe("test", 0.003);
+        observe("test", 60.0);
+        let text = gather();
+        assert!(text.contains("# TYPE url_manager_store_duration_seconds histogram"));
+        assert!(text.contains(
+            "url_manager_store_duration_seconds_bucket{operation=\"test\",le=\"+Inf\"} 3"
+        ));
+        assert_eq!(
+            sample("url_manager_store_duration_seconds_bucket{operation=\"test\",le=\"5\"}"),
+            2.0
+        );
+        assert_eq!(
+            sample("url_manager_store_duration_seconds_count{operation=\"test\"}"),
+            3.0
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+use crate::{Result, UrlManagerError};
+
+/// A tenant's own space of shortcuts, e.g. a team or a custom domain.
+///
+/// The same shortcut can be taken once per namespace, plus once by links
+/// without a namespace. Names are lowercased and may hold ASCII letters,
+/// digits, `-`, `_` and `.`, up to 253 characters so domains fit.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Namespace(String);
+
+impl Namespace {
+    pub fn new(name: impl Into<String>) -> Result<Self> {
+        let name = name.into().to_ascii_lowercase();
+        if name.is_empty() || name.len() > 253 {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "namespace '{name}' must be 1 to 253 characters"
+            )));
+        }
+        if let Some(c) = name
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "namespace '{name}' contains '{c}'"
+            )));
+        }
+        Ok(Namespace(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<&str> for Namespace {
+    type Error = UrlManagerError;
+
+    fn try_from(value: &str) -> Result<Self> {
+        Namespace::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespace() {
+        assert_eq!(Namespace::new("Team-A").unwrap().as_str(), "team-a");
+        assert_eq!(
+            Namespace::new("go.example.com").unwrap().to_string(),
+            "go.example.com"
+        );
+        assert!(Namespace::new("").is_err());
+        assert!(Namespace::new("a/b").is_err());
+        assert!(Namespace::new("x".repeat(254)).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use url::Url as UrlType;
+
+/// Removes analytics parameters such as `utm_source` or `fbclid` from query strings.
+///
+/// The default removes every `utm_*` parameter plus `fbclid`, `gclid` and
+/// `msclkid`; more can be added with [`TrackingParamStripper::param`] and
+/// [`TrackingParamStripper::prefix`]. Names are compared case-insensitively.
+///
+/// ```
+/// # use url_manager::{TrackingParamStripper, UrlType};
+/// let url = UrlType::parse("https://example.com/a?utm_source=x&id=7&fbclid=y")?;
+/// assert_eq!(
+///     TrackingParamStripper::default().strip(&url).as_str(),
+///     "https://example.com/a?id=7"
+/// );
+/// # Ok::<(), url_manager::ParseError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackingParamStripper {
+    names: Vec<String>,
+    prefixes: Vec<String>,
+}
+
+impl Default for TrackingParamStripper {
+    fn default() -> Self {
+        TrackingParamStripper {
+            names: ["fbclid", "gclid", "msclkid"].map(String::from).to_vec(),
+            prefixes: vec!["utm_".to_string()],
+        }
+    }
+}
+
+impl TrackingParamStripper {
+    /// A stripper that removes nothing until told which parameters to remove.
+    pub fn empty() -> Self {
+        TrackingParamStripper {
+            names: Vec::new(),
+            prefixes: Vec::new(),
+        }
+    }
+
+    /// Also removes parameters called `name`.
+    pub fn param(mut self, name: impl Into<String>) -> Self {
+        self.names.push(name.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Also removes parameters whose name starts with `prefix`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefixes.push(prefix.into().to_ascii_lowercase());
+        self
+    }
+
+    pub fn is_tracking(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.names.contains(&name) || self.prefixes.iter().any(|prefix| name.starts_with(prefix))
+    }
+
+    pub fn strip(&self, url: &UrlType) -> UrlType {
+        let mut url = url.clone();
+        if url.query().is_none() {
+            return url;
+        }
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .into_owned()
+            .filter(|(name, _)| !self.is_tracking(name))
+            .collect();
+        if pairs.len() == url.query_pairs().count() {
+            return url;
+        }
+        if pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        url
+    }
+}
+
+/// Rewrites URLs into a canonical form so equivalent ones compare equal.
+///
+/// Parsing already lowercases the scheme and, for `http`, `https` and the
+/// other special schemes, the host, drops default ports and resolves dot
+/// segments. On top of that the normalizer lowercases the host of other
+/// schemes, sorts query parameters by name and drops the fragment; the last
+/// two can be turned off. Tracking parameters are kept unless a
+/// [`TrackingParamStripper`] is set.
+///
+/// ```
+/// # use url_manager::{Normalizer, UrlType};
+/// let url = UrlType::parse("https://Example.com:443/a/../b?b=2&a=1#top")?;
+/// assert_eq!(
+///     Normalizer::new().normalize(&url).as_str(),
+///     "https://example.com/b?a=1&b=2"
+/// );
+/// # Ok::<(), url_manager::ParseError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Normalizer {
+    sort_query: bool,
+    strip_fragment: bool,
+    tracking: Option<TrackingParamStripper>,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Normalizer {
+            sort_query: true,
+            strip_fragment: true,
+            tracking: None,
+        }
+    }
+}
+
+impl Normalizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to sort query parameters by name; repeated names keep their order.
+    pub fn sort_query(mut self, sort_query: bool) -> Self {
+        self.sort_query = sort_query;
+        self
+    }
+
+    pub fn strip_fragment(mut self, strip_fragment: bool) -> Self {
+        self.strip_fragment = strip_fragment;
+        self
+    }
+
+    /// Also removes the parameters `stripper` considers tracking.
+    pub fn strip_tracking(mut self, stripper: TrackingParamStripper) -> Self {
+        self.tracking = Some(stripper);
+        self
+    }
+
+    pub fn normalize(&self, url: &UrlType) -> UrlType {
+        let mut url = match &self.tracking {
+            Some(stripper) => stripper.strip(url),
+            None => url.clone(),
+        };
+        if let Some(host) = url
+            .host_str()
+            .filter(|host| host.bytes().any(|b| b.is_ascii_uppercase()))
+        {
+            let host = host.to_ascii_lowercase();
+            // Hosts that parsed once still parse when lowercased.
+            let _ = url.set_host(Some(&host));
+        }
+        if self.sort_query && url.query().is_some() {
+            let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+            pairs.sort_by(|a, b| a.0.cmp(&b.0));
+            if pairs.is_empty() {
+                url.set_query(None);
+            } else {
+                url.query_pairs_mut().clear().extend_pairs(pairs);
+            }
+        }
+        if self.strip_fragment {
+            url.set_fragment(None);
+        }
+        url
+    }
+}
+
+/// Normalizes `url` with the default [`Normalizer`].
+pub fn normalize(url: &UrlType) -> UrlType {
+    Normalizer::default().normalize(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(normalizer: &Normalizer, url: &str) -> String {
+        normalizer
+            .normalize(&UrlType::parse(url).unwrap())
+            .to_string()
+    }
+
+    #[test]
+    fn test_normalize() {
+        let canonical = normalize(&UrlType::parse("https://example.com/b?a=1&b=2").unwrap());
+        assert_eq!(
+            normalize(&UrlType::parse("HTTPS://Example.COM:443/a/../b?b=2&a=1#x").unwrap()),
+            canonical
+        );
+
+        let default = &Normalizer::new();
+        assert_eq!(
+            normalized(default, "https://example.com/?"),
+            "https://example.com/"
+        );
+        assert_eq!(
+            normalized(default, "https://example.com/?b=1&a=2&b=0"),
+            "https://example.com/?a=2&b=1&b=0"
+        );
+        assert_eq!(
+            normalized(default, "foo://Host.Example/x"),
+            "foo://host.example/x"
+        );
+
+        let keep = &Normalizer::new().sort_query(false).strip_fragment(false);
+        assert_eq!(
+            normalized(keep, "https://example.com/?b=1&a=2#top"),
+            "https://example.com/?b=1&a=2#top"
+        );
+    }
+
+    #[test]
+    fn test_strip_tracking() {
+        let url = |text: &str| UrlType::parse(text).unwrap();
+        let default = TrackingParamStripper::default();
+        assert_eq!(
+            default
+                .strip(&url("https://example.com/?UTM_Medium=a&gclid=b&q=rust"))
+                .as_str(),
+            "https://example.com/?q=rust"
+        );
+        assert_eq!(
+            default
+                .strip(&url("https://example.com/?utm_source=x"))
+                .as_str(),
+            "https://example.com/"
+        );
+        // untouched URLs keep their encoding
+        assert_eq!(
+            default.strip(&url("https://example.com/?q=a%20b")).as_str(),
+            "https://example.com/?q=a%20b"
+        );
+
+        let custom = TrackingParamStripper::empty().param("ref").prefix("mc_");
+        assert_eq!(
+            custom
+                .strip(&url("https://example.com/?ref=x&mc_cid=y&utm_source=z"))
+                .as_str(),
+            "https://example.com/?utm_source=z"
+        );
+
+        let normalizer = Normalizer::new().strip_tracking(default);
+        assert_eq!(
+            normalized(&normalizer, "https://example.com/?b=1&utm_source=x&a=2"),
+            "https://example.com/?a=2&b=1"
+        );
+    }
+
+    // A URL assembled from pieces that have tripped up normalizers:
+    // host-less schemes, mixed-case and IDN hosts, ports, dot segments,
+    // percent escapes, repeated and tracking parameters.
+    fn random_url(rng: &mut impl rand::Rng) -> String {
+        const SCHEMES: &[&str] = &["https://", "http://", "HTTP://", "ftp://", "foo://"];
+        const OPAQUE: &[&str] = &[
+            "mailto:a@b.c",
+            "data:text/plain,hi",
+            "urn:isbn:1",
+            "file:///tmp/x",
+        ];
+        const HOSTS: &[&str] = &[
+            "example.com",
+            "Example.COM",
+            "xn--bcher-kva.ch",
+            "Bücher.ch",
+            "127.0.0.1",
+            "[::1]",
+            "a.b.c.d.e",
+        ];
+        const SEGMENTS: &[&str] = &["a", "..", ".", "%2F", "B", "ü", "", "a%20b"];
+        const KEYS: &[&str] = &[
+            "q",
+            "b",
+            "a",
+            "utm_source",
+            "UTM_Medium",
+            "gclid",
+            "",
+            "%26",
+            "k=v",
+        ];
+        if rng.gen_bool(0.1) {
+            return OPAQUE[rng.gen_range(0..OPAQUE.len())].to_string();
+        }
+        let mut url = format!(
+            "{}{}",
+            SCHEMES[rng.gen_range(0..SCHEMES.len())],
+            HOSTS[rng.gen_range(0..HOSTS.len())]
+        );
+        if rng.gen_bool(0.2) {
+            url += &format!(":{}", rng.gen_range(1..65536));
+        }
+        for _ in 0..rng.gen_range(0..4) {
+            url += "/";
+            url += SEGMENTS[rng.gen_range(0..SEGMENTS.len())];
+        }
+        if rng.gen_bool(0.7) {
+            url += "?";
+            for i in 0..rng.gen_range(0..5) {
+                if i > 0 {
+                    url += "&";
+                }
+                url += KEYS[rng.gen_range(0..KEYS.len())];
+                if rng.gen_bool(0.8) {
+                    url += &format!("={}", rng.gen_range(0..3));
+                }
+            }
+        }
+        if rng.gen_bool(0.3) {
+            url += "#frag";
+        }
+        url
+    }
+
+    #[test]
+    fn test_normalize_random_urls() {
+        use rand::SeedableRng;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x5eed);
+        let normalizers = [
+            Normalizer::new(),
+            Normalizer::new().sort_query(false).strip_fragment(false),
+            Normalizer::new().strip_tracking(TrackingParamStripper::default()),
+        ];
+        for _ in 0..2000 {
+            let text = random_url(&mut rng);
+            let Ok(url) = UrlType::parse(&text) else {
+                continue;
+            };
+            for normalizer in &normalizers {
+                let once = normalizer.normalize(&url);
+                assert_eq!(
+                    normalizer.normalize(&once),
+                    once,
+                    "not idempotent for {text}"
+                );
+                assert_eq!(UrlType::parse(once.as_str()).as_ref(), Ok(&once), "{text}");
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use url::Host;
+
+use crate::{idn, Result, UrlManagerError, UrlType};
+
+/// Rules for which targets may be shortened, e.g. to keep a public
+/// shortener from pointing at the network it runs in.
+///
+/// A new policy allows everything; every rule added narrows it down.
+/// Hosts match themselves and their subdomains, case-insensitively, and
+/// may be given in Unicode or punycode.
+///
+/// ```
+/// # use url_manager::{UrlPolicy, UrlType};
+/// let policy = UrlPolicy::new()
+///     .https_only()
+///     .block_private_addresses()
+///     .block_host("evil.example")
+///     .block_tld("zip");
+/// assert!(policy.check(&UrlType::parse("https://example.com/docs")?).is_ok());
+/// assert!(policy.check(&UrlType::parse("http://example.com/docs")?).is_err());
+/// assert!(policy.check(&UrlType::parse("https://169.254.169.254/latest")?).is_err());
+/// assert!(policy.check(&UrlType::parse("https://cdn.evil.example/")?).is_err());
+/// # Ok::<(), url_manager::ParseError>(())
+/// ```
+///
+/// Names are not resolved, so a domain pointing at a private address gets
+/// through `block_private_addresses`; only literal addresses and
+/// `localhost` are caught.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UrlPolicy {
+    https_only: bool,
+    block_private: bool,
+    blocked_hosts: Vec<String>,
+    blocked_tlds: Vec<String>,
+    allowed_hosts: Vec<String>,
+    block_homographs: bool,
+}
+
+impl UrlPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only allows `https` targets.
+    pub fn https_only(mut self) -> Self {
+        self.https_only = true;
+        self
+    }
+
+    /// Rejects loopback, private, link-local and other non-public address
+    /// literals, plus `localhost`.
+    pub fn block_private_addresses(mut self) -> Self {
+        self.block_private = true;
+        self
+    }
+
+    /// Rejects `host` and its subdomains.
+    pub fn block_host(mut self, host: impl Into<String>) -> Self {
+        self.blocked_hosts.push(canonical_host(&host.into()));
+        self
+    }
+
+    /// Rejects every host under the top-level domain `tld`, given without dot.
+    pub fn block_tld(mut self, tld: impl Into<String>) -> Self {
+        self.blocked_tlds
+            .push(canonical_host(tld.into().trim_start_matches('.')));
+        self
+    }
+
+    /// Only allows `host` and its subdomains, plus any other allowed hosts.
+    pub fn allow_host(mut self, host: impl Into<String>) -> Self {
+        self.allowed_hosts.push(canonical_host(&host.into()));
+        self
+    }
+
+    /// Rejects hosts that look like they imitate another, see
+    /// [`idn::homograph_warning`].
+    pub fn block_homographs(mut self) -> Self {
+        self.block_homographs = true;
+        self
+    }
+
+    /// Fails with `Forbidden` naming the rule `target` breaks, if any.
+    pub fn check(&self, target: &UrlType) -> Result<()> {
+        let forbidden = |reason: String| Err(UrlManagerError::Forbidden(reason));
+        if self.https_only && target.scheme() != "https" {
+            return forbidden(format!("'{target}' is not https"));
+        }
+        let Some(host) = target.host() else {
+            return forbidden(format!("'{target}' has no host"));
+        };
+        if self.block_private && !is_public(&host) {
+            return forbidden(format!("'{host}' is not a public address"));
+        }
+        let Host::Domain(domain) = host else {
+            return if self.allowed_hosts.is_empty() {
+                Ok(())
+            } else {
+                forbidden(format!("'{host}' is not an allowed host"))
+            };
+        };
+        let domain = canonical_host(domain);
+        if let Some(blocked) = self
+            .blocked_hosts
+            .iter()
+            .find(|blocked| is_within(&domain, blocked))
+        {
+            return forbidden(format!("'{domain}' is blocked as part of '{blocked}'"));
+        }
+        let tld = domain.rsplit('.').next().unwrap_or_default();
+        if self.blocked_tlds.iter().any(|blocked| blocked == tld) {
+            return forbidden(format!("'.{tld}' domains are blocked"));
+        }
+        if !self.allowed_hosts.is_empty()
+            && !self
+                .allowed_hosts
+                .iter()
+                .any(|allowed| is_within(&domain, allowed))
+        {
+            return forbidden(format!("'{domain}' is not an allowed host"));
+        }
+        if self.block_homographs {
+            if let Some(warning) = idn::homograph_warning(target) {
+                return forbidden(warning);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Unicode hosts are compared in the punycode form targets have.
+fn canonical_host(host: &str) -> String {
+    idn::ascii_host(host.trim().trim_end_matches('.'))
+}
+
+// Whether `host` is `domain` or one of its subdomains.
+fn is_within(host: &str, domain: &str) -> bool {
+    host.strip_suffix(domain)
+        .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+}
+
+fn is_public(host: &Host<&str>) -> bool {
+    match host {
+        Host::Domain(domain) => !is_within(&canonical_host(domain), "localhost"),
+        Host::Ipv4(ip) => is_public_v4(ip),
+        Host::Ipv6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(&ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        || a == 0)
+}
+
+fn is_public_v6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // unique local fc00::/7 and link-local fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(policy: &UrlPolicy, url: &str) -> bool {
+        policy.check(&UrlType::parse(url).unwrap()).is_ok()
+    }
+
+    #[test]
+    fn test_private_addresses() {
+        let policy = UrlPolicy::new().block_private_addresses();
+        for url in [
+            "http://169.254.169.254/latest/meta-data",
+            "http://127.0.0.1:8080/",
+            "http://10.1.2.3/",
+            "http://192.168.0.1/",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://2852039166/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://localhost/",
+            "http://api.localhost./",
+        ] {
+            assert!(!allowed(&policy, url), "{url} should be blocked");
+        }
+        for url in [
+            "http://93.184.216.34/",
+            "http://[2606:2800:220:1::]/",
+            "http://example.com/",
+        ] {
+            assert!(allowed(&policy, url), "{url} should be allowed");
+        }
+        assert!(allowed(&UrlPolicy::new(), "http://127.0.0.1/"));
+    }
+
+    #[test]
+    fn test_hosts_and_tlds() {
+        let policy = UrlPolicy::new()
+            .block_host("Evil.Example")
+            .block_tld(".zip");
+        assert!(!allowed(&policy, "https://evil.example/"));
+        assert!(!allowed(&policy, "https://a.b.EVIL.example./"));
+        assert!(allowed(&policy, "https://notevil.example/"));
+        assert!(!allowed(&policy, "https://download.zip/"));
+        assert!(allowed(&policy, "https://zip.example.com/"));
+
+        let policy = UrlPolicy::new().allow_host("example.com").https_only();
+        assert!(allowed(&policy, "https://docs.example.com/"));
+        assert!(!allowed(&policy, "http://docs.example.com/"));
+        assert!(!allowed(&policy, "https://example.org/"));
+        assert!(!allowed(&policy, "https://93.184.216.34/"));
+        assert!(matches!(
+            policy.check(&UrlType::parse("mailto:a@example.com").unwrap()),
+            Err(UrlManagerError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn test_unicode_hosts() {
+        let policy = UrlPolicy::new().block_host("Bücher.example");
+        assert!(!allowed(&policy, "https://shop.bücher.example/"));
+        assert!(!allowed(&policy, "https://xn--bcher-kva.example/"));
+
+        let policy = UrlPolicy::new().block_homographs();
+        assert!(allowed(&policy, "https://bücher.example/"));
+        assert!(allowed(&policy, "https://пример.рф/"));
+        assert!(!allowed(&policy, "https://\u{0430}pple.com/"));
+        assert!(!allowed(&policy, "https://xn--pple-43d.com/"));
+    }
+}
@@ -0,0 +1,312 @@
+//! The data model and code generation on `core` and `alloc` alone, for
+//! embedded and sandboxed uses.
+//!
+//! This is all the crate builds without its default `std` feature: no std
+//! collections beyond `alloc`, no clock, as times are unix milliseconds
+//! passed in by the caller, and no random numbers, as codes are derived
+//! from ids. With `std`, [`Base62`], [`CodeGenerator`] and the slug rules
+//! are the ones the rest of the crate uses, and a [`LinkRecord`] converts
+//! to and from a full [`Link`](crate::Link).
+//!
+//! ```
+//! use url_manager::pure::{check_slug, Base62, CodeGenerator, LinkRecord};
+//!
+//! let code = Base62::new().min_length(4).generate(125, 0);
+//! check_slug(&code)?;
+//! let mut link = LinkRecord::new(125, "https://example.com/");
+//! link.shortcut = Some(code);
+//! link.max_uses = Some(1);
+//! link.hit(1_700_000_000_000)?;
+//! assert!(link.hit(1_700_000_000_001).is_err());
+//! # Ok::<(), url_manager::pure::Error>(())
+//! ```
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Why a check in this module failed. With `std` it converts into the
+/// matching [`UrlManagerError`](crate::UrlManagerError).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The alphabet can't be used for codes, and why.
+    InvalidAlphabet(String),
+    /// The slug can't be used as a shortcut, and why.
+    InvalidSlug(String),
+    Expired,
+    UsesExhausted,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidAlphabet(reason) => write!(f, "invalid alphabet: {reason}"),
+            Error::InvalidSlug(reason) => write!(f, "invalid slug: {reason}"),
+            Error::Expired => f.write_str("link has expired"),
+            Error::UsesExhausted => f.write_str("link has no uses left"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+const BASE62_ALPHABET: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Base62 without the characters that are easily mistaken for one another
+/// when read aloud or retyped: `0`, `O`, `o`, `1`, `I` and `l`.
+pub const UNAMBIGUOUS_ALPHABET: &str = "23456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnpqrstuvwxyz";
+
+/// The fewest characters [`Base62::from_checked_alphabet`] accepts, so
+/// every character of a code carries at least 4 bits.
+pub const MIN_ALPHABET_SIZE: usize = 16;
+
+pub const MAX_SLUG_LENGTH: usize = 64;
+
+/// Slugs that would shadow routes of a shortener service.
+pub const RESERVED_SLUGS: &[&str] = &[
+    "admin", "api", "health", "healthz", "login", "logout", "metrics", "readyz", "static",
+];
+
+/// Turns link ids into short codes.
+pub trait CodeGenerator {
+    /// Returns the code for link `id`.
+    ///
+    /// `attempt` starts at 0 and is increased by [`unique_code`](crate::unique_code)
+    /// every time the previous code was already taken, so implementations
+    /// should return a different code for every attempt.
+    fn generate(&self, id: u64, attempt: u32) -> String;
+}
+
+/// Positional encoding of the id over an alphabet, Base62 by default.
+///
+/// Codes are left-padded with the first character of the alphabet up to
+/// the minimum length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Base62 {
+    alphabet: Vec<char>,
+    min_length: usize,
+}
+
+impl Default for Base62 {
+    fn default() -> Self {
+        Base62 {
+            alphabet: BASE62_ALPHABET.chars().collect(),
+            min_length: 0,
+        }
+    }
+}
+
+impl Base62 {
+    pub fn new() -> Self {
+        Base62::default()
+    }
+
+    /// Uses `alphabet` instead of `[0-9A-Za-z]`.
+    ///
+    /// The alphabet needs at least two characters and no duplicates.
+    pub fn from_alphabet(alphabet: &str) -> Result<Self, Error> {
+        let chars: Vec<char> = alphabet.chars().collect();
+        if chars.len() < 2 {
+            return Err(Error::InvalidAlphabet(
+                "alphabet needs at least two characters".to_string(),
+            ));
+        }
+        for (i, c) in chars.iter().enumerate() {
+            if chars[..i].contains(c) {
+                return Err(Error::InvalidAlphabet(format!("alphabet repeats '{c}'")));
+            }
+        }
+        Ok(Base62 {
+            alphabet: chars,
+            min_length: 0,
+        })
+    }
+
+    /// [`Base62::from_alphabet`] for alphabets supplied by an admin: they
+    /// also need at least [`MIN_ALPHABET_SIZE`] characters, all of them
+    /// usable in a slug unescaped, i.e. ASCII letters, digits, `-` or `_`.
+    pub fn from_checked_alphabet(alphabet: &str) -> Result<Self, Error> {
+        if let Some(c) = alphabet.chars().find(|&c| !is_slug_char(c)) {
+            return Err(Error::InvalidAlphabet(format!(
+                "alphabet contains '{c}', which can't be used in a slug"
+            )));
+        }
+        let base62 = Base62::from_alphabet(alphabet)?;
+        if base62.alphabet.len() < MIN_ALPHABET_SIZE {
+            return Err(Error::InvalidAlphabet(format!(
+                "alphabet has {} characters, at least {MIN_ALPHABET_SIZE} are needed",
+                base62.alphabet.len()
+            )));
+        }
+        Ok(base62)
+    }
+
+    /// Encodes over [`UNAMBIGUOUS_ALPHABET`].
+    pub fn unambiguous() -> Self {
+        Base62::from_alphabet(UNAMBIGUOUS_ALPHABET).unwrap()
+    }
+
+    pub fn min_length(mut self, min_length: usize) -> Self {
+        self.min_length = min_length;
+        self
+    }
+
+    pub fn alphabet(&self) -> String {
+        self.alphabet.iter().collect()
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn chars(&self) -> &[char] {
+        &self.alphabet
+    }
+
+    pub fn encode(&self, mut value: u64) -> String {
+        let base = self.alphabet.len() as u64;
+        let mut digits = Vec::new();
+        loop {
+            digits.push(self.alphabet[(value % base) as usize]);
+            value /= base;
+            if value == 0 {
+                break;
+            }
+        }
+        while digits.len() < self.min_length {
+            digits.push(self.alphabet[0]);
+        }
+        digits.iter().rev().collect()
+    }
+
+    /// Reverses [`Base62::encode`], `None` for foreign characters or overflow.
+    pub fn decode(&self, code: &str) -> Option<u64> {
+        let base = self.alphabet.len() as u64;
+        code.chars().try_fold(0u64, |value, c| {
+            let digit = self.alphabet.iter().position(|&a| a == c)? as u64;
+            value.checked_mul(base)?.checked_add(digit)
+        })
+    }
+}
+
+impl CodeGenerator for Base62 {
+    fn generate(&self, id: u64, attempt: u32) -> String {
+        // retries step through the id space by the 64-bit golden ratio so
+        // they don't land on the neighbouring ids
+        let value = id.wrapping_add(u64::from(attempt).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        self.encode(value)
+    }
+}
+
+/// Checks that `slug` can be used as a human-chosen shortcut: 1 to
+/// [`MAX_SLUG_LENGTH`] ASCII letters, digits, `-` or `_`, and none of the
+/// [`RESERVED_SLUGS`] in any casing.
+pub fn check_slug(slug: &str) -> Result<(), Error> {
+    if slug.is_empty() || slug.len() > MAX_SLUG_LENGTH {
+        return Err(Error::InvalidSlug(format!(
+            "'{slug}' must be between 1 and {MAX_SLUG_LENGTH} characters"
+        )));
+    }
+    if let Some(c) = slug.chars().find(|&c| !is_slug_char(c)) {
+        return Err(Error::InvalidSlug(format!("'{slug}' contains '{c}'")));
+    }
+    if RESERVED_SLUGS
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(slug))
+    {
+        return Err(Error::InvalidSlug(format!("'{slug}' is reserved")));
+    }
+    Ok(())
+}
+
+fn is_slug_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// The part of a link that decides where and whether it resolves, with
+/// times in unix milliseconds. URLs are kept as given; parsing them is left
+/// to the caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkRecord {
+    pub id: u64,
+    pub target: String,
+    pub namespace: Option<String>,
+    pub shortcut: Option<String>,
+    pub expires_at: Option<u64>,
+    pub max_uses: Option<u32>,
+    pub hits: u64,
+    pub last_hit_at: Option<u64>,
+}
+
+impl LinkRecord {
+    pub fn new(id: u64, target: impl Into<String>) -> Self {
+        LinkRecord {
+            id,
+            target: target.into(),
+            namespace: None,
+            shortcut: None,
+            expires_at: None,
+            max_uses: None,
+            hits: 0,
+            last_hit_at: None,
+        }
+    }
+
+    pub fn is_expired_at(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Whether a use-limited link has been resolved `max_uses` times.
+    pub fn is_exhausted(&self) -> bool {
+        self.max_uses
+            .is_some_and(|max_uses| self.hits >= u64::from(max_uses))
+    }
+
+    /// Fails with the reason the link can't be followed at `now`, if any.
+    pub fn check_resolvable(&self, now: u64) -> Result<(), Error> {
+        if self.is_expired_at(now) {
+            Err(Error::Expired)
+        } else if self.is_exhausted() {
+            Err(Error::UsesExhausted)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Counts a resolution at `now`, failing like
+    /// [`LinkRecord::check_resolvable`] without counting it.
+    pub fn hit(&mut self, now: u64) -> Result<(), Error> {
+        self.check_resolvable(now)?;
+        self.hits += 1;
+        self.last_hit_at = Some(now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_slug() {
+        assert_eq!(check_slug("launch-2024"), Ok(()));
+        assert!(matches!(check_slug(""), Err(Error::InvalidSlug(_))));
+        assert!(matches!(check_slug("a/b"), Err(Error::InvalidSlug(_))));
+        assert!(matches!(check_slug("Admin"), Err(Error::InvalidSlug(_))));
+        assert!(matches!(
+            Base62::from_checked_alphabet("abc"),
+            Err(Error::InvalidAlphabet(_))
+        ));
+    }
+
+    #[test]
+    fn test_link_record() {
+        let mut link = LinkRecord::new(1, "https://example.com/");
+        link.expires_at = Some(2_000);
+        link.max_uses = Some(2);
+        link.hit(1_000).unwrap();
+        assert_eq!(link.hit(2_000), Err(Error::Expired));
+        assert_eq!((link.hits, link.last_hit_at), (1, Some(1_000)));
+        link.expires_at = None;
+        link.hit(3_000).unwrap();
+        assert_eq!(link.check_resolvable(4_000), Err(Error::UsesExhausted));
+    }
+}
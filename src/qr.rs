@@ -0,0 +1,774 @@
+//! QR codes of short URLs for print, enabled by the `qr` feature.
+//!
+//! [`QrCode::encode`] puts bytes in the smallest QR code (ISO/IEC 18004)
+//! that holds them at error correction level M, which survives about 15%
+//! of the symbol being smudged or torn. [`Link::qr_code`] does so for the
+//! short URL of a link. Codes are written as SVG, which scales for print,
+//! or as PNG:
+//!
+//! ```
+//! # use url_manager::{Link, UrlType};
+//! let link = Link::builder().target("https://example.com/docs").slug("docs").build()?;
+//! let code = link.qr_code(&UrlType::parse("https://sho.rt/")?)?;
+//! let mut svg = Vec::new();
+//! code.write_svg(&mut svg)?;
+//! assert!(String::from_utf8(svg)?.starts_with("<svg"));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::io::Write;
+
+use crate::{Link, Result, UrlManagerError, UrlType};
+
+/// Light modules around the code, which scanners need to find it.
+const QUIET_ZONE: usize = 4;
+
+// Error correction codewords per block and blocks per version, at level M,
+// indexed by version; 0 is unused.
+const ECC_CODEWORDS_PER_BLOCK: [usize; 41] = [
+    0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+];
+const ECC_BLOCKS: [usize; 41] = [
+    0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23,
+    25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+];
+
+/// A QR code: a square of dark and light modules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    version: usize,
+    size: usize,
+    // row by row, `true` for dark
+    modules: Vec<bool>,
+}
+
+impl Link {
+    /// The QR code of the link's short URL, its shortcut joined onto
+    /// `base_url`. Fails with `InvalidLink` if it has no shortcut.
+    pub fn qr_code(&self, base_url: &UrlType) -> Result<QrCode> {
+        let shortcut = self
+            .shortcut()
+            .ok_or_else(|| UrlManagerError::InvalidLink("the link has no shortcut".to_string()))?;
+        let url = base_url.join(shortcut)?;
+        QrCode::encode(url.as_str().as_bytes())
+    }
+}
+
+impl QrCode {
+    /// Encodes `data` in byte mode in the smallest version that fits it,
+    /// failing with `InvalidLink` past the 2331 bytes of version 40.
+    pub fn encode(data: &[u8]) -> Result<QrCode> {
+        let version = (1..=40)
+            .find(|&version| data_bits(version, data.len()) <= data_codewords(version) * 8)
+            .ok_or_else(|| {
+                UrlManagerError::InvalidLink(format!("{} bytes don't fit in a QR code", data.len()))
+            })?;
+        let codewords = add_ecc_and_interleave(version, &data_codewords_for(version, data));
+
+        let mut code = Builder::new(version);
+        code.draw_function_patterns();
+        code.draw_codewords(&codewords);
+        // the mask leaving the fewest patterns that confuse scanners
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                code.apply_mask(mask);
+                code.draw_format_bits(mask);
+                let penalty = code.penalty();
+                code.apply_mask(mask);
+                penalty
+            })
+            .unwrap_or(0);
+        code.apply_mask(mask);
+        code.draw_format_bits(mask);
+        Ok(QrCode {
+            version,
+            size: code.size,
+            modules: code.modules,
+        })
+    }
+
+    /// 1 to 40, for 21 to 177 modules a side.
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    /// Modules per side, without the quiet zone.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the module in column `x` and row `y` is dark; outside the
+    /// code, i.e. in the quiet zone, it is light.
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y * self.size + x]
+    }
+
+    /// Writes the code as an SVG image one unit per module, with its quiet
+    /// zone, to be scaled to the size it is printed at.
+    pub fn write_svg(&self, mut writer: impl Write) -> Result<()> {
+        let side = self.size + 2 * QUIET_ZONE;
+        write!(
+            writer,
+            r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {side} {side}" shape-rendering="crispEdges"><rect width="{side}" height="{side}" fill="#fff"/><path fill="#000" d=""##
+        )?;
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.is_dark(x, y) {
+                    write!(writer, "M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE)?;
+                }
+            }
+        }
+        writeln!(writer, r#""/></svg>"#)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the code as a grayscale PNG image `scale` pixels per module,
+    /// with its quiet zone.
+    pub fn write_png(&self, mut writer: impl Write, scale: usize) -> Result<()> {
+        let scale = scale.max(1);
+        let side = (self.size + 2 * QUIET_ZONE) * scale;
+        let width = u32::try_from(side)
+            .map_err(|_| UrlManagerError::InvalidLink(format!("{side} pixels is too wide")))?;
+        let mut pixels = Vec::with_capacity((side + 1) * side);
+        for row in 0..side {
+            // no filter
+            pixels.push(0);
+            let y = (row / scale).wrapping_sub(QUIET_ZONE);
+            pixels.extend((0..side).map(|column| {
+                let x = (column / scale).wrapping_sub(QUIET_ZONE);
+                if self.is_dark(x, y) {
+                    0x00
+                } else {
+                    0xff
+                }
+            }));
+        }
+
+        writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&width.to_be_bytes());
+        // 8-bit grayscale, deflate, adaptive filtering, not interlaced
+        header.extend_from_slice(&[8, 0, 0, 0, 0]);
+        write_chunk(&mut writer, b"IHDR", &header)?;
+        write_chunk(&mut writer, b"IDAT", &zlib_stored(&pixels))?;
+        write_chunk(&mut writer, b"IEND", &[])?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+// The modules of a code being drawn, and which of them are function
+// patterns that the data and the mask leave alone.
+struct Builder {
+    size: usize,
+    version: usize,
+    modules: Vec<bool>,
+    function: Vec<bool>,
+}
+
+impl Builder {
+    fn new(version: usize) -> Self {
+        let size = version * 4 + 17;
+        Builder {
+            size,
+            version,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        for i in 0..self.size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        let far = self.size - 4;
+        for (x, y) in [(3, 3), (far, 3), (3, far)] {
+            self.draw_finder(x, y);
+        }
+        let positions = alignment_positions(self.version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // the corners taken by finder patterns
+                if (i, j) != (0, 0) && (i, j) != (0, last) && (i, j) != (last, 0) {
+                    self.draw_alignment(x, y);
+                }
+            }
+        }
+        // reserved until the mask is known
+        self.draw_format_bits(0);
+        self.draw_version();
+    }
+
+    // A finder pattern centered on `x`, `y`, with its light separator.
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4..=4_isize {
+            for dx in -4..=4_isize {
+                let (Some(xx), Some(yy)) = (x.checked_add_signed(dx), y.checked_add_signed(dy))
+                else {
+                    continue;
+                };
+                if xx < self.size && yy < self.size {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(xx, yy, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2..=2_isize {
+            for dx in -2..=2_isize {
+                self.set_function(
+                    x.wrapping_add_signed(dx),
+                    y.wrapping_add_signed(dy),
+                    dx.abs().max(dy.abs()) != 1,
+                );
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u8) {
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        let size = self.size;
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        // always dark
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+        let bits = version_bits(self.version);
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 == 1;
+            let (a, b) = (self.size - 11 + i % 3, i / 3);
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    // Fills the non-function modules in two-column strips, zigzagging up
+    // and down from the bottom right.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let bits = codewords.len() * 8;
+        let mut i = 0;
+        let mut right = self.size - 1;
+        loop {
+            // skips the vertical timing pattern
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..self.size {
+                let y = if upward {
+                    self.size - 1 - vertical
+                } else {
+                    vertical
+                };
+                for x in [right, right - 1] {
+                    if !self.function[y * self.size + x] && i < bits {
+                        self.modules[y * self.size + x] =
+                            (codewords[i / 8] >> (7 - i % 8)) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 3 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    // XORs `mask` onto the non-function modules; applied again, it undoes
+    // itself.
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let flip = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if flip && !self.function[y * self.size + x] {
+                    self.modules[y * self.size + x] ^= true;
+                }
+            }
+        }
+    }
+
+    // The standard's penalty for long runs, 2x2 blocks, finder look-alikes
+    // and an uneven share of dark modules.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+        for transposed in [false, true] {
+            let at = |line: usize, i: usize| {
+                if transposed {
+                    self.get(line, i)
+                } else {
+                    self.get(i, line)
+                }
+            };
+            for line in 0..size {
+                let mut run = 1;
+                for i in 1..size {
+                    if at(line, i) == at(line, i - 1) {
+                        run += 1;
+                    } else {
+                        run = 1;
+                    }
+                    if run == 5 {
+                        penalty += 3;
+                    } else if run > 5 {
+                        penalty += 1;
+                    }
+                }
+                // dark-light-dark-dark-dark-light-dark with 4 light on a side
+                let dark = |i: isize| usize::try_from(i).is_ok_and(|i| i < size && at(line, i));
+                for start in 0..(size - 6) as isize {
+                    let finder = (0..7).all(|k| dark(start + k) == (k != 1 && k != 5));
+                    let light = |from: isize| (from..from + 4).all(|i| !dark(i));
+                    if finder && (light(start - 4) || light(start + 7)) {
+                        penalty += 40;
+                    }
+                }
+            }
+        }
+        for y in 1..size {
+            for x in 1..size {
+                let dark = self.get(x, y);
+                if dark == self.get(x - 1, y)
+                    && dark == self.get(x, y - 1)
+                    && dark == self.get(x - 1, y - 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+        let total = size * size;
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        // 10 per 5% away from half dark
+        let k = (dark * 20)
+            .abs_diff(total * 10)
+            .div_ceil(total)
+            .saturating_sub(1);
+        penalty + k * 10
+    }
+}
+
+// The centers of alignment patterns along either axis.
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let count = version / 7 + 2;
+    let step = (version * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let size = version * 4 + 17;
+    let mut positions: Vec<usize> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+// Level M and `mask`, BCH-coded and masked as the standard has it.
+fn format_bits(mask: u8) -> u32 {
+    // level M is 0b00
+    let data = u32::from(mask);
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    ((data << 10) | remainder) ^ 0x5412
+}
+
+fn version_bits(version: usize) -> u32 {
+    let version = version as u32;
+    let mut remainder = version;
+    for _ in 0..12 {
+        remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+    }
+    (version << 12) | remainder
+}
+
+// Modules left for data and error correction once function patterns are
+// drawn.
+fn raw_data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version] * ECC_BLOCKS[version]
+}
+
+// The bits `length` bytes take in byte mode, with the mode and count.
+fn data_bits(version: usize, length: usize) -> usize {
+    let count_bits = if version <= 9 { 8 } else { 16 };
+    4 + count_bits + length * 8
+}
+
+// `data` in byte mode, terminated and padded to the version's capacity.
+fn data_codewords_for(version: usize, data: &[u8]) -> Vec<u8> {
+    let capacity = data_codewords(version);
+    let mut bits = Bits::default();
+    bits.push(0b0100, 4);
+    bits.push(data.len() as u32, if version <= 9 { 8 } else { 16 });
+    for &byte in data {
+        bits.push(u32::from(byte), 8);
+    }
+    let terminator = (capacity * 8 - bits.len).min(4);
+    bits.push(0, terminator);
+    bits.push(0, (8 - bits.len % 8) % 8);
+    let mut codewords = bits.bytes;
+    for pad in [0xec, 0x11].into_iter().cycle() {
+        if codewords.len() >= capacity {
+            break;
+        }
+        codewords.push(pad);
+    }
+    codewords
+}
+
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bits {
+    fn push(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                self.bytes[self.len / 8] |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+// Splits `data` into the version's blocks, appends each block's error
+// correction and interleaves them all, as they are laid out in the code.
+fn add_ecc_and_interleave(version: usize, data: &[u8]) -> Vec<u8> {
+    let blocks = ECC_BLOCKS[version];
+    let ecc_length = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = raw_data_modules(version) / 8;
+    // the short blocks come first, the rest have one more data codeword
+    let short_blocks = blocks - raw_codewords % blocks;
+    let short_length = raw_codewords / blocks;
+    let divisor = reed_solomon_divisor(ecc_length);
+
+    let mut padded = Vec::with_capacity(blocks);
+    let mut start = 0;
+    for i in 0..blocks {
+        let length = short_length - ecc_length + usize::from(i >= short_blocks);
+        let mut block = data[start..start + length].to_vec();
+        start += length;
+        let ecc = reed_solomon_remainder(&block, &divisor);
+        if i < short_blocks {
+            block.push(0);
+        }
+        block.extend_from_slice(&ecc);
+        padded.push(block);
+    }
+    let mut codewords = Vec::with_capacity(raw_codewords);
+    for i in 0..=short_length {
+        for (j, block) in padded.iter().enumerate() {
+            // skips the padding of short blocks
+            if i != short_length - ecc_length || j >= short_blocks {
+                codewords.push(block[i]);
+            }
+        }
+    }
+    codewords
+}
+
+// The generator polynomial of `degree`, highest coefficient first and
+// the leading 1 left out.
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0; degree];
+    divisor[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_multiply(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    divisor
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut remainder = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+        for (r, &d) in remainder.iter_mut().zip(divisor) {
+            *r ^= gf_multiply(d, factor);
+        }
+    }
+    remainder
+}
+
+// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u8 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x1d);
+        z ^= ((y >> i) & 1) * x;
+    }
+    z
+}
+
+fn write_chunk(mut writer: impl Write, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    let length = u32::try_from(data.len())
+        .map_err(|_| UrlManagerError::InvalidLink("image too large".to_string()))?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(&[&kind[..], data].concat());
+    writer.write_all(&crc.to_be_bytes())?;
+    Ok(())
+}
+
+// The CRC-32 of PNG chunks, reflected with the polynomial 0xedb88320.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+// A zlib stream of `data` in uncompressed blocks; codes are small, and
+// this keeps the crate free of a deflate implementation.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        stream.push(u8::from(blocks.peek().is_none()));
+        let length = block.len() as u16;
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    stream.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    stream
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reed_solomon() {
+        // "HELLO WORLD" at 1-M, from the worked example of the standard
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            reed_solomon_remainder(&data, &reed_solomon_divisor(10)),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+    }
+
+    #[test]
+    fn test_format_and_version_bits() {
+        assert_eq!(format_bits(0), 0b101010000010010);
+        assert_eq!(format_bits(7), 0b100101010100000);
+        assert_eq!(version_bits(7), 0b000111110010010100);
+        assert_eq!(alignment_positions(7), [6, 22, 38]);
+        assert_eq!(alignment_positions(32), [6, 34, 60, 86, 112, 138]);
+        // every version's codewords fill its modules but the remainder bits
+        for version in 1..=40 {
+            let total =
+                data_codewords(version) + ECC_CODEWORDS_PER_BLOCK[version] * ECC_BLOCKS[version];
+            assert_eq!(total, raw_data_modules(version) / 8, "version {version}");
+        }
+    }
+
+    // Reads the data codewords back, the mask taken from the format bits.
+    fn read(code: &QrCode) -> Vec<u8> {
+        let mut format = 0;
+        for i in (0..6).rev() {
+            format = (format << 1) | u32::from(code.is_dark(8, i));
+        }
+        let mut bits = Vec::new();
+        for i in [7, 8] {
+            bits.push(code.is_dark(8, i));
+        }
+        bits.push(code.is_dark(7, 8));
+        for i in 9..15 {
+            bits.push(code.is_dark(14 - i, 8));
+        }
+        for (i, &bit) in bits.iter().enumerate() {
+            format |= u32::from(bit) << (i + 6);
+        }
+        let mask = ((format ^ 0x5412) >> 10) as u8;
+        assert_eq!(format_bits(mask), format, "format bits");
+
+        let mut builder = Builder::new(code.version);
+        builder.draw_function_patterns();
+        builder.modules.clone_from(&code.modules);
+        builder.apply_mask(mask);
+        let length = raw_data_modules(code.version) / 8;
+        let mut codewords = vec![0; length];
+        let mut i = 0;
+        for (x, y) in strips(code.size) {
+            if !builder.function[y * code.size + x] && i < length * 8 {
+                if builder.get(x, y) {
+                    codewords[i / 8] |= 0x80 >> (i % 8);
+                }
+                i += 1;
+            }
+        }
+        codewords
+    }
+
+    // The modules in the order `draw_codewords` visits them.
+    fn strips(size: usize) -> Vec<(usize, usize)> {
+        let mut modules = Vec::new();
+        let mut right = size - 1;
+        loop {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                let y = if upward {
+                    size - 1 - vertical
+                } else {
+                    vertical
+                };
+                modules.push((right, y));
+                modules.push((right - 1, y));
+            }
+            if right < 3 {
+                break;
+            }
+            right -= 2;
+        }
+        modules
+    }
+
+    #[test]
+    fn test_encode() {
+        let url = b"https://sho.rt/docs";
+        let code = QrCode::encode(url).unwrap();
+        assert_eq!((code.version(), code.size()), (2, 25));
+        // finder patterns in three corners, the dark module by the bottom one
+        for (x, y) in [(0, 0), (24, 0), (0, 24), (3, 3), (21, 3), (3, 21), (8, 17)] {
+            assert!(code.is_dark(x, y), "({x}, {y})");
+        }
+        for (x, y) in [(7, 7), (17, 7), (7, 17), (1, 1), (25, 0)] {
+            assert!(!code.is_dark(x, y), "({x}, {y})");
+        }
+
+        // one block at 2-M, so the data codewords come first
+        let codewords = read(&code);
+        let expected = data_codewords_for(2, url);
+        assert_eq!(codewords[..expected.len()], expected);
+        assert_eq!(codewords, add_ecc_and_interleave(2, &expected));
+        // byte mode, 19 bytes
+        assert_eq!(expected[..2], [0x41, 0x36]);
+
+        let long = vec![b'a'; 2331];
+        assert_eq!(QrCode::encode(&long).unwrap().version(), 40);
+        assert!(QrCode::encode(&[b'a'; 2332]).is_err());
+    }
+
+    #[test]
+    fn test_interleave() {
+        // 5-M: two blocks of 43 data and 24 error correction codewords
+        let data: Vec<u8> = (0..86).collect();
+        let codewords = add_ecc_and_interleave(5, &data);
+        assert_eq!(codewords.len(), 134);
+        assert_eq!(codewords[..4], [0, 43, 1, 44]);
+        let ecc = reed_solomon_remainder(&data[43..], &reed_solomon_divisor(24));
+        assert_eq!(codewords[87], ecc[0]);
+    }
+
+    #[test]
+    fn test_link_qr_code() {
+        let base_url = UrlType::parse("https://sho.rt/go/").unwrap();
+        let link = Link::builder()
+            .target("https://example.com/docs")
+            .slug("docs")
+            .build()
+            .unwrap();
+        let code = link.qr_code(&base_url).unwrap();
+        assert_eq!(code, QrCode::encode(b"https://sho.rt/go/docs").unwrap());
+
+        let mut svg = Vec::new();
+        code.write_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains(r#"viewBox="0 0 33 33""#), "{svg}");
+        assert!(svg.contains("M4,4h1v1h-1z"), "{svg}");
+
+        let mut png = Vec::new();
+        code.write_png(&mut png, 2).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x42\0\0\0\x42"));
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+
+        assert!(Link::default().qr_code(&base_url).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{sync, Result, UrlManagerError};
+
+// Above this many clients, full buckets are dropped before adding another.
+const MAX_IDLE_BUCKETS: usize = 4096;
+
+/// One client's tokens.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn full(burst: u32, now: Instant) -> Self {
+        TokenBucket {
+            tokens: f64::from(burst),
+            last: now,
+        }
+    }
+
+    /// Refills for the time since the last call and takes a token, or
+    /// fails with how long until the next one.
+    pub(crate) fn take(&mut self, burst: u32, refill: Duration, now: Instant) -> Result<()> {
+        let elapsed = now.saturating_duration_since(self.last);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() / refill.as_secs_f64()).min(f64::from(burst));
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(UrlManagerError::RateLimited(
+                refill.mul_f64(1.0 - self.tokens),
+            ))
+        }
+    }
+
+    fn is_full(&self, burst: u32, refill: Duration, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last);
+        self.tokens + elapsed.as_secs_f64() / refill.as_secs_f64() >= f64::from(burst)
+    }
+}
+
+/// A token-bucket rate limiter keyed by client, e.g. by IP address or API key.
+///
+/// Every client starts with `burst` tokens and regains one each `refill`
+/// interval, up to `burst` again. Each request takes a token; without one
+/// it fails with `RateLimited`, carrying the time until the next token.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use url_manager::{RateLimiter, UrlManagerError};
+/// let limiter = RateLimiter::new(2, Duration::from_secs(10))?;
+/// assert!(limiter.check("203.0.113.7").is_ok());
+/// assert!(limiter.check("203.0.113.7").is_ok());
+/// assert!(matches!(limiter.check("203.0.113.7"), Err(UrlManagerError::RateLimited(_))));
+/// assert!(limiter.check("198.51.100.1").is_ok());
+/// # Ok::<(), UrlManagerError>(())
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter {
+    burst: u32,
+    refill: Duration,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Allows `burst` requests at once and one more every `refill`.
+    pub fn new(burst: u32, refill: Duration) -> Result<Self> {
+        if burst == 0 || refill.is_zero() {
+            return Err(UrlManagerError::InvalidConfig(
+                "rate limits need a burst and a refill interval above zero".to_string(),
+            ));
+        }
+        Ok(RateLimiter {
+            burst,
+            refill,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Allows `requests` per minute on average, all of them at once at most.
+    pub fn per_minute(requests: u32) -> Result<Self> {
+        Self::new(requests, Duration::from_secs(60) / requests.max(1))
+    }
+
+    /// Takes a token for `client`.
+    pub fn check(&self, client: &str) -> Result<()> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: &str, now: Instant) -> Result<()> {
+        let mut buckets = sync::lock(&self.buckets);
+        if !buckets.contains_key(client) && buckets.len() >= MAX_IDLE_BUCKETS {
+            buckets.retain(|_, bucket| !bucket.is_full(self.burst, self.refill, now));
+        }
+        buckets
+            .entry(client.to_string())
+            .or_insert_with(|| TokenBucket::full(self.burst, now))
+            .take(self.burst, self.refill, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(1)).unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.check_at("a", start).unwrap();
+        }
+        let Err(UrlManagerError::RateLimited(wait)) = limiter.check_at("a", start) else {
+            panic!("fourth request should be limited");
+        };
+        assert_eq!(wait, Duration::from_secs(1));
+
+        let later = start + Duration::from_millis(1500);
+        limiter.check_at("a", later).unwrap();
+        assert!(limiter.check_at("a", later).is_err());
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            limiter.check_at("a", much_later).unwrap();
+        }
+        assert!(limiter.check_at("a", much_later).is_err());
+        assert!(limiter.check_at("b", much_later).is_ok());
+    }
+
+    #[test]
+    fn test_config() {
+        assert!(RateLimiter::new(0, Duration::from_secs(1)).is_err());
+        assert!(RateLimiter::new(1, Duration::ZERO).is_err());
+        let limiter = RateLimiter::per_minute(30).unwrap();
+        assert_eq!(limiter.refill, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_drops_idle_buckets() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(1)).unwrap();
+        let start = Instant::now();
+        for client in 0..MAX_IDLE_BUCKETS {
+            limiter.check_at(&client.to_string(), start).unwrap();
+        }
+        limiter
+            .check_at("new", start + Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(sync::lock(&limiter.buckets).len(), 1);
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Result, UrlManagerError};
+
+/// The HTTP status a link redirects with, see [`Link::redirect_status`](crate::Link::redirect_status).
+///
+/// Permanent redirects may be cached by browsers and CDNs, so the target
+/// of a link using one shouldn't change; temporary ones are followed anew
+/// every time, which also keeps every hit counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RedirectStatus {
+    /// 301, permanent; clients may turn a POST into a GET.
+    MovedPermanently,
+    /// 302, temporary; clients may turn a POST into a GET.
+    #[default]
+    Found,
+    /// 307, temporary, keeping the request method.
+    TemporaryRedirect,
+    /// 308, permanent, keeping the request method.
+    PermanentRedirect,
+}
+
+impl RedirectStatus {
+    pub fn code(&self) -> u16 {
+        match self {
+            RedirectStatus::MovedPermanently => 301,
+            RedirectStatus::Found => 302,
+            RedirectStatus::TemporaryRedirect => 307,
+            RedirectStatus::PermanentRedirect => 308,
+        }
+    }
+
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            RedirectStatus::MovedPermanently | RedirectStatus::PermanentRedirect
+        )
+    }
+}
+
+impl TryFrom<u16> for RedirectStatus {
+    type Error = UrlManagerError;
+
+    fn try_from(code: u16) -> Result<Self> {
+        match code {
+            301 => Ok(RedirectStatus::MovedPermanently),
+            302 => Ok(RedirectStatus::Found),
+            307 => Ok(RedirectStatus::TemporaryRedirect),
+            308 => Ok(RedirectStatus::PermanentRedirect),
+            _ => Err(UrlManagerError::InvalidConfig(format!(
+                "{code} is not a redirect status"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for RedirectStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl FromStr for RedirectStatus {
+    type Err = UrlManagerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<u16>()
+            .map_err(|_| UrlManagerError::InvalidConfig(format!("{s} is not a redirect status")))?
+            .try_into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        for code in [301, 302, 307, 308] {
+            let status = RedirectStatus::try_from(code).unwrap();
+            assert_eq!(status.code(), code);
+            assert_eq!(
+                status.to_string().parse::<RedirectStatus>().unwrap(),
+                status
+            );
+            assert_eq!(status.is_permanent(), code == 301 || code == 308);
+        }
+        assert!(RedirectStatus::try_from(200).is_err());
+        assert!("moved".parse::<RedirectStatus>().is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::json::Value;
+use crate::{HitMetadata, Result, UrlManagerError, UrlType};
+
+/// A mobile platform, as told by a user agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Platform {
+    Ios,
+    Android,
+}
+
+impl Platform {
+    /// The platform `user_agent` runs on, if it is one of these.
+    pub fn detect(user_agent: &str) -> Option<Platform> {
+        if user_agent.contains("Android") {
+            Some(Platform::Android)
+        } else if ["iPhone", "iPad", "iPod"]
+            .iter()
+            .any(|device| user_agent.contains(device))
+        {
+            Some(Platform::Ios)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Platform::Ios => "ios",
+            Platform::Android => "android",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = UrlManagerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ios" => Ok(Platform::Ios),
+            "android" => Ok(Platform::Android),
+            _ => Err(UrlManagerError::InvalidLink(format!(
+                "unknown platform '{s}'"
+            ))),
+        }
+    }
+}
+
+/// Sends the visitors a condition matches to their own target, e.g. iOS
+/// users to the App Store, see [`Link::rules`](crate::Link::rules).
+///
+/// Every condition that is set must match; a rule without conditions
+/// matches everyone.
+///
+/// ```
+/// # use url_manager::{Link, Platform, RedirectRule};
+/// let link = Link::builder()
+///     .target("https://example.com/app")
+///     .rule(
+///         RedirectRule::new("https://apps.apple.com/app/id123".parse()?)
+///             .platform(Platform::Ios),
+///     )
+///     .rule(RedirectRule::new("https://example.com/de/app".parse()?).countries(["DE", "AT"]))
+///     .build()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectRule {
+    target: UrlType,
+    platform: Option<Platform>,
+    countries: Vec<String>,
+}
+
+impl RedirectRule {
+    pub fn new(target: UrlType) -> Self {
+        RedirectRule {
+            target,
+            platform: None,
+            countries: Vec::new(),
+        }
+    }
+
+    /// Only visitors on `platform`.
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.platform = Some(platform);
+        self
+    }
+
+    /// Only visitors from one of `countries`, as ISO 3166 alpha-2 codes
+    /// compared case-insensitively.
+    pub fn countries<I>(mut self, countries: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.countries = countries
+            .into_iter()
+            .map(|country| country.into().to_ascii_uppercase())
+            .collect();
+        self
+    }
+
+    pub fn target(&self) -> &UrlType {
+        &self.target
+    }
+
+    pub fn get_platform(&self) -> Option<Platform> {
+        self.platform
+    }
+
+    pub fn get_countries(&self) -> &[String] {
+        &self.countries
+    }
+
+    pub fn matches(&self, metadata: &HitMetadata) -> bool {
+        let platform = self.platform.is_none_or(|platform| {
+            metadata.user_agent.as_deref().and_then(Platform::detect) == Some(platform)
+        });
+        let country = self.countries.is_empty()
+            || metadata.country.as_deref().is_some_and(|country| {
+                self.countries
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(country))
+            });
+        platform && country
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        Value::object([
+            ("target", Value::from(self.target.as_str())),
+            ("platform", Value::from(self.platform.map(|p| p.as_str()))),
+            (
+                "countries",
+                Value::from(
+                    self.countries
+                        .iter()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<RedirectRule> {
+        let target = value
+            .get("target")
+            .and_then(Value::as_str)
+            .ok_or_else(|| UrlManagerError::InvalidLink("rule without target".to_string()))?;
+        let platform = match value.get("platform") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(platform)) => Some(platform.parse()?),
+            Some(_) => {
+                return Err(UrlManagerError::InvalidLink(
+                    "rule 'platform' is not a string".to_string(),
+                ))
+            }
+        };
+        let countries = match value.get("countries") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(countries)) => countries
+                .iter()
+                .map(|country| {
+                    country.as_str().map(str::to_string).ok_or_else(|| {
+                        UrlManagerError::InvalidLink(
+                            "rule 'countries' holds a non-string".to_string(),
+                        )
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Some(_) => {
+                return Err(UrlManagerError::InvalidLink(
+                    "rule 'countries' is not an array".to_string(),
+                ))
+            }
+        };
+        let rule = RedirectRule {
+            target: UrlType::parse(target)?,
+            platform,
+            countries: Vec::new(),
+        };
+        Ok(rule.countries(countries))
+    }
+}
+
+/// Finds the country of a client address, for [`RedirectRule::countries`].
+///
+/// There is no MaxMind implementation yet since the crate doesn't depend on
+/// `maxminddb`; a closure around a reader can be used meanwhile.
+pub trait GeoIp {
+    /// The ISO 3166 alpha-2 code of the country `addr` is in, if known.
+    fn country(&self, addr: IpAddr) -> Option<String>;
+}
+
+impl<F: Fn(IpAddr) -> Option<String>> GeoIp for F {
+    fn country(&self, addr: IpAddr) -> Option<String> {
+        self(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IPHONE: &str =
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15";
+    const ANDROID: &str = "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36";
+
+    fn visitor(user_agent: &str, country: Option<&str>) -> HitMetadata {
+        HitMetadata {
+            user_agent: Some(user_agent.to_string()),
+            country: country.map(str::to_string),
+            ..HitMetadata::default()
+        }
+    }
+
+    #[test]
+    fn test_platform() {
+        assert_eq!(Platform::detect(IPHONE), Some(Platform::Ios));
+        assert_eq!(Platform::detect(ANDROID), Some(Platform::Android));
+        assert_eq!(Platform::detect("curl/8.0"), None);
+        assert_eq!("ios".parse::<Platform>().unwrap(), Platform::Ios);
+        assert!("windows".parse::<Platform>().is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let target = UrlType::parse("https://example.com/de").unwrap();
+        let german_ios = RedirectRule::new(target.clone())
+            .platform(Platform::Ios)
+            .countries(["de", "AT"]);
+        assert!(german_ios.matches(&visitor(IPHONE, Some("DE"))));
+        assert!(german_ios.matches(&visitor(IPHONE, Some("at"))));
+        assert!(!german_ios.matches(&visitor(IPHONE, Some("FR"))));
+        assert!(!german_ios.matches(&visitor(IPHONE, None)));
+        assert!(!german_ios.matches(&visitor(ANDROID, Some("DE"))));
+        assert!(RedirectRule::new(target).matches(&HitMetadata::default()));
+
+        let loaded = RedirectRule::from_json(&german_ios.to_json()).unwrap();
+        assert_eq!(loaded, german_ios);
+    }
+}
@@ -0,0 +1,454 @@
+//! Checking targets against phishing and malware lists before links to
+//! them are stored.
+//!
+//! A [`TargetScanner`] rates a target; [`ScanningStore`] wraps an
+//! [`AsyncLinkStore`] and scans every link it creates or updates, keeping
+//! the [`Verdict`] on the link for later audit. With the `safe-browsing`
+//! feature, `SafeBrowsing` asks Google's Safe Browsing Lookup API.
+
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+
+use crate::{AsyncLinkStore, Link, Namespace, Result, UrlManagerError, UrlType};
+
+/// How a [`TargetScanner`] rated a target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Safe,
+    /// Doubtful but not known to be harmful, e.g. unwanted software. The
+    /// link is created and the reason kept with it.
+    Suspicious(String),
+    /// Known phishing or malware. [`ScanningStore`] refuses the link unless
+    /// it is set to [`OnUnsafe::Flag`].
+    Unsafe(String),
+}
+
+impl Verdict {
+    pub fn is_safe(&self) -> bool {
+        *self == Verdict::Safe
+    }
+}
+
+/// Formats as `safe`, `suspicious: <reason>` or `unsafe: <reason>`, which
+/// is also how the verdict is stored.
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Safe => write!(f, "safe"),
+            Verdict::Suspicious(reason) => write!(f, "suspicious: {reason}"),
+            Verdict::Unsafe(reason) => write!(f, "unsafe: {reason}"),
+        }
+    }
+}
+
+impl FromStr for Verdict {
+    type Err = UrlManagerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, reason) = s.split_once(':').unwrap_or((s, ""));
+        let reason = reason.trim().to_string();
+        match kind.trim() {
+            "safe" => Ok(Verdict::Safe),
+            "suspicious" => Ok(Verdict::Suspicious(reason)),
+            "unsafe" => Ok(Verdict::Unsafe(reason)),
+            _ => Err(UrlManagerError::InvalidLink(format!(
+                "unknown verdict '{s}'"
+            ))),
+        }
+    }
+}
+
+/// Rates link targets, usually by asking an outside service.
+///
+/// Errors mean the target couldn't be rated; [`ScanningStore`] passes them
+/// on rather than storing an unscanned link.
+pub trait TargetScanner {
+    fn scan(&self, target: &UrlType) -> impl Future<Output = Result<Verdict>> + Send;
+}
+
+/// What [`ScanningStore`] does with links whose target is [`Verdict::Unsafe`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnUnsafe {
+    /// Fails with `Forbidden`.
+    #[default]
+    Reject,
+    /// Stores the link with its verdict, for review later.
+    Flag,
+}
+
+/// An [`AsyncLinkStore`] that scans the target of every link created,
+/// upserted or updated through it and records the verdict on the link.
+///
+/// ```
+/// # use url_manager::scan::{ScanningStore, TargetScanner, Verdict};
+/// # use url_manager::{AsyncLinkStore, InMemoryLinkStore, Link, Result, SyncStoreAdapter, UrlType};
+/// struct NoZip;
+///
+/// impl TargetScanner for NoZip {
+///     async fn scan(&self, target: &UrlType) -> Result<Verdict> {
+///         Ok(match target.host_str() {
+///             Some(host) if host.ends_with(".zip") => Verdict::Unsafe("zip domain".into()),
+///             _ => Verdict::Safe,
+///         })
+///     }
+/// }
+///
+/// # async fn run() -> Result<()> {
+/// let mut store = ScanningStore::new(SyncStoreAdapter::new(InMemoryLinkStore::new()), NoZip);
+/// let link = Link::builder().target("https://example.com").build()?;
+/// store.create(link.clone()).await?;
+/// assert_eq!(store.get(link.id()).await?.unwrap().verdict(), Some(&Verdict::Safe));
+///
+/// let link = Link::builder().target("https://invoice.zip").build()?;
+/// assert!(store.create(link).await.is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct ScanningStore<S, T> {
+    inner: S,
+    scanner: T,
+    on_unsafe: OnUnsafe,
+}
+
+impl<S, T> ScanningStore<S, T>
+where
+    S: AsyncLinkStore,
+    T: TargetScanner,
+{
+    pub fn new(inner: S, scanner: T) -> Self {
+        ScanningStore {
+            inner,
+            scanner,
+            on_unsafe: OnUnsafe::default(),
+        }
+    }
+
+    pub fn on_unsafe(mut self, on_unsafe: OnUnsafe) -> Self {
+        self.on_unsafe = on_unsafe;
+        self
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    async fn scan(&self, link: &mut Link) -> Result<()> {
+        let verdict = self.scanner.scan(link.target()).await?;
+        if let (Verdict::Unsafe(reason), OnUnsafe::Reject) = (&verdict, self.on_unsafe) {
+            return Err(UrlManagerError::Forbidden(format!(
+                "'{}' is unsafe: {reason}",
+                link.target()
+            )));
+        }
+        link.set_verdict(verdict);
+        Ok(())
+    }
+}
+
+impl<S, T> AsyncLinkStore for ScanningStore<S, T>
+where
+    S: AsyncLinkStore + Send + Sync,
+    T: TargetScanner + Send + Sync,
+{
+    async fn get(&self, id: u64) -> Result<Option<Link>> {
+        self.inner.get(id).await
+    }
+
+    async fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        self.inner.get_by_shortcut_in(namespace, shortcut).await
+    }
+
+    async fn create(&mut self, mut link: Link) -> Result<()> {
+        self.scan(&mut link).await?;
+        self.inner.create(link).await
+    }
+
+    async fn upsert(&mut self, mut link: Link) -> Result<()> {
+        self.scan(&mut link).await?;
+        self.inner.upsert(link).await
+    }
+
+    async fn update(&mut self, id: u64, mut link: Link) -> Result<()> {
+        self.scan(&mut link).await?;
+        self.inner.update(id, link).await
+    }
+
+    async fn delete(&mut self, id: u64) -> Result<()> {
+        self.inner.delete(id).await
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.inner.list(offset, limit).await
+    }
+
+    async fn count(&self) -> Result<usize> {
+        self.inner.count().await
+    }
+
+    async fn purge_expired(&mut self) -> Result<usize> {
+        self.inner.purge_expired().await
+    }
+}
+
+#[cfg(feature = "safe-browsing")]
+pub use safe_browsing::{HttpPost, SafeBrowsing};
+
+#[cfg(feature = "safe-browsing")]
+mod safe_browsing {
+    use std::future::Future;
+
+    use super::{TargetScanner, Verdict};
+    use crate::json::{self, Value};
+    use crate::{Result, UrlManagerError, UrlType};
+
+    const ENDPOINT: &str = "https://safebrowsing.googleapis.com/v4/threatMatches:find";
+
+    // Threats that make a target unsafe; the rest only make it suspicious.
+    const UNSAFE_THREATS: [&str; 2] = ["MALWARE", "SOCIAL_ENGINEERING"];
+
+    /// Sends a JSON body to a URL and returns the response body.
+    ///
+    /// The crate has no HTTP client of its own, so [`SafeBrowsing`] is
+    /// handed one through this. Non-2xx responses should be errors.
+    pub trait HttpPost {
+        fn post(&self, url: &str, body: String) -> impl Future<Output = Result<String>> + Send;
+    }
+
+    /// A [`TargetScanner`] backed by the Safe Browsing v4 Lookup API
+    /// (`threatMatches:find`).
+    ///
+    /// Malware and phishing matches are [`Verdict::Unsafe`], unwanted and
+    /// potentially harmful software [`Verdict::Suspicious`].
+    #[derive(Debug, Clone)]
+    pub struct SafeBrowsing<H> {
+        api_key: String,
+        client_id: String,
+        client_version: String,
+        http: H,
+    }
+
+    impl<H: HttpPost> SafeBrowsing<H> {
+        pub fn new(api_key: impl Into<String>, http: H) -> Self {
+            SafeBrowsing {
+                api_key: api_key.into(),
+                client_id: env!("CARGO_PKG_NAME").to_string(),
+                client_version: env!("CARGO_PKG_VERSION").to_string(),
+                http,
+            }
+        }
+
+        /// How requests identify the client, the crate's name and version by default.
+        pub fn client(mut self, id: impl Into<String>, version: impl Into<String>) -> Self {
+            self.client_id = id.into();
+            self.client_version = version.into();
+            self
+        }
+
+        fn request_url(&self) -> String {
+            UrlType::parse_with_params(ENDPOINT, [("key", &self.api_key)])
+                .map(String::from)
+                .unwrap_or_else(|_| ENDPOINT.to_string())
+        }
+
+        fn request_body(&self, target: &UrlType) -> String {
+            let strings = |items: &[&str]| Value::from(items.to_vec());
+            Value::object([
+                (
+                    "client",
+                    Value::object([
+                        ("clientId", Value::from(self.client_id.as_str())),
+                        ("clientVersion", Value::from(self.client_version.as_str())),
+                    ]),
+                ),
+                (
+                    "threatInfo",
+                    Value::object([
+                        (
+                            "threatTypes",
+                            strings(&[
+                                "MALWARE",
+                                "SOCIAL_ENGINEERING",
+                                "UNWANTED_SOFTWARE",
+                                "POTENTIALLY_HARMFUL_APPLICATION",
+                            ]),
+                        ),
+                        ("platformTypes", strings(&["ANY_PLATFORM"])),
+                        ("threatEntryTypes", strings(&["URL"])),
+                        (
+                            "threatEntries",
+                            Value::Array(vec![Value::object([(
+                                "url",
+                                Value::from(target.as_str()),
+                            )])]),
+                        ),
+                    ]),
+                ),
+            ])
+            .to_string()
+        }
+    }
+
+    // Reads a `threatMatches:find` response; an empty object means no match.
+    fn verdict(body: &str) -> Result<Verdict> {
+        let body = json::parse(body).map_err(|e| {
+            UrlManagerError::backend(format!("Safe Browsing response is not JSON: {e}"))
+        })?;
+        let mut threats: Vec<&str> = match body.get("matches") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(matches)) => matches
+                .iter()
+                .filter_map(|m| m.get("threatType").and_then(Value::as_str))
+                .collect(),
+            Some(_) => {
+                return Err(UrlManagerError::backend(
+                    "Safe Browsing 'matches' is not an array",
+                ))
+            }
+        };
+        threats.sort_unstable();
+        threats.dedup();
+        let reason = threats.join(", ");
+        Ok(if threats.is_empty() {
+            Verdict::Safe
+        } else if threats.iter().any(|t| UNSAFE_THREATS.contains(t)) {
+            Verdict::Unsafe(reason)
+        } else {
+            Verdict::Suspicious(reason)
+        })
+    }
+
+    impl<H: HttpPost + Sync> TargetScanner for SafeBrowsing<H> {
+        async fn scan(&self, target: &UrlType) -> Result<Verdict> {
+            let response = self
+                .http
+                .post(&self.request_url(), self.request_body(target))
+                .await?;
+            verdict(&response)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::store::block_on;
+
+        struct Canned(&'static str);
+
+        impl HttpPost for Canned {
+            async fn post(&self, url: &str, body: String) -> Result<String> {
+                assert!(url.starts_with(ENDPOINT));
+                assert!(url.ends_with("?key=s3cr%26t"));
+                let body = json::parse(&body).unwrap();
+                assert!(body.get("threatInfo").is_some());
+                Ok(self.0.to_string())
+            }
+        }
+
+        fn scan(response: &'static str) -> Result<Verdict> {
+            let scanner = SafeBrowsing::new("s3cr&t", Canned(response));
+            block_on(scanner.scan(&UrlType::parse("https://example.com").unwrap()))
+        }
+
+        #[test]
+        fn test_safe_browsing() {
+            assert_eq!(scan("{}").unwrap(), Verdict::Safe);
+            assert_eq!(
+                scan(
+                    r#"{"matches": [
+                        {"threatType": "UNWANTED_SOFTWARE", "platformType": "ANY_PLATFORM"},
+                        {"threatType": "SOCIAL_ENGINEERING", "platformType": "WINDOWS"},
+                        {"threatType": "SOCIAL_ENGINEERING", "platformType": "LINUX"}
+                    ]}"#
+                )
+                .unwrap(),
+                Verdict::Unsafe("SOCIAL_ENGINEERING, UNWANTED_SOFTWARE".to_string())
+            );
+            assert_eq!(
+                scan(r#"{"matches": [{"threatType": "POTENTIALLY_HARMFUL_APPLICATION"}]}"#)
+                    .unwrap(),
+                Verdict::Suspicious("POTENTIALLY_HARMFUL_APPLICATION".to_string())
+            );
+            assert!(matches!(
+                scan("<html>"),
+                Err(UrlManagerError::StorageBackend(_))
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::block_on;
+    use crate::{InMemoryLinkStore, LinkStore, SyncStoreAdapter};
+
+    // Rates targets by their first path segment.
+    struct ByPath;
+
+    impl TargetScanner for ByPath {
+        async fn scan(&self, target: &UrlType) -> Result<Verdict> {
+            match target.path() {
+                "/phish" => Ok(Verdict::Unsafe("phishing".to_string())),
+                "/odd" => Ok(Verdict::Suspicious("odd".to_string())),
+                "/down" => Err(UrlManagerError::backend("scanner down")),
+                _ => Ok(Verdict::Safe),
+            }
+        }
+    }
+
+    fn link(path: &str) -> Link {
+        Link::builder()
+            .target(format!("https://example.com{path}").as_str())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_scanning_store() {
+        let mut store = ScanningStore::new(SyncStoreAdapter::new(InMemoryLinkStore::new()), ByPath);
+        let odd = link("/odd");
+        block_on(store.create(odd.clone())).unwrap();
+        assert_eq!(
+            block_on(store.get(odd.id())).unwrap().unwrap().verdict(),
+            Some(&Verdict::Suspicious("odd".to_string()))
+        );
+        assert!(matches!(
+            block_on(store.create(link("/phish"))),
+            Err(UrlManagerError::Forbidden(_))
+        ));
+        assert!(matches!(
+            block_on(store.update(odd.id(), link("/down"))),
+            Err(UrlManagerError::StorageBackend(_))
+        ));
+        assert_eq!(block_on(store.count()).unwrap(), 1);
+
+        let mut store = store.on_unsafe(OnUnsafe::Flag);
+        let phish = link("/phish");
+        block_on(store.upsert(phish.clone())).unwrap();
+        let stored = store.get_ref().get_ref().get(phish.id()).unwrap().unwrap();
+        assert!(!stored.verdict().unwrap().is_safe());
+        assert_eq!(stored.verdict().unwrap().to_string(), "unsafe: phishing");
+    }
+
+    #[test]
+    fn test_verdict_round_trip() {
+        for verdict in [
+            Verdict::Safe,
+            Verdict::Suspicious("UNWANTED_SOFTWARE".to_string()),
+            Verdict::Unsafe("MALWARE: test page".to_string()),
+        ] {
+            assert_eq!(verdict.to_string().parse::<Verdict>().unwrap(), verdict);
+        }
+        assert!("fine".parse::<Verdict>().is_err());
+    }
+}
@@ -0,0 +1,367 @@
+//! Serde support, enabled by the `serde` feature.
+//!
+//! Links and URLs are (de)serialized through the same JSON model the file
+//! store uses, so the formats can't drift apart. That model is
+//! self-describing, so formats without `deserialize_any` (e.g. bincode)
+//! can't read it back.
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::fmt;
+
+use crate::json::Value;
+use crate::{Link, Url};
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Number(n) => {
+                if let Ok(n) = n.parse::<u64>() {
+                    serializer.serialize_u64(n)
+                } else if let Ok(n) = n.parse::<i64>() {
+                    serializer.serialize_i64(n)
+                } else {
+                    serializer.serialize_f64(n.parse().unwrap_or_default())
+                }
+            }
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Value::Object(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (key, value) in fields {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a JSON-like value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut fields = Vec::new();
+        while let Some(entry) = map.next_entry()? {
+            fields.push(entry);
+        }
+        Ok(Value::Object(fields))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+impl Serialize for Link {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Link {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Link::from_json(&value).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Url {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Url {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Url::from_json(&value).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{UrlExtension, UrlType};
+    use serde::de::value::{Error, MapDeserializer, SeqDeserializer};
+    use serde::de::IntoDeserializer;
+    use serde::ser::Impossible;
+
+    // Just enough of a self-describing format to round-trip through Value.
+
+    struct ToValue;
+
+    macro_rules! unsupported {
+        ($($name:ident($($arg:ty),*)),*) => {
+            $(fn $name(self, $(_: $arg),*) -> Result<Value, Error> {
+                Err(serde::ser::Error::custom(stringify!($name)))
+            })*
+        };
+    }
+
+    impl Serializer for ToValue {
+        type Ok = Value;
+        type Error = Error;
+        type SerializeSeq = Collect;
+        type SerializeMap = Collect;
+        type SerializeTuple = Impossible<Value, Error>;
+        type SerializeTupleStruct = Impossible<Value, Error>;
+        type SerializeTupleVariant = Impossible<Value, Error>;
+        type SerializeStruct = Impossible<Value, Error>;
+        type SerializeStructVariant = Impossible<Value, Error>;
+
+        fn serialize_unit(self) -> Result<Value, Error> {
+            Ok(Value::Null)
+        }
+        fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+            Ok(Value::from(v))
+        }
+        fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+            Ok(Value::from(v))
+        }
+        fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+            Ok(Value::from(v))
+        }
+        fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+            Ok(Value::from(v))
+        }
+        fn serialize_str(self, v: &str) -> Result<Value, Error> {
+            Ok(Value::from(v))
+        }
+        fn serialize_seq(self, _: Option<usize>) -> Result<Collect, Error> {
+            Ok(Collect::default())
+        }
+        fn serialize_map(self, _: Option<usize>) -> Result<Collect, Error> {
+            Ok(Collect::default())
+        }
+        unsupported!(
+            serialize_i8(i8),
+            serialize_i16(i16),
+            serialize_i32(i32),
+            serialize_u8(u8),
+            serialize_u16(u16),
+            serialize_u32(u32),
+            serialize_f32(f32),
+            serialize_char(char),
+            serialize_bytes(&[u8]),
+            serialize_none(),
+            serialize_unit_struct(&'static str),
+            serialize_unit_variant(&'static str, u32, &'static str)
+        );
+        fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<Value, Error> {
+            Err(serde::ser::Error::custom("serialize_some"))
+        }
+        fn serialize_newtype_struct<T: ?Sized + Serialize>(
+            self,
+            _: &'static str,
+            _: &T,
+        ) -> Result<Value, Error> {
+            Err(serde::ser::Error::custom("serialize_newtype_struct"))
+        }
+        fn serialize_newtype_variant<T: ?Sized + Serialize>(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: &T,
+        ) -> Result<Value, Error> {
+            Err(serde::ser::Error::custom("serialize_newtype_variant"))
+        }
+        fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Error> {
+            Err(serde::ser::Error::custom("serialize_tuple"))
+        }
+        fn serialize_tuple_struct(
+            self,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeTupleStruct, Error> {
+            Err(serde::ser::Error::custom("serialize_tuple_struct"))
+        }
+        fn serialize_tuple_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeTupleVariant, Error> {
+            Err(serde::ser::Error::custom("serialize_tuple_variant"))
+        }
+        fn serialize_struct(
+            self,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeStruct, Error> {
+            Err(serde::ser::Error::custom("serialize_struct"))
+        }
+        fn serialize_struct_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeStructVariant, Error> {
+            Err(serde::ser::Error::custom("serialize_struct_variant"))
+        }
+    }
+
+    #[derive(Default)]
+    struct Collect {
+        items: Vec<Value>,
+        key: Option<String>,
+        fields: Vec<(String, Value)>,
+    }
+
+    impl SerializeSeq for Collect {
+        type Ok = Value;
+        type Error = Error;
+        fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+            self.items.push(value.serialize(ToValue)?);
+            Ok(())
+        }
+        fn end(self) -> Result<Value, Error> {
+            Ok(Value::Array(self.items))
+        }
+    }
+
+    impl SerializeMap for Collect {
+        type Ok = Value;
+        type Error = Error;
+        fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+            self.key = key.serialize(ToValue)?.as_str().map(str::to_string);
+            Ok(())
+        }
+        fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+            let key = self.key.take().unwrap_or_default();
+            self.fields.push((key, value.serialize(ToValue)?));
+            Ok(())
+        }
+        fn end(self) -> Result<Value, Error> {
+            Ok(Value::Object(self.fields))
+        }
+    }
+
+    struct FromValue(Value);
+
+    impl<'de> IntoDeserializer<'de, Error> for FromValue {
+        type Deserializer = Self;
+        fn into_deserializer(self) -> Self {
+            self
+        }
+    }
+
+    impl<'de> Deserializer<'de> for FromValue {
+        type Error = Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self.0 {
+                Value::Null => visitor.visit_unit(),
+                Value::Bool(b) => visitor.visit_bool(b),
+                Value::Number(n) => visitor.visit_u64(n.parse().unwrap()),
+                Value::String(s) => visitor.visit_string(s),
+                Value::Array(items) => {
+                    visitor.visit_seq(SeqDeserializer::new(items.into_iter().map(FromValue)))
+                }
+                Value::Object(fields) => visitor.visit_map(MapDeserializer::new(
+                    fields.into_iter().map(|(k, v)| (k, FromValue(v))),
+                )),
+            }
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map struct enum identifier ignored_any
+        }
+    }
+
+    #[test]
+    fn test_link_round_trip() {
+        let link = Link::builder()
+            .id(7)
+            .target(UrlType::parse("https://www.example.com/docs").unwrap())
+            .shortcut("docs")
+            .build()
+            .unwrap();
+
+        let value = link.serialize(ToValue).unwrap();
+        assert_eq!(value.get("shortcut").and_then(Value::as_str), Some("docs"));
+        let loaded = Link::deserialize(FromValue(value)).unwrap();
+
+        assert_eq!(loaded.id(), 7);
+        assert_eq!(loaded.target(), link.target());
+        assert_eq!(loaded.shortcut(), Some("docs"));
+
+        let broken = Value::object([("id", Value::from("seven"))]);
+        assert!(Link::deserialize(FromValue(broken)).is_err());
+    }
+
+    #[test]
+    fn test_url_round_trip() {
+        let mut url = Url::parse("https://www.example.com/a").unwrap();
+        url.shorten().unwrap();
+
+        let value = url.serialize(ToValue).unwrap();
+        let loaded = Url::deserialize(FromValue(value)).unwrap();
+        assert_eq!(loaded, url);
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+
+use super::Request;
+use crate::crypto::{base64_encode, sha256, URL_SAFE};
+use crate::ratelimit::TokenBucket;
+use crate::{sync, Result, UrlManagerError};
+
+/// What an [`ApiKey`] may do. Each scope includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Scope {
+    /// Reading links through the API.
+    ReadOnly,
+    /// Reading and creating links, and deleting those of the key's owner,
+    /// see [`ApiKey::owner_id`].
+    Create,
+    /// Everything, including deleting any link.
+    Admin,
+}
+
+impl FromStr for Scope {
+    type Err = UrlManagerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "read-only" | "readonly" | "read" => Ok(Scope::ReadOnly),
+            "create" => Ok(Scope::Create),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(UrlManagerError::InvalidConfig(format!(
+                "unknown scope '{s}', expected read-only, create or admin"
+            ))),
+        }
+    }
+}
+
+/// The holder of an API key and what it is allowed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    name: String,
+    scope: Scope,
+    requests_per_minute: Option<u32>,
+    owner_id: Option<u64>,
+}
+
+impl ApiKey {
+    pub fn new(name: impl Into<String>, scope: Scope) -> Self {
+        ApiKey {
+            name: name.into(),
+            scope,
+            requests_per_minute: None,
+            owner_id: None,
+        }
+    }
+
+    /// Lets the key make `requests` API calls per minute on average, all
+    /// of them at once at most.
+    pub fn rate_limit(mut self, requests: u32) -> Self {
+        self.requests_per_minute = Some(requests);
+        self
+    }
+
+    /// Makes the key act for the user `owner_id`: links it creates are
+    /// theirs, and unless it has the [`Scope::Admin`] scope it only sees
+    /// and deletes their links, the latter with just [`Scope::Create`].
+    pub fn owner_id(mut self, owner_id: u64) -> Self {
+        self.owner_id = Some(owner_id);
+        self
+    }
+
+    /// Who the key was issued to, for logs.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn scope(&self) -> Scope {
+        self.scope
+    }
+
+    pub fn requests_per_minute(&self) -> Option<u32> {
+        self.requests_per_minute
+    }
+
+    pub fn get_owner_id(&self) -> Option<u64> {
+        self.owner_id
+    }
+
+    /// Fails with `Forbidden` if the key has a narrower scope than `scope`.
+    pub(crate) fn require(&self, scope: Scope) -> Result<()> {
+        if self.scope < scope {
+            return Err(UrlManagerError::Forbidden(format!(
+                "key '{}' lacks the {scope:?} scope",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// The user whose links alone the key may see and delete, if it is
+    /// limited to one.
+    pub(crate) fn restricted_to(&self) -> Option<u64> {
+        self.owner_id.filter(|_| self.scope < Scope::Admin)
+    }
+}
+
+/// The API keys a [`Server`](super::Server) accepts.
+///
+/// Only SHA-256 hashes of the keys are held, so a dump of the store
+/// doesn't leak usable keys. Requests carry their key as
+/// `Authorization: Bearer <key>` or in an `X-API-Key` header.
+#[derive(Debug, Default)]
+pub struct ApiKeyStore {
+    keys: RwLock<HashMap<[u8; 32], ApiKey>>,
+    buckets: Mutex<HashMap<[u8; 32], TokenBucket>>,
+}
+
+impl ApiKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts `key`, e.g. one read from configuration, for `api_key`.
+    pub fn insert(&self, key: &str, api_key: ApiKey) {
+        sync::write(&self.keys).insert(sha256(key.as_bytes()), api_key);
+    }
+
+    /// Issues a new random key for `api_key` and returns it; it can't be
+    /// read back later.
+    pub fn generate(&self, api_key: ApiKey) -> String {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let key = base64_encode(&bytes, URL_SAFE);
+        self.insert(&key, api_key);
+        key
+    }
+
+    /// Stops accepting `key`, returning whether it was known.
+    pub fn revoke(&self, key: &str) -> bool {
+        let hash = sha256(key.as_bytes());
+        sync::lock(&self.buckets).remove(&hash);
+        sync::write(&self.keys).remove(&hash).is_some()
+    }
+
+    /// The holder of `key` if it is known and has at least `scope`.
+    ///
+    /// Fails with `Unauthorized` for missing or unknown keys and with
+    /// `Forbidden` for keys with a narrower scope.
+    pub fn authorize(&self, key: Option<&str>, scope: Scope) -> Result<ApiKey> {
+        let key = key.ok_or_else(|| UrlManagerError::Unauthorized("API key required".into()))?;
+        let api_key = self
+            .get(key)
+            .ok_or_else(|| UrlManagerError::Unauthorized("unknown API key".into()))?;
+        api_key.require(scope)?;
+        Ok(api_key)
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<ApiKey> {
+        sync::read(&self.keys).get(&sha256(key.as_bytes())).cloned()
+    }
+
+    /// Counts a call made with `key`, failing with `RateLimited` if the
+    /// key has used up its rate limit.
+    pub(crate) fn take_request(&self, key: &str, api_key: &ApiKey) -> Result<()> {
+        let Some(limit) = api_key.requests_per_minute.filter(|&limit| limit > 0) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        sync::lock(&self.buckets)
+            .entry(sha256(key.as_bytes()))
+            .or_insert_with(|| TokenBucket::full(limit, now))
+            .take(limit, Duration::from_secs(60) / limit, now)
+    }
+}
+
+/// Checks bearer tokens issued by someone else, e.g. an OpenID Connect
+/// provider, for a [`Server`](super::Server) to accept alongside or instead
+/// of its [`ApiKeyStore`].
+///
+/// The verifier turns a valid token into the [`ApiKey`] it stands for,
+/// naming the user and granting a scope, typically from the token's claims,
+/// and fails with `Unauthorized` for tokens it doesn't accept. There is no
+/// built-in OIDC verifier since the crate has no JWT or HTTP client; one
+/// built on e.g. `openidconnect` plugs in here:
+///
+/// ```
+/// # use url_manager::server::{ApiKey, Scope, TokenVerifier};
+/// # use url_manager::{Result, UrlManagerError};
+/// let verifier = |token: &str| -> Result<ApiKey> {
+///     // look up the token at the provider's introspection endpoint …
+///     match token {
+///         "alice-token" => Ok(ApiKey::new("alice@example.com", Scope::Create).owner_id(1)),
+///         _ => Err(UrlManagerError::Unauthorized("invalid token".into())),
+///     }
+/// };
+/// assert_eq!(verifier.verify("alice-token")?.name(), "alice@example.com");
+/// # Ok::<(), UrlManagerError>(())
+/// ```
+pub trait TokenVerifier {
+    fn verify(&self, token: &str) -> Result<ApiKey>;
+}
+
+impl<F: Fn(&str) -> Result<ApiKey>> TokenVerifier for F {
+    fn verify(&self, token: &str) -> Result<ApiKey> {
+        self(token)
+    }
+}
+
+/// The API key `request` carries, if any.
+pub(crate) fn request_key(request: &Request) -> Option<&str> {
+    request
+        .header_value("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| request.header_value("x-api-key"))
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        let keys = ApiKeyStore::new();
+        let reader = keys.generate(ApiKey::new("dashboard", Scope::ReadOnly));
+        keys.insert("admin-key", ApiKey::new("ops", Scope::Admin));
+
+        assert_eq!(
+            keys.authorize(Some(&reader), Scope::ReadOnly)
+                .unwrap()
+                .name(),
+            "dashboard"
+        );
+        assert!(matches!(
+            keys.authorize(Some(&reader), Scope::Create),
+            Err(UrlManagerError::Forbidden(_))
+        ));
+        assert!(keys.authorize(Some("admin-key"), Scope::Create).is_ok());
+        for key in [None, Some("nope")] {
+            assert!(matches!(
+                keys.authorize(key, Scope::ReadOnly),
+                Err(UrlManagerError::Unauthorized(_))
+            ));
+        }
+
+        assert!(keys.revoke(&reader));
+        assert!(!keys.revoke(&reader));
+        assert!(keys.authorize(Some(&reader), Scope::ReadOnly).is_err());
+        assert_eq!("Read-Only".parse::<Scope>().unwrap(), Scope::ReadOnly);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let keys = ApiKeyStore::new();
+        let limited = ApiKey::new("bot", Scope::Create).rate_limit(2);
+        keys.insert("bot-key", limited.clone());
+        assert!(keys.take_request("bot-key", &limited).is_ok());
+        assert!(keys.take_request("bot-key", &limited).is_ok());
+        let Err(UrlManagerError::RateLimited(wait)) = keys.take_request("bot-key", &limited) else {
+            panic!("third call should be limited");
+        };
+        assert!(wait <= Duration::from_secs(30));
+
+        let unlimited = ApiKey::new("ops", Scope::Admin);
+        assert!((0..100).all(|_| keys.take_request("ops-key", &unlimited).is_ok()));
+    }
+}
@@ -0,0 +1,117 @@
+use super::escape_html;
+use crate::link::check_target;
+use crate::{Link, Result};
+
+/// The page shown instead of a redirect for links with
+/// [`Link::interstitial`], rendered from an HTML template.
+///
+/// The template's `{target}`, `{title}` and `{shortcut}` are replaced with
+/// the HTML-escaped target, the title of the link's [`Link::preview`] or
+/// else the target, and the link's shortcut. Links whose target isn't an
+/// http or https URL, e.g. a `javascript:` one stored before targets were
+/// checked, aren't rendered.
+///
+/// ```
+/// # use url_manager::server::Interstitial;
+/// let page = Interstitial::new(
+///     "<p>Leaving example.com for <a href=\"{target}\">{title}</a></p>",
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interstitial {
+    template: String,
+}
+
+impl Interstitial {
+    /// The built-in template, which names the target and continues there
+    /// after five seconds.
+    pub const DEFAULT_TEMPLATE: &'static str = "<!DOCTYPE html>\n\
+        <html><head><meta charset=\"utf-8\"><title>Redirecting to {title}</title>\n\
+        <meta http-equiv=\"refresh\" content=\"5; url={target}\"></head>\n\
+        <body><p>You are being redirected to <a href=\"{target}\">{target}</a>.</p></body></html>\n";
+
+    pub fn new(template: impl Into<String>) -> Self {
+        Interstitial {
+            template: template.into(),
+        }
+    }
+
+    /// The page for `link`, failing with `InvalidLink` if its target isn't
+    /// an http or https URL.
+    pub fn render(&self, link: &Link) -> Result<String> {
+        check_target(link.target())?;
+        let target = escape_html(link.target().as_str());
+        let title = link
+            .preview()
+            .and_then(|preview| preview.title.as_deref())
+            .map_or_else(|| target.clone(), escape_html);
+        let shortcut = escape_html(link.shortcut().unwrap_or_default());
+
+        let mut page = String::with_capacity(self.template.len() + target.len());
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            page += &rest[..start];
+            rest = &rest[start..];
+            let value = [
+                ("{target}", &target),
+                ("{title}", &title),
+                ("{shortcut}", &shortcut),
+            ]
+            .into_iter()
+            .find(|(placeholder, _)| rest.starts_with(placeholder));
+            match value {
+                Some((placeholder, value)) => {
+                    page += value;
+                    rest = &rest[placeholder.len()..];
+                }
+                None => {
+                    page.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        Ok(page + rest)
+    }
+}
+
+impl Default for Interstitial {
+    fn default() -> Self {
+        Interstitial::new(Self::DEFAULT_TEMPLATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let link = Link::builder()
+            .target("https://example.com/?a=1&b=2")
+            .slug("docs")
+            .build()
+            .unwrap();
+        let page = Interstitial::new("{shortcut} -> {target} {title} {other}")
+            .render(&link)
+            .unwrap();
+        assert_eq!(
+            page,
+            "docs -> https://example.com/?a=1&amp;b=2 https://example.com/?a=1&amp;b=2 {other}"
+        );
+        assert!(Interstitial::default()
+            .render(&link)
+            .unwrap()
+            .contains("content=\"5; url=https://example.com/?a=1&amp;b=2\""));
+    }
+
+    #[test]
+    fn test_script_target() {
+        let mut link = Link::builder()
+            .target("https://example.com/")
+            .build()
+            .unwrap();
+        let script = "javascript://example.com/%0Aalert(1)".parse().unwrap();
+        link.schedule_target(script, std::time::SystemTime::UNIX_EPOCH);
+        assert!(Interstitial::default().render(&link).is_err());
+    }
+}
@@ -0,0 +1,2219 @@
+//! HTTP redirect server, enabled by the `server` feature.
+//!
+//! The routes are plain functions from [`Request`] to [`Response`], so they
+//! can be mounted in any web framework; [`Server::serve`] runs them on a
+//! small blocking listener from the standard library.
+//!
+//! | Route | Action |
+//! | ----- | ------ |
+//! | `GET /:slug` | redirect to the link target, or ask for its password |
+//! | `GET /:slug+` | show the [`Interstitial`] naming the target, without counting a hit |
+//! | `POST /:slug` | redirect to a protected link's target given a form field `password` |
+//! | `GET /api/links` | list links, filtered by `?q=` on shortcut and target and `?tag=`, paged with `?offset=` and `?limit=` (50 by default) |
+//! | `GET /api/links/:id` | show a link |
+//! | `GET /api/links/:id/clicks` | the link's clicks per day and top referrers and countries, from [`Server::analytics`] |
+//! | `POST /api/links` | create a link from `{"target": …, "slug": …, "password": …, "expires_in": seconds, "interstitial": bool, "redirect_status": 301…308}` |
+//! | `PATCH /api/links/:id` | change a link's `target`, `password`, `interstitial` or `redirect_status`; needs `If-Match` with the `ETag` it was shown with, and fails with 412 if it was changed since |
+//! | `DELETE /api/links/:id` | move a link to the trash, see [`LinkStore::soft_delete`] |
+//! | `POST /api/links/:id/share` | mint a share token for previewing the link, good for `{"expires_in": seconds}` or a day, see [`Server::share_tokens`] |
+//! | `DELETE /api/links/:id/share/:token` | revoke a share token |
+//! | `GET /share/:token` | the link's interstitial for whoever holds the token, without counting a hit |
+//! | `GET /metrics` | Prometheus metrics, with the `metrics` feature |
+//! | `GET /healthz` | 200 while the store answers, 503 otherwise |
+//! | `GET /readyz` | like `/healthz`, and 503 once a [`Shutdown`] was requested |
+//! | `GET /admin` | a page for listing, searching, creating and deleting links and charting their clicks through the routes above, with the `admin-ui` feature |
+//!
+//! Links with [`Link::variants`](crate::Link::variants) send each client
+//! address to the same variant. [`Link::rules`](crate::Link::rules) see the
+//! client's user agent, and its country with [`Server::geoip`]. Hits from
+//! crawlers and preview bots aren't counted with [`Server::bot_detector`],
+//! which can also serve them the link's preview instead of the redirect.
+//! With [`Server::domains`], each custom domain serves the links of its
+//! own namespace.
+//! Links with [`Link::interstitial`], or all of them with
+//! [`Server::interstitial_for_all`], get an [`Interstitial`] page first.
+//!
+//! With [`Server::api_keys`], the `/api` routes need a key with the
+//! [`Scope`] to read, create or delete, and `/metrics` a read-only one;
+//! redirects stay public. Bearer tokens of an identity provider can be
+//! accepted as well through a [`TokenVerifier`], see
+//! [`Server::token_verifier`]. Keys given an [`ApiKey::owner_id`] create links
+//! for that user and, short of the admin scope, only see and delete theirs.
+//! Creating and resolving can each be rate limited
+//! per client with a [`RateLimiter`], see [`Server::create_rate_limit`].
+//!
+//! [`Server::serve`] stops once its [`Shutdown`] is requested, e.g. by
+//! SIGTERM with [`Shutdown::on_signals`]: it finishes the requests in
+//! flight, then flushes clicks and events and closes the store, see
+//! [`Server::close`].
+
+mod auth;
+mod http;
+mod interstitial;
+mod shutdown;
+
+pub use auth::{ApiKey, ApiKeyStore, Scope, TokenVerifier};
+pub use http::{Request, Response};
+pub use interstitial::Interstitial;
+pub use shutdown::Shutdown;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::analytics::{Analytics, Count, Summary, DAY};
+use crate::clicks::follow_hit;
+use crate::crypto::{base64_encode, sha256, URL_SAFE};
+use crate::events::{self, Event, EventBus};
+use crate::json::{self, Value};
+use crate::link::{check_target, unix_millis};
+use crate::{
+    metrics, spawn_purger, sync, BotDetector, ClickRecorder, DomainRegistry, GeoIp, HitMetadata,
+    Link, LinkQuery, LinkStore, Namespace, Purger, RateLimiter, RedirectStatus, Result,
+    ShareTokens, SignedLink, SlugFilter, UrlManagerError, UrlPolicy, UrlType,
+};
+
+// How often `Server::serve` checks for new connections and a shutdown.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+// Defaults for `Server::connection_timeout` and `Server::max_connections`.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_CONNECTIONS: usize = 512;
+
+// The longest a link or share token can be made to last, a century, which
+// keeps expiries well within what clocks and millisecond timestamps hold.
+const MAX_EXPIRES_IN: Duration = Duration::from_secs(100 * 365 * DAY.as_secs());
+
+#[cfg(feature = "admin-ui")]
+const ADMIN_PAGE: &str = include_str!("admin.html");
+
+/// What [`Server`] does with hits from bots, see [`Server::bot_detector`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnBot {
+    /// Redirects them like everyone else, without counting the hit.
+    #[default]
+    Uncounted,
+    /// Serves them a page with the link's [`Link::preview`] as Open Graph
+    /// tags instead of the redirect, without counting the hit either.
+    Preview,
+}
+
+/// Serves the links of a [`LinkStore`] over HTTP.
+///
+/// Clones share the same store, behind one `Mutex` since [`LinkStore`]
+/// writes take it by `&mut`. Requests therefore take turns on the store,
+/// redirects included: each holds the lock to look its link up and again
+/// to count the hit, though not while checking a password. A store that
+/// reads concurrently, like [`InMemoryLinkStore`](crate::InMemoryLinkStore),
+/// only gets to when used directly.
+pub struct Server<S> {
+    store: Arc<Mutex<S>>,
+    clicks: Option<Arc<dyn ClickRecorder + Send + Sync>>,
+    honor_do_not_track: bool,
+    analytics: Option<Arc<dyn Analytics + Send + Sync>>,
+    policy: UrlPolicy,
+    signing_key: Option<Arc<[u8]>>,
+    shares: Option<Arc<ShareTokens>>,
+    api_keys: Option<Arc<ApiKeyStore>>,
+    tokens: Option<Arc<dyn TokenVerifier + Send + Sync>>,
+    create_limit: Option<Arc<RateLimiter>>,
+    resolve_limit: Option<Arc<RateLimiter>>,
+    events: Option<EventBus>,
+    geoip: Option<Arc<dyn GeoIp + Send + Sync>>,
+    domains: Option<Arc<DomainRegistry>>,
+    bots: Option<(Arc<dyn BotDetector + Send + Sync>, OnBot)>,
+    interstitial: Interstitial,
+    interstitial_for_all: bool,
+    slug_filter: SlugFilter,
+    case_insensitive: bool,
+    redirect_status: RedirectStatus,
+    shutdown: Shutdown,
+    connection_timeout: Duration,
+    max_connections: usize,
+}
+
+impl<S> Clone for Server<S> {
+    fn clone(&self) -> Self {
+        Server {
+            store: Arc::clone(&self.store),
+            clicks: self.clicks.clone(),
+            honor_do_not_track: self.honor_do_not_track,
+            analytics: self.analytics.clone(),
+            policy: self.policy.clone(),
+            signing_key: self.signing_key.clone(),
+            shares: self.shares.clone(),
+            api_keys: self.api_keys.clone(),
+            tokens: self.tokens.clone(),
+            create_limit: self.create_limit.clone(),
+            resolve_limit: self.resolve_limit.clone(),
+            events: self.events.clone(),
+            geoip: self.geoip.clone(),
+            domains: self.domains.clone(),
+            bots: self.bots.clone(),
+            interstitial: self.interstitial.clone(),
+            interstitial_for_all: self.interstitial_for_all,
+            slug_filter: self.slug_filter.clone(),
+            case_insensitive: self.case_insensitive,
+            redirect_status: self.redirect_status,
+            shutdown: self.shutdown.clone(),
+            connection_timeout: self.connection_timeout,
+            max_connections: self.max_connections,
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Server<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("store", &self.store)
+            .field("clicks", &self.clicks.is_some())
+            .field("honor_do_not_track", &self.honor_do_not_track)
+            .field("analytics", &self.analytics.is_some())
+            .field("shares", &self.shares)
+            .field("policy", &self.policy)
+            .field("signing_key", &self.signing_key.is_some())
+            .field("api_keys", &self.api_keys.is_some())
+            .field("tokens", &self.tokens.is_some())
+            .field("create_limit", &self.create_limit)
+            .field("resolve_limit", &self.resolve_limit)
+            .field("events", &self.events)
+            .field("geoip", &self.geoip.is_some())
+            .field("domains", &self.domains)
+            .field("bots", &self.bots.as_ref().map(|(_, on_bot)| on_bot))
+            .field("interstitial", &self.interstitial)
+            .field("interstitial_for_all", &self.interstitial_for_all)
+            .field("slug_filter", &self.slug_filter)
+            .field("case_insensitive", &self.case_insensitive)
+            .field("redirect_status", &self.redirect_status)
+            .field("shutdown", &self.shutdown)
+            .field("connection_timeout", &self.connection_timeout)
+            .field("max_connections", &self.max_connections)
+            .finish()
+    }
+}
+
+impl<S: LinkStore> Server<S> {
+    pub fn new(store: S) -> Self {
+        Server {
+            store: Arc::new(Mutex::new(store)),
+            clicks: None,
+            honor_do_not_track: false,
+            analytics: None,
+            policy: UrlPolicy::default(),
+            signing_key: None,
+            shares: None,
+            api_keys: None,
+            tokens: None,
+            create_limit: None,
+            resolve_limit: None,
+            events: None,
+            geoip: None,
+            domains: None,
+            bots: None,
+            interstitial: Interstitial::default(),
+            interstitial_for_all: false,
+            slug_filter: SlugFilter::new(),
+            case_insensitive: false,
+            redirect_status: RedirectStatus::Found,
+            shutdown: Shutdown::new(),
+            connection_timeout: CONNECTION_TIMEOUT,
+            max_connections: MAX_CONNECTIONS,
+        }
+    }
+
+    /// Redirects links without a [`Link::redirect_status`] of their own
+    /// with `status` instead of 302; one of 301, 302, 307 or 308.
+    ///
+    /// Permanent redirects are sent as cacheable by shared caches, for a
+    /// day at most and not past the link's expiry or scheduled target
+    /// change; temporary ones and links that depend on the visitor or count
+    /// their uses are sent with `no-store`.
+    pub fn redirect_status(mut self, status: u16) -> Result<Self> {
+        self.redirect_status = RedirectStatus::try_from(status)?;
+        Ok(self)
+    }
+
+    /// Hands every redirect to `recorder`, with the request's referrer,
+    /// user agent and client address; wrap it in a
+    /// [`DedupClickRecorder`](crate::DedupClickRecorder) to count repeated
+    /// clicks by a client once.
+    pub fn click_recorder(mut self, recorder: impl ClickRecorder + Send + Sync + 'static) -> Self {
+        self.clicks = Some(Arc::new(recorder));
+        self
+    }
+
+    /// Records the clicks of clients sending `DNT: 1` or `Sec-GPC: 1`
+    /// without referrer, user agent, country or address, see
+    /// [`HitMetadata::do_not_track`]; they are still counted. Combine it
+    /// with a [`PrivateClickRecorder`](crate::analytics::PrivateClickRecorder)
+    /// for the clicks of everyone else.
+    pub fn honor_do_not_track(mut self, enabled: bool) -> Self {
+        self.honor_do_not_track = enabled;
+        self
+    }
+
+    /// Serves the links of a namespace on each domain claimed in `domains`:
+    /// requests resolve shortcuts, and create links, in the namespace of
+    /// the host they were sent to. Other hosts use the shared namespace.
+    pub fn domains(mut self, domains: impl Into<Arc<DomainRegistry>>) -> Self {
+        self.domains = Some(domains.into());
+        self
+    }
+
+    /// Answers `GET /api/links/:id/clicks` from `analytics`, typically the
+    /// same store as the [`Server::click_recorder`]. Without it the route
+    /// only has the link's hit count.
+    pub fn analytics(mut self, analytics: impl Analytics + Send + Sync + 'static) -> Self {
+        self.analytics = Some(Arc::new(analytics));
+        self
+    }
+
+    /// Looks up the country of each client address with `geoip`, for
+    /// [`RedirectRule::countries`](crate::RedirectRule::countries).
+    pub fn geoip(mut self, geoip: impl GeoIp + Send + Sync + 'static) -> Self {
+        self.geoip = Some(Arc::new(geoip));
+        self
+    }
+
+    /// Tells bots apart with `detector` and handles their hits as `on_bot`
+    /// says; they are never counted or recorded.
+    pub fn bot_detector(
+        mut self,
+        detector: impl BotDetector + Send + Sync + 'static,
+        on_bot: OnBot,
+    ) -> Self {
+        self.bots = Some((Arc::new(detector), on_bot));
+        self
+    }
+
+    /// Renders interstitials with `page` instead of the built-in template.
+    pub fn interstitial(mut self, page: Interstitial) -> Self {
+        self.interstitial = page;
+        self
+    }
+
+    /// Shows the interstitial for every link, not just those with
+    /// [`Link::interstitial`].
+    pub fn interstitial_for_all(mut self, enabled: bool) -> Self {
+        self.interstitial_for_all = enabled;
+        self
+    }
+
+    /// Checks the slugs of created links with `filter` instead of
+    /// [`SlugFilter::new`]; refused slugs get 400.
+    pub fn slug_filter(mut self, filter: SlugFilter) -> Self {
+        self.slug_filter = filter;
+        self
+    }
+
+    /// Matches slugs regardless of case, like
+    /// [`LinkService::case_insensitive`](crate::LinkService::case_insensitive):
+    /// created links get lowercase slugs and redirects look up the
+    /// lowercased slug.
+    pub fn case_insensitive(mut self, enabled: bool) -> Self {
+        self.case_insensitive = enabled;
+        self
+    }
+
+    /// Refuses to create links whose target breaks `policy`, with 403.
+    pub fn policy(mut self, policy: UrlPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Only redirects for [`SignedLink`] tokens signed with `key`, checked
+    /// before the store is asked; bare shortcuts get 403. Created links
+    /// need an `expires_in` and come back with their `token`.
+    pub fn signing_key(mut self, key: impl Into<Vec<u8>>) -> Result<Self> {
+        let key = key.into();
+        if key.len() < SignedLink::MIN_KEY_LENGTH {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "signing keys need at least {} bytes",
+                SignedLink::MIN_KEY_LENGTH
+            )));
+        }
+        self.signing_key = Some(key.into());
+        Ok(self)
+    }
+
+    /// Lets `POST /api/links/:id/share` mint [`ShareTokens`] for links the
+    /// caller may see, e.g. a key's own links, and serves each link's
+    /// interstitial on `GET /share/:token` to anyone holding one until it
+    /// expires or is revoked. Keep a clone of `tokens` to revoke them
+    /// outside the API. Without it these routes answer 404.
+    pub fn share_tokens(mut self, tokens: impl Into<Arc<ShareTokens>>) -> Self {
+        self.shares = Some(tokens.into());
+        self
+    }
+
+    /// Requires a key from `keys` for the `/api` routes: reading needs
+    /// [`Scope::ReadOnly`], creating and deleting [`Scope::Create`], though
+    /// only [`Scope::Admin`] deletes links of other owners, or of none, see
+    /// [`ApiKey::owner_id`]. Missing or unknown keys get 401, keys over
+    /// their rate limit 429.
+    pub fn api_keys(mut self, keys: impl Into<Arc<ApiKeyStore>>) -> Self {
+        self.api_keys = Some(keys.into());
+        self
+    }
+
+    /// Also accepts bearer tokens `verifier` vouches for on the `/api`
+    /// routes, e.g. access tokens of an OpenID Connect provider, with the
+    /// scope of the [`ApiKey`] it returns; keys from [`Server::api_keys`]
+    /// are tried first. Setting a verifier alone requires a token like
+    /// `api_keys` requires a key.
+    pub fn token_verifier(mut self, verifier: impl TokenVerifier + Send + Sync + 'static) -> Self {
+        self.tokens = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Limits link creation per client: per API key when the request has
+    /// one, otherwise per remote address. Over the limit, clients get 429
+    /// with a `Retry-After`.
+    pub fn create_rate_limit(mut self, limiter: impl Into<Arc<RateLimiter>>) -> Self {
+        self.create_limit = Some(limiter.into());
+        self
+    }
+
+    /// Limits redirects per remote address, like [`Server::create_rate_limit`],
+    /// which slows down scanning for valid shortcuts.
+    pub fn resolve_rate_limit(mut self, limiter: impl Into<Arc<RateLimiter>>) -> Self {
+        self.resolve_limit = Some(limiter.into());
+        self
+    }
+
+    /// Publishes an [`Event`] on `bus` for every link created, followed or
+    /// deleted through the server.
+    pub fn events(mut self, bus: EventBus) -> Self {
+        self.events = Some(bus);
+        self
+    }
+
+    /// Stops [`Server::serve`] and fails `/readyz` once `shutdown` is
+    /// requested.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Gives [`Server::serve`] clients `timeout`, 10 seconds by default, to
+    /// send their request and again to take the response; slower ones get
+    /// 408 or are dropped, so they can't hold a thread or a shutdown up.
+    pub fn connection_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_timeout = timeout.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Has [`Server::serve`] answer 503 to new connections while `max`, 512
+    /// by default, are being served.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// Waits for the click recorder and the event sinks to deliver what
+    /// they were handed, then [closes](LinkStore::close) the store. Called
+    /// by [`Server::serve`] when it stops; call it yourself when mounting
+    /// [`Server::handle`] elsewhere. Everything is tried even if a step
+    /// fails; the first error is returned.
+    pub fn close(&self) -> Result<()> {
+        let clicks = self.clicks.as_ref().map_or(Ok(()), |clicks| clicks.flush());
+        let events = self.events.as_ref().map_or(Ok(()), EventBus::flush);
+        let store = sync::lock(&self.store).close();
+        clicks.and(events).and(store)
+    }
+
+    /// Routes `request` to the matching handler.
+    pub fn handle(&self, request: &Request) -> Response {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let result = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["api", "links"]) => {
+                self.authorized(request, Scope::ReadOnly, |key| self.list(request, key))
+            }
+            ("GET", ["api", "links", id]) => {
+                self.authorized(request, Scope::ReadOnly, |key| self.show(id, key))
+            }
+            ("GET", ["api", "links", id, "clicks"]) => {
+                self.authorized(request, Scope::ReadOnly, |key| self.clicks(id, key))
+            }
+            ("POST", ["api", "links"]) => self.authorized(request, Scope::Create, |key| {
+                self.throttle(&self.create_limit, request)?;
+                self.create(request, key)
+            }),
+            ("PATCH", ["api", "links", id]) => {
+                self.authorized(request, Scope::Create, |key| self.update(request, id, key))
+            }
+            ("DELETE", ["api", "links", id]) => {
+                self.authorized(request, Scope::Create, |key| self.delete(id, key))
+            }
+            ("POST", ["api", "links", id, "share"]) => {
+                self.authorized(request, Scope::Create, |key| self.share(request, id, key))
+            }
+            ("DELETE", ["api", "links", id, "share", token]) => {
+                self.authorized(request, Scope::Create, |key| self.unshare(id, token, key))
+            }
+            (
+                _,
+                ["api", "links"]
+                | ["api", "links", _]
+                | ["api", "links", _, "clicks" | "share"]
+                | ["api", "links", _, "share", _],
+            ) => Ok(error_body(405, "method not allowed")),
+            (_, ["api", ..]) => Err(UrlManagerError::NotFound),
+            ("GET" | "HEAD", ["share", token]) => self.shared(token),
+            ("GET" | "HEAD", ["healthz"]) => Ok(self.health(false)),
+            ("GET" | "HEAD", ["readyz"]) => Ok(self.health(true)),
+            #[cfg(feature = "admin-ui")]
+            ("GET", ["admin"]) => Ok(Response::new(200)
+                .header("Cache-Control", "no-cache")
+                .body("text/html; charset=utf-8", ADMIN_PAGE)),
+            #[cfg(feature = "metrics")]
+            ("GET", ["metrics"]) => self.authorized(request, Scope::ReadOnly, |_| {
+                Ok(Response::new(200).body("text/plain; version=0.0.4", crate::metrics::gather()))
+            }),
+            ("GET" | "HEAD", [slug]) if !slug.is_empty() => self
+                .throttle(&self.resolve_limit, request)
+                .and_then(|()| self.redirect(request, slug, None)),
+            ("POST", [slug]) if !slug.is_empty() => {
+                if let Err(e) = self.throttle(&self.resolve_limit, request) {
+                    return error_response(&e);
+                }
+                let password = url::form_urlencoded::parse(&request.body)
+                    .find(|(name, _)| name == "password")
+                    .map(|(_, password)| password.into_owned());
+                self.redirect(request, slug, Some(password.unwrap_or_default()))
+            }
+            _ => Err(UrlManagerError::NotFound),
+        };
+        result.unwrap_or_else(|e| error_response(&e))
+    }
+
+    // `password` is `Some` when the password form was submitted.
+    fn redirect(
+        &self,
+        request: &Request,
+        path: &str,
+        password: Option<String>,
+    ) -> Result<Response> {
+        // `slug+` shows the interstitial without following the link
+        let (shortcut, peek) = match path.strip_suffix('+') {
+            Some(shortcut) if !shortcut.is_empty() => (shortcut, true),
+            _ => (path, false),
+        };
+        let slug = match &self.signing_key {
+            Some(key) => SignedLink::verify(key, shortcut)?,
+            None => shortcut,
+        };
+        let slug = &*self.canonical(slug);
+        let mut metadata = HitMetadata {
+            referrer: request.header_value("referer").map(str::to_string),
+            user_agent: request.header_value("user-agent").map(str::to_string),
+            country: self
+                .geoip
+                .as_ref()
+                .zip(request.remote_addr)
+                .and_then(|(geoip, addr)| geoip.country(addr)),
+            visitor: request.remote_addr.map(|addr| addr.to_string()),
+            bot: false,
+            do_not_track: self.honor_do_not_track
+                && ["DNT", "Sec-GPC"]
+                    .iter()
+                    .any(|name| request.header_value(name).map(str::trim) == Some("1")),
+        };
+        let on_bot = match &self.bots {
+            Some((detector, on_bot)) if detector.is_bot(&metadata) => Some(*on_bot),
+            _ => None,
+        };
+        metadata.bot = on_bot.is_some();
+        let namespace = self.namespace(request);
+        // HEAD only checks where the link goes, which isn't a hit
+        let counted = !peek && request.method != "HEAD";
+        let result = if !counted {
+            self.resolve(namespace.as_ref(), slug, password.as_deref())
+        } else {
+            self.record_hit(namespace.as_ref(), slug, password.as_deref(), metadata)
+        };
+        let link = match result {
+            Err(UrlManagerError::PasswordRequired) => {
+                return Ok(password_form(path, password.is_some()))
+            }
+            result => result?,
+        };
+        // links stored before targets were checked can still hold others
+        check_target(link.target())?;
+        if peek {
+            return self.interstitial_page(&link);
+        }
+        match on_bot {
+            Some(OnBot::Preview) => return preview_page(&link),
+            Some(OnBot::Uncounted) => {}
+            None if counted => events::publish(&self.events, || Event::LinkResolved(link.clone())),
+            None => {}
+        }
+        if self.interstitial_for_all || link.interstitial() {
+            return self.interstitial_page(&link);
+        }
+        let status = link.redirect_status().unwrap_or(self.redirect_status);
+        Ok(Response::new(status.code())
+            .header("Location", link.target().as_str())
+            .header("Cache-Control", cache_control(&link, status)))
+    }
+
+    // `LinkStore::resolve_with_password_in`, checking the password, which
+    // takes a while on purpose, without holding the store's lock.
+    fn resolve(
+        &self,
+        namespace: Option<&Namespace>,
+        slug: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        let link = sync::lock(&self.store)
+            .get_by_shortcut_in(namespace, slug)?
+            .ok_or(UrlManagerError::NotFound)?;
+        link.check_access(password)?;
+        Ok(link)
+    }
+
+    // `record_hit_with_password_in`, with the store locked only to look the
+    // link up and then to count it, see `LinkStore::record_resolved_hit`.
+    fn record_hit(
+        &self,
+        namespace: Option<&Namespace>,
+        slug: &str,
+        password: Option<&str>,
+        metadata: HitMetadata,
+    ) -> Result<Link> {
+        let link = metrics::resolution(|| {
+            let link = self.resolve(namespace, slug, password)?;
+            if metadata.bot {
+                return Ok(link);
+            }
+            sync::lock(&self.store).record_resolved_hit(&link)
+        })?;
+        follow_hit(
+            &mut *sync::lock(&self.store),
+            self.clicks.as_deref().map(|c| c as &dyn ClickRecorder),
+            link,
+            metadata,
+        )
+    }
+
+    // 200 while the store answers; for readiness, 503 once shutting down.
+    fn health(&self, readiness: bool) -> Response {
+        let status = |status: u16, message: &str| {
+            json_response(status, Value::object([("status", Value::from(message))]))
+                .header("Cache-Control", "no-store")
+        };
+        if readiness && self.shutdown.is_requested() {
+            return status(503, "shutting down");
+        }
+        match sync::lock(&self.store).ping() {
+            Ok(()) => status(200, "ok"),
+            Err(e) => status(503, &format!("store unavailable: {e}")),
+        }
+    }
+
+    // The namespace of the domain the request was sent to, if claimed.
+    fn namespace(&self, request: &Request) -> Option<Namespace> {
+        let domains = self.domains.as_ref()?;
+        domains.namespace_for(request.header_value("Host")?)
+    }
+
+    fn canonical<'a>(&self, slug: &'a str) -> Cow<'a, str> {
+        if self.case_insensitive {
+            Cow::Owned(slug.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(slug)
+        }
+    }
+
+    fn interstitial_page(&self, link: &Link) -> Result<Response> {
+        Ok(Response::new(200)
+            .header("Cache-Control", "no-store")
+            .body("text/html; charset=utf-8", self.interstitial.render(link)?))
+    }
+
+    fn create(&self, request: &Request, api_key: Option<&ApiKey>) -> Result<Response> {
+        let body = json_body(request)?;
+        let target = body
+            .get("target")
+            .and_then(Value::as_str)
+            .ok_or_else(|| UrlManagerError::InvalidLink("target is required".to_string()))?;
+
+        let mut builder = Link::builder().target(target);
+        if let Some(namespace) = self.namespace(request) {
+            builder = builder.namespace(namespace);
+        }
+        if let Some(slug) = body.get("slug").and_then(Value::as_str) {
+            self.slug_filter.check(slug)?;
+            builder = builder.slug(self.canonical(slug));
+        }
+        if let Some(password) = body.get("password").and_then(Value::as_str) {
+            builder = builder.password(password);
+        }
+        if let Some(ttl) = expires_in(&body)? {
+            builder = builder.expires_in(ttl);
+        }
+        if let Some(interstitial) = interstitial(&body)? {
+            builder = builder.interstitial(interstitial);
+        }
+        if let Some(status) = redirect_status(&body)? {
+            builder = builder.redirect_status(status);
+        }
+        if let Some(owner_id) = api_key.and_then(ApiKey::get_owner_id) {
+            builder = builder.owner_id(owner_id);
+        }
+        let link = builder.build()?;
+        let signed = match &self.signing_key {
+            Some(key) => Some(SignedLink::sign(key, link.clone())?),
+            None => None,
+        };
+        self.check_target(request, &link)?;
+        metrics::store_call("create", || sync::lock(&self.store).create(link.clone()))?;
+        metrics::link_created();
+        events::publish(&self.events, || Event::LinkCreated(link.clone()));
+        let mut body = link.to_public_json();
+        if let (Value::Object(fields), Some(signed)) = (&mut body, signed) {
+            fields.push(("token".to_string(), Value::from(signed.token())));
+        }
+        Ok(json_response(201, body).header("ETag", etag(&link)))
+    }
+
+    fn check_target(&self, request: &Request, link: &Link) -> Result<()> {
+        self.policy.check(link.target())?;
+        if is_own_host(request, link.target()) {
+            return Err(UrlManagerError::Forbidden(format!(
+                "'{}' points back at this server",
+                link.target()
+            )));
+        }
+        Ok(())
+    }
+
+    // Runs `handler` with the request's API key if it allows `scope`, or
+    // without one if anyone may since no API keys are configured.
+    fn authorized(
+        &self,
+        request: &Request,
+        scope: Scope,
+        handler: impl FnOnce(Option<&ApiKey>) -> Result<Response>,
+    ) -> Result<Response> {
+        let api_key = match (&self.api_keys, &self.tokens) {
+            (None, None) => return handler(None),
+            (Some(keys), None) => keys.authorize(auth::request_key(request), scope)?,
+            (keys, Some(tokens)) => {
+                let key = auth::request_key(request)
+                    .ok_or_else(|| UrlManagerError::Unauthorized("API key required".into()))?;
+                let api_key = match keys.as_ref().and_then(|keys| keys.get(key)) {
+                    Some(api_key) => api_key,
+                    None => tokens.verify(key)?,
+                };
+                api_key.require(scope)?;
+                api_key
+            }
+        };
+        if let Some(keys) = &self.api_keys {
+            keys.take_request(auth::request_key(request).unwrap_or_default(), &api_key)?;
+        }
+        handler(Some(&api_key))
+    }
+
+    // Takes a token for the request's client from `limiter`, if there is one.
+    fn throttle(&self, limiter: &Option<Arc<RateLimiter>>, request: &Request) -> Result<()> {
+        let Some(limiter) = limiter else {
+            return Ok(());
+        };
+        let client = match (auth::request_key(request), request.remote_addr) {
+            (Some(key), _) => format!("key:{}", base64_encode(&sha256(key.as_bytes()), URL_SAFE)),
+            (None, Some(addr)) => format!("ip:{addr}"),
+            (None, None) => "unknown".to_string(),
+        };
+        limiter.check(&client)
+    }
+
+    fn list(&self, request: &Request, api_key: Option<&ApiKey>) -> Result<Response> {
+        let query_string = request.query.as_deref().unwrap_or_default();
+        let params: HashMap<String, String> = url::form_urlencoded::parse(query_string.as_bytes())
+            .into_owned()
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+        let number = |name: &str, default: usize| match params.get(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| UrlManagerError::InvalidLink(format!("{name} is not a number"))),
+            None => Ok(default),
+        };
+        let offset = number("offset", 0)?;
+        let limit = number("limit", 50)?;
+        let mut query = LinkQuery::new();
+        if let Some(tag) = params.get("tag") {
+            query = query.tag(tag);
+        }
+        if let Some(owner_id) = api_key.and_then(ApiKey::restricted_to) {
+            query = query.owner_id(owner_id);
+        }
+        let search = params.get("q").map(|q| q.to_lowercase());
+        let links = metrics::store_call("find", || sync::lock(&self.store).find(&query))?
+            .into_iter()
+            .filter(|link| {
+                search.as_deref().is_none_or(|search| {
+                    link.shortcut()
+                        .is_some_and(|shortcut| shortcut.to_lowercase().contains(search))
+                        || link.display_target().to_lowercase().contains(search)
+                })
+            })
+            .skip(offset)
+            .take(limit)
+            .map(|link| link.to_public_json())
+            .collect();
+        Ok(json_response(200, Value::Array(links)))
+    }
+
+    fn show(&self, id: &str, api_key: Option<&ApiKey>) -> Result<Response> {
+        let link = self.visible_link(id, api_key)?;
+        Ok(json_response(200, link.to_public_json()).header("ETag", etag(&link)))
+    }
+
+    fn clicks(&self, id: &str, api_key: Option<&ApiKey>) -> Result<Response> {
+        const TOP: usize = 5;
+        let link = self.visible_link(id, api_key)?;
+        let summary = match &self.analytics {
+            Some(analytics) => analytics.summary(link.id(), DAY, TOP)?,
+            None => Summary {
+                total: link.hit_count(),
+                buckets: Vec::new(),
+                top_referrers: Vec::new(),
+                top_user_agents: Vec::new(),
+                top_countries: Vec::new(),
+            },
+        };
+        let counts = |counts: Vec<Count>| {
+            Value::Array(
+                counts
+                    .into_iter()
+                    .map(|count| {
+                        Value::object([
+                            ("value", Value::from(count.value)),
+                            ("hits", Value::from(count.hits)),
+                        ])
+                    })
+                    .collect(),
+            )
+        };
+        let days = summary
+            .buckets
+            .iter()
+            .map(|bucket| {
+                Value::object([
+                    ("start", Value::from(unix_millis(bucket.start))),
+                    ("hits", Value::from(bucket.hits)),
+                ])
+            })
+            .collect();
+        Ok(json_response(
+            200,
+            Value::object([
+                ("total", Value::from(summary.total)),
+                ("days", Value::Array(days)),
+                ("top_referrers", counts(summary.top_referrers)),
+                ("top_countries", counts(summary.top_countries)),
+            ]),
+        ))
+    }
+
+    // The live link stored under `id`, if the key may see it.
+    fn visible_link(&self, id: &str, api_key: Option<&ApiKey>) -> Result<Link> {
+        let id = id.parse().map_err(|_| UrlManagerError::NotFound)?;
+        let store = sync::lock(&self.store);
+        match api_key.and_then(ApiKey::restricted_to) {
+            Some(owner_id) => metrics::store_call("get", || store.get_owned(owner_id, id)),
+            None => metrics::store_call("get", || store.get(id))?
+                .filter(|link| !link.is_deleted())
+                .ok_or(UrlManagerError::NotFound),
+        }
+    }
+
+    // Changing takes the admin scope, or a key acting for the owner, and the
+    // version the client last saw; `If-Match: *` changes any version.
+    fn update(&self, request: &Request, id: &str, api_key: Option<&ApiKey>) -> Result<Response> {
+        let Some(if_match) = request.header_value("If-Match") else {
+            return Ok(error_body(428, "If-Match with the link's ETag is required"));
+        };
+        let version = match if_match.trim() {
+            "*" => None,
+            tag => Some(parse_etag(tag).ok_or_else(|| {
+                UrlManagerError::InvalidLink(format!("'{tag}' is no ETag of this server"))
+            })?),
+        };
+        let body = json_body(request)?;
+        if let Some(api_key) = api_key.filter(|key| key.scope() < Scope::Admin) {
+            if api_key.restricted_to().is_none() {
+                return Err(UrlManagerError::Forbidden(format!(
+                    "key '{}' lacks the Admin scope",
+                    api_key.name()
+                )));
+            }
+        }
+        let mut link = self.visible_link(id, api_key)?;
+
+        if let Some(target) = body.get("target") {
+            let target = target.as_str().ok_or_else(|| {
+                UrlManagerError::InvalidLink("target is not a string".to_string())
+            })?;
+            let target = UrlType::parse(target)?;
+            check_target(&target)?;
+            // due at once, see `Link::schedule_target`
+            link.schedule_target(target, SystemTime::UNIX_EPOCH);
+        }
+        if let Some(password) = body.get("password").and_then(Value::as_str) {
+            link.set_password(password);
+        }
+        if let Some(interstitial) = interstitial(&body)? {
+            link.set_interstitial(interstitial);
+        }
+        if let Some(status) = redirect_status(&body)? {
+            link.set_redirect_status(Some(status));
+        }
+        self.check_target(request, &link)?;
+
+        let mut store = sync::lock(&self.store);
+        let id = link.id();
+        let version = version.unwrap_or(link.version());
+        metrics::store_call("update", || store.update_if_version(id, version, link))?;
+        let link = store.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        drop(store);
+        Ok(json_response(200, link.to_public_json()).header("ETag", etag(&link)))
+    }
+
+    // Sharing takes whatever showing the link takes.
+    fn share(&self, request: &Request, id: &str, api_key: Option<&ApiKey>) -> Result<Response> {
+        const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+        let shares = self.shares.as_ref().ok_or(UrlManagerError::NotFound)?;
+        let link = self.visible_link(id, api_key)?;
+        let ttl = if request.body.is_empty() {
+            DEFAULT_TTL
+        } else {
+            expires_in(&json_body(request)?)?.unwrap_or(DEFAULT_TTL)
+        };
+        let token = shares.mint(link.id(), ttl);
+        Ok(json_response(
+            201,
+            Value::object([
+                ("token", Value::from(token.as_str())),
+                ("url", Value::from(format!("/share/{token}"))),
+                ("expires_at", Value::from(unix_millis(token.expires_at()))),
+            ]),
+        ))
+    }
+
+    fn unshare(&self, id: &str, token: &str, api_key: Option<&ApiKey>) -> Result<Response> {
+        let shares = self.shares.as_ref().ok_or(UrlManagerError::NotFound)?;
+        let link = self.visible_link(id, api_key)?;
+        if shares.link_id(token)? != link.id() {
+            return Err(UrlManagerError::NotFound);
+        }
+        shares.revoke(token)?;
+        Ok(Response::new(204))
+    }
+
+    fn shared(&self, token: &str) -> Result<Response> {
+        let shares = self.shares.as_ref().ok_or(UrlManagerError::NotFound)?;
+        let id = shares.verify(token)?;
+        let link = metrics::store_call("get", || sync::lock(&self.store).get(id))?
+            .filter(|link| !link.is_deleted())
+            .ok_or(UrlManagerError::NotFound)?;
+        Ok(self
+            .interstitial_page(&link)?
+            .header("X-Robots-Tag", "noindex"))
+    }
+
+    // Deleting takes the admin scope, or a key acting for the owner.
+    fn delete(&self, id: &str, api_key: Option<&ApiKey>) -> Result<Response> {
+        let id = id.parse().map_err(|_| UrlManagerError::NotFound)?;
+        let mut store = sync::lock(&self.store);
+        match api_key {
+            Some(api_key) if api_key.scope() < Scope::Admin => {
+                let Some(owner_id) = api_key.restricted_to() else {
+                    return Err(UrlManagerError::Forbidden(format!(
+                        "key '{}' lacks the Admin scope",
+                        api_key.name()
+                    )));
+                };
+                metrics::store_call("soft_delete", || store.delete_owned(owner_id, id))?;
+            }
+            _ => metrics::store_call("soft_delete", || store.soft_delete(id))?,
+        }
+        drop(store);
+        events::publish(&self.events, || Event::LinkDeleted(id));
+        Ok(Response::new(204))
+    }
+}
+
+impl<S: LinkStore + Send + 'static> Server<S> {
+    /// Purges expired links from the store every `interval`, see [`spawn_purger`].
+    pub fn spawn_purger(&self, interval: Duration) -> Purger {
+        spawn_purger(Arc::clone(&self.store), interval)
+    }
+
+    /// Accepts connections on `listener`, one thread per connection and up
+    /// to [`Server::max_connections`] at once, until it fails or the
+    /// [`Server::shutdown`] is requested. On shutdown it waits for the
+    /// connections in flight, which the [`Server::connection_timeout`] keeps
+    /// idle clients from dragging out, and then [`Server::close`]s.
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        // polled so a requested shutdown is noticed without a connection
+        listener.set_nonblocking(true)?;
+        let mut connections: Vec<thread::JoinHandle<io::Result<()>>> = Vec::new();
+        while !self.shutdown.is_requested() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    stream.set_write_timeout(Some(self.connection_timeout))?;
+                    connections.retain(|connection| !connection.is_finished());
+                    if connections.len() >= self.max_connections {
+                        let busy = error_body(503, "too many connections");
+                        let _ = busy.header("Retry-After", "1").write_to(&stream);
+                        continue;
+                    }
+                    let server = self.clone();
+                    connections.push(thread::spawn(move || server.serve_connection(stream)));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        for connection in connections {
+            let _ = connection.join();
+        }
+        self.close().map_err(io::Error::other)
+    }
+
+    fn serve_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let reader = Deadline {
+            stream: &stream,
+            deadline: Instant::now().checked_add(self.connection_timeout),
+        };
+        let response = match Request::read_from(reader) {
+            Ok(Some(mut request)) => {
+                request.remote_addr = stream.peer_addr().ok().map(|addr| addr.ip());
+                self.handle(&request)
+            }
+            Ok(None) => return Ok(()),
+            // read timeouts are `WouldBlock` on Unix
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                error_body(408, "request timed out")
+            }
+            Err(e) => error_body(400, &e.to_string()),
+        };
+        response.write_to(&stream)
+    }
+}
+
+// Reads a request off `stream` until `deadline`, as a read timeout alone
+// would let a client trickling it in byte by byte take as long as it likes.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Option<Instant>,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if left.is_some_and(|left| left.is_zero()) {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(left)?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+// Whether `target` is on the host the request was sent to, which would make
+// the new link redirect to this server again.
+fn is_own_host(request: &Request, target: &UrlType) -> bool {
+    let own = request
+        .header_value("Host")
+        .and_then(|host| UrlType::parse(&format!("http://{host}")).ok());
+    match (own.as_ref().and_then(UrlType::host_str), target.host_str()) {
+        (Some(own), Some(host)) => own.eq_ignore_ascii_case(host.trim_end_matches('.')),
+        _ => false,
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+// Asks for the password of the protected link `slug`, posting back to it.
+fn password_form(slug: &str, retry: bool) -> Response {
+    let slug = escape_html(slug);
+    let message = if retry {
+        "<p>Wrong password, try again.</p>"
+    } else {
+        ""
+    };
+    let page = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Password required</title></head>\n\
+         <body><form method=\"post\" action=\"/{slug}\">{message}\
+         <label>Password <input type=\"password\" name=\"password\" autofocus></label> \
+         <button type=\"submit\">Continue</button></form></body></html>\n"
+    );
+    Response::new(401)
+        .header("Cache-Control", "no-store")
+        .body("text/html; charset=utf-8", page)
+}
+
+// Open Graph tags for bots unfurling the link, and a refresh for anyone
+// else ending up here.
+fn preview_page(link: &Link) -> Result<Response> {
+    check_target(link.target())?;
+    let target = escape_html(link.target().as_str());
+    let preview = link.preview();
+    let title = preview
+        .and_then(|preview| preview.title.as_deref())
+        .map_or_else(|| target.clone(), escape_html);
+    let mut tags = format!(
+        "<meta property=\"og:url\" content=\"{target}\">\n\
+         <meta property=\"og:title\" content=\"{title}\">\n"
+    );
+    if let Some(description) = preview.and_then(|preview| preview.description.as_deref()) {
+        tags += &format!(
+            "<meta property=\"og:description\" content=\"{}\">\n",
+            escape_html(description)
+        );
+    }
+    if let Some(image) = preview.and_then(|preview| preview.image.as_ref()) {
+        tags += &format!(
+            "<meta property=\"og:image\" content=\"{}\">\n",
+            escape_html(image.as_str())
+        );
+    }
+    let page = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title>\n{tags}\
+         <meta http-equiv=\"refresh\" content=\"0; url={target}\"></head>\n\
+         <body><a href=\"{target}\">{title}</a></body></html>\n"
+    );
+    Ok(Response::new(200).body("text/html; charset=utf-8", page))
+}
+
+fn cache_control(link: &Link, status: RedirectStatus) -> String {
+    const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+    let per_visitor = !link.rules().is_empty() || !link.variants().is_empty();
+    let counted = link.max_uses().is_some() || link.is_protected();
+    if !status.is_permanent() || per_visitor || counted {
+        return "no-store".to_string();
+    }
+    let now = SystemTime::now();
+    let max_age = [link.expires_at(), link.scheduled_target().map(|(_, at)| at)]
+        .into_iter()
+        .flatten()
+        .map(|until| until.duration_since(now).unwrap_or_default())
+        .fold(MAX_AGE, Duration::min);
+    format!("public, max-age={}", max_age.as_secs())
+}
+
+fn json_body(request: &Request) -> Result<Value> {
+    let body = std::str::from_utf8(&request.body)
+        .map_err(|_| UrlManagerError::InvalidLink("body is not utf-8".to_string()))?;
+    json::parse(body).map_err(|e| UrlManagerError::InvalidLink(format!("body is not JSON: {e}")))
+}
+
+fn interstitial(body: &Value) -> Result<Option<bool>> {
+    match body.get("interstitial") {
+        None => Ok(None),
+        Some(Value::Bool(interstitial)) => Ok(Some(*interstitial)),
+        Some(_) => Err(UrlManagerError::InvalidLink(
+            "interstitial is not a boolean".to_string(),
+        )),
+    }
+}
+
+// The `expires_in` of a request, in seconds and at most `MAX_EXPIRES_IN`.
+fn expires_in(body: &Value) -> Result<Option<Duration>> {
+    let Some(value) = body.get("expires_in") else {
+        return Ok(None);
+    };
+    let secs = value.as_u64().ok_or_else(|| {
+        UrlManagerError::InvalidLink("expires_in is not a number of seconds".to_string())
+    })?;
+    if secs > MAX_EXPIRES_IN.as_secs() {
+        return Err(UrlManagerError::InvalidLink(format!(
+            "expires_in is more than {} seconds",
+            MAX_EXPIRES_IN.as_secs()
+        )));
+    }
+    Ok(Some(Duration::from_secs(secs)))
+}
+
+fn redirect_status(body: &Value) -> Result<Option<RedirectStatus>> {
+    let Some(value) = body.get("redirect_status") else {
+        return Ok(None);
+    };
+    let status = value
+        .as_u64()
+        .and_then(|code| u16::try_from(code).ok())
+        .ok_or_else(|| {
+            UrlManagerError::InvalidLink("redirect_status is not a number".to_string())
+        })?;
+    RedirectStatus::try_from(status)
+        .map(Some)
+        .map_err(|e| UrlManagerError::InvalidLink(e.to_string()))
+}
+
+// The ETag of a link is its version, see `Link::version`.
+fn etag(link: &Link) -> String {
+    format!("\"{}\"", link.version())
+}
+
+fn parse_etag(tag: &str) -> Option<u64> {
+    tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+}
+
+fn json_response(status: u16, body: Value) -> Response {
+    Response::new(status).body("application/json", body.to_string())
+}
+
+fn error_body(status: u16, message: &str) -> Response {
+    json_response(status, Value::object([("error", Value::from(message))]))
+}
+
+/// Maps an error to the status code clients should see.
+pub fn error_response(error: &UrlManagerError) -> Response {
+    let status = match error {
+        UrlManagerError::NotFound => 404,
+        UrlManagerError::PasswordRequired | UrlManagerError::Unauthorized(_) => 401,
+        UrlManagerError::Forbidden(_) => 403,
+        UrlManagerError::Expired | UrlManagerError::UsesExhausted => 410,
+        UrlManagerError::DuplicateId(_) | UrlManagerError::ShortcutCollision(_) => 409,
+        UrlManagerError::Conflict(_) => 412,
+        UrlManagerError::RateLimited(_) => 429,
+        UrlManagerError::InvalidUrl(_)
+        | UrlManagerError::InvalidSlug(_)
+        | UrlManagerError::InvalidLink(_) => 400,
+        _ => 500,
+    };
+    let response = error_body(status, &error.to_string());
+    match error {
+        UrlManagerError::RateLimited(retry_after) => response.header(
+            "Retry-After",
+            retry_after.as_secs_f64().ceil().max(1.0).to_string(),
+        ),
+        _ => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+    use crate::metadata::Preview;
+    use crate::{Domain, InMemoryClickRecorder, InMemoryLinkStore, KnownBots, RedirectRule};
+    use std::io::{Read, Write};
+    use std::net::IpAddr;
+
+    fn create(server: &Server<InMemoryLinkStore>, body: &str) -> Response {
+        server.handle(&Request::new("POST", "/api/links").body(body))
+    }
+
+    #[test]
+    fn test_create_redirect_delete() {
+        let server = Server::new(InMemoryLinkStore::new());
+
+        let response = create(
+            &server,
+            r#"{"target": "https://example.com/docs", "slug": "docs"}"#,
+        );
+        assert_eq!(response.status, 201);
+        let link = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        let id = link.get("id").and_then(Value::as_u64).unwrap();
+
+        let response = server.handle(&Request::new("GET", "/docs"));
+        assert_eq!(response.status, 302);
+        assert_eq!(
+            response.header_value("location"),
+            Some("https://example.com/docs")
+        );
+        let response = server.handle(&Request::new("HEAD", "/docs"));
+        assert_eq!(response.status, 302);
+        assert_eq!(
+            response.header_value("location"),
+            Some("https://example.com/docs")
+        );
+        let stored = sync::lock(&server.store).get(id).unwrap().unwrap();
+        assert_eq!(stored.hit_count(), 1, "HEAD counted as a hit");
+
+        assert_eq!(
+            create(
+                &server,
+                r#"{"target": "https://example.com", "slug": "docs"}"#
+            )
+            .status,
+            409
+        );
+        assert_eq!(create(&server, r#"{"target": "nope"}"#).status, 400);
+        assert_eq!(create(&server, "not json").status, 400);
+        // rejected by the parser's depth limit instead of overflowing the stack
+        assert_eq!(create(&server, &"[".repeat(500_000)).status, 400);
+        let script = r#"{"target": "javascript://example.com/%0Aalert(1)", "slug": "xss"}"#;
+        assert_eq!(create(&server, script).status, 400);
+        assert_eq!(server.handle(&Request::new("GET", "/xss")).status, 404);
+        for expires_in in [u64::MAX, MAX_EXPIRES_IN.as_secs() + 1] {
+            let body = format!(
+                r#"{{"target": "https://example.com", "slug": "forever", "expires_in": {expires_in}}}"#
+            );
+            assert_eq!(create(&server, &body).status, 400);
+        }
+
+        let path = format!("/api/links/{id}");
+        assert_eq!(server.handle(&Request::new("DELETE", &path)).status, 204);
+        assert_eq!(server.handle(&Request::new("DELETE", &path)).status, 404);
+        assert_eq!(server.handle(&Request::new("GET", "/docs")).status, 404);
+        assert_eq!(server.handle(&Request::new("GET", &path)).status, 404);
+        let trashed = sync::lock(&server.store).get(id).unwrap().unwrap();
+        assert!(trashed.is_deleted());
+        assert_eq!(
+            server.handle(&Request::new("PUT", "/api/links")).status,
+            405
+        );
+        assert_eq!(server.handle(&Request::new("GET", "/api/nope")).status, 404);
+        assert_eq!(
+            server
+                .handle(&Request::new("POST", &format!("{path}/nope")))
+                .status,
+            404
+        );
+        assert_eq!(
+            server
+                .handle(&Request::new("PUT", &format!("{path}/share")))
+                .status,
+            405
+        );
+    }
+
+    #[test]
+    fn test_concurrent_edits() {
+        let server = Server::new(InMemoryLinkStore::new());
+        let response = create(
+            &server,
+            r#"{"target": "https://example.com/docs", "slug": "docs"}"#,
+        );
+        let etag = response.header_value("etag").unwrap().to_string();
+        let link = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        let path = format!(
+            "/api/links/{}",
+            link.get("id").and_then(Value::as_u64).unwrap()
+        );
+        assert_eq!(
+            server
+                .handle(&Request::new("GET", &path))
+                .header_value("etag"),
+            Some(etag.as_str())
+        );
+        let patch = |if_match: Option<&str>, body: &str| {
+            let mut request = Request::new("PATCH", &path).body(body);
+            if let Some(tag) = if_match {
+                request = request.header("If-Match", tag);
+            }
+            server.handle(&request)
+        };
+
+        let first = r#"{"target": "https://example.com/v2", "interstitial": true}"#;
+        assert_eq!(patch(None, first).status, 428);
+        assert_eq!(patch(Some("v1"), first).status, 400);
+        let response = patch(Some(&etag), first);
+        assert_eq!(response.status, 200);
+        let changed = response.header_value("etag").unwrap().to_string();
+        assert_ne!(changed, etag);
+        // a second admin still holding the first version
+        let response = patch(Some(&etag), r#"{"target": "https://example.com/v3"}"#);
+        assert_eq!(response.status, 412);
+
+        let stored = sync::lock(&server.store).resolve("docs").unwrap();
+        assert_eq!(stored.target().as_str(), "https://example.com/v2");
+        assert!(stored.interstitial());
+        assert_eq!(
+            patch(Some(&changed), r#"{"redirect_status": 200}"#).status,
+            400
+        );
+        assert_eq!(
+            patch(
+                Some("*"),
+                r#"{"target": "https://example.com/v3", "interstitial": false}"#
+            )
+            .status,
+            200
+        );
+        assert_eq!(
+            server
+                .handle(&Request::new("GET", "/docs"))
+                .header_value("location"),
+            Some("https://example.com/v3")
+        );
+        let script = r#"{"target": "javascript://example.com/%0Aalert(1)"}"#;
+        assert_eq!(patch(Some("*"), script).status, 400);
+    }
+
+    #[test]
+    fn test_events() {
+        let bus = EventBus::new();
+        let kinds = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&kinds);
+        bus.subscribe(move |event: &Event| seen.lock().unwrap().push(event.kind()));
+        let server = Server::new(InMemoryLinkStore::new()).events(bus);
+
+        let response = create(
+            &server,
+            r#"{"target": "https://example.com", "slug": "docs"}"#,
+        );
+        let link = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        let id = link.get("id").and_then(Value::as_u64).unwrap();
+        server.handle(&Request::new("GET", "/docs"));
+        server.handle(&Request::new("GET", "/nope"));
+        server.handle(&Request::new("DELETE", &format!("/api/links/{id}")));
+        assert_eq!(
+            *kinds.lock().unwrap(),
+            [
+                EventKind::LinkCreated,
+                EventKind::LinkResolved,
+                EventKind::LinkDeleted
+            ]
+        );
+    }
+
+    #[test]
+    fn test_policy() {
+        let server = Server::new(InMemoryLinkStore::new())
+            .policy(UrlPolicy::new().block_private_addresses());
+        let response = create(&server, r#"{"target": "http://169.254.169.254/latest"}"#);
+        assert_eq!(response.status, 403);
+        assert_eq!(
+            create(&server, r#"{"target": "https://example.com"}"#).status,
+            201
+        );
+    }
+
+    #[test]
+    fn test_password() {
+        let server = Server::new(InMemoryLinkStore::new());
+        let response = create(
+            &server,
+            r#"{"target": "https://example.com/secret", "slug": "secret", "password": "hunter2"}"#,
+        );
+        assert_eq!(response.status, 201);
+        let body = String::from_utf8(response.body).unwrap();
+        assert!(body.contains(r#""protected":true"#));
+        assert!(!body.contains("pbkdf2"));
+
+        let response = server.handle(&Request::new("GET", "/secret"));
+        assert_eq!(response.status, 401);
+        assert!(String::from_utf8(response.body)
+            .unwrap()
+            .contains(r#"action="/secret""#));
+
+        let submit = |password: &str| {
+            server.handle(
+                &Request::new("POST", "/secret")
+                    .header("Content-Type", "application/x-www-form-urlencoded")
+                    .body(format!("password={password}")),
+            )
+        };
+        let response = submit("nope");
+        assert_eq!(response.status, 401);
+        assert!(String::from_utf8(response.body)
+            .unwrap()
+            .contains("Wrong password"));
+        let response = submit("hunter%32");
+        assert_eq!(response.status, 302);
+        assert_eq!(
+            response.header_value("Location"),
+            Some("https://example.com/secret")
+        );
+    }
+
+    #[test]
+    fn test_signed_links() {
+        assert!(Server::new(InMemoryLinkStore::new())
+            .signing_key("short")
+            .is_err());
+        let server = Server::new(InMemoryLinkStore::new())
+            .signing_key("0123456789abcdef0123456789abcdef")
+            .unwrap();
+        assert_eq!(
+            create(&server, r#"{"target": "https://example.com"}"#).status,
+            400
+        );
+        let response = create(
+            &server,
+            r#"{"target": "https://example.com/invite", "slug": "invite", "expires_in": 3600}"#,
+        );
+        assert_eq!(response.status, 201);
+        let body = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        let token = body.get("token").and_then(Value::as_str).unwrap();
+        assert!(token.starts_with("invite."));
+
+        let response = server.handle(&Request::new("GET", &format!("/{token}")));
+        assert_eq!(response.status, 302);
+        assert_eq!(
+            response.header_value("Location"),
+            Some("https://example.com/invite")
+        );
+        assert_eq!(server.handle(&Request::new("GET", "/invite")).status, 403);
+        let forged = token.replacen("invite", "other", 1);
+        assert_eq!(
+            server
+                .handle(&Request::new("GET", &format!("/{forged}")))
+                .status,
+            403
+        );
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let keys = ApiKeyStore::new();
+        keys.insert("reader", ApiKey::new("dashboard", Scope::ReadOnly));
+        keys.insert("writer", ApiKey::new("app", Scope::Create).rate_limit(2));
+        keys.insert("editor", ApiKey::new("cms", Scope::Create));
+        keys.insert("root", ApiKey::new("ops", Scope::Admin));
+        let server = Server::new(InMemoryLinkStore::new()).api_keys(keys);
+        let post = |key: &str| {
+            server.handle(
+                &Request::new("POST", "/api/links")
+                    .header("Authorization", &format!("Bearer {key}"))
+                    .body(r#"{"target": "https://example.com", "slug": "docs"}"#),
+            )
+        };
+
+        assert_eq!(
+            create(&server, r#"{"target": "https://example.com"}"#).status,
+            401
+        );
+        assert_eq!(post("reader").status, 403);
+        let response = post("writer");
+        assert_eq!(response.status, 201);
+        let body = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        let path = format!(
+            "/api/links/{}",
+            body.get("id").and_then(Value::as_u64).unwrap()
+        );
+        assert_eq!(post("writer").status, 409);
+        assert_eq!(post("writer").status, 429);
+
+        let request = |method: &str, key: &str| {
+            server.handle(&Request::new(method, &path).header("X-API-Key", key))
+        };
+        assert_eq!(request("GET", "reader").status, 200);
+        assert_eq!(request("DELETE", "reader").status, 403);
+        // deleting a link of no owner takes Admin
+        assert_eq!(request("DELETE", "editor").status, 403);
+        assert_eq!(request("GET", "editor").status, 200);
+        assert_eq!(request("DELETE", "root").status, 204);
+        assert_eq!(request("GET", "nope").status, 401);
+        assert_eq!(server.handle(&Request::new("GET", "/docs")).status, 404);
+    }
+
+    #[test]
+    fn test_list_and_clicks() {
+        let recorder = Arc::new(InMemoryClickRecorder::new());
+        let server = Server::new(InMemoryLinkStore::new())
+            .click_recorder(Arc::clone(&recorder))
+            .analytics(Arc::clone(&recorder));
+        for (slug, target) in [
+            ("docs", "https://example.com/docs"),
+            ("blog", "https://blog.example.org/"),
+            ("shop", "https://bücher.example/"),
+        ] {
+            create(
+                &server,
+                &format!(r#"{{"target": "{target}", "slug": "{slug}"}}"#),
+            );
+        }
+        let list = |query: &str| {
+            let response = server.handle(&Request::new("GET", &format!("/api/links{query}")));
+            assert_eq!(response.status, 200, "{query}");
+            let body = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+            let Value::Array(links) = body else {
+                panic!("{query} didn't list links");
+            };
+            links
+                .iter()
+                .map(|link| {
+                    link.get("shortcut")
+                        .and_then(Value::as_str)
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut all = list("");
+        all.sort();
+        assert_eq!(all, ["blog", "docs", "shop"]);
+        assert_eq!(list("?q=EXAMPLE.com&tag="), ["docs"]);
+        assert_eq!(list("?q=bücher"), ["shop"]);
+        assert_eq!(list("?q=bl"), ["blog"]);
+        assert_eq!(list("?offset=1&limit=1"), [list("")[1].clone()]);
+        assert_eq!(list("?offset=2").len(), 1);
+        assert!(list("?tag=none").is_empty());
+        assert_eq!(
+            server
+                .handle(&Request::new("GET", "/api/links?limit=many"))
+                .status,
+            400
+        );
+
+        server.handle(&Request::new("GET", "/docs").header("Referer", "https://news.example/"));
+        server.handle(&Request::new("GET", "/docs"));
+        let id = sync::lock(&server.store)
+            .get_by_shortcut("docs")
+            .unwrap()
+            .unwrap()
+            .id();
+        let response = server.handle(&Request::new("GET", &format!("/api/links/{id}/clicks")));
+        assert_eq!(response.status, 200);
+        let body = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        assert_eq!(body.get("total").and_then(Value::as_u64), Some(2));
+        let Some(Value::Array(days)) = body.get("days") else {
+            panic!("no days");
+        };
+        assert_eq!(days.len(), 1);
+        let Some(Value::Array(referrers)) = body.get("top_referrers") else {
+            panic!("no referrers");
+        };
+        assert_eq!(
+            referrers[0].get("value").and_then(Value::as_str),
+            Some("https://news.example/")
+        );
+        assert_eq!(
+            server
+                .handle(&Request::new("GET", "/api/links/1/clicks"))
+                .status,
+            404
+        );
+    }
+
+    #[cfg(feature = "admin-ui")]
+    #[test]
+    fn test_admin_page() {
+        let server = Server::new(InMemoryLinkStore::new());
+        let response = server.handle(&Request::new("GET", "/admin"));
+        assert_eq!(response.status, 200);
+        let page = std::str::from_utf8(&response.body).unwrap();
+        assert!(page.contains("/api/links"));
+    }
+
+    #[test]
+    fn test_domains() {
+        let domains = DomainRegistry::new();
+        for (host, namespace) in [("go.acme.com", "acme"), ("lnk.beta.io", "beta")] {
+            domains
+                .claim(Domain::new(host, Namespace::new(namespace).unwrap()).unwrap())
+                .unwrap();
+        }
+        let server = Server::new(InMemoryLinkStore::new()).domains(domains);
+        for (host, target) in [
+            ("go.acme.com", "https://acme.example/"),
+            ("lnk.beta.io:8080", "https://beta.example/"),
+            ("sho.rt", "https://example.com/"),
+        ] {
+            let response = server.handle(
+                &Request::new("POST", "/api/links")
+                    .header("Host", host)
+                    .body(format!(r#"{{"target": "{target}", "slug": "x"}}"#)),
+            );
+            assert_eq!(response.status, 201, "{host}");
+        }
+        for (host, target) in [
+            ("GO.acme.com", "https://acme.example/"),
+            ("lnk.beta.io", "https://beta.example/"),
+            ("sho.rt", "https://example.com/"),
+            ("unclaimed.example", "https://example.com/"),
+        ] {
+            let response = server.handle(&Request::new("GET", "/x").header("Host", host));
+            assert_eq!(response.header_value("Location"), Some(target), "{host}");
+        }
+    }
+
+    #[test]
+    fn test_token_verifier() {
+        let keys = ApiKeyStore::new();
+        keys.insert("root", ApiKey::new("ops", Scope::Admin));
+        let server = Server::new(InMemoryLinkStore::new())
+            .api_keys(keys)
+            .token_verifier(|token: &str| match token {
+                "id-token-alice" => Ok(ApiKey::new("alice@example.com", Scope::Create).owner_id(1)),
+                "id-token-viewer" => Ok(ApiKey::new("viewer@example.com", Scope::ReadOnly)),
+                _ => Err(UrlManagerError::Unauthorized("invalid token".into())),
+            });
+        let post = |token: &str| {
+            server.handle(
+                &Request::new("POST", "/api/links")
+                    .header("Authorization", &format!("Bearer {token}"))
+                    .body(r#"{"target": "https://example.com"}"#),
+            )
+        };
+        let response = post("id-token-alice");
+        assert_eq!(response.status, 201);
+        let body = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        assert_eq!(body.get("owner_id").and_then(Value::as_u64), Some(1));
+        assert_eq!(post("id-token-viewer").status, 403);
+        assert_eq!(post("forged").status, 401);
+        assert_eq!(post("root").status, 201);
+        assert_eq!(
+            Server::new(InMemoryLinkStore::new())
+                .token_verifier(|_: &str| Ok(ApiKey::new("anyone", Scope::Admin)))
+                .handle(&Request::new("GET", "/api/links/1"))
+                .status,
+            401
+        );
+    }
+
+    #[test]
+    fn test_owner_keys() {
+        let keys = ApiKeyStore::new();
+        keys.insert("alice", ApiKey::new("alice", Scope::Create).owner_id(1));
+        keys.insert("bob", ApiKey::new("bob", Scope::Create).owner_id(2));
+        keys.insert("root", ApiKey::new("ops", Scope::Admin).owner_id(3));
+        let server = Server::new(InMemoryLinkStore::new()).api_keys(keys);
+        let response = server.handle(
+            &Request::new("POST", "/api/links")
+                .header("X-API-Key", "alice")
+                .body(r#"{"target": "https://example.com"}"#),
+        );
+        assert_eq!(response.status, 201);
+        let body = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        assert_eq!(body.get("owner_id").and_then(Value::as_u64), Some(1));
+        let path = format!(
+            "/api/links/{}",
+            body.get("id").and_then(Value::as_u64).unwrap()
+        );
+
+        let request = |method: &str, key: &str| {
+            server.handle(&Request::new(method, &path).header("X-API-Key", key))
+        };
+        assert_eq!(request("GET", "bob").status, 404);
+        assert_eq!(request("DELETE", "bob").status, 404);
+        assert_eq!(request("GET", "root").status, 200);
+        assert_eq!(request("GET", "alice").status, 200);
+        assert_eq!(request("DELETE", "alice").status, 204);
+        assert_eq!(request("GET", "alice").status, 404);
+    }
+
+    #[test]
+    fn test_do_not_track() {
+        let recorder = Arc::new(InMemoryClickRecorder::new());
+        let server = Server::new(InMemoryLinkStore::new())
+            .click_recorder(Arc::clone(&recorder))
+            .honor_do_not_track(true);
+        create(
+            &server,
+            r#"{"target": "https://example.com", "slug": "docs"}"#,
+        );
+        let visit = |header: Option<(&str, &str)>| {
+            let mut request = Request::new("GET", "/docs")
+                .header("Referer", "https://news.example/")
+                .remote_addr([192, 0, 2, 1].into());
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            assert_eq!(server.handle(&request).status, 302);
+        };
+        visit(Some(("DNT", "1")));
+        visit(Some(("Sec-GPC", "1")));
+        visit(Some(("DNT", "0")));
+
+        let clicks = recorder.all_clicks();
+        assert_eq!(clicks.len(), 3);
+        assert!(clicks[..2]
+            .iter()
+            .all(|click| click.referrer.is_none() && click.visitor.is_none()));
+        assert_eq!(clicks[2].visitor.as_deref(), Some("192.0.2.1"));
+        assert_eq!(
+            sync::lock(&server.store)
+                .resolve("docs")
+                .unwrap()
+                .hit_count(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_share_tokens() {
+        let keys = ApiKeyStore::new();
+        keys.insert("alice", ApiKey::new("alice", Scope::Create).owner_id(1));
+        keys.insert("bob", ApiKey::new("bob", Scope::Create).owner_id(2));
+        let shares = Arc::new(ShareTokens::new(b"0123456789abcdef".to_vec()).unwrap());
+        let server = Server::new(InMemoryLinkStore::new())
+            .api_keys(keys)
+            .share_tokens(Arc::clone(&shares));
+        let response = server.handle(
+            &Request::new("POST", "/api/links")
+                .header("X-API-Key", "alice")
+                .body(r#"{"target": "https://example.com/draft"}"#),
+        );
+        let body = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        let path = format!(
+            "/api/links/{}/share",
+            body.get("id").and_then(Value::as_u64).unwrap()
+        );
+        let share = |key: &str, body: &str| {
+            server.handle(
+                &Request::new("POST", &path)
+                    .header("X-API-Key", key)
+                    .body(body),
+            )
+        };
+
+        assert_eq!(share("bob", "").status, 404);
+        assert_eq!(share("alice", r#"{"expires_in": "soon"}"#).status, 400);
+        let forever = format!(r#"{{"expires_in": {}}}"#, u64::MAX);
+        assert_eq!(share("alice", &forever).status, 400);
+        let response = share("alice", r#"{"expires_in": 600}"#);
+        assert_eq!(response.status, 201);
+        let body = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        let url = body.get("url").and_then(Value::as_str).unwrap();
+        let token = body.get("token").and_then(Value::as_str).unwrap();
+
+        // no API key needed, and no hit counted
+        let response = server.handle(&Request::new("GET", url));
+        assert_eq!(response.status, 200);
+        assert!(std::str::from_utf8(&response.body)
+            .unwrap()
+            .contains("https://example.com/draft"));
+        assert_eq!(
+            sync::lock(&server.store).list(0, 1).unwrap()[0].hit_count(),
+            0
+        );
+        let forged = url.replacen('.', "x.", 1);
+        assert_eq!(server.handle(&Request::new("GET", &forged)).status, 403);
+
+        let revoke = |key: &str| {
+            server.handle(
+                &Request::new("DELETE", &format!("{path}/{token}")).header("X-API-Key", key),
+            )
+        };
+        assert_eq!(revoke("bob").status, 404);
+        assert_eq!(revoke("alice").status, 204);
+        assert_eq!(server.handle(&Request::new("GET", url)).status, 403);
+        assert_eq!(shares.revoked(), 1);
+        assert_eq!(
+            Server::new(InMemoryLinkStore::new())
+                .handle(&Request::new("GET", url))
+                .status,
+            404
+        );
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let server = Server::new(InMemoryLinkStore::new())
+            .create_rate_limit(RateLimiter::new(1, Duration::from_secs(60)).unwrap())
+            .resolve_rate_limit(RateLimiter::new(2, Duration::from_secs(60)).unwrap());
+        let from = |ip: [u8; 4], request: Request| server.handle(&request.remote_addr(ip.into()));
+        let post =
+            || Request::new("POST", "/api/links").body(r#"{"target": "https://example.com"}"#);
+        assert_eq!(from([192, 0, 2, 1], post()).status, 201);
+        let response = from([192, 0, 2, 1], post());
+        assert_eq!(response.status, 429);
+        assert_eq!(response.header_value("Retry-After"), Some("60"));
+        assert_eq!(from([192, 0, 2, 2], post()).status, 201);
+
+        for status in [404, 404, 429] {
+            assert_eq!(
+                from([192, 0, 2, 3], Request::new("GET", "/guess")).status,
+                status
+            );
+        }
+        assert_eq!(
+            from([192, 0, 2, 4], Request::new("GET", "/guess")).status,
+            404
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics() {
+        let server = Server::new(InMemoryLinkStore::new());
+        create(
+            &server,
+            r#"{"target": "https://example.com", "slug": "counted"}"#,
+        );
+        server.handle(&Request::new("GET", "/counted"));
+        server.handle(&Request::new("GET", "/uncounted"));
+
+        let response = server.handle(&Request::new("GET", "/metrics"));
+        assert_eq!(response.status, 200);
+        let body = String::from_utf8(response.body).unwrap();
+        for line in [
+            "# TYPE url_manager_links_created_total counter",
+            "# TYPE url_manager_not_found_total counter",
+            "url_manager_store_duration_seconds_count{operation=\"create\"}",
+            "url_manager_store_duration_seconds_count{operation=\"resolve\"}",
+        ] {
+            assert!(body.contains(line), "missing {line}");
+        }
+
+        let keys = ApiKeyStore::new();
+        keys.insert("reader", ApiKey::new("prometheus", Scope::ReadOnly));
+        let server = server.api_keys(keys);
+        assert_eq!(server.handle(&Request::new("GET", "/metrics")).status, 401);
+        let request = Request::new("GET", "/metrics").header("X-API-Key", "reader");
+        assert_eq!(server.handle(&request).status, 200);
+    }
+
+    #[test]
+    fn test_own_host() {
+        let server = Server::new(InMemoryLinkStore::new());
+        let request = |target: &str| {
+            Request::new("POST", "/api/links")
+                .header("Host", "sho.rt:8080")
+                .body(format!(r#"{{"target": "{target}"}}"#))
+        };
+        assert_eq!(server.handle(&request("https://SHO.RT/abc")).status, 403);
+        assert_eq!(server.handle(&request("http://sho.rt.:8080/")).status, 403);
+        assert_eq!(server.handle(&request("https://example.com/")).status, 201);
+    }
+
+    #[test]
+    fn test_geoip_rules() {
+        let mut store = InMemoryLinkStore::new();
+        let link = Link::builder()
+            .target("https://example.com/")
+            .slug("shop")
+            .rule(RedirectRule::new("https://example.de/".parse().unwrap()).countries(["DE"]))
+            .build()
+            .unwrap();
+        store.create(link).unwrap();
+        let server = Server::new(store)
+            .geoip(|addr: IpAddr| (addr == IpAddr::from([192, 0, 2, 1])).then(|| "DE".to_string()));
+        let from = |addr: [u8; 4]| {
+            let request = Request::new("GET", "/shop").remote_addr(IpAddr::from(addr));
+            server
+                .handle(&request)
+                .header_value("location")
+                .map(str::to_string)
+        };
+        assert_eq!(from([192, 0, 2, 1]).as_deref(), Some("https://example.de/"));
+        assert_eq!(
+            from([198, 51, 100, 1]).as_deref(),
+            Some("https://example.com/")
+        );
+    }
+
+    #[test]
+    fn test_bots() {
+        let mut store = InMemoryLinkStore::new();
+        let mut link = Link::builder()
+            .target("https://example.com/launch")
+            .slug("launch")
+            .build()
+            .unwrap();
+        link.set_preview(Preview {
+            title: Some("Launch <day>".to_string()),
+            ..Preview::default()
+        });
+        store.create(link.clone()).unwrap();
+        let get = |server: &Server<InMemoryLinkStore>, user_agent: &str| {
+            server.handle(&Request::new("GET", "/launch").header("User-Agent", user_agent))
+        };
+
+        let server = Server::new(store).bot_detector(KnownBots::new(), OnBot::Uncounted);
+        assert_eq!(get(&server, "Twitterbot/1.0").status, 302);
+        assert_eq!(get(&server, "Mozilla/5.0").status, 302);
+        let hits = |server: &Server<InMemoryLinkStore>| {
+            let store = server.store.lock().unwrap();
+            store.get(link.id()).unwrap().unwrap().hit_count()
+        };
+        assert_eq!(hits(&server), 1);
+
+        let server = server.bot_detector(KnownBots::new(), OnBot::Preview);
+        let response = get(&server, "Slackbot-LinkExpanding 1.0");
+        assert_eq!(response.status, 200);
+        let page = String::from_utf8(response.body).unwrap();
+        assert!(page.contains(r#"<meta property="og:title" content="Launch &lt;day&gt;">"#));
+        assert!(page.contains(r#"content="0; url=https://example.com/launch""#));
+        assert_eq!(get(&server, "Mozilla/5.0").status, 302);
+        assert_eq!(hits(&server), 2);
+    }
+
+    #[test]
+    fn test_script_target() {
+        // as stored before targets were checked
+        let mut link = Link::builder()
+            .target("https://example.com/")
+            .slug("xss")
+            .interstitial(true)
+            .build()
+            .unwrap();
+        let script = UrlType::parse("javascript://example.com/%0Aalert(1)").unwrap();
+        link.schedule_target(script, SystemTime::UNIX_EPOCH);
+        let mut store = InMemoryLinkStore::new();
+        store.create(link).unwrap();
+        let server = Server::new(store);
+        for path in ["/xss", "/xss+"] {
+            let response = server.handle(&Request::new("GET", path));
+            assert_eq!(response.status, 400);
+            assert_eq!(
+                response.header_value("content-type"),
+                Some("application/json")
+            );
+        }
+        let server = server.bot_detector(KnownBots::new(), OnBot::Preview);
+        let response =
+            server.handle(&Request::new("GET", "/xss").header("User-Agent", "Twitterbot/1.0"));
+        assert_eq!(response.status, 400);
+    }
+
+    #[test]
+    fn test_interstitial() {
+        let mut store = InMemoryLinkStore::new();
+        let notice = Link::builder()
+            .target("https://example.com/terms")
+            .slug("terms")
+            .interstitial(true)
+            .build()
+            .unwrap();
+        store.create(notice.clone()).unwrap();
+        store
+            .create_with_slug("docs", "https://example.com/docs".parse().unwrap())
+            .unwrap();
+        let server =
+            Server::new(store).interstitial(Interstitial::new("to {target} via {shortcut}"));
+        let get = |server: &Server<InMemoryLinkStore>, path: &str| {
+            let response = server.handle(&Request::new("GET", path));
+            (response.status, String::from_utf8(response.body).unwrap())
+        };
+
+        assert_eq!(
+            get(&server, "/terms"),
+            (200, "to https://example.com/terms via terms".to_string())
+        );
+        assert_eq!(get(&server, "/docs").0, 302);
+        assert_eq!(
+            get(&server, "/docs+"),
+            (200, "to https://example.com/docs via docs".to_string())
+        );
+        assert_eq!(get(&server, "/nope+").0, 404);
+        let hits = server
+            .store
+            .lock()
+            .unwrap()
+            .get(notice.id())
+            .unwrap()
+            .unwrap()
+            .hit_count();
+        assert_eq!(hits, 1);
+
+        let server = server.interstitial_for_all(true);
+        assert_eq!(get(&server, "/docs").0, 200);
+
+        let response = server.handle(
+            &Request::new("POST", "/api/links")
+                .body(r#"{"target": "https://example.com/x", "interstitial": true}"#),
+        );
+        assert_eq!(response.status, 201);
+        let body = json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap();
+        assert_eq!(body.get("interstitial"), Some(&Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let server = Server::new(InMemoryLinkStore::new()).case_insensitive(true);
+        let response = server.handle(
+            &Request::new("POST", "/api/links")
+                .body(r#"{"target": "https://example.com/docs", "slug": "Docs"}"#),
+        );
+        assert_eq!(response.status, 201);
+        let response = server.handle(&Request::new("GET", "/DOCS"));
+        assert_eq!(response.status, 302);
+        assert_eq!(
+            response.header_value("location"),
+            Some("https://example.com/docs")
+        );
+    }
+
+    #[test]
+    fn test_redirect_status() {
+        let mut store = InMemoryLinkStore::new();
+        store
+            .create_with_slug(
+                "docs",
+                crate::UrlType::parse("https://example.com").unwrap(),
+            )
+            .unwrap();
+        let expiring = Link::builder()
+            .target("https://example.com/sale")
+            .slug("sale")
+            .expires_in(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        store.create(expiring).unwrap();
+        let temporary = Link::builder()
+            .target("https://example.com/today")
+            .slug("today")
+            .redirect_status(RedirectStatus::TemporaryRedirect)
+            .build()
+            .unwrap();
+        store.create(temporary).unwrap();
+        let server = Server::new(store).redirect_status(301).unwrap();
+        let response = server.handle(&Request::new("GET", "/docs"));
+        assert_eq!(response.status, 301);
+        assert_eq!(
+            response.header_value("cache-control"),
+            Some("public, max-age=86400")
+        );
+        let response = server.handle(&Request::new("GET", "/sale"));
+        let max_age = response.header_value("cache-control").unwrap();
+        assert!(
+            ["public, max-age=59", "public, max-age=60"].contains(&max_age),
+            "{max_age}"
+        );
+        let response = server.handle(&Request::new("GET", "/today"));
+        assert_eq!(response.status, 307);
+        assert_eq!(response.header_value("cache-control"), Some("no-store"));
+        let link = server
+            .store
+            .lock()
+            .unwrap()
+            .get_by_shortcut("docs")
+            .unwrap();
+        assert_eq!(link.map(|link| link.hit_count()), Some(1));
+
+        assert!(Server::new(InMemoryLinkStore::new())
+            .redirect_status(200)
+            .is_err());
+    }
+
+    #[test]
+    fn test_survives_poisoned_store() {
+        let server = Server::new(InMemoryLinkStore::new());
+        let store = Arc::clone(&server.store);
+        let _ = thread::spawn(move || {
+            let _guard = store.lock().unwrap();
+            panic!("poisoning the store");
+        })
+        .join();
+
+        let response = create(
+            &server,
+            r#"{"target": "https://example.com", "slug": "docs"}"#,
+        );
+        assert_eq!(response.status, 201);
+        assert_eq!(server.handle(&Request::new("GET", "/docs")).status, 302);
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(InMemoryLinkStore::new());
+        thread::spawn(move || server.serve(listener));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"), "{response}");
+    }
+    #[test]
+    fn test_idle_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let server = Server::new(InMemoryLinkStore::new())
+            .connection_timeout(Duration::from_millis(500))
+            .max_connections(1)
+            .shutdown(shutdown.clone());
+        let serving = thread::spawn(move || server.serve(listener));
+
+        // sends nothing, taking up the only connection
+        let mut idle = TcpStream::connect(address).unwrap();
+        idle.write_all(b"GET /").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        let mut busy = TcpStream::connect(address).unwrap();
+        let mut response = String::new();
+        busy.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+
+        let start = Instant::now();
+        shutdown.request();
+        while !serving.is_finished() {
+            assert!(start.elapsed() < Duration::from_secs(5), "shutdown hangs");
+            thread::sleep(Duration::from_millis(10));
+        }
+        serving.join().unwrap().unwrap();
+        let mut response = String::new();
+        idle.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408"), "{response}");
+    }
+
+    #[test]
+    fn test_health_and_shutdown() {
+        #[derive(Default)]
+        struct Flushed(std::sync::atomic::AtomicBool);
+
+        impl ClickRecorder for Flushed {
+            fn record(&self, _: crate::Click) -> Result<()> {
+                Ok(())
+            }
+
+            fn flush(&self) -> Result<()> {
+                self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let flushed = Arc::new(Flushed::default());
+        let server = Server::new(InMemoryLinkStore::new())
+            .click_recorder(Arc::clone(&flushed))
+            .shutdown(shutdown.clone());
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let serving = {
+            let server = server.clone();
+            thread::spawn(move || server.serve(listener))
+        };
+
+        assert!(get("/healthz").starts_with("HTTP/1.1 200"));
+        let ready = get("/readyz");
+        assert!(ready.starts_with("HTTP/1.1 200"), "{ready}");
+        assert!(ready.ends_with(r#"{"status":"ok"}"#), "{ready}");
+
+        shutdown.request();
+        serving.join().unwrap().unwrap();
+        assert!(flushed.0.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(TcpStream::connect(address).is_err());
+        let ready = server.handle(&Request::new("GET", "/readyz"));
+        assert_eq!(ready.status, 503);
+        assert_eq!(server.handle(&Request::new("GET", "/healthz")).status, 200);
+    }
+}
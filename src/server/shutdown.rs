@@ -0,0 +1,68 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Set by the signal handler installed by `Shutdown::on_signals`.
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+/// Tells a [`Server`](super::Server) to stop, see [`Server::shutdown`](super::Server::shutdown).
+///
+/// Clones share the same request, so one can be kept to call
+/// [`Shutdown::request`] on while the server holds another.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    signals: bool,
+}
+
+impl Shutdown {
+    /// A shutdown requested only through [`Shutdown::request`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A shutdown that is also requested by SIGINT or SIGTERM, e.g. Ctrl-C
+    /// or `docker stop`. Fails with `Unsupported` on platforms without
+    /// these signals.
+    pub fn on_signals() -> io::Result<Self> {
+        install_handlers()?;
+        Ok(Shutdown {
+            requested: Arc::default(),
+            signals: true,
+        })
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst) || (self.signals && SIGNALLED.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(unix)]
+fn install_handlers() -> io::Result<()> {
+    extern "C" fn on_signal(_: libc::c_int) {
+        SIGNALLED.store(true, Ordering::SeqCst);
+    }
+
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only stores to an atomic, which is
+        // async-signal-safe.
+        let previous =
+            unsafe { libc::signal(signal, on_signal as *const () as libc::sighandler_t) };
+        if previous == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn install_handlers() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "shutting down on signals needs a Unix platform",
+    ))
+}
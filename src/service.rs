@@ -0,0 +1,949 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::clicks;
+use crate::events::{self, Event, EventBus};
+use crate::link::check_target;
+use crate::{
+    metrics, unique_code, unique_id, Action, Base62, CodeGenerator, Link, LinkBuilder, LinkStore,
+    Namespace, RandomIds, RateLimiter, RedirectStatus, Result, SlugFilter, UrlManagerError,
+    UrlPolicy, UrlType, User,
+};
+
+/// A link just created by [`LinkService`], with the URL to hand out.
+#[derive(Debug, Clone)]
+pub struct ShortLink {
+    pub link: Link,
+    /// The shortcut joined onto the service's base URL, if it has one.
+    pub url: Option<UrlType>,
+}
+
+/// A link for [`LinkService::create_batch`] to create: a target, under
+/// `slug` if one is given and under a generated shortcut otherwise.
+#[derive(Debug, Clone)]
+pub struct NewLink {
+    target: String,
+    slug: Option<String>,
+}
+
+impl NewLink {
+    pub fn new(target: impl Into<String>) -> Self {
+        NewLink {
+            target: target.into(),
+            slug: None,
+        }
+    }
+
+    pub fn slug(mut self, slug: impl Into<String>) -> Self {
+        self.slug = Some(slug.into());
+        self
+    }
+}
+
+/// The operations of a shortener on top of a store: shortening, resolving
+/// and expiring links.
+///
+/// Shortcuts come from the generator and are checked against the store,
+/// ids are random and checked the same way. This is the entry point the
+/// command line uses.
+///
+/// Targets on the service's own hosts, the base URL's and any added with
+/// [`LinkService::own_host`], are short links themselves. By default they
+/// are refused so no redirect loops can be built; with
+/// [`LinkService::max_chain_depth`] they are followed through the store and
+/// allowed if they end at an outside target within that many hops.
+///
+/// ```
+/// # use url_manager::{Base62, InMemoryLinkStore, LinkService};
+/// let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new().min_length(6))
+///     .base_url("https://sho.rt/")?;
+/// let short = service.shorten("https://www.example.com/docs")?;
+/// let shortcut = short.link.shortcut().unwrap();
+/// assert_eq!(short.url.unwrap().as_str(), format!("https://sho.rt/{shortcut}"));
+/// assert_eq!(
+///     service.resolve(shortcut)?.target().as_str(),
+///     "https://www.example.com/docs"
+/// );
+/// # Ok::<(), url_manager::UrlManagerError>(())
+/// ```
+#[derive(Debug)]
+pub struct LinkService<S, G = Base62> {
+    store: S,
+    generator: G,
+    namespace: Option<Namespace>,
+    user: Option<User>,
+    policy: UrlPolicy,
+    base_url: Option<UrlType>,
+    own_hosts: Vec<String>,
+    max_chain_depth: usize,
+    limiter: Option<Arc<RateLimiter>>,
+    events: Option<EventBus>,
+    redirect_status: Option<RedirectStatus>,
+    slug_filter: SlugFilter,
+    case_insensitive: bool,
+}
+
+impl<S: LinkStore, G: CodeGenerator> LinkService<S, G> {
+    pub fn new(store: S, generator: G) -> Self {
+        LinkService {
+            store,
+            generator,
+            namespace: None,
+            user: None,
+            policy: UrlPolicy::default(),
+            base_url: None,
+            own_hosts: Vec::new(),
+            max_chain_depth: 0,
+            limiter: None,
+            events: None,
+            redirect_status: None,
+            slug_filter: SlugFilter::new(),
+            case_insensitive: false,
+        }
+    }
+
+    /// Works on the shortcuts in `namespace` instead of the shared ones.
+    pub fn namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Acts on behalf of `user`: links shortened are theirs, and only links
+    /// they [`User::can_manage`] can be changed, others fail with
+    /// `NotFound`. Operations their [`Role`](crate::Role) doesn't allow
+    /// fail with `Forbidden`. Without a user, everything is allowed.
+    pub fn user(mut self, user: User) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// Refuses to shorten targets that break `policy`.
+    pub fn policy(mut self, policy: UrlPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Where shortcuts are served, e.g. `https://sho.rt/`; used for [`ShortLink::url`].
+    pub fn base_url<T>(mut self, base_url: T) -> Result<Self>
+    where
+        T: TryInto<UrlType>,
+        T::Error: Into<UrlManagerError>,
+    {
+        let base_url = base_url.try_into().map_err(Into::into)?;
+        if base_url.cannot_be_a_base() {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "'{base_url}' can't be a base URL"
+            )));
+        }
+        self.base_url = Some(base_url);
+        Ok(self)
+    }
+
+    /// Treats `host` as serving this service's shortcuts, like the base URL's host.
+    pub fn own_host(mut self, host: impl Into<String>) -> Self {
+        self.own_hosts.push(host.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Allows targets that are short links of this service, as long as they
+    /// lead to an outside target within `depth` hops. 0, the default, refuses
+    /// them outright.
+    pub fn max_chain_depth(mut self, depth: usize) -> Self {
+        self.max_chain_depth = depth;
+        self
+    }
+
+    /// Shares `limiter` for [`LinkService::throttle`].
+    pub fn rate_limiter(mut self, limiter: impl Into<Arc<RateLimiter>>) -> Self {
+        self.limiter = Some(limiter.into());
+        self
+    }
+
+    /// Publishes an [`Event`] on `bus` for every link created, resolved or
+    /// expired through the service.
+    pub fn events(mut self, bus: EventBus) -> Self {
+        self.events = Some(bus);
+        self
+    }
+
+    /// Makes the links shortened through the service redirect with
+    /// `status`, see [`Link::redirect_status`].
+    pub fn redirect_status(mut self, status: RedirectStatus) -> Self {
+        self.redirect_status = Some(status);
+        self
+    }
+
+    /// Checks vanity slugs with `filter` and regenerates codes it refuses,
+    /// instead of [`SlugFilter::new`].
+    pub fn slug_filter(mut self, filter: SlugFilter) -> Self {
+        self.slug_filter = filter;
+        self
+    }
+
+    /// Matches slugs regardless of case: new links are stored with
+    /// lowercase shortcuts and lookups are lowercased, so `Docs` finds
+    /// `docs`. Generated codes lose their uppercase letters, which shrinks
+    /// the keyspace; the default strict mode keeps it. Links stored with
+    /// uppercase shortcuts before can't be found anymore, so turn this on
+    /// for a new store or one holding lowercase shortcuts only.
+    pub fn case_insensitive(mut self, enabled: bool) -> Self {
+        self.case_insensitive = enabled;
+        self
+    }
+
+    /// Takes a token for `client`, e.g. an address or API key, before it
+    /// shortens or resolves; fails with `RateLimited` when it has none left.
+    /// Without a rate limiter everything passes.
+    pub fn throttle(&self, client: &str) -> Result<()> {
+        match &self.limiter {
+            Some(limiter) => limiter.check(client),
+            None => Ok(()),
+        }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Creates a link to `target` under a generated shortcut.
+    pub fn shorten<T>(&mut self, target: T) -> Result<ShortLink>
+    where
+        T: TryInto<UrlType>,
+        T::Error: Into<UrlManagerError>,
+    {
+        self.authorize(Action::Shorten)?;
+        let id = unique_id(&RandomIds, |id| Ok(self.store.get(id)?.is_some()))?;
+        let shortcut = unique_code(&self.generator, id, |code| {
+            let code = self.canonical(code);
+            Ok(!self.slug_filter.allows(&code)
+                || self
+                    .store
+                    .get_by_shortcut_in(self.namespace.as_ref(), &code)?
+                    .is_some())
+        })?;
+        let shortcut = self.canonical(&shortcut).into_owned();
+        self.create(Link::builder().target(target).shortcut(shortcut), id)
+    }
+
+    /// Creates a link to `target` under the human-chosen `slug`, see
+    /// [`LinkStore::create_with_slug`]; fails with `InvalidSlug` if the
+    /// service's [`SlugFilter`] refuses it.
+    pub fn shorten_with_slug<T>(&mut self, target: T, slug: &str) -> Result<ShortLink>
+    where
+        T: TryInto<UrlType>,
+        T::Error: Into<UrlManagerError>,
+    {
+        self.authorize(Action::Shorten)?;
+        self.slug_filter.check(slug)?;
+        let slug = self.canonical(slug).into_owned();
+        let id = unique_id(&RandomIds, |id| Ok(self.store.get(id)?.is_some()))?;
+        self.create(Link::builder().target(target).slug(slug), id)
+    }
+
+    /// Creates a link for each of `links` like [`LinkService::shorten`], or
+    /// [`LinkService::shorten_with_slug`] for those with a slug, and stores
+    /// them together with [`LinkStore::create_batch`]. Returns the outcome of
+    /// each in order; fails as a whole only if the service's user may not
+    /// shorten.
+    pub fn create_batch(&mut self, links: Vec<NewLink>) -> Result<Vec<Result<ShortLink>>> {
+        self.authorize(Action::Shorten)?;
+        let mut ids = HashSet::new();
+        let mut shortcuts = HashSet::new();
+        let built: Vec<Result<Link>> = links
+            .into_iter()
+            .map(|new| self.build_new(new, &mut ids, &mut shortcuts))
+            .collect();
+        let valid = built.iter().flatten().cloned().collect();
+        let mut stored =
+            metrics::store_call("create_batch", || self.store.create_batch(valid)).into_iter();
+        Ok(built
+            .into_iter()
+            .map(|link| {
+                link?;
+                let link = stored.next().expect("one result per link")?;
+                metrics::link_created();
+                events::publish(&self.events, || Event::LinkCreated(link.clone()));
+                Ok(self.short_link(link))
+            })
+            .collect())
+    }
+
+    // Builds `new` with an id and shortcut neither stored nor among those
+    // taken earlier in the batch.
+    fn build_new(
+        &self,
+        new: NewLink,
+        ids: &mut HashSet<u64>,
+        shortcuts: &mut HashSet<String>,
+    ) -> Result<Link> {
+        let id = unique_id(&RandomIds, |id| {
+            Ok(ids.contains(&id) || self.store.get(id)?.is_some())
+        })?;
+        let builder = Link::builder().target(new.target.as_str());
+        let builder = match new.slug {
+            Some(slug) => {
+                self.slug_filter.check(&slug)?;
+                builder.slug(self.canonical(&slug).into_owned())
+            }
+            None => {
+                let shortcut = unique_code(&self.generator, id, |code| {
+                    let code = self.canonical(code);
+                    Ok(!self.slug_filter.allows(&code)
+                        || shortcuts.contains(&*code)
+                        || self
+                            .store
+                            .get_by_shortcut_in(self.namespace.as_ref(), &code)?
+                            .is_some())
+                })?;
+                builder.shortcut(self.canonical(&shortcut).into_owned())
+            }
+        };
+        let link = self.build(builder, id)?;
+        ids.insert(id);
+        if let Some(shortcut) = link.shortcut() {
+            shortcuts.insert(shortcut.to_string());
+        }
+        Ok(link)
+    }
+
+    fn create(&mut self, builder: LinkBuilder, id: u64) -> Result<ShortLink> {
+        let link = self.build(builder, id)?;
+        metrics::store_call("create", || self.store.create(link.clone()))?;
+        metrics::link_created();
+        events::publish(&self.events, || Event::LinkCreated(link.clone()));
+        Ok(self.short_link(link))
+    }
+
+    fn build(&self, mut builder: LinkBuilder, id: u64) -> Result<Link> {
+        builder = builder.id(id);
+        if let Some(namespace) = &self.namespace {
+            builder = builder.namespace(namespace.clone());
+        }
+        if let Some(status) = self.redirect_status {
+            builder = builder.redirect_status(status);
+        }
+        if let Some(user) = &self.user {
+            builder = builder.owner_id(user.id());
+        }
+        let link = builder.build()?;
+        self.policy.check(link.target())?;
+        self.check_chain(&link)?;
+        Ok(link)
+    }
+
+    /// Follows `slug`, counting the hit; fails like [`LinkStore::resolve`].
+    /// A link with [`Link::variants`] resolves to a random one of them.
+    pub fn resolve(&mut self, slug: &str) -> Result<Link> {
+        let slug = &*self.canonical(slug);
+        let link = metrics::resolution(|| self.store.record_hit_in(self.namespace.as_ref(), slug))?;
+        let link = clicks::pick_variant(&mut self.store, link, None)?;
+        events::publish(&self.events, || Event::LinkResolved(link.clone()));
+        Ok(link)
+    }
+
+    /// Follows each of `slugs` like [`LinkService::resolve`], returning the
+    /// outcome of each in order. Every hit is counted on its own, so this is
+    /// no faster than resolving one at a time; to only look links up, use
+    /// [`LinkStore::resolve_batch_in`] on the store.
+    pub fn resolve_batch(&mut self, slugs: &[&str]) -> Vec<Result<Link>> {
+        slugs.iter().map(|slug| self.resolve(slug)).collect()
+    }
+
+    /// Follows the password-protected `slug`, counting the hit if
+    /// `password` matches; see [`LinkStore::resolve_with_password`].
+    pub fn resolve_with_password(&mut self, slug: &str, password: &str) -> Result<Link> {
+        let slug = &*self.canonical(slug);
+        let link = metrics::resolution(|| {
+            self.store
+                .record_hit_with_password_in(self.namespace.as_ref(), slug, Some(password))
+        })?;
+        let link = clicks::pick_variant(&mut self.store, link, None)?;
+        events::publish(&self.events, || Event::LinkResolved(link.clone()));
+        Ok(link)
+    }
+
+    /// Makes `slug` stop resolving from now on and returns the expired link.
+    pub fn expire(&mut self, slug: &str) -> Result<Link> {
+        let link = self.change(slug, Link::expire)?;
+        events::publish(&self.events, || Event::LinkExpired(link.clone()));
+        Ok(link)
+    }
+
+    /// Makes `slug` resolve only with `password` and returns the protected link.
+    pub fn protect(&mut self, slug: &str, password: &str) -> Result<Link> {
+        self.change(slug, |link| link.set_password(password))
+    }
+
+    /// Makes `slug` resolve to `target` from `at` on and returns the changed
+    /// link; `target` is checked like the target of a new link.
+    pub fn schedule_target<T>(&mut self, slug: &str, target: T, at: SystemTime) -> Result<Link>
+    where
+        T: TryInto<UrlType>,
+        T::Error: Into<UrlManagerError>,
+    {
+        let target = target.try_into().map_err(Into::into)?;
+        check_target(&target)?;
+        self.policy.check(&target)?;
+        let mut switched = self.managed(slug)?;
+        switched.schedule_target(target.clone(), SystemTime::UNIX_EPOCH);
+        self.check_chain(&switched)?;
+        metrics::store_call("update", || {
+            self.store
+                .update_with(switched.id(), |link| link.schedule_target(target, at))
+        })
+    }
+
+    fn change(&mut self, slug: &str, change: impl FnOnce(&mut Link)) -> Result<Link> {
+        let link = self.managed(slug)?;
+        metrics::store_call("update", || self.store.update_with(link.id(), change))
+    }
+
+    /// The service user's links, or every link for admins and without a
+    /// user; see [`LinkStore::list`].
+    pub fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        match &self.user {
+            Some(user) if !user.can(Action::ChangeAny) => {
+                self.store.list_owned(user.id(), offset, limit)
+            }
+            _ => self.store.list(offset, limit),
+        }
+    }
+
+    /// Every namespace in use, see [`LinkStore::namespaces`]; admins only.
+    pub fn namespaces(&self) -> Result<Vec<Namespace>> {
+        self.authorize(Action::ListNamespaces)?;
+        self.store.namespaces()
+    }
+
+    /// Removes expired and used up links, see
+    /// [`LinkStore::purge_expired`]; admins only.
+    pub fn purge_expired(&mut self) -> Result<usize> {
+        self.authorize(Action::Purge)?;
+        metrics::store_call("purge_expired", || self.store.purge_expired())
+    }
+
+    /// Empties the trash of links older than `older_than`, see
+    /// [`LinkStore::purge`]; admins only.
+    pub fn purge(&mut self, older_than: Duration) -> Result<usize> {
+        self.authorize(Action::Purge)?;
+        metrics::store_call("purge", || self.store.purge(older_than))
+    }
+
+    // Fails with `Forbidden` if the service's user may not do `action`.
+    fn authorize(&self, action: Action) -> Result<()> {
+        match &self.user {
+            Some(user) => user.check(action),
+            None => Ok(()),
+        }
+    }
+
+    // The link behind `slug`, if the service's user may change it.
+    fn managed(&self, slug: &str) -> Result<Link> {
+        self.authorize(Action::ChangeOwn)?;
+        let slug = self.canonical(slug);
+        metrics::store_call("get", || {
+            self.store
+                .get_by_shortcut_in(self.namespace.as_ref(), &slug)
+        })?
+        .filter(|link| self.user.as_ref().is_none_or(|user| user.can_manage(link)))
+        .ok_or(UrlManagerError::NotFound)
+    }
+
+    // The form shortcuts are stored and looked up in.
+    fn canonical<'a>(&self, slug: &'a str) -> Cow<'a, str> {
+        if self.case_insensitive {
+            Cow::Owned(slug.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(slug)
+        }
+    }
+
+    fn is_own_host(&self, url: &UrlType) -> bool {
+        url.host_str().is_some_and(|host| {
+            let host = host.trim_end_matches('.');
+            self.base_url
+                .as_ref()
+                .and_then(UrlType::host_str)
+                .is_some_and(|own| own.eq_ignore_ascii_case(host))
+                || self
+                    .own_hosts
+                    .iter()
+                    .any(|own| own.eq_ignore_ascii_case(host))
+        })
+    }
+
+    // Follows targets on our own hosts through the store, failing on loops,
+    // dead ends and chains longer than `max_chain_depth`.
+    fn check_chain(&self, link: &Link) -> Result<()> {
+        let mut target = link.target().clone();
+        let mut depth = 0;
+        while self.is_own_host(&target) {
+            let forbidden = |reason: String| Err(UrlManagerError::Forbidden(reason));
+            if self.max_chain_depth == 0 {
+                return forbidden(format!("'{target}' points back at this shortener"));
+            }
+            if depth == self.max_chain_depth {
+                return forbidden(format!(
+                    "'{}' is more than {depth} short links deep",
+                    link.target()
+                ));
+            }
+            let shortcut = target
+                .path_segments()
+                .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+                .unwrap_or_default();
+            let shortcut = self.canonical(shortcut).into_owned();
+            if link.shortcut() == Some(shortcut.as_str()) {
+                return forbidden(format!("'{}' leads back to itself", link.target()));
+            }
+            let Some(next) = self
+                .store
+                .get_by_shortcut_in(self.namespace.as_ref(), &shortcut)?
+            else {
+                return forbidden(format!("'{target}' is not a short link here"));
+            };
+            target = next.target().clone();
+            depth += 1;
+        }
+        Ok(())
+    }
+
+    fn short_link(&self, link: Link) -> ShortLink {
+        let url = self
+            .base_url
+            .as_ref()
+            .zip(link.shortcut())
+            .and_then(|(base, shortcut)| base.join(shortcut).ok());
+        ShortLink { link, url }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+    use crate::{InMemoryLinkStore, Role};
+
+    #[test]
+    fn test_shorten_resolve_expire() {
+        let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new().min_length(4));
+
+        let short = service.shorten("https://example.com/a").unwrap();
+        assert!(short.url.is_none());
+        let shortcut = short.link.shortcut().unwrap().to_string();
+        assert!(shortcut.len() >= 4);
+        assert_eq!(service.resolve(&shortcut).unwrap().hit_count(), 1);
+
+        let expired = service.expire(&shortcut).unwrap();
+        assert!(expired.is_expired());
+        assert!(matches!(
+            service.resolve(&shortcut),
+            Err(UrlManagerError::Expired)
+        ));
+        assert!(matches!(
+            service.expire("nope"),
+            Err(UrlManagerError::NotFound)
+        ));
+
+        let mut service = service.base_url("https://sho.rt/go/").unwrap();
+        let docs = service
+            .shorten_with_slug("https://example.com/docs", "docs")
+            .unwrap();
+        assert_eq!(docs.url.unwrap().as_str(), "https://sho.rt/go/docs");
+        assert!(matches!(
+            service.shorten_with_slug("https://example.com", "docs"),
+            Err(UrlManagerError::ShortcutCollision(_))
+        ));
+        assert!(service.shorten("mailto:someone@example.com").is_err());
+        assert_eq!(service.store().count().unwrap(), 2);
+        assert!(service.base_url("mailto:x@example.com").is_err());
+    }
+
+    #[test]
+    fn test_slug_filter() {
+        // spells a blocked word on the first attempt
+        struct Rude;
+        impl CodeGenerator for Rude {
+            fn generate(&self, id: u64, attempt: u32) -> String {
+                match attempt {
+                    0 => "5h1t".to_string(),
+                    _ => Base62::new().generate(id, attempt),
+                }
+            }
+        }
+
+        let mut service = LinkService::new(InMemoryLinkStore::new(), Rude)
+            .slug_filter(SlugFilter::new().deny("pricing"));
+        let short = service.shorten("https://example.com/a").unwrap();
+        assert_ne!(short.link.shortcut(), Some("5h1t"));
+        for slug in ["pricing", "api", "what-the-fuck"] {
+            assert!(matches!(
+                service.shorten_with_slug("https://example.com/b", slug),
+                Err(UrlManagerError::InvalidSlug(_))
+            ));
+        }
+        let mut service = service.slug_filter(SlugFilter::empty());
+        assert!(service
+            .shorten_with_slug("https://example.com/b", "pricing")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let mut strict = LinkService::new(InMemoryLinkStore::new(), Base62::new());
+        strict
+            .shorten_with_slug("https://example.com/docs", "Docs")
+            .unwrap();
+        assert!(strict.resolve("Docs").is_ok());
+        assert!(matches!(
+            strict.resolve("docs"),
+            Err(UrlManagerError::NotFound)
+        ));
+
+        let mut service =
+            LinkService::new(InMemoryLinkStore::new(), Base62::new()).case_insensitive(true);
+        let docs = service
+            .shorten_with_slug("https://example.com/docs", "Docs")
+            .unwrap();
+        assert_eq!(docs.link.shortcut(), Some("docs"));
+        assert_eq!(service.resolve("DOCS").unwrap().id(), docs.link.id());
+        assert!(matches!(
+            service.shorten_with_slug("https://example.com/other", "dOcS"),
+            Err(UrlManagerError::ShortcutCollision(_))
+        ));
+        let short = service.shorten("https://example.com/a").unwrap();
+        let code = short.link.shortcut().unwrap();
+        assert_eq!(code, code.to_ascii_lowercase());
+        let upper = code.to_ascii_uppercase();
+        assert_eq!(service.resolve(&upper).unwrap().id(), short.link.id());
+        assert!(service.expire("DOCS").unwrap().is_expired());
+    }
+
+    #[test]
+    fn test_redirect_status() {
+        let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new())
+            .redirect_status(RedirectStatus::PermanentRedirect);
+        let short = service.shorten("https://example.com/a").unwrap();
+        let stored = service.store().get(short.link.id()).unwrap().unwrap();
+        assert_eq!(
+            stored.redirect_status(),
+            Some(RedirectStatus::PermanentRedirect)
+        );
+        let loaded = Link::from_json(&stored.to_json()).unwrap();
+        assert_eq!(loaded.redirect_status(), stored.redirect_status());
+    }
+
+    #[test]
+    fn test_password() {
+        let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new());
+        service
+            .shorten_with_slug("https://example.com/secret", "secret")
+            .unwrap();
+        let protected = service.protect("secret", "hunter2").unwrap();
+        assert!(protected.is_protected());
+        assert!(!protected.to_json().to_string().contains("hunter2"));
+
+        assert!(matches!(
+            service.resolve("secret"),
+            Err(UrlManagerError::PasswordRequired)
+        ));
+        assert!(matches!(
+            service.resolve_with_password("secret", "hunter3"),
+            Err(UrlManagerError::PasswordRequired)
+        ));
+        let link = service.resolve_with_password("secret", "hunter2").unwrap();
+        assert_eq!(link.target().as_str(), "https://example.com/secret");
+        assert_eq!(link.hit_count(), 1);
+        assert!(service
+            .store()
+            .resolve_with_password("secret", "hunter2")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_events() {
+        let bus = EventBus::new();
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        bus.subscribe(move |event: &Event| seen.lock().unwrap().push(event.clone()));
+        let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new()).events(bus);
+
+        let short = service.shorten("https://example.com").unwrap();
+        let shortcut = short.link.shortcut().unwrap().to_string();
+        service.resolve(&shortcut).unwrap();
+        assert!(service.resolve("nope").is_err());
+        service.expire(&shortcut).unwrap();
+
+        let events = events.lock().unwrap();
+        let kinds: Vec<EventKind> = events.iter().map(Event::kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                EventKind::LinkCreated,
+                EventKind::LinkResolved,
+                EventKind::LinkExpired
+            ]
+        );
+        assert_eq!(events[1].link().unwrap().hit_count(), 1);
+    }
+
+    #[test]
+    fn test_throttle() {
+        let service = LinkService::new(InMemoryLinkStore::new(), Base62::new());
+        assert!((0..10).all(|_| service.throttle("anyone").is_ok()));
+        let service = service.rate_limiter(RateLimiter::per_minute(1).unwrap());
+        service.throttle("203.0.113.7").unwrap();
+        assert!(matches!(
+            service.throttle("203.0.113.7"),
+            Err(UrlManagerError::RateLimited(_))
+        ));
+        assert!(service.throttle("198.51.100.1").is_ok());
+    }
+
+    #[test]
+    fn test_schedule_target() {
+        let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new())
+            .policy(UrlPolicy::new().block_private_addresses());
+        service
+            .shorten_with_slug("https://example.com/teaser", "launch")
+            .unwrap();
+        let launch = SystemTime::now() + Duration::from_secs(3600);
+        let scheduled = service
+            .schedule_target("launch", "https://example.com/product", launch)
+            .unwrap();
+        assert_eq!(
+            scheduled
+                .scheduled_target()
+                .map(|(target, _)| target.as_str()),
+            Some("https://example.com/product")
+        );
+        assert_eq!(
+            service.resolve("launch").unwrap().target().as_str(),
+            "https://example.com/teaser"
+        );
+        assert_eq!(
+            scheduled.target_at(launch).as_str(),
+            "https://example.com/product"
+        );
+
+        service
+            .schedule_target("launch", "https://example.com/product", SystemTime::now())
+            .unwrap();
+        assert_eq!(
+            service.resolve("launch").unwrap().target().as_str(),
+            "https://example.com/product"
+        );
+        assert!(matches!(
+            service.schedule_target("launch", "http://localhost/admin", launch),
+            Err(UrlManagerError::Forbidden(_))
+        ));
+        assert!(matches!(
+            service.schedule_target("nope", "https://example.com", launch),
+            Err(UrlManagerError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_user() {
+        let mut alice =
+            LinkService::new(InMemoryLinkStore::new(), Base62::new()).user(User::new(1, "alice"));
+        let short = alice
+            .shorten_with_slug("https://example.com", "docs")
+            .unwrap();
+        assert_eq!(short.link.owner_id(), Some(1));
+        alice.protect("docs", "secret").unwrap();
+
+        let mut bob = LinkService::new(alice.into_store(), Base62::new()).user(User::new(2, "bob"));
+        assert_eq!(
+            bob.resolve_with_password("docs", "secret")
+                .unwrap()
+                .hit_count(),
+            1
+        );
+        assert!(matches!(bob.expire("docs"), Err(UrlManagerError::NotFound)));
+        assert!(matches!(
+            bob.schedule_target("docs", "https://example.org", SystemTime::now()),
+            Err(UrlManagerError::NotFound)
+        ));
+
+        let mut admin =
+            LinkService::new(bob.into_store(), Base62::new()).user(User::new(3, "ops").admin());
+        assert!(admin.expire("docs").unwrap().is_expired());
+        assert_eq!(admin.store().list_owned(1, 0, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_roles() {
+        fn forbidden<T>(result: Result<T>) -> bool {
+            matches!(result, Err(UrlManagerError::Forbidden(_)))
+        }
+        let as_user = |store, role| {
+            LinkService::new(store, Base62::new()).user(User::new(1, "someone").role(role))
+        };
+        let mut store = InMemoryLinkStore::new();
+        let docs = Link::builder()
+            .target("https://example.com")
+            .slug("docs")
+            .namespace(Namespace::new("team").unwrap())
+            .owner_id(1)
+            .build()
+            .unwrap();
+        store.create(docs).unwrap();
+        store
+            .create_with_slug("shared", "https://example.com".parse().unwrap())
+            .unwrap();
+
+        let mut viewer = as_user(store, Role::Viewer);
+        assert!(forbidden(viewer.shorten("https://example.com")));
+        assert!(forbidden(
+            viewer.shorten_with_slug("https://example.com", "mine")
+        ));
+        assert!(forbidden(viewer.expire("shared")));
+        assert!(forbidden(viewer.purge_expired()));
+        assert!(forbidden(viewer.purge(Duration::ZERO)));
+        assert!(forbidden(viewer.namespaces()));
+        assert!(viewer.resolve("shared").is_ok());
+        assert!(viewer
+            .list(0, 10)
+            .unwrap()
+            .iter()
+            .all(|link| link.owner_id() == Some(1)));
+
+        let mut editor = as_user(viewer.into_store(), Role::Editor);
+        assert!(editor.shorten("https://example.com").is_ok());
+        assert!(matches!(
+            editor.expire("shared"),
+            Err(UrlManagerError::NotFound)
+        ));
+        assert!(forbidden(editor.purge_expired()));
+        assert!(forbidden(editor.purge(Duration::ZERO)));
+        assert!(forbidden(editor.namespaces()));
+        assert_eq!(editor.list(0, 10).unwrap().len(), 2);
+
+        let mut admin = as_user(editor.into_store(), Role::Admin);
+        assert!(admin.expire("shared").is_ok());
+        assert_eq!(
+            admin.namespaces().unwrap(),
+            [Namespace::new("team").unwrap()]
+        );
+        assert_eq!(admin.purge_expired().unwrap(), 1);
+        assert_eq!(admin.purge(Duration::ZERO).unwrap(), 0);
+        assert_eq!(admin.list(0, 10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_policy() {
+        let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new())
+            .policy(UrlPolicy::new().block_private_addresses());
+        assert!(matches!(
+            service.shorten("http://169.254.169.254/latest/meta-data"),
+            Err(UrlManagerError::Forbidden(_))
+        ));
+        assert!(service
+            .shorten_with_slug("http://localhost:8080", "local")
+            .is_err());
+        assert_eq!(service.store().count().unwrap(), 0);
+        assert!(service.shorten("https://example.com").is_ok());
+    }
+
+    #[test]
+    fn test_redirect_loops() {
+        let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new())
+            .base_url("https://sho.rt/")
+            .unwrap()
+            .own_host("go.sho.rt");
+        service
+            .shorten_with_slug("https://example.com", "outside")
+            .unwrap();
+        for target in ["https://sho.rt/outside", "http://GO.sho.rt/outside"] {
+            assert!(
+                matches!(service.shorten(target), Err(UrlManagerError::Forbidden(_))),
+                "{target} should be refused"
+            );
+        }
+
+        let mut service = service.max_chain_depth(2);
+        service
+            .shorten_with_slug("https://sho.rt/outside", "one")
+            .unwrap();
+        service
+            .shorten_with_slug("https://sho.rt/one", "two")
+            .unwrap();
+        assert!(service
+            .shorten_with_slug("https://sho.rt/two", "three")
+            .is_err());
+        assert!(service
+            .shorten_with_slug("https://sho.rt/missing", "dead")
+            .is_err());
+        // a link to a shortcut that doesn't exist yet can't be made to point back
+        assert!(service
+            .shorten_with_slug("https://sho.rt/self", "self")
+            .is_err());
+        assert_eq!(service.store().count().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_namespace() {
+        let team = Namespace::new("team").unwrap();
+        let mut store = InMemoryLinkStore::new();
+        store
+            .create_with_slug("docs", UrlType::parse("https://example.com").unwrap())
+            .unwrap();
+        let mut service = LinkService::new(store, Base62::new()).namespace(team.clone());
+
+        let docs = service
+            .shorten_with_slug("https://team.example.com", "docs")
+            .unwrap();
+        assert_eq!(docs.link.namespace(), Some(&team));
+        assert_eq!(service.resolve("docs").unwrap().id(), docs.link.id());
+        service.expire("docs").unwrap();
+        assert!(service.store().resolve("docs").is_ok());
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new())
+            .base_url("https://sho.rt/")
+            .unwrap();
+        let mut batch: Vec<NewLink> = (0..100)
+            .map(|i| NewLink::new(format!("https://example.com/campaign/{i}")))
+            .collect();
+        batch.push(NewLink::new("https://example.com/docs").slug("docs"));
+        batch.push(NewLink::new("https://example.com/other").slug("docs"));
+        batch.push(NewLink::new("mailto:someone@example.com"));
+        let results = service.create_batch(batch).unwrap();
+        assert_eq!(results.len(), 103);
+        assert!(results[..101].iter().all(Result::is_ok));
+        assert!(matches!(
+            results[101],
+            Err(UrlManagerError::ShortcutCollision(_))
+        ));
+        assert!(results[102].is_err());
+        assert_eq!(service.store().count().unwrap(), 101);
+        let docs = results[100].as_ref().unwrap();
+        assert_eq!(docs.url.as_ref().unwrap().as_str(), "https://sho.rt/docs");
+
+        let first = results[0].as_ref().unwrap().link.shortcut().unwrap();
+        let resolved = service.resolve_batch(&[first, "docs", "nope"]);
+        assert_eq!(resolved[0].as_ref().unwrap().hit_count(), 1);
+        assert_eq!(resolved[1].as_ref().unwrap().id(), docs.link.id());
+        assert!(matches!(resolved[2], Err(UrlManagerError::NotFound)));
+
+        let viewer = User::new(7, "viewer").role(Role::Viewer);
+        let mut service = LinkService::new(InMemoryLinkStore::new(), Base62::new()).user(viewer);
+        assert!(matches!(
+            service.create_batch(vec![NewLink::new("https://example.com")]),
+            Err(UrlManagerError::Forbidden(_))
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::crypto::{base64_decode, base64_encode, constant_time_eq, HmacSha256, URL_SAFE};
+use crate::link::now;
+use crate::signed::{check_key, unix_secs};
+use crate::{sync, Base62, Result, UrlManagerError};
+
+// Bytes of the HMAC kept in tokens, as for `SignedLink`.
+const SIGNATURE_BYTES: usize = 16;
+
+/// Mints and checks time-boxed tokens that let someone without an account
+/// preview a link otherwise only its owner can see, see [`Link::owner_id`](crate::Link::owner_id).
+///
+/// Tokens read `<id>.<expiry>.<nonce>.<signature>`: the link id, the expiry
+/// in unix seconds and a random nonce, base62-encoded, and an HMAC-SHA256
+/// over them. Checking one needs neither the store nor a record of minted
+/// tokens; only revoked tokens are kept, on a denylist that forgets them
+/// once they expire. The denylist lives in memory, so revocations are lost
+/// on restart and not shared between instances.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use url_manager::{ShareTokens, UrlManagerError};
+/// let tokens = ShareTokens::new(b"a secret of at least 16 bytes".to_vec())?;
+/// let token = tokens.mint(7, Duration::from_secs(3600));
+/// assert_eq!(tokens.verify(token.as_str())?, 7);
+///
+/// tokens.revoke(token.as_str())?;
+/// assert!(matches!(tokens.verify(token.as_str()), Err(UrlManagerError::Forbidden(_))));
+/// # Ok::<(), UrlManagerError>(())
+/// ```
+pub struct ShareTokens {
+    key: Vec<u8>,
+    // canonical signature of each revoked token and when it expires
+    revoked: Mutex<HashMap<String, SystemTime>>,
+}
+
+/// A token minted by [`ShareTokens::mint`].
+#[derive(Debug, Clone)]
+pub struct ShareToken {
+    link_id: u64,
+    expires_at: SystemTime,
+    token: String,
+}
+
+impl ShareTokens {
+    /// Signs with `key`, which needs at least
+    /// [`SignedLink::MIN_KEY_LENGTH`](crate::SignedLink::MIN_KEY_LENGTH) bytes.
+    pub fn new(key: impl Into<Vec<u8>>) -> Result<Self> {
+        let key = key.into();
+        check_key(&key)?;
+        Ok(ShareTokens {
+            key,
+            revoked: Mutex::default(),
+        })
+    }
+
+    /// A token for the link `link_id` that is good for `ttl`.
+    pub fn mint(&self, link_id: u64, ttl: Duration) -> ShareToken {
+        let base62 = Base62::new();
+        let expiry = unix_secs(now() + ttl);
+        let expires_at = UNIX_EPOCH + Duration::from_secs(expiry);
+        let payload = format!(
+            "{}.{}.{}",
+            base62.encode(link_id),
+            base62.encode(expiry),
+            base62.encode(rand::random())
+        );
+        let signature = self.signature(&payload);
+        ShareToken {
+            link_id,
+            expires_at,
+            token: format!("{payload}.{signature}"),
+        }
+    }
+
+    /// Checks `token` against the key, the clock and the denylist and
+    /// returns the id of its link.
+    ///
+    /// Fails with `Forbidden` if the token is malformed, its signature
+    /// doesn't match or it was revoked, and with `Expired` once its expiry
+    /// has passed.
+    pub fn verify(&self, token: &str) -> Result<u64> {
+        self.verify_at(token, now())
+    }
+
+    /// [`ShareTokens::verify`] as of `time`.
+    pub fn verify_at(&self, token: &str, time: SystemTime) -> Result<u64> {
+        let (link_id, expires_at, signature) = self.parse(token)?;
+        if expires_at <= time {
+            return Err(UrlManagerError::Expired);
+        }
+        if sync::lock(&self.revoked).contains_key(&signature) {
+            return Err(UrlManagerError::Forbidden(
+                "share token was revoked".to_string(),
+            ));
+        }
+        Ok(link_id)
+    }
+
+    /// The id of the link of `token`, expired or revoked as it may be.
+    /// Fails with `Forbidden` if it isn't one of these tokens.
+    pub fn link_id(&self, token: &str) -> Result<u64> {
+        self.parse(token).map(|(link_id, _, _)| link_id)
+    }
+
+    /// Puts `token` on the denylist until it expires, returning the id of
+    /// its link. Fails with `Forbidden` if it isn't one of these tokens.
+    pub fn revoke(&self, token: &str) -> Result<u64> {
+        let (link_id, expires_at, signature) = self.parse(token)?;
+        let now = now();
+        let mut revoked = sync::lock(&self.revoked);
+        revoked.retain(|_, expires_at| *expires_at > now);
+        if expires_at > now {
+            revoked.insert(signature, expires_at);
+        }
+        Ok(link_id)
+    }
+
+    /// How many unexpired tokens are revoked.
+    pub fn revoked(&self) -> usize {
+        let now = now();
+        sync::lock(&self.revoked)
+            .values()
+            .filter(|expires_at| **expires_at > now)
+            .count()
+    }
+
+    // The link id, expiry and signature of `token` if it was signed with the
+    // key. The signature is the one minted rather than the one given, which
+    // may encode the same bytes differently.
+    fn parse(&self, token: &str) -> Result<(u64, SystemTime, String)> {
+        let forbidden = || UrlManagerError::Forbidden("invalid share token".to_string());
+        let (payload, given) = token.rsplit_once('.').ok_or_else(forbidden)?;
+        let mut parts = payload.split('.');
+        let (Some(link_id), Some(expiry), Some(_nonce), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(forbidden());
+        };
+        let given_bytes = base64_decode(given, URL_SAFE).ok_or_else(forbidden)?;
+        let signature = self.signature(payload);
+        let expected = base64_decode(&signature, URL_SAFE).ok_or_else(forbidden)?;
+        if !constant_time_eq(&given_bytes, &expected) {
+            return Err(forbidden());
+        }
+        let base62 = Base62::new();
+        let link_id = base62.decode(link_id).ok_or_else(forbidden)?;
+        let expires_at = base62
+            .decode(expiry)
+            .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+            .ok_or_else(forbidden)?;
+        Ok((link_id, expires_at, signature))
+    }
+
+    fn signature(&self, payload: &str) -> String {
+        let mac = HmacSha256::new(&self.key).mac(payload.as_bytes());
+        base64_encode(&mac[..SIGNATURE_BYTES], URL_SAFE)
+    }
+}
+
+impl fmt::Debug for ShareTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShareTokens")
+            .field("revoked", &self.revoked())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ShareToken {
+    pub fn link_id(&self) -> u64 {
+        self.link_id
+    }
+
+    pub fn expires_at(&self) -> SystemTime {
+        self.expires_at
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.token
+    }
+}
+
+impl fmt::Display for ShareToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_mint_and_verify() {
+        let tokens = ShareTokens::new(KEY).unwrap();
+        let token = tokens.mint(42, Duration::from_secs(60));
+        assert_eq!(token.link_id(), 42);
+        assert_eq!(tokens.verify(token.as_str()).unwrap(), 42);
+        assert_ne!(
+            tokens.mint(42, Duration::from_secs(60)).as_str(),
+            token.as_str()
+        );
+
+        let later = SystemTime::now() + Duration::from_secs(120);
+        assert!(matches!(
+            tokens.verify_at(token.as_str(), later),
+            Err(UrlManagerError::Expired)
+        ));
+
+        let (_, rest) = token.as_str().split_once('.').unwrap();
+        let other = ShareTokens::new(b"fedcba9876543210fedcba9876543210".to_vec()).unwrap();
+        assert!(other.verify(token.as_str()).is_err());
+        for forged in [
+            format!("{}.{rest}", Base62::new().encode(43)),
+            format!("{token}.x"),
+            token.as_str().replace('.', "-"),
+            String::new(),
+        ] {
+            assert!(matches!(
+                tokens.verify(&forged),
+                Err(UrlManagerError::Forbidden(_))
+            ));
+        }
+        assert!(matches!(
+            ShareTokens::new(b"short".to_vec()),
+            Err(UrlManagerError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_revoke() {
+        let tokens = ShareTokens::new(KEY).unwrap();
+        let token = tokens.mint(7, Duration::from_secs(60));
+        let other = tokens.mint(7, Duration::from_secs(60));
+        assert_eq!(tokens.revoke(token.as_str()).unwrap(), 7);
+        assert!(matches!(
+            tokens.verify(token.as_str()),
+            Err(UrlManagerError::Forbidden(_))
+        ));
+        assert_eq!(tokens.verify(other.as_str()).unwrap(), 7);
+        assert_eq!(tokens.revoked(), 1);
+        assert_eq!(tokens.link_id(token.as_str()).unwrap(), 7);
+        assert!(tokens.revoke("7.nope.x.y").is_err());
+
+        // the last character with its unused bits flipped
+        let (payload, signature) = token.as_str().rsplit_once('.').unwrap();
+        let (rest, last) = signature.split_at(signature.len() - 1);
+        let index = URL_SAFE
+            .iter()
+            .position(|&c| c == last.as_bytes()[0])
+            .unwrap();
+        let neighbour = URL_SAFE[index ^ 1] as char;
+        let altered = format!("{payload}.{rest}{neighbour}");
+        assert!(matches!(
+            tokens.verify(&altered),
+            Err(UrlManagerError::Forbidden(_))
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt;
+use url::{ParseError, Url as UrlType};
+
+use crate::Result;
+
+/// Shortening behaviour for URL types.
+pub trait UrlExtension {
+    // UrlExtension should be able to dictate
+    // how the shorten method behaves
+    // basically have an in-memory implementation
+    // and provide a way for other implementations to work in the same way
+    // So we can take advantage of i.e PostgreSQL's domain types to do all the heavy lifting
+    fn shorten(&mut self) -> Result<bool>;
+    //fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error>;
+}
+
+/// Shows the origin, whether or not the URL has been shortened yet.
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.origin)
+    }
+}
+
+impl From<Url> for String {
+    fn from(value: Url) -> Self {
+        value.origin.to_string()
+    }
+}
+
+impl PartialEq<Url> for &str {
+    fn eq(&self, other: &Url) -> bool {
+        *self == other.origin.to_string().as_str()
+    }
+}
+
+impl PartialEq<Url> for UrlType {
+    fn eq(&self, other: &Url) -> bool {
+        *self == other.origin
+    }
+}
+
+/// A URL together with its shortcut.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Url {
+    origin: UrlType,
+    shortcut: String,
+}
+
+impl Url {
+    /// Wraps `origin` without a shortcut; call [`UrlExtension::shorten`] to compute one.
+    pub fn new(origin: UrlType) -> Self {
+        Url {
+            origin,
+            shortcut: String::new(),
+        }
+    }
+
+    /// Parses `input` and wraps it, see [`Url::new`].
+    pub fn parse(input: &str) -> Result<Self> {
+        Ok(Url::new(UrlType::parse(input)?))
+    }
+
+    /// The URL that was shortened.
+    pub fn origin(&self) -> &UrlType {
+        &self.origin
+    }
+
+    /// The shortcut, empty until the URL has been shortened.
+    pub fn shortcut(&self) -> &str {
+        &self.shortcut
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn to_json(&self) -> crate::json::Value {
+        use crate::json::Value;
+        Value::object([
+            ("origin", Value::from(self.origin.as_str())),
+            ("shortcut", Value::from(self.shortcut.as_str())),
+        ])
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_json(value: &crate::json::Value) -> Result<Self> {
+        use crate::json::Value;
+        use crate::UrlManagerError;
+        let field = |name: &str| {
+            value
+                .get(name)
+                .and_then(Value::as_str)
+                .ok_or_else(|| UrlManagerError::InvalidLink(format!("'{name}' is not a string")))
+        };
+        Ok(Url {
+            origin: UrlType::parse(field("origin")?)?,
+            shortcut: field("shortcut")?.to_string(),
+        })
+    }
+}
+
+impl UrlExtension for Url {
+    /// Uses the host as the shortcut, failing with `InvalidUrl` for URLs
+    /// without one such as `mailto:`, `data:` or `file:` URLs. The shortcut
+    /// is left as it was on failure.
+    fn shorten(&mut self) -> Result<bool> {
+        match self.origin.host_str() {
+            Some(host) if !host.is_empty() => {
+                self.shortcut = host.to_string();
+                Ok(true)
+            }
+            _ => Err(ParseError::EmptyHost.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::{Any, TypeId};
+    use url::Url as UrlType;
+
+    #[test]
+    fn test_type_works() {
+        let myurl = Url {
+            origin: UrlType::parse("https://www.example.com").unwrap(),
+            shortcut: String::from("example"),
+        };
+
+        assert_eq!(myurl.type_id(), TypeId::of::<Url>(), "Type not matched");
+    }
+
+    #[test]
+    fn test_shorten() {
+        let mut myurl = Url {
+            origin: UrlType::parse("https://www.example.com").unwrap(),
+            shortcut: String::from("example"),
+        };
+        assert!(myurl.shorten().unwrap());
+    }
+
+    #[test]
+    fn test_shorten_new() {
+        let myurl = Url {
+            origin: UrlType::parse("https://www.example.com").unwrap(),
+            shortcut: String::from("example"),
+        };
+        let expect = url::Url::parse("https://www.example.com").unwrap();
+
+        assert!(expect == myurl, "got '{myurl}' instead of '{expect}'");
+
+        let expect = url::Url::parse("https://www.example.co").unwrap();
+
+        assert!(expect != myurl, "'{myurl}' should not match '{expect}'");
+    }
+
+    #[test]
+    fn test_shorten_without_host() {
+        for input in [
+            "mailto:someone@example.com",
+            "data:text/plain,hello",
+            "file:///etc/hosts",
+        ] {
+            let mut myurl = Url::parse(input).unwrap();
+            assert!(
+                matches!(
+                    myurl.shorten(),
+                    Err(crate::UrlManagerError::InvalidUrl(ParseError::EmptyHost))
+                ),
+                "{input} should not shorten"
+            );
+            assert!(myurl.shortcut().is_empty());
+            assert_eq!(myurl.to_string(), input, "display falls back to the origin");
+        }
+    }
+
+    #[test]
+    fn test_url_new() {
+        let mut myurl = Url::parse("https://www.example.com/some/path").unwrap();
+        assert!(myurl.shortcut().is_empty());
+
+        myurl.shorten().unwrap();
+        assert_eq!(myurl.shortcut(), "www.example.com");
+        assert_eq!(myurl.origin().path(), "/some/path");
+    }
+}
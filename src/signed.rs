@@ -0,0 +1,187 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::crypto::{base64_decode, base64_encode, constant_time_eq, HmacSha256, URL_SAFE};
+use crate::link::now;
+use crate::{Base62, Link, Result, UrlManagerError, UrlType};
+
+// Bytes of the HMAC kept in tokens; 128 bits are plenty against forgery
+// and keep the URL short.
+const SIGNATURE_BYTES: usize = 16;
+
+/// A link whose shortcut is handed out as a signed, expiring token, so
+/// tampered or expired short URLs are turned away without a store lookup.
+///
+/// Tokens read `<shortcut>.<expiry>.<signature>`: the expiry in unix
+/// seconds, base62-encoded, and an HMAC-SHA256 over both. Slugs can't hold
+/// a `.`, so the parts can't be confused. The target still lives in the
+/// store under the shortcut; store [`SignedLink::link`] as usual.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use url_manager::SignedLink;
+/// let key = b"a secret of at least 16 bytes";
+/// let signed = SignedLink::generate(key, "https://example.com/invite", Duration::from_secs(3600))?;
+/// let shortcut = SignedLink::verify(key, signed.token())?;
+/// assert_eq!(Some(shortcut), signed.link().shortcut());
+///
+/// let forged = signed.token().replacen('.', "x.", 1);
+/// assert!(SignedLink::verify(key, &forged).is_err());
+/// # Ok::<(), url_manager::UrlManagerError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct SignedLink {
+    link: Link,
+    token: String,
+}
+
+impl SignedLink {
+    /// Keys shorter than this are refused.
+    pub const MIN_KEY_LENGTH: usize = 16;
+
+    /// Creates a link to `target` with a generated shortcut that expires
+    /// after `ttl`, and signs it with `key`.
+    pub fn generate<T>(key: &[u8], target: T, ttl: Duration) -> Result<SignedLink>
+    where
+        T: TryInto<UrlType>,
+        T::Error: Into<UrlManagerError>,
+    {
+        let link = Link::builder().target(target).expires_in(ttl).build()?;
+        Self::sign(key, link)
+    }
+
+    /// Signs an existing link, which needs a shortcut and an expiry.
+    pub fn sign(key: &[u8], link: Link) -> Result<SignedLink> {
+        check_key(key)?;
+        let shortcut = link
+            .shortcut()
+            .ok_or_else(|| UrlManagerError::InvalidLink("signed links need a shortcut".into()))?;
+        let expires_at = link
+            .expires_at()
+            .ok_or_else(|| UrlManagerError::InvalidLink("signed links need an expiry".into()))?;
+        let expiry = Base62::new().encode(unix_secs(expires_at));
+        let signature = signature(key, shortcut, &expiry);
+        let token = format!("{shortcut}.{expiry}.{signature}");
+        Ok(SignedLink { link, token })
+    }
+
+    /// Checks `token` against `key` and the clock and returns its shortcut.
+    ///
+    /// Fails with `Forbidden` if the token is malformed or its signature
+    /// doesn't match, and with `Expired` once its expiry has passed.
+    pub fn verify<'a>(key: &[u8], token: &'a str) -> Result<&'a str> {
+        Self::verify_at(key, token, now())
+    }
+
+    /// [`SignedLink::verify`] as of `time`.
+    pub fn verify_at<'a>(key: &[u8], token: &'a str, time: SystemTime) -> Result<&'a str> {
+        check_key(key)?;
+        let forbidden = || UrlManagerError::Forbidden("invalid link signature".to_string());
+        let mut parts = token.split('.');
+        let (Some(shortcut), Some(expiry), Some(given), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(forbidden());
+        };
+        let given = base64_decode(given, URL_SAFE).ok_or_else(forbidden)?;
+        let expected =
+            base64_decode(&signature(key, shortcut, expiry), URL_SAFE).ok_or_else(forbidden)?;
+        if !constant_time_eq(&given, &expected) {
+            return Err(forbidden());
+        }
+        let expires_at = Base62::new()
+            .decode(expiry)
+            .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+            .ok_or_else(forbidden)?;
+        if expires_at <= time {
+            return Err(UrlManagerError::Expired);
+        }
+        Ok(shortcut)
+    }
+
+    pub fn link(&self) -> &Link {
+        &self.link
+    }
+
+    pub fn into_link(self) -> Link {
+        self.link
+    }
+
+    /// What to put in the short URL instead of the bare shortcut.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+pub(crate) fn check_key(key: &[u8]) -> Result<()> {
+    if key.len() < SignedLink::MIN_KEY_LENGTH {
+        return Err(UrlManagerError::InvalidConfig(format!(
+            "signing keys need at least {} bytes",
+            SignedLink::MIN_KEY_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn signature(key: &[u8], shortcut: &str, expiry: &str) -> String {
+    let mac = HmacSha256::new(key).mac(format!("{shortcut}.{expiry}").as_bytes());
+    base64_encode(&mac[..SIGNATURE_BYTES], URL_SAFE)
+}
+
+// Rounded up, so a token never outlives its link.
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    since.as_secs() + u64::from(since.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signed =
+            SignedLink::generate(KEY, "https://example.com", Duration::from_secs(60)).unwrap();
+        let shortcut = signed.link().shortcut().unwrap();
+        assert!(signed.token().starts_with(&format!("{shortcut}.")));
+        assert_eq!(SignedLink::verify(KEY, signed.token()).unwrap(), shortcut);
+
+        let later = SystemTime::now() + Duration::from_secs(120);
+        assert!(matches!(
+            SignedLink::verify_at(KEY, signed.token(), later),
+            Err(UrlManagerError::Expired)
+        ));
+
+        let other_key = b"fedcba9876543210fedcba9876543210";
+        let (_, rest) = signed.token().split_once('.').unwrap();
+        for token in [
+            format!("other.{rest}"),
+            signed.token().replace('.', "-"),
+            format!("{}.x", signed.token()),
+            String::new(),
+        ] {
+            assert!(matches!(
+                SignedLink::verify(KEY, &token),
+                Err(UrlManagerError::Forbidden(_))
+            ));
+        }
+        assert!(SignedLink::verify(other_key, signed.token()).is_err());
+    }
+
+    #[test]
+    fn test_sign_needs_key_shortcut_and_expiry() {
+        let link = Link::builder()
+            .target("https://example.com")
+            .build()
+            .unwrap();
+        assert!(matches!(
+            SignedLink::sign(KEY, link),
+            Err(UrlManagerError::InvalidLink(_))
+        ));
+        assert!(matches!(
+            SignedLink::generate(b"short", "https://example.com", Duration::from_secs(60)),
+            Err(UrlManagerError::InvalidConfig(_))
+        ));
+    }
+}
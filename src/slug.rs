@@ -0,0 +1,190 @@
+use crate::pure::check_slug;
+pub use crate::pure::{MAX_SLUG_LENGTH, RESERVED_SLUGS};
+use crate::{Result, UrlManagerError};
+
+/// Checks that `slug` can be used as a human-chosen shortcut.
+///
+/// Slugs are 1 to [`MAX_SLUG_LENGTH`] ASCII letters, digits, `-` or `_`,
+/// and can't be one of the [`RESERVED_SLUGS`] in any casing.
+pub fn validate_slug(slug: &str) -> Result<()> {
+    Ok(check_slug(slug)?)
+}
+
+/// Words generated shortcuts never contain and [`SlugFilter::new`] keeps
+/// out of slugs, also when spelled with digits like `5h1t`.
+pub const BLOCKED_WORDS: &[&str] = &[
+    "bitch", "cunt", "dildo", "fuck", "jizz", "nigg", "porn", "pussy", "shit", "slut", "twat",
+    "wank", "whore",
+];
+
+/// Keeps slugs off a denylist and free of blocked words.
+///
+/// Denied slugs are refused when they match exactly, in any casing; blocked
+/// words anywhere in a slug, after reading digits as the letters they look
+/// like and dropping `-` and `_`. [`LinkService`](crate::LinkService) checks
+/// vanity slugs with one and regenerates codes it refuses; generated codes
+/// are always kept clear of [`BLOCKED_WORDS`] and [`RESERVED_SLUGS`].
+///
+/// ```
+/// # use url_manager::SlugFilter;
+/// let filter = SlugFilter::new().deny("pricing").block_word("scam");
+/// assert!(filter.allows("spring-sale"));
+/// assert!(!filter.allows("Pricing"));
+/// assert!(!filter.allows("api"));
+/// assert!(!filter.allows("not-a-5c4m"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlugFilter {
+    denied: Vec<String>,
+    words: Vec<String>,
+}
+
+impl SlugFilter {
+    /// Denies the [`RESERVED_SLUGS`] and blocks the [`BLOCKED_WORDS`].
+    pub fn new() -> Self {
+        RESERVED_SLUGS
+            .iter()
+            .fold(SlugFilter::empty(), |filter, slug| filter.deny(*slug))
+            .block_words(BLOCKED_WORDS.iter().copied())
+    }
+
+    /// A filter letting everything through.
+    pub fn empty() -> Self {
+        SlugFilter {
+            denied: Vec::new(),
+            words: Vec::new(),
+        }
+    }
+
+    /// Refuses the slug `slug`, e.g. a route of the service.
+    pub fn deny(mut self, slug: impl Into<String>) -> Self {
+        self.denied.push(slug.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Refuses slugs containing `word`.
+    pub fn block_word(mut self, word: impl Into<String>) -> Self {
+        self.words.push(read_as_letters(&word.into()));
+        self
+    }
+
+    pub fn block_words<I>(self, words: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        words.into_iter().fold(self, SlugFilter::block_word)
+    }
+
+    /// Fails with `InvalidSlug` if `slug` is denied or contains a blocked
+    /// word.
+    pub fn check(&self, slug: &str) -> Result<()> {
+        if self
+            .denied
+            .iter()
+            .any(|denied| denied.eq_ignore_ascii_case(slug))
+        {
+            return Err(UrlManagerError::InvalidSlug(format!(
+                "'{slug}' is reserved"
+            )));
+        }
+        let letters = read_as_letters(slug);
+        if self
+            .words
+            .iter()
+            .any(|word| letters.contains(word.as_str()))
+        {
+            return Err(UrlManagerError::InvalidSlug(format!(
+                "'{slug}' contains a blocked word"
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn allows(&self, slug: &str) -> bool {
+        self.check(slug).is_ok()
+    }
+}
+
+impl Default for SlugFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Lowercases `text`, reads look-alike digits and symbols as letters and
+// drops separators, so `Sh-1t` reads as `shit`.
+fn read_as_letters(text: &str) -> String {
+    text.chars()
+        .filter(|c| !matches!(c, '-' | '_'))
+        .map(|c| match c.to_ascii_lowercase() {
+            '0' => 'o',
+            '1' | '!' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            '8' => 'b',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_slug() {
+        for slug in ["docs", "Spring-Sale_2024", "a"] {
+            assert!(validate_slug(slug).is_ok(), "{slug} should be valid");
+        }
+        for slug in ["", "has space", "ümlaut", "a/b", "API", "health"] {
+            assert!(
+                matches!(validate_slug(slug), Err(UrlManagerError::InvalidSlug(_))),
+                "{slug} should be rejected"
+            );
+        }
+        assert!(validate_slug(&"x".repeat(MAX_SLUG_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn test_slug_filter() {
+        let filter = SlugFilter::new();
+        for slug in ["docs", "Spring-Sale_2024", "class", "analytics", "sussex"] {
+            assert!(filter.allows(slug), "{slug} should be allowed");
+        }
+        for slug in ["metrics", "STATIC", "holy-sh1t", "F_U_C_K", "b1tch"] {
+            assert!(
+                matches!(filter.check(slug), Err(UrlManagerError::InvalidSlug(_))),
+                "{slug} should be refused"
+            );
+        }
+        assert!(SlugFilter::empty().allows("api"));
+        assert!(!SlugFilter::empty().deny("pricing").allows("PRICING"));
+    }
+
+    #[test]
+    fn test_random_slugs() {
+        use rand::{Rng, SeedableRng};
+
+        const CHARS: &[char] = &[
+            'a', 'Z', '0', '-', '_', ' ', '/', '.', 'ü', '\u{0}', '?', '%',
+        ];
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x5eed);
+        for _ in 0..2000 {
+            let slug: String = (0..rng.gen_range(0..MAX_SLUG_LENGTH + 4))
+                .map(|_| CHARS[rng.gen_range(0..CHARS.len())])
+                .collect();
+            if validate_slug(&slug).is_ok() {
+                assert!((1..=MAX_SLUG_LENGTH).contains(&slug.len()));
+                // accepted slugs survive a round trip through a URL path
+                let url = crate::UrlType::parse("https://sho.rt/")
+                    .unwrap()
+                    .join(&slug)
+                    .unwrap();
+                assert_eq!(url.path(), format!("/{slug}"));
+            }
+        }
+    }
+}
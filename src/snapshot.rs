@@ -0,0 +1,297 @@
+//! Point-in-time backups of a store, see [`LinkStore::snapshot`].
+//!
+//! A snapshot is a JSON-lines archive: a header with the format name,
+//! [`VERSION`] and creation time, one record per link, trashed ones
+//! included, one per recorded click, and a trailer with the counts and a
+//! SHA-256 of every line before it. Links are written in full, hashes,
+//! previews and health included, so they come back as they were whatever
+//! the backend.
+//!
+//! ```text
+//! {"format":"url-manager-snapshot","version":1,"created_at":1700000000000}
+//! {"link":{"id":7,"target":"https://example.com/",…}}
+//! {"click":{"link_id":7,"at":1700000000000,"referrer":null,…}}
+//! {"end":{"links":1,"clicks":1,"sha256":"9f86d0…"}}
+//! ```
+
+use std::io::{BufRead, Write};
+use std::time::SystemTime;
+
+use crate::crypto::Sha256;
+use crate::json::{self, Value};
+use crate::link::{from_unix_millis, now, unix_millis};
+use crate::{Click, ClickRecorder, Link, LinkQuery, LinkStore, Result, UrlManagerError};
+
+/// Names the format in the header.
+pub const FORMAT: &str = "url-manager-snapshot";
+
+/// The format version written; snapshots of later versions are refused.
+pub const VERSION: u64 = 1;
+
+/// The links, and optionally the clicks, of a store at one point in time.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    created_at: SystemTime,
+    links: Vec<Link>,
+    clicks: Vec<Click>,
+}
+
+impl Snapshot {
+    /// Every link in `store`, trashed ones included, ordered by id.
+    pub fn of(store: &dyn LinkStore) -> Result<Self> {
+        let mut links = store.find(&LinkQuery::new().deleted())?;
+        for link in store.iter() {
+            links.push(link?);
+        }
+        links.sort_by_key(Link::id);
+        Ok(Snapshot {
+            created_at: now(),
+            links,
+            clicks: Vec::new(),
+        })
+    }
+
+    /// Also keeps `clicks`, e.g. from [`InMemoryClickRecorder::all_clicks`](crate::InMemoryClickRecorder::all_clicks).
+    pub fn with_clicks(mut self, clicks: Vec<Click>) -> Self {
+        self.clicks = clicks;
+        self
+    }
+
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    pub fn links(&self) -> &[Link] {
+        &self.links
+    }
+
+    pub fn clicks(&self) -> &[Click] {
+        &self.clicks
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        let mut hash = Sha256::new();
+        let mut write_line = |record: Value| -> Result<()> {
+            let line = format!("{record}\n");
+            hash.update(line.as_bytes());
+            writer.write_all(line.as_bytes())?;
+            Ok(())
+        };
+        write_line(Value::object([
+            ("format", Value::from(FORMAT)),
+            ("version", Value::from(VERSION)),
+            ("created_at", Value::from(unix_millis(self.created_at))),
+        ]))?;
+        for link in &self.links {
+            write_line(Value::object([("link", link.to_json())]))?;
+        }
+        for click in &self.clicks {
+            write_line(Value::object([("click", click.to_json())]))?;
+        }
+        let trailer = Value::object([(
+            "end",
+            Value::object([
+                ("links", Value::from(self.links.len() as u64)),
+                ("clicks", Value::from(self.clicks.len() as u64)),
+                ("sha256", Value::from(hex(&hash.finish()))),
+            ]),
+        )]);
+        writeln!(writer, "{trailer}")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a snapshot written by [`Snapshot::write_to`], failing with
+    /// `StorageBackend` if it is truncated, altered or of a later version.
+    pub fn read_from(mut reader: impl BufRead) -> Result<Self> {
+        let corrupt = |reason: String| UrlManagerError::backend(format!("bad snapshot: {reason}"));
+        let mut hash = Sha256::new();
+        let mut line = String::new();
+        let mut number = 0;
+        let mut snapshot = None;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(corrupt("it ends before its trailer".to_string()));
+            }
+            number += 1;
+            let record = json::parse(&line).map_err(|e| corrupt(format!("line {number}: {e}")))?;
+            let Some(snapshot) = &mut snapshot else {
+                snapshot = Some(header(&record).map_err(corrupt)?);
+                hash.update(line.as_bytes());
+                continue;
+            };
+            if let Some(end) = record.get("end") {
+                trailer(end, snapshot, hash.finish()).map_err(corrupt)?;
+                break;
+            }
+            hash.update(line.as_bytes());
+            let invalid = |e: UrlManagerError| corrupt(format!("line {number}: {e}"));
+            match (record.get("link"), record.get("click")) {
+                (Some(link), _) => snapshot.links.push(Link::from_json(link).map_err(invalid)?),
+                (_, Some(click)) => snapshot
+                    .clicks
+                    .push(Click::from_json(click).map_err(invalid)?),
+                _ => return Err(corrupt(format!("line {number} is no link or click"))),
+            }
+        }
+        line.clear();
+        if reader.read_line(&mut line)? > 0 {
+            return Err(corrupt("there is more after its trailer".to_string()));
+        }
+        Ok(snapshot.expect("the header was read"))
+    }
+
+    /// Stores every link of the snapshot in `store`, replacing links with
+    /// the same id; links created since the snapshot are kept. Returns how
+    /// many links were restored.
+    pub fn restore_into(&self, store: &mut dyn LinkStore) -> Result<usize> {
+        for link in &self.links {
+            store.upsert(link.clone())?;
+        }
+        Ok(self.links.len())
+    }
+
+    /// Hands every click of the snapshot to `recorder`, returning how many.
+    pub fn replay_clicks(&self, recorder: &dyn ClickRecorder) -> Result<usize> {
+        for click in &self.clicks {
+            recorder.record(click.clone())?;
+        }
+        recorder.flush()?;
+        Ok(self.clicks.len())
+    }
+}
+
+fn header(record: &Value) -> std::result::Result<Snapshot, String> {
+    if record.get("format").and_then(Value::as_str) != Some(FORMAT) {
+        return Err(format!("it doesn't start with a {FORMAT} header"));
+    }
+    match record.get("version").and_then(Value::as_u64) {
+        Some(version) if version <= VERSION => {}
+        Some(version) => return Err(format!("version {version} is newer than {VERSION}")),
+        None => return Err("the header has no version".to_string()),
+    }
+    let created_at = record
+        .get("created_at")
+        .and_then(Value::as_u64)
+        .ok_or("the header has no creation time")?;
+    Ok(Snapshot {
+        created_at: from_unix_millis(created_at),
+        links: Vec::new(),
+        clicks: Vec::new(),
+    })
+}
+
+fn trailer(end: &Value, snapshot: &Snapshot, sha256: [u8; 32]) -> std::result::Result<(), String> {
+    let count = |name: &str| end.get(name).and_then(Value::as_u64);
+    if count("links") != Some(snapshot.links.len() as u64)
+        || count("clicks") != Some(snapshot.clicks.len() as u64)
+    {
+        return Err("its record counts don't match its trailer".to_string());
+    }
+    if end.get("sha256").and_then(Value::as_str) != Some(hex(&sha256).as_str()) {
+        return Err("its checksum doesn't match".to_string());
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileLinkStore, InMemoryClickRecorder, InMemoryLinkStore};
+    use std::io::BufReader;
+
+    fn store() -> InMemoryLinkStore {
+        let mut store = InMemoryLinkStore::new();
+        for slug in ["docs", "blog", "shop"] {
+            let mut link = Link::builder()
+                .target(format!("https://example.com/{slug}").as_str())
+                .slug(slug);
+            if slug == "docs" {
+                link = link.password("hunter2");
+            }
+            store.create(link.build().unwrap()).unwrap();
+        }
+        store.record_hit("blog").unwrap();
+        let shop = store.resolve("shop").unwrap();
+        store.soft_delete(shop.id()).unwrap();
+        store
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let store = store();
+        let clicks = InMemoryClickRecorder::new();
+        let blog = store.resolve("blog").unwrap();
+        clicks
+            .record(Click {
+                link_id: blog.id(),
+                at: now(),
+                referrer: Some("https://news.example/".to_string()),
+                user_agent: None,
+                country: Some("DE".to_string()),
+                visitor: Some("192.0.2.1".to_string()),
+            })
+            .unwrap();
+
+        let mut archive = Vec::new();
+        Snapshot::of(&store)
+            .unwrap()
+            .with_clicks(clicks.all_clicks())
+            .write_to(&mut archive)
+            .unwrap();
+        let snapshot = Snapshot::read_from(archive.as_slice()).unwrap();
+        assert_eq!(snapshot.links().len(), 3);
+
+        let path = std::env::temp_dir().join(format!("snapshot-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut restored = FileLinkStore::open(&path).unwrap();
+        assert_eq!(snapshot.restore_into(&mut restored).unwrap(), 3);
+        let replayed = InMemoryClickRecorder::new();
+        assert_eq!(snapshot.replay_clicks(&replayed).unwrap(), 1);
+        assert_eq!(replayed.all_clicks(), clicks.all_clicks());
+
+        let blog_restored = restored.resolve("blog").unwrap();
+        assert_eq!(blog_restored.to_json(), blog.to_json());
+        assert_eq!(blog_restored.hit_count(), 1);
+        assert!(restored.resolve_with_password("docs", "hunter2").is_ok());
+        assert!(restored.resolve("shop").is_err());
+        assert_eq!(restored.find(&LinkQuery::new().deleted()).unwrap().len(), 1);
+        drop(restored);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut copy = InMemoryLinkStore::new();
+        copy.restore_snapshot(BufReader::new(archive.as_slice()))
+            .unwrap();
+        assert_eq!(copy.count().unwrap(), 2);
+        let mut again = Vec::new();
+        assert_eq!(copy.snapshot(&mut again).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_damaged() {
+        let mut archive = Vec::new();
+        store().snapshot(&mut archive).unwrap();
+        let text = String::from_utf8(archive).unwrap();
+        let read = |text: &str| match Snapshot::read_from(text.as_bytes()) {
+            Err(UrlManagerError::StorageBackend(e)) => e.to_string(),
+            other => panic!("{other:?}"),
+        };
+
+        let lines: Vec<&str> = text.lines().collect();
+        let truncated = lines[..lines.len() - 1].join("\n");
+        assert!(read(&truncated).contains("before its trailer"));
+        let altered = text.replace("example.com/blog", "example.com/evil");
+        assert!(read(&altered).contains("checksum"));
+        let dropped = format!("{}\n{}\n", lines[0], lines[lines.len() - 1]);
+        assert!(read(&dropped).contains("counts"));
+        let newer = text.replace("\"version\":1", "\"version\":2");
+        assert!(read(&newer).contains("newer"));
+        assert!(read("{\"id\":1}\n").contains("header"));
+        assert!(read(&format!("{text}{}\n", lines[1])).contains("after its trailer"));
+    }
+}
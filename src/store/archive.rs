@@ -0,0 +1,398 @@
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::{LinkQuery, LinkStore};
+use crate::analytics::{Analytics, DAY};
+use crate::json::{self, Value};
+use crate::link::now;
+use crate::{Click, ClickRecorder, Link, Result, UrlManagerError};
+
+/// Object storage in the shape of the `object_store` crate: whole objects
+/// put, fetched and deleted by key, and keys listed by prefix. Keys are
+/// `/`-separated paths.
+///
+/// Implement it over an `object_store::ObjectStore` for S3, GCS or Azure to
+/// give an [`ArchiveStore`] a bucket; [`LocalObjectStore`] keeps objects in
+/// a directory.
+pub trait ObjectStore {
+    /// Stores `bytes` under `key`, replacing what was there.
+    fn put(&self, key: &str, bytes: Vec<u8>) -> Result<()>;
+    /// The object stored under `key`, if any.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    /// Removes `key`; removing a missing key is not an error.
+    fn delete(&self, key: &str) -> Result<()>;
+    /// Every key starting with `prefix`, in order.
+    fn list(&self, prefix: &str) -> Result<Vec<String>>;
+}
+
+/// An [`ObjectStore`] keeping each object in a file below a directory, the
+/// key being its path relative to it.
+#[derive(Debug, Clone)]
+pub struct LocalObjectStore {
+    root: PathBuf,
+}
+
+impl LocalObjectStore {
+    /// Keeps objects below `root`, which is created on the first `put`.
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        LocalObjectStore {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if key
+            .split('/')
+            .any(|segment| matches!(segment, "" | "." | ".."))
+        {
+            return Err(UrlManagerError::backend(format!(
+                "invalid object key '{key}'"
+            )));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl ObjectStore for LocalObjectStore {
+    fn put(&self, key: &str, bytes: Vec<u8>) -> Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // written aside and renamed so readers never see half an object
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)?) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.path(key)?) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut dirs = vec![(self.root.clone(), String::new())];
+        while let Some((dir, key)) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                let child = format!("{key}{name}");
+                if entry.file_type()?.is_dir() {
+                    dirs.push((entry.path(), format!("{child}/")));
+                } else if child.starts_with(prefix) && !child.ends_with(".tmp") {
+                    keys.push(child);
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+// What an archive needs of a click recorder: taking clicks away and
+// reading them back.
+trait Clicks: ClickRecorder + Analytics {}
+
+impl<R: ClickRecorder + Analytics> Clicks for R {}
+
+/// Cold storage for links that are no longer followed, keeping the hot
+/// [`LinkStore`] small.
+///
+/// [`ArchiveStore::archive_stale`] moves expired and trashed links out of a
+/// store into objects, one per link under `links/<id>.json`, along with
+/// their clicks if given a recorder. [`ArchiveStore::restore`] brings a
+/// link back on demand as it was archived, trashed or expired, and its
+/// clicks with it. Links are written to the archive before they are
+/// deleted, so an interrupted move leaves a copy behind rather than
+/// nothing.
+///
+/// ```
+/// # use url_manager::{ArchiveStore, InMemoryLinkStore, LinkStore, LocalObjectStore};
+/// # use std::time::Duration;
+/// # let dir = std::env::temp_dir().join(format!("archive-doc-{}", std::process::id()));
+/// let mut store = InMemoryLinkStore::new();
+/// let link = store.create_with_slug("old", "https://example.com/".parse()?)?;
+/// store.soft_delete(link.id())?;
+///
+/// let archive = ArchiveStore::new(LocalObjectStore::new(&dir));
+/// assert_eq!(archive.archive_stale(&mut store, Duration::ZERO)?, 1);
+/// assert!(store.get(link.id())?.is_none());
+///
+/// archive.restore(&mut store, link.id())?;
+/// store.restore(link.id())?;
+/// assert_eq!(store.resolve("old")?.id(), link.id());
+/// # std::fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct ArchiveStore<O> {
+    objects: O,
+    prefix: String,
+    clicks: Option<Arc<dyn Clicks + Send + Sync>>,
+}
+
+impl<O: fmt::Debug> fmt::Debug for ArchiveStore<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchiveStore")
+            .field("objects", &self.objects)
+            .field("prefix", &self.prefix)
+            .field("clicks", &self.clicks.is_some())
+            .finish()
+    }
+}
+
+impl<O: ObjectStore> ArchiveStore<O> {
+    pub fn new(objects: O) -> Self {
+        ArchiveStore {
+            objects,
+            prefix: "links/".to_string(),
+            clicks: None,
+        }
+    }
+
+    /// Keeps links under `prefix` instead of `links/`, e.g. to share a
+    /// bucket.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Moves the clicks on archived links out of `recorder` and back.
+    pub fn clicks<R>(mut self, recorder: Arc<R>) -> Self
+    where
+        R: ClickRecorder + Analytics + Send + Sync + 'static,
+    {
+        self.clicks = Some(recorder);
+        self
+    }
+
+    pub fn get_ref(&self) -> &O {
+        &self.objects
+    }
+
+    /// Moves the link stored under `id` and its clicks into the archive,
+    /// failing with `NotFound` if there is none.
+    pub fn archive<S>(&self, store: &mut S, id: u64) -> Result<()>
+    where
+        S: LinkStore + ?Sized,
+    {
+        let link = store.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        let clicks = match &self.clicks {
+            // clicks are recorded as they happen, a day ahead leaves margin
+            Some(recorder) => recorder.clicks_between(SystemTime::UNIX_EPOCH..now() + DAY)?,
+            None => Vec::new(),
+        };
+        let object = Value::object([
+            ("link", link.to_json()),
+            (
+                "clicks",
+                Value::Array(
+                    clicks
+                        .iter()
+                        .filter(|click| click.link_id == id)
+                        .map(Click::to_json)
+                        .collect(),
+                ),
+            ),
+        ]);
+        self.objects
+            .put(&self.key(id), object.to_string().into_bytes())?;
+        if let Some(recorder) = &self.clicks {
+            recorder.forget(id)?;
+        }
+        store.delete(id)
+    }
+
+    /// Archives every link that expired or was trashed at least
+    /// `older_than` ago, returning how many were archived.
+    pub fn archive_stale<S>(&self, store: &mut S, older_than: Duration) -> Result<usize>
+    where
+        S: LinkStore + ?Sized,
+    {
+        let cutoff = now()
+            .checked_sub(older_than)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut ids = Vec::new();
+        for link in store.iter() {
+            let link = link?;
+            if link.expires_at().is_some_and(|at| at <= cutoff) {
+                ids.push(link.id());
+            }
+        }
+        for link in store.find(&LinkQuery::new().deleted())? {
+            if link.deleted_at().is_some_and(|at| at <= cutoff) {
+                ids.push(link.id());
+            }
+        }
+        for &id in &ids {
+            self.archive(store, id)?;
+        }
+        Ok(ids.len())
+    }
+
+    /// The archived link with `id`, if any.
+    pub fn get(&self, id: u64) -> Result<Option<Link>> {
+        match self.read(id)? {
+            Some((link, _)) => Ok(Some(link)),
+            None => Ok(None),
+        }
+    }
+
+    /// The ids of the archived links, in order.
+    pub fn ids(&self) -> Result<Vec<u64>> {
+        let mut ids: Vec<u64> = self
+            .objects
+            .list(&self.prefix)?
+            .iter()
+            .filter_map(|key| {
+                key.strip_prefix(&self.prefix)?
+                    .strip_suffix(".json")?
+                    .parse()
+                    .ok()
+            })
+            .collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Puts the archived link with `id` back into `store` as it was
+    /// archived, and its clicks back into the recorder, then drops it from
+    /// the archive. Fails with `NotFound` if it isn't archived, and like
+    /// [`LinkStore::create`] if `store` can't take it, keeping it archived.
+    pub fn restore<S>(&self, store: &mut S, id: u64) -> Result<Link>
+    where
+        S: LinkStore + ?Sized,
+    {
+        let (link, clicks) = self.read(id)?.ok_or(UrlManagerError::NotFound)?;
+        store.create(link.clone())?;
+        if let Some(recorder) = &self.clicks {
+            for click in clicks {
+                recorder.record(click)?;
+            }
+        }
+        self.objects.delete(&self.key(id))?;
+        Ok(link)
+    }
+
+    fn key(&self, id: u64) -> String {
+        format!("{}{id:020}.json", self.prefix)
+    }
+
+    fn read(&self, id: u64) -> Result<Option<(Link, Vec<Click>)>> {
+        let key = self.key(id);
+        let Some(bytes) = self.objects.get(&key)? else {
+            return Ok(None);
+        };
+        let invalid = || UrlManagerError::backend(format!("invalid archived link '{key}'"));
+        let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let object = json::parse(&text).map_err(|_| invalid())?;
+        let link = Link::from_json(object.get("link").ok_or_else(invalid)?)?;
+        let clicks = match object.get("clicks") {
+            Some(Value::Array(clicks)) => {
+                clicks.iter().map(Click::from_json).collect::<Result<_>>()?
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Some((link, clicks)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryClickRecorder, InMemoryLinkStore, UrlType};
+
+    #[test]
+    fn test_archive_and_restore() {
+        let dir = std::env::temp_dir().join(format!("archive-{}", rand::random::<u64>()));
+        let mut store = InMemoryLinkStore::new();
+        let target = UrlType::parse("https://example.com/").unwrap();
+        let live = store.create_with_slug("live", target.clone()).unwrap();
+        let trashed = store.create_with_slug("trashed", target).unwrap();
+        store.soft_delete(trashed.id()).unwrap();
+
+        let clicks = Arc::new(InMemoryClickRecorder::new());
+        for link_id in [live.id(), trashed.id(), trashed.id()] {
+            clicks
+                .record(Click {
+                    link_id,
+                    at: now(),
+                    referrer: None,
+                    user_agent: None,
+                    country: None,
+                    visitor: None,
+                })
+                .unwrap();
+        }
+        let archive = ArchiveStore::new(LocalObjectStore::new(&dir)).clicks(Arc::clone(&clicks));
+        assert_eq!(
+            archive
+                .archive_stale(&mut store, Duration::from_secs(60))
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            archive.archive_stale(&mut store, Duration::ZERO).unwrap(),
+            1
+        );
+        assert!(store.get(trashed.id()).unwrap().is_none());
+        assert_eq!(archive.ids().unwrap(), [trashed.id()]);
+        assert!(archive.get(trashed.id()).unwrap().unwrap().is_deleted());
+        assert_eq!(clicks.all_clicks().len(), 1);
+
+        let restored = archive.restore(&mut store, trashed.id()).unwrap();
+        assert_eq!(restored.shortcut(), Some("trashed"));
+        assert!(store.get(trashed.id()).unwrap().is_some());
+        assert_eq!(clicks.clicks(trashed.id()).len(), 2);
+        assert!(archive.ids().unwrap().is_empty());
+        assert!(matches!(
+            archive.restore(&mut store, trashed.id()),
+            Err(UrlManagerError::NotFound)
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_local_object_store() {
+        let dir = std::env::temp_dir().join(format!("objects-{}", rand::random::<u64>()));
+        let objects = LocalObjectStore::new(&dir);
+        assert_eq!(objects.list("").unwrap(), Vec::<String>::new());
+        objects.put("a/b/c.json", b"{}".to_vec()).unwrap();
+        objects.put("a/d.json", b"[]".to_vec()).unwrap();
+        objects.put("e.json", b"1".to_vec()).unwrap();
+        assert_eq!(objects.list("a/").unwrap(), ["a/b/c.json", "a/d.json"]);
+        assert_eq!(objects.get("e.json").unwrap(), Some(b"1".to_vec()));
+        objects.delete("e.json").unwrap();
+        objects.delete("e.json").unwrap();
+        assert_eq!(objects.get("e.json").unwrap(), None);
+        assert!(objects.put("../escape", Vec::new()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
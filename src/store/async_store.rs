@@ -0,0 +1,135 @@
+use std::future::Future;
+
+use super::LinkStore;
+use crate::{Link, Namespace, Result};
+
+/// Asynchronous counterpart of [`LinkStore`] for backends such as Postgres or Redis.
+///
+/// Any synchronous store can be used where an `AsyncLinkStore` is expected
+/// by wrapping it in a [`SyncStoreAdapter`].
+pub trait AsyncLinkStore {
+    fn get(&self, id: u64) -> impl Future<Output = Result<Option<Link>>> + Send;
+    fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> impl Future<Output = Result<Option<Link>>> + Send;
+    fn create(&mut self, link: Link) -> impl Future<Output = Result<()>> + Send;
+    fn upsert(&mut self, link: Link) -> impl Future<Output = Result<()>> + Send;
+    fn update(&mut self, id: u64, link: Link) -> impl Future<Output = Result<()>> + Send;
+    fn delete(&mut self, id: u64) -> impl Future<Output = Result<()>> + Send;
+    fn list(&self, offset: usize, limit: usize) -> impl Future<Output = Result<Vec<Link>>> + Send;
+    fn count(&self) -> impl Future<Output = Result<usize>> + Send;
+    fn purge_expired(&mut self) -> impl Future<Output = Result<usize>> + Send;
+
+    fn get_by_shortcut(&self, shortcut: &str) -> impl Future<Output = Result<Option<Link>>> + Send {
+        self.get_by_shortcut_in(None, shortcut)
+    }
+}
+
+/// Exposes a synchronous [`LinkStore`] through [`AsyncLinkStore`].
+///
+/// Every call runs the wrapped store inline, so this is only suitable for
+/// stores that don't block for long, like [`InMemoryLinkStore`](super::InMemoryLinkStore).
+#[derive(Debug, Default)]
+pub struct SyncStoreAdapter<S> {
+    inner: S,
+}
+
+impl<S: LinkStore> SyncStoreAdapter<S> {
+    pub fn new(inner: S) -> Self {
+        SyncStoreAdapter { inner }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> AsyncLinkStore for SyncStoreAdapter<S>
+where
+    S: LinkStore + Send + Sync,
+{
+    async fn get(&self, id: u64) -> Result<Option<Link>> {
+        self.inner.get(id)
+    }
+
+    async fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        self.inner.get_by_shortcut_in(namespace, shortcut)
+    }
+
+    async fn create(&mut self, link: Link) -> Result<()> {
+        self.inner.create(link)
+    }
+
+    async fn upsert(&mut self, link: Link) -> Result<()> {
+        self.inner.upsert(link)
+    }
+
+    async fn update(&mut self, id: u64, link: Link) -> Result<()> {
+        self.inner.update(id, link)
+    }
+
+    async fn delete(&mut self, id: u64) -> Result<()> {
+        self.inner.delete(id)
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.inner.list(offset, limit)
+    }
+
+    async fn count(&self) -> Result<usize> {
+        self.inner.count()
+    }
+
+    async fn purge_expired(&mut self) -> Result<usize> {
+        self.inner.purge_expired()
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::yield_now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryLinkStore, UrlManagerError};
+
+    #[test]
+    fn test_sync_adapter() {
+        let mut store = SyncStoreAdapter::new(InMemoryLinkStore::new());
+        let link = Link::default();
+        let id = link.id();
+
+        block_on(store.create(link.clone())).unwrap();
+        assert!(block_on(store.get(id)).unwrap().is_some());
+        assert!(store.get_ref().get(id).unwrap().is_some());
+
+        block_on(store.update(id, link)).unwrap();
+        block_on(store.delete(id)).unwrap();
+        assert!(matches!(
+            block_on(store.delete(id)),
+            Err(UrlManagerError::NotFound)
+        ));
+    }
+}
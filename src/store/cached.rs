@@ -0,0 +1,437 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{LinkQuery, LinkStore, Revision, Transactional};
+use crate::{metrics, sync, Link, Namespace, Result};
+
+/// How many lookups a [`CachedLinkStore`] answered from its cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A [`LinkStore`] keeping the most recently read links of a slower store,
+/// e.g. one backed by a database, in memory.
+///
+/// `get` and `get_by_shortcut_in`, and so the `resolve` family, are served
+/// from a least-recently-used cache of `capacity` links, each kept for at
+/// most `ttl`. Writes go to the wrapped store and drop the links they touch
+/// from the cache, so changes made through this store are seen at once;
+/// changes made to the backend behind its back are seen within `ttl`.
+/// Misses aren't cached, so new links resolve immediately.
+///
+/// Hits are counted by the wrapped store, which can do it atomically; the
+/// counted link then replaces the cached one.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use url_manager::{CachedLinkStore, InMemoryLinkStore, LinkStore};
+/// let mut store = CachedLinkStore::new(InMemoryLinkStore::new())
+///     .capacity(10_000)
+///     .ttl(Duration::from_secs(30));
+/// let link = store.create_with_slug("docs", "https://example.com/docs".parse()?)?;
+/// assert_eq!(store.resolve("docs")?.id(), link.id());
+/// assert_eq!(store.resolve("docs")?.id(), link.id());
+/// assert_eq!(store.cache_stats().hits, 1);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct CachedLinkStore<S> {
+    inner: S,
+    capacity: usize,
+    ttl: Duration,
+    cache: Mutex<Cache>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    links: HashMap<u64, Entry>,
+    // (namespace, shortcut) to id
+    shortcuts: HashMap<(String, String), u64>,
+    // last use to id; the first entry is the least recently used
+    recency: BTreeMap<u64, u64>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+#[derive(Debug)]
+struct Entry {
+    link: Link,
+    cached_at: Instant,
+    used: u64,
+    shortcuts: Vec<(String, String)>,
+}
+
+impl<S: LinkStore> CachedLinkStore<S> {
+    /// Caches up to 1024 links of `inner` for a minute each.
+    pub fn new(inner: S) -> Self {
+        CachedLinkStore {
+            inner,
+            capacity: 1024,
+            ttl: Duration::from_secs(60),
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// Keeps at most `capacity` links; 0 disables the cache.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Reads a link from the wrapped store again once it has been cached
+    /// for `ttl`.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        sync::lock(&self.cache).stats
+    }
+
+    /// Drops every cached link.
+    pub fn clear(&self) {
+        let mut cache = sync::lock(&self.cache);
+        let stats = cache.stats;
+        *cache = Cache {
+            stats,
+            ..Cache::default()
+        };
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn invalidate(&self, id: u64) {
+        sync::lock(&self.cache).remove(id);
+    }
+
+    fn cached(&self, lookup: impl FnOnce(&mut Cache) -> Option<u64>) -> Option<Link> {
+        let mut cache = sync::lock(&self.cache);
+        let now = Instant::now();
+        let found = lookup(&mut cache).and_then(|id| {
+            let fresh = cache.links.get(&id)?.cached_at + self.ttl > now;
+            if fresh {
+                cache.touch(id)
+            } else {
+                cache.remove(id);
+                None
+            }
+        });
+        let counter = if found.is_some() {
+            &mut cache.stats.hits
+        } else {
+            &mut cache.stats.misses
+        };
+        *counter += 1;
+        metrics::cache_lookup(found.is_some());
+        found
+    }
+
+    fn keep(&self, link: &Link, shortcut: Option<(String, String)>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut cache = sync::lock(&self.cache);
+        let id = link.id();
+        let mut shortcuts = cache
+            .remove(id)
+            .map(|entry| entry.shortcuts)
+            .unwrap_or_default();
+        if let Some(key) = shortcut {
+            if !shortcuts.contains(&key) {
+                shortcuts.push(key);
+            }
+        }
+        while cache.links.len() >= self.capacity {
+            let Some((_, oldest)) = cache.recency.pop_first() else {
+                break;
+            };
+            cache.remove(oldest);
+        }
+        for key in &shortcuts {
+            cache.shortcuts.insert(key.clone(), id);
+        }
+        cache.tick += 1;
+        let used = cache.tick;
+        cache.recency.insert(used, id);
+        cache.links.insert(
+            id,
+            Entry {
+                link: link.clone(),
+                cached_at: Instant::now(),
+                used,
+                shortcuts,
+            },
+        );
+    }
+}
+
+impl Cache {
+    fn touch(&mut self, id: u64) -> Option<Link> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.links.get_mut(&id)?;
+        self.recency.remove(&entry.used);
+        entry.used = tick;
+        self.recency.insert(tick, id);
+        Some(entry.link.clone())
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Entry> {
+        let entry = self.links.remove(&id)?;
+        self.recency.remove(&entry.used);
+        for key in &entry.shortcuts {
+            if self.shortcuts.get(key) == Some(&id) {
+                self.shortcuts.remove(key);
+            }
+        }
+        Some(entry)
+    }
+}
+
+fn shortcut_key(namespace: Option<&Namespace>, shortcut: &str) -> (String, String) {
+    (
+        namespace.map_or("", Namespace::as_str).to_string(),
+        shortcut.to_string(),
+    )
+}
+
+impl<S: LinkStore> LinkStore for CachedLinkStore<S> {
+    fn get(&self, id: u64) -> Result<Option<Link>> {
+        if let Some(link) = self.cached(|_| Some(id)) {
+            return Ok(Some(link));
+        }
+        let link = self.inner.get(id)?;
+        if let Some(link) = &link {
+            self.keep(link, None);
+        }
+        Ok(link)
+    }
+
+    fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        let key = shortcut_key(namespace, shortcut);
+        if let Some(link) = self.cached(|cache| cache.shortcuts.get(&key).copied()) {
+            return Ok(Some(link));
+        }
+        let link = self.inner.get_by_shortcut_in(namespace, shortcut)?;
+        if let Some(link) = &link {
+            self.keep(link, Some(key));
+        }
+        Ok(link)
+    }
+
+    fn create(&mut self, link: Link) -> Result<()> {
+        self.inner.create(link)
+    }
+
+    fn create_batch(&mut self, links: Vec<Link>) -> Vec<Result<Link>> {
+        self.inner.create_batch(links)
+    }
+
+    fn upsert(&mut self, link: Link) -> Result<()> {
+        self.invalidate(link.id());
+        self.inner.upsert(link)
+    }
+
+    fn update(&mut self, id: u64, link: Link) -> Result<()> {
+        self.invalidate(id);
+        self.inner.update(id, link)
+    }
+
+    fn update_if_version(&mut self, id: u64, version: u64, link: Link) -> Result<()> {
+        self.invalidate(id);
+        self.inner.update_if_version(id, version, link)
+    }
+
+    fn update_with(&mut self, id: u64, change: impl FnOnce(&mut Link)) -> Result<Link> {
+        self.invalidate(id);
+        self.inner.update_with(id, change)
+    }
+
+    fn record_hit_with_password_in(
+        &mut self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        let result = self
+            .inner
+            .record_hit_with_password_in(namespace, shortcut, password);
+        match &result {
+            Ok(link) => self.keep(link, Some(shortcut_key(namespace, shortcut))),
+            // e.g. deleted or used up behind the cache's back
+            Err(_) => {
+                let key = shortcut_key(namespace, shortcut);
+                let mut cache = sync::lock(&self.cache);
+                if let Some(id) = cache.shortcuts.get(&key).copied() {
+                    cache.remove(id);
+                }
+            }
+        }
+        result
+    }
+
+    fn record_resolved_hit(&mut self, link: &Link) -> Result<Link> {
+        let result = self.inner.record_resolved_hit(link);
+        match &result {
+            Ok(link) => self.keep(link, None),
+            Err(_) => self.invalidate(link.id()),
+        }
+        result
+    }
+
+    fn record_variant_hit(&mut self, id: u64, variant: usize) -> Result<Link> {
+        self.invalidate(id);
+        self.inner.record_variant_hit(id, variant)
+    }
+
+    fn delete(&mut self, id: u64) -> Result<()> {
+        self.invalidate(id);
+        self.inner.delete(id)
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.inner.list(offset, limit)
+    }
+
+    fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.inner.list_deleted(offset, limit)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Link>> + '_> {
+        self.inner.iter()
+    }
+
+    fn find(&self, query: &LinkQuery) -> Result<Vec<Link>> {
+        self.inner.find(query)
+    }
+
+    fn count(&self) -> Result<usize> {
+        self.inner.count()
+    }
+
+    fn ping(&self) -> Result<()> {
+        self.inner.ping()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn purge_expired(&mut self) -> Result<usize> {
+        self.clear();
+        self.inner.purge_expired()
+    }
+
+    fn history(&self, id: u64) -> Result<Vec<Revision>> {
+        self.inner.history(id)
+    }
+}
+
+/// Rolling back also drops every cached link.
+impl<S: Transactional> Transactional for CachedLinkStore<S> {
+    fn begin(&mut self) -> Result<()> {
+        self.inner.begin()
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let result = self.inner.commit();
+        if result.is_err() {
+            self.clear();
+        }
+        result
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        let result = self.inner.rollback();
+        self.clear();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryLinkStore, UrlManagerError, UrlType};
+
+    fn link(slug: &str) -> Link {
+        Link::builder()
+            .target(UrlType::parse("https://example.com").unwrap())
+            .slug(slug)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_through_and_invalidation() {
+        let mut store = CachedLinkStore::new(InMemoryLinkStore::new());
+        let docs = link("docs");
+        store.create(docs.clone()).unwrap();
+
+        assert!(store.get_by_shortcut("docs").unwrap().is_some());
+        assert!(store.get(docs.id()).unwrap().is_some());
+        assert_eq!(store.cache_stats(), CacheStats { hits: 1, misses: 1 });
+
+        store
+            .update_with(docs.id(), |link| {
+                link.add_tag("changed");
+            })
+            .unwrap();
+        let cached = store.get_by_shortcut("docs").unwrap().unwrap();
+        assert!(cached.has_tag("changed"));
+
+        assert_eq!(store.record_hit("docs").unwrap().hit_count(), 1);
+        assert_eq!(store.resolve("docs").unwrap().hit_count(), 1);
+
+        store.delete(docs.id()).unwrap();
+        assert!(store.get(docs.id()).unwrap().is_none());
+        assert!(matches!(
+            store.resolve("docs"),
+            Err(UrlManagerError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut store = CachedLinkStore::new(InMemoryLinkStore::new()).capacity(2);
+        let links: Vec<Link> = ["a", "b", "c"].into_iter().map(link).collect();
+        for link in &links {
+            store.create(link.clone()).unwrap();
+        }
+        store.get(links[0].id()).unwrap();
+        store.get(links[1].id()).unwrap();
+        store.get(links[0].id()).unwrap();
+        // evicts b, the least recently used
+        store.get(links[2].id()).unwrap();
+        let before = store.cache_stats();
+        store.get(links[0].id()).unwrap();
+        store.get(links[1].id()).unwrap();
+        let after = store.cache_stats();
+        assert_eq!(after.hits - before.hits, 1);
+        assert_eq!(after.misses - before.misses, 1);
+        assert_eq!(sync::lock(&store.cache).links.len(), 2);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let mut store = CachedLinkStore::new(InMemoryLinkStore::new()).ttl(Duration::ZERO);
+        let docs = link("docs");
+        store.create(docs.clone()).unwrap();
+        store.get(docs.id()).unwrap();
+        store.get(docs.id()).unwrap();
+        assert_eq!(store.cache_stats(), CacheStats { hits: 0, misses: 2 });
+    }
+}
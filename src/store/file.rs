@@ -0,0 +1,446 @@
+use std::path::Path;
+
+use super::journal::{self, Journal};
+use super::map::LinkMap;
+use super::{LinkQuery, LinkStore, Transactional};
+use crate::{Link, Namespace, Result, UrlManagerError};
+
+/// A [`LinkStore`] persisting links to an append-only JSON-lines log.
+///
+/// Every mutation appends one record; the log is rewritten to contain only
+/// the live links once it holds more than twice as many records as links.
+/// On open, a torn record left at the end of the log by a crash is dropped,
+/// while a damaged record anywhere else fails the open. A change that can't
+/// be appended is undone, so the links never run ahead of the log.
+#[derive(Debug)]
+pub struct FileLinkStore {
+    journal: Journal,
+    links: LinkMap,
+}
+
+impl FileLinkStore {
+    /// Opens the log at `path`, creating it if it doesn't exist, and replays it.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let (journal, links) = Journal::open(path)?;
+        Ok(FileLinkStore { journal, links })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.journal.path()
+    }
+
+    /// All live links, in no particular order.
+    pub fn links(&self) -> impl Iterator<Item = &Link> {
+        self.links.values()
+    }
+
+    /// Rewrites the log so it only holds the live links; fails while a
+    /// transaction is open.
+    pub fn compact(&mut self) -> Result<()> {
+        if self.links.in_transaction() {
+            return Err(journal::compacting_in_transaction());
+        }
+        self.journal.compact(&self.links)
+    }
+
+    // Logs the link now stored under `id`, see `journal::log`.
+    fn log(&mut self, id: u64, previous: Option<Link>) -> Result<()> {
+        journal::log(Some(&mut self.journal), &mut self.links, id, previous)
+    }
+}
+
+impl LinkStore for FileLinkStore {
+    fn get(&self, id: u64) -> Result<Option<Link>> {
+        Ok(self.links.get(id).cloned())
+    }
+
+    fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        Ok(self.links.get_by_shortcut(namespace, shortcut).cloned())
+    }
+
+    fn create(&mut self, link: Link) -> Result<()> {
+        let id = link.id();
+        self.links.insert_new(link)?;
+        self.log(id, None)
+    }
+
+    fn create_batch(&mut self, batch: Vec<Link>) -> Vec<Result<Link>> {
+        journal::create_batch(Some(&mut self.journal), &mut self.links, batch)
+    }
+
+    fn upsert(&mut self, link: Link) -> Result<()> {
+        let id = link.id();
+        let previous = self.links.get(id).cloned();
+        self.links.insert(id, link)?;
+        self.log(id, previous)
+    }
+
+    fn update(&mut self, id: u64, link: Link) -> Result<()> {
+        let previous = self.links.get(id).ok_or(UrlManagerError::NotFound)?;
+        let link = link.updated_from(previous);
+        let previous = previous.clone();
+        self.links.insert(id, link)?;
+        self.log(id, Some(previous))
+    }
+
+    fn update_if_version(&mut self, id: u64, version: u64, link: Link) -> Result<()> {
+        let previous = self.links.get(id).ok_or(UrlManagerError::NotFound)?;
+        if previous.version() != version {
+            return Err(UrlManagerError::Conflict(previous.version()));
+        }
+        self.update(id, link)
+    }
+
+    fn update_with(&mut self, id: u64, change: impl FnOnce(&mut Link)) -> Result<Link> {
+        let previous = self.links.get(id).cloned();
+        let link = self.links.modify(id, change)?.clone();
+        self.log(id, previous)?;
+        Ok(link)
+    }
+
+    fn record_hit_with_password_in(
+        &mut self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        let checked = self
+            .links
+            .get_by_shortcut(namespace, shortcut)
+            .cloned()
+            .ok_or(UrlManagerError::NotFound)?;
+        checked.check_access(password)?;
+        self.record_resolved_hit(&checked)
+    }
+
+    fn record_resolved_hit(&mut self, link: &Link) -> Result<Link> {
+        let previous = self.links.get(link.id()).cloned();
+        let link = self.links.hit(link)?.clone();
+        self.log(link.id(), previous)?;
+        Ok(link)
+    }
+
+    fn record_variant_hit(&mut self, id: u64, variant: usize) -> Result<Link> {
+        let previous = self.links.get(id).cloned();
+        let link = self.links.hit_variant(id, variant)?.clone();
+        self.log(id, previous)?;
+        Ok(link.resolved_to(variant))
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        Ok(self
+            .links
+            .live()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        Ok(self
+            .links
+            .trashed()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Link>> + '_> {
+        Box::new(self.links.live().cloned().map(Ok))
+    }
+
+    fn find(&self, query: &LinkQuery) -> Result<Vec<Link>> {
+        Ok(self.links.find(query))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.journal.sync()
+    }
+
+    fn count(&self) -> Result<usize> {
+        Ok(self.links.live().count())
+    }
+
+    fn purge_expired(&mut self) -> Result<usize> {
+        let expired = self.links.purge_expired();
+        // not undone on failure: the links are expired either way, and a
+        // replay brings back only what the next purge removes again
+        if !self.links.in_transaction() {
+            for &id in &expired {
+                self.journal.delete(id, &self.links)?;
+            }
+        }
+        Ok(expired.len())
+    }
+
+    fn delete(&mut self, id: u64) -> Result<()> {
+        let previous = self.links.remove(id).ok_or(UrlManagerError::NotFound)?;
+        self.log(id, Some(previous))
+    }
+}
+
+impl Transactional for FileLinkStore {
+    fn begin(&mut self) -> Result<()> {
+        self.links.begin()
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let changed = self.links.changed()?;
+        if let Err(e) = self.journal.commit(&changed, &self.links) {
+            self.links.rollback()?;
+            return Err(e);
+        }
+        self.links.commit()
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.links.rollback()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UrlType;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "url-manager-{}-{}.jsonl",
+            name,
+            rand::random::<u32>()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_persists_across_reopen() {
+        let path = temp_path("reopen");
+        let target = UrlType::parse("https://www.example.com/docs").unwrap();
+        {
+            let mut store = FileLinkStore::open(&path).unwrap();
+            store
+                .create(
+                    Link::builder()
+                        .id(1)
+                        .target(target.clone())
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap();
+            store
+                .create(
+                    Link::builder()
+                        .id(2)
+                        .target(target.clone())
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap();
+            store.delete(2).unwrap();
+        }
+
+        let store = FileLinkStore::open(&path).unwrap();
+        assert_eq!(store.get(1).unwrap().unwrap().target(), &target);
+        assert!(store.get(2).unwrap().is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_id() {
+        let path = temp_path("duplicate");
+        let link = |target: &str| {
+            Link::builder()
+                .id(1)
+                .target(UrlType::parse(target).unwrap())
+                .build()
+                .unwrap()
+        };
+        {
+            let mut store = FileLinkStore::open(&path).unwrap();
+            store.create(link("https://example.com/a")).unwrap();
+            assert!(matches!(
+                store.create(link("https://example.com/b")),
+                Err(UrlManagerError::DuplicateId(1))
+            ));
+            assert_eq!(
+                store.journal.records(),
+                1,
+                "a rejected create is not logged"
+            );
+            store.upsert(link("https://example.com/c")).unwrap();
+        }
+
+        let store = FileLinkStore::open(&path).unwrap();
+        assert_eq!(
+            store.get(1).unwrap().unwrap().target().as_str(),
+            "https://example.com/c"
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_update_with_persists() {
+        let path = temp_path("update-with");
+        let link = Link::default();
+        {
+            let mut store = FileLinkStore::open(&path).unwrap();
+            store.create(link.clone()).unwrap();
+            store
+                .update_with(link.id(), |link| {
+                    link.add_tag("beta");
+                })
+                .unwrap();
+        }
+
+        let store = FileLinkStore::open(&path).unwrap();
+        assert!(store.get(link.id()).unwrap().unwrap().has_tag("beta"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_recovers_from_torn_write() {
+        let path = temp_path("torn");
+        {
+            let mut store = FileLinkStore::open(&path).unwrap();
+            store
+                .create(
+                    Link::builder()
+                        .id(1)
+                        .target(UrlType::parse("https://example.com").unwrap())
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+        let mut log = OpenOptions::new().append(true).open(&path).unwrap();
+        log.write_all(br#"{"op":"put","link":{"id":2,"#).unwrap();
+
+        let mut store = FileLinkStore::open(&path).unwrap();
+        assert!(store.get(1).unwrap().is_some());
+        store.delete(1).unwrap();
+
+        let store = FileLinkStore::open(&path).unwrap();
+        assert!(store.get(1).unwrap().is_none(), "log was not repaired");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_fails_on_damaged_record() {
+        let path = temp_path("damaged");
+        {
+            let mut store = FileLinkStore::open(&path).unwrap();
+            store.create(Link::default()).unwrap();
+            store.create(Link::default()).unwrap();
+        }
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, contents.replacen("\"op\"", "\"op", 1)).unwrap();
+
+        let error = FileLinkStore::open(&path).unwrap_err();
+        assert!(matches!(error, UrlManagerError::StorageBackend(_)));
+        assert!(error.to_string().contains(":1:"), "{error}");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_undoes_failed_writes() {
+        let path = temp_path("failed-write");
+        let mut store = FileLinkStore::open(&path).unwrap();
+        let docs = Link::builder()
+            .target("https://example.com/docs")
+            .slug("docs")
+            .build()
+            .unwrap();
+        store.create(docs.clone()).unwrap();
+        store.journal.fail_writes();
+
+        let blog = Link::builder()
+            .target("https://example.com/blog")
+            .slug("blog")
+            .build()
+            .unwrap();
+        assert!(store.create(blog.clone()).is_err());
+        assert!(store.upsert(blog.clone()).is_err());
+        assert!(store
+            .create_with_slug("news", docs.target().clone())
+            .is_err());
+        assert!(store.update(docs.id(), blog.clone()).is_err());
+        assert!(store
+            .update_with(docs.id(), |link| {
+                link.add_tag("beta");
+            })
+            .is_err());
+        assert!(store.record_hit("docs").is_err());
+        assert!(store.delete(docs.id()).is_err());
+
+        assert_eq!(store.count().unwrap(), 1);
+        assert!(store.get(blog.id()).unwrap().is_none());
+        assert!(store.get_by_shortcut("news").unwrap().is_none());
+        let stored = store.get(docs.id()).unwrap().unwrap();
+        assert_eq!(stored.shortcut(), Some("docs"));
+        assert!(!stored.has_tag("beta"));
+        assert_eq!(stored.hit_count(), 0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_compact() {
+        let path = temp_path("compact");
+        let mut store = FileLinkStore::open(&path).unwrap();
+        let link = Link::default();
+        let id = link.id();
+        store.create(link.clone()).unwrap();
+        for _ in 0..10 {
+            store.update(id, link.clone()).unwrap();
+        }
+        assert_eq!(store.journal.records(), 11);
+
+        store.compact().unwrap();
+        assert_eq!(store.journal.records(), 1);
+        store.delete(id).unwrap();
+
+        let store = FileLinkStore::open(&path).unwrap();
+        assert_eq!(store.journal.records(), 2);
+        assert!(store.get(id).unwrap().is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_create_batch() {
+        let path = temp_path("batch");
+        let mut store = FileLinkStore::open(&path).unwrap();
+        let link = |id, slug: &str| {
+            Link::builder()
+                .id(id)
+                .target("https://example.com")
+                .slug(slug)
+                .build()
+                .unwrap()
+        };
+        let results = store.create_batch(vec![link(1, "a"), link(2, "a"), link(3, "b")]);
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(UrlManagerError::ShortcutCollision(_))
+        ));
+        assert!(results[2].is_ok());
+        assert_eq!(store.journal.records(), 2);
+
+        let store = FileLinkStore::open(&path).unwrap();
+        assert_eq!(store.count().unwrap(), 2);
+        let resolved = store.resolve_batch(&["b", "a", "c"]);
+        assert_eq!(resolved[0].as_ref().unwrap().id(), 3);
+        assert_eq!(resolved[1].as_ref().unwrap().id(), 1);
+        assert!(matches!(resolved[2], Err(UrlManagerError::NotFound)));
+        fs::remove_file(path).unwrap();
+    }
+}
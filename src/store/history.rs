@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use super::{LinkQuery, LinkStore, Transactional};
+use crate::link::now;
+use crate::{Link, Namespace, Result, UrlManagerError};
+
+/// One change to a link, see [`LinkStore::history`].
+#[derive(Debug, Clone)]
+pub struct Revision {
+    pub at: SystemTime,
+    /// Who made the change, if the store was told.
+    pub actor: Option<String>,
+    pub change: Change,
+    /// The link as the change left it, `None` once deleted.
+    pub link: Option<Link>,
+}
+
+/// What a [`Revision`] did to its link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Change {
+    Created,
+    /// Changed the named fields, e.g. `target`, `shortcut` or `expires_at`.
+    Updated(Vec<&'static str>),
+    Deleted,
+}
+
+/// A [`LinkStore`] recording every change made through it as a
+/// [`Revision`], for [`LinkStore::history`].
+///
+/// Creations, updates and deletions are recorded, together with the actor
+/// set with [`AuditedLinkStore::set_actor`]; hits aren't, and neither are
+/// updates that only bump timestamps. The history is kept in memory next to
+/// the wrapped store, so it starts over when the process does.
+///
+/// ```
+/// # use url_manager::{AuditedLinkStore, Change, InMemoryLinkStore, LinkStore};
+/// let mut store = AuditedLinkStore::new(InMemoryLinkStore::new());
+/// store.set_actor(Some("alice"));
+/// let link = store.create_with_slug("launch", "https://example.com/soon".parse()?)?;
+/// store.update_with(link.id(), |link| {
+///     link.add_tag("campaign");
+/// })?;
+///
+/// let history = store.history(link.id())?;
+/// assert_eq!(history[0].change, Change::Created);
+/// assert_eq!(history[1].change, Change::Updated(vec!["tags"]));
+/// assert_eq!(history[1].actor.as_deref(), Some("alice"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct AuditedLinkStore<S> {
+    inner: S,
+    actor: Option<String>,
+    revisions: HashMap<u64, Vec<Revision>>,
+    // id to how many revisions it had before the open transaction
+    undo: Option<HashMap<u64, usize>>,
+}
+
+impl<S: LinkStore> AuditedLinkStore<S> {
+    pub fn new(inner: S) -> Self {
+        AuditedLinkStore {
+            inner,
+            actor: None,
+            revisions: HashMap::new(),
+            undo: None,
+        }
+    }
+
+    /// Attributes the following changes to `actor`.
+    pub fn set_actor(&mut self, actor: Option<impl Into<String>>) {
+        self.actor = actor.map(Into::into);
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn record(&mut self, id: u64, change: Change, link: Option<Link>) {
+        let revisions = self.revisions.entry(id).or_default();
+        if let Some(undo) = &mut self.undo {
+            undo.entry(id).or_insert(revisions.len());
+        }
+        revisions.push(Revision {
+            at: now(),
+            actor: self.actor.clone(),
+            change,
+            link,
+        });
+    }
+
+    // Records the stored link under `id` as a change from `previous`.
+    fn record_update(&mut self, id: u64, previous: Option<Link>) -> Result<Option<Link>> {
+        let current = self.inner.get(id)?;
+        match (&previous, &current) {
+            (None, Some(_)) => self.record(id, Change::Created, current.clone()),
+            (Some(_), None) => self.record(id, Change::Deleted, None),
+            (Some(previous), Some(link)) => {
+                let fields = link.changes_from(previous);
+                if !fields.is_empty() {
+                    self.record(id, Change::Updated(fields), current.clone());
+                }
+            }
+            (None, None) => {}
+        }
+        Ok(current)
+    }
+
+    // Records the deletion of every link with a history that is gone.
+    fn record_removed(&mut self) -> Result<()> {
+        let mut removed = Vec::new();
+        for (&id, revisions) in &self.revisions {
+            let live = revisions.last().is_some_and(|last| last.link.is_some());
+            if live && self.inner.get(id)?.is_none() {
+                removed.push(id);
+            }
+        }
+        for id in removed {
+            self.record(id, Change::Deleted, None);
+        }
+        Ok(())
+    }
+}
+
+impl<S: LinkStore> LinkStore for AuditedLinkStore<S> {
+    fn get(&self, id: u64) -> Result<Option<Link>> {
+        self.inner.get(id)
+    }
+
+    fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        self.inner.get_by_shortcut_in(namespace, shortcut)
+    }
+
+    fn create(&mut self, link: Link) -> Result<()> {
+        let id = link.id();
+        self.inner.create(link)?;
+        self.record_update(id, None).map(drop)
+    }
+
+    fn upsert(&mut self, link: Link) -> Result<()> {
+        let id = link.id();
+        let previous = self.inner.get(id)?;
+        self.inner.upsert(link)?;
+        self.record_update(id, previous).map(drop)
+    }
+
+    fn update(&mut self, id: u64, link: Link) -> Result<()> {
+        let previous = self.inner.get(id)?;
+        self.inner.update(id, link)?;
+        self.record_update(id, previous).map(drop)
+    }
+
+    fn update_if_version(&mut self, id: u64, version: u64, link: Link) -> Result<()> {
+        let previous = self.inner.get(id)?;
+        self.inner.update_if_version(id, version, link)?;
+        self.record_update(id, previous).map(drop)
+    }
+
+    fn update_with(&mut self, id: u64, change: impl FnOnce(&mut Link)) -> Result<Link> {
+        let previous = self.inner.get(id)?;
+        let link = self.inner.update_with(id, change)?;
+        self.record_update(id, previous)?;
+        Ok(link)
+    }
+
+    fn record_hit_with_password_in(
+        &mut self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        self.inner
+            .record_hit_with_password_in(namespace, shortcut, password)
+    }
+
+    fn record_resolved_hit(&mut self, link: &Link) -> Result<Link> {
+        self.inner.record_resolved_hit(link)
+    }
+
+    fn record_variant_hit(&mut self, id: u64, variant: usize) -> Result<Link> {
+        self.inner.record_variant_hit(id, variant)
+    }
+
+    fn delete(&mut self, id: u64) -> Result<()> {
+        self.inner.delete(id)?;
+        self.record(id, Change::Deleted, None);
+        Ok(())
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.inner.list(offset, limit)
+    }
+
+    fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.inner.list_deleted(offset, limit)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Link>> + '_> {
+        self.inner.iter()
+    }
+
+    fn find(&self, query: &LinkQuery) -> Result<Vec<Link>> {
+        self.inner.find(query)
+    }
+
+    fn count(&self) -> Result<usize> {
+        self.inner.count()
+    }
+
+    fn ping(&self) -> Result<()> {
+        self.inner.ping()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn purge_expired(&mut self) -> Result<usize> {
+        let purged = self.inner.purge_expired()?;
+        if purged > 0 {
+            self.record_removed()?;
+        }
+        Ok(purged)
+    }
+
+    fn history(&self, id: u64) -> Result<Vec<Revision>> {
+        match self.revisions.get(&id) {
+            Some(revisions) => Ok(revisions.clone()),
+            None if self.inner.get(id)?.is_some() => Ok(Vec::new()),
+            None => Err(UrlManagerError::NotFound),
+        }
+    }
+}
+
+/// Rolling back also drops the revisions recorded in the transaction.
+impl<S: Transactional> Transactional for AuditedLinkStore<S> {
+    fn begin(&mut self) -> Result<()> {
+        self.inner.begin()?;
+        self.undo = Some(HashMap::new());
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let result = self.inner.commit();
+        match result {
+            Ok(()) => self.undo = None,
+            // the inner store has rolled back
+            Err(_) => self.drop_uncommitted(),
+        }
+        result
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.inner.rollback()?;
+        self.drop_uncommitted();
+        Ok(())
+    }
+}
+
+impl<S> AuditedLinkStore<S> {
+    fn drop_uncommitted(&mut self) {
+        for (id, len) in self.undo.take().unwrap_or_default() {
+            match self.revisions.get_mut(&id) {
+                Some(revisions) if len > 0 => revisions.truncate(len),
+                _ => {
+                    self.revisions.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryLinkStore, UrlType};
+
+    #[test]
+    fn test_history() {
+        let mut store = AuditedLinkStore::new(InMemoryLinkStore::new());
+        let link = store
+            .create_with_slug(
+                "launch",
+                UrlType::parse("https://example.com/soon").unwrap(),
+            )
+            .unwrap();
+        store.set_actor(Some("alice"));
+        let moved = Link::builder()
+            .id(link.id())
+            .target(UrlType::parse("https://example.com/now").unwrap())
+            .slug("launch")
+            .build()
+            .unwrap();
+        store.update(link.id(), moved.clone()).unwrap();
+        store.update(link.id(), moved).unwrap();
+        store.record_hit("launch").unwrap();
+        let now = SystemTime::now();
+        let current = store.link_at(link.id(), now).unwrap().unwrap();
+        assert_eq!(current.target().as_str(), "https://example.com/now");
+        assert!(store
+            .link_at(link.id(), SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .is_none());
+        store.soft_delete(link.id()).unwrap();
+        store.set_actor(None::<String>);
+        store.purge(std::time::Duration::ZERO).unwrap();
+
+        let history = store.history(link.id()).unwrap();
+        let changes: Vec<&Change> = history.iter().map(|revision| &revision.change).collect();
+        assert_eq!(
+            changes,
+            [
+                &Change::Created,
+                &Change::Updated(vec!["origin", "target"]),
+                &Change::Updated(vec!["deleted_at"]),
+                &Change::Deleted,
+            ]
+        );
+        let actors: Vec<Option<&str>> = history
+            .iter()
+            .map(|revision| revision.actor.as_deref())
+            .collect();
+        assert_eq!(actors, [None, Some("alice"), Some("alice"), None]);
+        assert!(history[3].link.is_none());
+        assert!(matches!(store.history(0), Err(UrlManagerError::NotFound)));
+    }
+
+    #[test]
+    fn test_purge_expired_is_recorded() {
+        let mut store = AuditedLinkStore::new(InMemoryLinkStore::new());
+        let link = store
+            .create_with_slug("soon", UrlType::parse("https://example.com").unwrap())
+            .unwrap();
+        store.update_with(link.id(), Link::expire).unwrap();
+        assert_eq!(store.purge_expired().unwrap(), 1);
+        let history = store.history(link.id()).unwrap();
+        assert_eq!(history.last().unwrap().change, Change::Deleted);
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut store = AuditedLinkStore::new(InMemoryLinkStore::new());
+        let link = store
+            .create_with_slug("docs", UrlType::parse("https://example.com").unwrap())
+            .unwrap();
+        let result = store.transaction(|store| {
+            store.soft_delete(link.id())?;
+            store.create_with_slug("blog", UrlType::parse("https://example.com").unwrap())?;
+            Err::<(), _>(UrlManagerError::NotFound)
+        });
+        assert!(matches!(result, Err(UrlManagerError::NotFound)));
+        assert_eq!(store.history(link.id()).unwrap().len(), 1);
+        assert!(store.revisions.len() == 1 && store.resolve("blog").is_err());
+
+        store.transaction(|store| store.delete(link.id())).unwrap();
+        assert_eq!(store.history(link.id()).unwrap().len(), 2);
+        assert!(store.rollback().is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::map::LinkMap;
+use crate::json::{self, Value};
+use crate::{Link, Result, UrlManagerError};
+
+// Don't bother compacting tiny logs.
+const COMPACT_MIN_RECORDS: usize = 1024;
+
+/// An append-only JSON-lines log of link changes, one `put`, `delete` or
+/// `batch` record per line, shared by [`FileLinkStore`](super::FileLinkStore) and
+/// journaled [`InMemoryLinkStore`](super::InMemoryLinkStore)s.
+///
+/// A `batch` holds the changes of a transaction, so a crash while it is
+/// written loses all of them or none. The log is rewritten to contain only
+/// the current links once it holds more than twice as many records as links.
+#[derive(Debug)]
+pub(super) struct Journal {
+    path: PathBuf,
+    log: File,
+    records: usize,
+}
+
+impl Journal {
+    /// Opens the log at `path`, creating it if it doesn't exist, and replays
+    /// it. A torn record left at the end by a crash is dropped; any other
+    /// record that doesn't replay, like one damaged in the middle of the log,
+    /// fails the open with a `StorageBackend` error naming its line, as
+    /// skipping it would silently lose or resurrect links.
+    pub(super) fn open(path: impl AsRef<Path>) -> Result<(Self, LinkMap)> {
+        let path = path.as_ref().to_path_buf();
+        // a leftover from a compaction interrupted before the rename
+        let _ = fs::remove_file(compaction_path(&path));
+
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut contents = String::new();
+        log.read_to_string(&mut contents)?;
+
+        let mut links = LinkMap::default();
+        let mut records = 0;
+        let mut valid_len = 0;
+        for (number, line) in contents.split_inclusive('\n').enumerate() {
+            if !line.ends_with('\n') {
+                break;
+            }
+            json::parse(line)
+                .map_err(UrlManagerError::backend)
+                .and_then(|record| apply(&mut links, &record))
+                .map_err(|e| {
+                    UrlManagerError::backend(format!("{}:{}: {e}", path.display(), number + 1))
+                })?;
+            records += 1;
+            valid_len += line.len();
+        }
+        if valid_len < contents.len() {
+            log.set_len(valid_len as u64)?;
+        }
+
+        let journal = Journal { path, log, records };
+        Ok((journal, links))
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[cfg(test)]
+    pub(super) fn records(&self) -> usize {
+        self.records
+    }
+
+    // Reopens the log read-only, so appending to it fails.
+    #[cfg(test)]
+    pub(super) fn fail_writes(&mut self) {
+        self.log = File::open(&self.path).unwrap();
+    }
+
+    /// Records that `link` is now stored; `links` is what a compaction
+    /// would write.
+    pub(super) fn put(&mut self, link: &Link, links: &LinkMap) -> Result<()> {
+        self.append([put_record(link)], links)
+    }
+
+    /// Records that the link under `id` is gone.
+    pub(super) fn delete(&mut self, id: u64, links: &LinkMap) -> Result<()> {
+        self.append([delete_record(id)], links)
+    }
+
+    /// Records the links under `ids` as they are now stored in `links`, or
+    /// that they are gone, in one record.
+    pub(super) fn commit(&mut self, ids: &[u64], links: &LinkMap) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let records = ids
+            .iter()
+            .map(|&id| match links.get(id) {
+                Some(link) => put_record(link),
+                None => delete_record(id),
+            })
+            .collect();
+        let batch = Value::object([
+            ("op", Value::from("batch")),
+            ("records", Value::Array(records)),
+        ]);
+        self.append([batch], links)
+    }
+
+    /// Rewrites the log so it only holds `links`.
+    pub(super) fn compact(&mut self, links: &LinkMap) -> Result<()> {
+        let tmp = compaction_path(&self.path);
+        let mut file = File::create(&tmp)?;
+        let mut buffer = String::new();
+        for link in links.values() {
+            buffer.push_str(&put_record(link).to_string());
+            buffer.push('\n');
+        }
+        file.write_all(buffer.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+
+        self.log = OpenOptions::new().append(true).open(&self.path)?;
+        self.records = links.len();
+        Ok(())
+    }
+
+    /// Waits until the log is on disk.
+    pub(super) fn sync(&self) -> Result<()> {
+        self.log.sync_all()?;
+        Ok(())
+    }
+
+    // Writes `records` in one go.
+    fn append(&mut self, records: impl IntoIterator<Item = Value>, links: &LinkMap) -> Result<()> {
+        let mut lines = String::new();
+        let mut count = 0;
+        for record in records {
+            lines.push_str(&record.to_string());
+            lines.push('\n');
+            count += 1;
+        }
+        self.log.write_all(lines.as_bytes())?;
+        self.log.flush()?;
+        self.records += count;
+
+        if self.records > COMPACT_MIN_RECORDS && self.records > 2 * links.len() {
+            self.compact(links)?;
+        }
+        Ok(())
+    }
+}
+
+/// Journals the link now stored under `id` in `links`, or its removal,
+/// unless a transaction is open, which is journaled on commit. If that
+/// fails, `previous` is put back so `links` doesn't run ahead of the log.
+pub(super) fn log(
+    journal: Option<&mut Journal>,
+    links: &mut LinkMap,
+    id: u64,
+    previous: Option<Link>,
+) -> Result<()> {
+    let Some(journal) = journal.filter(|_| !links.in_transaction()) else {
+        return Ok(());
+    };
+    let result = match links.get(id) {
+        Some(link) => journal.put(link, links),
+        None => journal.delete(id, links),
+    };
+    if result.is_err() {
+        match previous {
+            Some(previous) => {
+                let _ = links.insert(id, previous);
+            }
+            None => {
+                links.remove(id);
+            }
+        }
+    }
+    result
+}
+
+/// Creates the links of `batch` that can be created in `links`, see
+/// [`LinkStore::create_batch`](super::LinkStore::create_batch), journaling
+/// them in one write unless a transaction is open. If that fails, they are
+/// removed again.
+pub(super) fn create_batch(
+    journal: Option<&mut Journal>,
+    links: &mut LinkMap,
+    batch: Vec<Link>,
+) -> Vec<Result<Link>> {
+    let mut results: Vec<Result<Link>> = batch
+        .into_iter()
+        .map(|link| {
+            links.insert_new(link.clone())?;
+            Ok(link)
+        })
+        .collect();
+    let Some(journal) = journal.filter(|_| !links.in_transaction()) else {
+        return results;
+    };
+    let records = results.iter().flatten().map(put_record);
+    if let Err(e) = journal.append(records, links) {
+        let reason = e.to_string();
+        for result in &mut results {
+            if let Ok(link) = result {
+                links.remove(link.id());
+                *result = Err(UrlManagerError::backend(reason.clone()));
+            }
+        }
+    }
+    results
+}
+
+pub(super) fn compacting_in_transaction() -> UrlManagerError {
+    UrlManagerError::backend("can't compact the log while a transaction is open")
+}
+
+fn compaction_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".compact");
+    path.with_file_name(name)
+}
+
+fn put_record(link: &Link) -> Value {
+    Value::object([("op", Value::from("put")), ("link", link.to_json())])
+}
+
+fn delete_record(id: u64) -> Value {
+    Value::object([("op", Value::from("delete")), ("id", Value::from(id))])
+}
+
+fn apply(links: &mut LinkMap, record: &Value) -> Result<()> {
+    let invalid = || UrlManagerError::backend(format!("invalid log record: {record}"));
+    match record.get("op").and_then(Value::as_str) {
+        Some("put") => {
+            let link = Link::from_json(record.get("link").ok_or_else(invalid)?)?;
+            links.insert(link.id(), link)?;
+        }
+        Some("delete") => {
+            let id = record
+                .get("id")
+                .and_then(Value::as_u64)
+                .ok_or_else(invalid)?;
+            links.remove(id);
+        }
+        Some("batch") => match record.get("records") {
+            Some(Value::Array(records)) => {
+                for record in records {
+                    apply(links, record)?;
+                }
+            }
+            _ => return Err(invalid()),
+        },
+        _ => return Err(invalid()),
+    }
+    Ok(())
+}
@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::ops::Bound;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::json;
+use crate::link::now;
+use crate::{sync, Link, Namespace, Result, UrlManagerError};
+
+// Ids are zero-padded so keys list in id order.
+const LINK_PREFIX: &str = "link:";
+const SHORTCUT_PREFIX: &str = "shortcut:";
+
+/// A key-value store in the shape of Cloudflare Workers KV: string keys and
+/// values, values that expire, and keys listed by prefix a page at a time.
+///
+/// Implement it over a Workers KV namespace, or a D1 table of keys and
+/// values, to keep links at the edge with a [`KvLinkStore`]. The futures
+/// needn't be `Send`, as those of Workers bindings aren't.
+pub trait KvStore {
+    /// The value stored under `key`, if any and not expired.
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<String>>>;
+    /// Stores `value` under `key`, dropping it after `ttl` if given.
+    fn put(
+        &self,
+        key: &str,
+        value: String,
+        ttl: Option<Duration>,
+    ) -> impl Future<Output = Result<()>>;
+    /// Removes `key`; removing a missing key is not an error.
+    fn delete(&self, key: &str) -> impl Future<Output = Result<()>>;
+    /// Up to `limit` keys starting with `prefix` in key order, continuing
+    /// after `cursor`, the [`KvPage::cursor`] of the page before.
+    fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> impl Future<Output = Result<KvPage>>;
+}
+
+/// A page of keys returned by [`KvStore::list`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KvPage {
+    pub keys: Vec<String>,
+    /// Where the next page starts; `None` on the last page.
+    pub cursor: Option<String>,
+}
+
+/// A [`KvStore`] in a map, for tests and local development.
+#[derive(Debug, Default)]
+pub struct InMemoryKv {
+    // value and expiry of each key
+    entries: Mutex<BTreeMap<String, (String, Option<SystemTime>)>>,
+}
+
+impl InMemoryKv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many keys are stored and not expired.
+    pub fn len(&self) -> usize {
+        let now = now();
+        sync::lock(&self.entries)
+            .values()
+            .filter(|(_, expires_at)| expires_at.is_none_or(|at| at > now))
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl KvStore for InMemoryKv {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let now = now();
+        Ok(sync::lock(&self.entries)
+            .get(key)
+            .filter(|(_, expires_at)| expires_at.is_none_or(|at| at > now))
+            .map(|(value, _)| value.clone()))
+    }
+
+    async fn put(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<()> {
+        let expires_at = ttl.map(|ttl| now() + ttl);
+        sync::lock(&self.entries).insert(key.to_string(), (value, expires_at));
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        sync::lock(&self.entries).remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, cursor: Option<&str>, limit: usize) -> Result<KvPage> {
+        let now = now();
+        let start = match cursor {
+            Some(cursor) => Bound::Excluded(cursor),
+            None => Bound::Included(prefix),
+        };
+        let entries = sync::lock(&self.entries);
+        let mut keys = entries
+            .range::<str, _>((start, Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(_, (_, expires_at))| expires_at.is_none_or(|at| at > now))
+            .map(|(key, _)| key.clone());
+        let page: Vec<String> = keys.by_ref().take(limit).collect();
+        let cursor = match keys.next() {
+            Some(_) => page.last().cloned(),
+            None => None,
+        };
+        Ok(KvPage { keys: page, cursor })
+    }
+}
+
+/// Links kept in a [`KvStore`], for running the shortener at the edge,
+/// e.g. on Cloudflare Workers with the `wasm` feature.
+///
+/// Each link is stored as JSON under `link:<id>` and its shortcut under
+/// `shortcut:<namespace>:<shortcut>`, pointing at the id. Links that expire
+/// are stored with a TTL, so the KV store drops them once they do and they
+/// stop resolving with `NotFound` rather than `Expired`.
+///
+/// Its methods are async and take `&self` like the KV bindings they sit
+/// on; their futures are `Send` only if those of `K` are, which is why it
+/// doesn't implement [`AsyncLinkStore`](super::AsyncLinkStore). KV stores
+/// have no transactions and are eventually consistent: two edges creating
+/// the same shortcut at once can both succeed, the later write winning, and
+/// concurrent hits can exceed `max_uses`.
+#[derive(Debug, Default)]
+pub struct KvLinkStore<K> {
+    kv: K,
+}
+
+impl<K: KvStore> KvLinkStore<K> {
+    pub fn new(kv: K) -> Self {
+        KvLinkStore { kv }
+    }
+
+    pub fn get_ref(&self) -> &K {
+        &self.kv
+    }
+
+    pub fn into_inner(self) -> K {
+        self.kv
+    }
+
+    /// Returns the link stored under `id`, if any.
+    pub async fn get(&self, id: u64) -> Result<Option<Link>> {
+        match self.kv.get(&link_key(id)).await? {
+            Some(value) => Ok(Some(parse_link(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the link reachable under `shortcut` in `namespace`, if any.
+    pub async fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        let Some(id) = self.owner(namespace, shortcut).await? else {
+            return Ok(None);
+        };
+        // the index may lag behind the link it points at
+        Ok(self
+            .get(id)
+            .await?
+            .filter(|link| link.namespace() == namespace && link.shortcut() == Some(shortcut)))
+    }
+
+    /// Looks up the link behind `shortcut` for following it, failing like
+    /// [`LinkStore::resolve_in`](super::LinkStore::resolve_in).
+    pub async fn resolve_in(&self, namespace: Option<&Namespace>, shortcut: &str) -> Result<Link> {
+        let link = self
+            .get_by_shortcut_in(namespace, shortcut)
+            .await?
+            .ok_or(UrlManagerError::NotFound)?;
+        link.check_resolvable()?;
+        Ok(link)
+    }
+
+    /// Counts a resolution of `shortcut` and returns the link it resolved
+    /// to, failing like [`KvLinkStore::resolve_in`].
+    pub async fn record_hit_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Link> {
+        let mut link = self.resolve_in(namespace, shortcut).await?;
+        link.hit();
+        self.put(&link).await?;
+        Ok(link)
+    }
+
+    /// Stores `link`, failing with `DuplicateId` if a link with its id is
+    /// already stored and with `ShortcutCollision` if another link in its
+    /// namespace has its shortcut.
+    pub async fn create(&self, link: Link) -> Result<()> {
+        if self.get(link.id()).await?.is_some() {
+            return Err(UrlManagerError::DuplicateId(link.id()));
+        }
+        self.check_shortcut(link.id(), &link).await?;
+        self.put(&link).await?;
+        self.index(&link).await
+    }
+
+    /// Replaces the link stored under `id`, failing with `NotFound` if
+    /// there is none and with `ShortcutCollision` if another link has the
+    /// new shortcut.
+    pub async fn update(&self, id: u64, link: Link) -> Result<()> {
+        let previous = self.get(id).await?.ok_or(UrlManagerError::NotFound)?;
+        self.check_shortcut(id, &link).await?;
+        let link = link.updated_from(&previous);
+        self.put(&link).await?;
+        if let Some(shortcut) = previous.shortcut() {
+            if (link.namespace(), link.shortcut()) != (previous.namespace(), Some(shortcut)) {
+                let key = shortcut_key(previous.namespace(), shortcut);
+                self.kv.delete(&key).await?;
+            }
+        }
+        self.index(&link).await
+    }
+
+    /// Removes the link stored under `id`, failing with `NotFound` if there
+    /// is none.
+    pub async fn delete(&self, id: u64) -> Result<()> {
+        let link = self.get(id).await?.ok_or(UrlManagerError::NotFound)?;
+        if let Some(shortcut) = link.shortcut() {
+            self.kv
+                .delete(&shortcut_key(link.namespace(), shortcut))
+                .await?;
+        }
+        self.kv.delete(&link_key(id)).await
+    }
+
+    /// Returns up to `limit` links outside the trash after skipping
+    /// `offset`, ordered by id. Skipping walks the keys before `offset`, as
+    /// KV stores only page by cursor.
+    pub async fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        let mut links = Vec::new();
+        let mut skipped = 0;
+        let mut cursor = None;
+        while links.len() < limit {
+            let page = self
+                .kv
+                .list(LINK_PREFIX, cursor.as_deref(), super::PAGE)
+                .await?;
+            for key in &page.keys {
+                let Some(value) = self.kv.get(key).await? else {
+                    continue;
+                };
+                let link = parse_link(&value)?;
+                if link.is_deleted() {
+                    continue;
+                } else if skipped < offset {
+                    skipped += 1;
+                } else if links.len() < limit {
+                    links.push(link);
+                }
+            }
+            match page.cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        Ok(links)
+    }
+
+    async fn owner(&self, namespace: Option<&Namespace>, shortcut: &str) -> Result<Option<u64>> {
+        let key = shortcut_key(namespace, shortcut);
+        match self.kv.get(&key).await? {
+            Some(id) => id
+                .parse()
+                .map(Some)
+                .map_err(|_| UrlManagerError::backend(format!("invalid id under {key}: {id}"))),
+            None => Ok(None),
+        }
+    }
+
+    async fn check_shortcut(&self, id: u64, link: &Link) -> Result<()> {
+        let Some(shortcut) = link.shortcut() else {
+            return Ok(());
+        };
+        let taken = match self.owner(link.namespace(), shortcut).await? {
+            Some(owner) if owner != id => self
+                .get_by_shortcut_in(link.namespace(), shortcut)
+                .await?
+                .is_some(),
+            _ => false,
+        };
+        if taken {
+            return Err(UrlManagerError::ShortcutCollision(shortcut.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn put(&self, link: &Link) -> Result<()> {
+        let value = link.to_json().to_string();
+        self.kv.put(&link_key(link.id()), value, ttl(link)).await
+    }
+
+    async fn index(&self, link: &Link) -> Result<()> {
+        match link.shortcut() {
+            Some(shortcut) => {
+                let key = shortcut_key(link.namespace(), shortcut);
+                self.kv.put(&key, link.id().to_string(), ttl(link)).await
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn link_key(id: u64) -> String {
+    format!("{LINK_PREFIX}{id:020}")
+}
+
+fn shortcut_key(namespace: Option<&Namespace>, shortcut: &str) -> String {
+    let namespace = namespace.map_or("", Namespace::as_str);
+    format!("{SHORTCUT_PREFIX}{namespace}:{shortcut}")
+}
+
+fn parse_link(value: &str) -> Result<Link> {
+    Link::from_json(&json::parse(value).map_err(UrlManagerError::backend)?)
+}
+
+// How long the KV store keeps a link; trashed links are kept until purged.
+fn ttl(link: &Link) -> Option<Duration> {
+    if link.is_deleted() {
+        return None;
+    }
+    link.expires_at()
+        .map(|expires_at| expires_at.duration_since(now()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::block_on;
+
+    fn link(slug: &str) -> Link {
+        Link::builder()
+            .target(format!("https://example.com/{slug}").as_str())
+            .slug(slug)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_kv_link_store() {
+        let store = KvLinkStore::new(InMemoryKv::new());
+        let docs = link("docs");
+        let id = docs.id();
+        block_on(store.create(docs.clone())).unwrap();
+        assert!(matches!(
+            block_on(store.create(docs)),
+            Err(UrlManagerError::DuplicateId(_))
+        ));
+        assert!(matches!(
+            block_on(store.create(link("docs"))),
+            Err(UrlManagerError::ShortcutCollision(_))
+        ));
+
+        let hit = block_on(store.record_hit_in(None, "docs")).unwrap();
+        assert_eq!(hit.hit_count(), 1);
+        assert_eq!(block_on(store.resolve_in(None, "docs")).unwrap().id(), id);
+
+        // renaming frees the old shortcut and keeps the hits
+        let renamed = Link::builder()
+            .id(id)
+            .target("https://example.com/guide")
+            .slug("guide")
+            .build()
+            .unwrap();
+        block_on(store.update(id, renamed)).unwrap();
+        assert!(block_on(store.get_by_shortcut_in(None, "docs"))
+            .unwrap()
+            .is_none());
+        let guide = block_on(store.resolve_in(None, "guide")).unwrap();
+        assert_eq!(guide.hit_count(), 1);
+        assert_eq!(guide.version(), 2);
+        block_on(store.create(link("docs"))).unwrap();
+
+        block_on(store.delete(id)).unwrap();
+        assert!(matches!(
+            block_on(store.resolve_in(None, "guide")),
+            Err(UrlManagerError::NotFound)
+        ));
+        assert_eq!(store.get_ref().len(), 2);
+    }
+
+    #[test]
+    fn test_list_and_expiry() {
+        let store = KvLinkStore::new(InMemoryKv::new());
+        let mut ids = Vec::new();
+        for n in 0..5 {
+            let link = link(&format!("l{n}"));
+            ids.push(link.id());
+            block_on(store.create(link)).unwrap();
+        }
+        ids.sort_unstable();
+        let listed: Vec<u64> = block_on(store.list(1, 3))
+            .unwrap()
+            .iter()
+            .map(Link::id)
+            .collect();
+        assert_eq!(listed, ids[1..4]);
+
+        let kv = InMemoryKv::new();
+        block_on(kv.put("a", "1".to_string(), Some(Duration::ZERO))).unwrap();
+        block_on(kv.put("b", "2".to_string(), None)).unwrap();
+        assert_eq!(block_on(kv.get("a")).unwrap(), None);
+        let page = block_on(kv.list("", None, 1)).unwrap();
+        assert_eq!((page.keys, page.cursor), (vec!["b".to_string()], None));
+    }
+}
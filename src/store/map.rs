@@ -0,0 +1,351 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+use std::time::SystemTime;
+
+use super::LinkQuery;
+use crate::link::now;
+use crate::{normalize, Link, Namespace, Result, UrlManagerError};
+
+/// Links by id plus shortcut → id, normalized target → ids and creation
+/// order indexes, shared by the in-process stores. Links are indexed under
+/// their scheduled target too, so they are found once it takes over.
+///
+/// Shortcuts are indexed per namespace, with `""` standing for links without
+/// one since namespaces can't be empty.
+///
+/// Between [`LinkMap::begin`] and [`LinkMap::commit`] the map remembers each
+/// changed link as it was before, so [`LinkMap::rollback`] can put it back.
+#[derive(Debug, Default)]
+pub(crate) struct LinkMap {
+    by_id: HashMap<u64, Link>,
+    by_shortcut: HashMap<String, HashMap<String, u64>>,
+    by_target: HashMap<String, BTreeSet<u64>>,
+    by_creation: BTreeSet<(SystemTime, u64)>,
+    // id to the link before the open transaction, `None` if there was none
+    undo: Option<HashMap<u64, Option<Link>>>,
+}
+
+impl LinkMap {
+    pub(crate) fn get(&self, id: u64) -> Option<&Link> {
+        self.by_id.get(&id)
+    }
+
+    fn owner(&self, namespace: Option<&Namespace>, shortcut: &str) -> Option<u64> {
+        self.by_shortcut
+            .get(namespace.map_or("", Namespace::as_str))?
+            .get(shortcut)
+            .copied()
+    }
+
+    pub(crate) fn get_by_shortcut(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Option<&Link> {
+        self.owner(namespace, shortcut)
+            .and_then(|id| self.by_id.get(&id))
+    }
+
+    // Counts a hit on `checked`, whose password the caller checked, see
+    // `Link::check_access_as`.
+    pub(crate) fn hit(&mut self, checked: &Link) -> Result<&Link> {
+        let id = checked.id();
+        self.remember(id);
+        let link = self.by_id.get_mut(&id).ok_or(UrlManagerError::NotFound)?;
+        link.check_access_as(checked)?;
+        link.hit();
+        Ok(link)
+    }
+
+    pub(crate) fn hit_variant(&mut self, id: u64, variant: usize) -> Result<&Link> {
+        self.remember(id);
+        let link = self.by_id.get_mut(&id).ok_or(UrlManagerError::NotFound)?;
+        link.hit_variant(variant)?;
+        Ok(link)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &Link> {
+        self.by_id.values()
+    }
+
+    /// Links ordered by creation time, then id.
+    pub(crate) fn ordered(&self) -> impl Iterator<Item = &Link> {
+        self.by_creation.iter().map(|(_, id)| &self.by_id[id])
+    }
+
+    /// [`LinkMap::ordered`] without the links in the trash.
+    pub(crate) fn live(&self) -> impl Iterator<Item = &Link> {
+        self.ordered().filter(|link| !link.is_deleted())
+    }
+
+    /// [`LinkMap::ordered`] with just the links in the trash.
+    pub(crate) fn trashed(&self) -> impl Iterator<Item = &Link> {
+        self.ordered().filter(|link| link.is_deleted())
+    }
+
+    /// [`LinkMap::live`] starting after the creation time and id `after`.
+    pub(crate) fn live_after(&self, after: (SystemTime, u64)) -> impl Iterator<Item = &Link> {
+        self.by_creation
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .map(|(_, id)| &self.by_id[id])
+            .filter(|link| !link.is_deleted())
+    }
+
+    /// Links matching `query` in creation order, using the target index if
+    /// the query has a target.
+    pub(crate) fn find(&self, query: &LinkQuery) -> Vec<Link> {
+        let Some(target) = query.get_target() else {
+            return self
+                .ordered()
+                .filter(|link| query.matches(link))
+                .cloned()
+                .collect();
+        };
+        let mut links: Vec<Link> = self
+            .by_target
+            .get(target.as_str())
+            .into_iter()
+            .flatten()
+            .map(|id| &self.by_id[id])
+            .filter(|link| query.matches(link))
+            .cloned()
+            .collect();
+        links.sort_by_key(|link| (link.created_at(), link.id()));
+        links
+    }
+
+    /// Fails if a link other than `id` already uses the shortcut of `link`
+    /// in its namespace.
+    pub(crate) fn check_shortcut(&self, id: u64, link: &Link) -> Result<()> {
+        match link.shortcut() {
+            Some(shortcut)
+                if self
+                    .owner(link.namespace(), shortcut)
+                    .is_some_and(|owner| owner != id) =>
+            {
+                Err(UrlManagerError::ShortcutCollision(shortcut.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies `change` to the link stored under `id` and stores the result,
+    /// failing with `NotFound` if there is none.
+    pub(crate) fn modify(&mut self, id: u64, change: impl FnOnce(&mut Link)) -> Result<&Link> {
+        let mut link = self.get(id).ok_or(UrlManagerError::NotFound)?.clone();
+        change(&mut link);
+        link.touch();
+        self.insert(id, link)?;
+        Ok(&self.by_id[&id])
+    }
+
+    /// Stores `link` under its id, failing with `DuplicateId` if one is stored.
+    pub(crate) fn insert_new(&mut self, link: Link) -> Result<()> {
+        if self.by_id.contains_key(&link.id()) {
+            return Err(UrlManagerError::DuplicateId(link.id()));
+        }
+        self.insert(link.id(), link)
+    }
+
+    /// Stores `link` under `id`, replacing any previous link and its shortcut.
+    pub(crate) fn insert(&mut self, id: u64, link: Link) -> Result<()> {
+        self.check_shortcut(id, &link)?;
+        self.remember(id);
+        if let Some(shortcut) = link.shortcut() {
+            self.by_shortcut
+                .entry(link.namespace().map_or("", Namespace::as_str).to_string())
+                .or_default()
+                .insert(shortcut.to_string(), id);
+        }
+        let created = (link.created_at(), id);
+        let targets: Vec<String> = link
+            .targets()
+            .map(|target| normalize(target).to_string())
+            .collect();
+        if let Some(previous) = self.by_id.insert(id, link) {
+            self.by_creation.remove(&(previous.created_at(), id));
+            self.unindex_target(id, &previous);
+            self.unindex(id, &previous);
+        }
+        self.by_creation.insert(created);
+        for target in targets {
+            self.by_target.entry(target).or_default().insert(id);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn remove(&mut self, id: u64) -> Option<Link> {
+        self.remember(id);
+        let link = self.by_id.remove(&id)?;
+        self.by_creation.remove(&(link.created_at(), id));
+        self.unindex_target(id, &link);
+        self.unindex(id, &link);
+        Some(link)
+    }
+
+    /// Removes every expired or used up link, returning their ids.
+    pub(crate) fn purge_expired(&mut self) -> Vec<u64> {
+        let now = now();
+        let expired: Vec<u64> = self
+            .by_id
+            .values()
+            .filter(|link| link.is_expired_at(now) || link.is_exhausted())
+            .map(Link::id)
+            .collect();
+        for &id in &expired {
+            self.remove(id);
+        }
+        expired
+    }
+
+    /// Starts a transaction; fails if one is open already.
+    pub(crate) fn begin(&mut self) -> Result<()> {
+        if self.undo.is_some() {
+            return Err(UrlManagerError::backend("a transaction is open already"));
+        }
+        self.undo = Some(HashMap::new());
+        Ok(())
+    }
+
+    pub(crate) fn in_transaction(&self) -> bool {
+        self.undo.is_some()
+    }
+
+    /// The ids of the links changed in the open transaction; fails if there
+    /// is none.
+    pub(crate) fn changed(&self) -> Result<Vec<u64>> {
+        let undo = self.undo.as_ref().ok_or_else(no_transaction)?;
+        Ok(undo.keys().copied().collect())
+    }
+
+    /// Keeps the changes of the open transaction; fails if there is none.
+    pub(crate) fn commit(&mut self) -> Result<()> {
+        self.undo.take().ok_or_else(no_transaction).map(drop)
+    }
+
+    /// Undoes the changes of the open transaction; fails if there is none.
+    pub(crate) fn rollback(&mut self) -> Result<()> {
+        let undo = self.undo.take().ok_or_else(no_transaction)?;
+        // everything goes first, so no shortcut is in the way
+        for &id in undo.keys() {
+            self.remove(id);
+        }
+        for (id, link) in undo {
+            if let Some(link) = link {
+                self.insert(id, link).expect("it was stored before");
+            }
+        }
+        Ok(())
+    }
+
+    // Notes the link under `id` for the open transaction to undo.
+    fn remember(&mut self, id: u64) {
+        if let Some(undo) = &mut self.undo {
+            undo.entry(id)
+                .or_insert_with(|| self.by_id.get(&id).cloned());
+        }
+    }
+
+    fn unindex_target(&mut self, id: u64, link: &Link) {
+        for target in link.targets() {
+            let target = normalize(target);
+            if let Some(ids) = self.by_target.get_mut(target.as_str()) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.by_target.remove(target.as_str());
+                }
+            }
+        }
+    }
+
+    // Drops the index entry of a replaced or removed link, unless the
+    // shortcut now belongs to the link that replaced it.
+    fn unindex(&mut self, id: u64, link: &Link) {
+        let Some(shortcut) = link.shortcut() else {
+            return;
+        };
+        let still_used = self.by_id.get(&id).is_some_and(|current| {
+            current.namespace() == link.namespace() && current.shortcut() == Some(shortcut)
+        });
+        if still_used || self.owner(link.namespace(), shortcut) != Some(id) {
+            return;
+        }
+        let namespace = link.namespace().map_or("", Namespace::as_str);
+        if let Some(shortcuts) = self.by_shortcut.get_mut(namespace) {
+            shortcuts.remove(shortcut);
+            if shortcuts.is_empty() {
+                self.by_shortcut.remove(namespace);
+            }
+        }
+    }
+}
+
+fn no_transaction() -> UrlManagerError {
+    UrlManagerError::backend("no transaction is open")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UrlType;
+
+    fn link(id: u64, shortcut: &str) -> Link {
+        Link::builder()
+            .id(id)
+            .target(UrlType::parse("https://example.com").unwrap())
+            .shortcut(shortcut)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_shortcut_index() {
+        let mut map = LinkMap::default();
+        map.insert(1, link(1, "a")).unwrap();
+        assert_eq!(map.get_by_shortcut(None, "a").map(Link::id), Some(1));
+        assert!(map.insert(2, link(2, "a")).is_err());
+
+        // renaming frees the old shortcut
+        map.insert(1, link(1, "b")).unwrap();
+        assert!(map.get_by_shortcut(None, "a").is_none());
+        assert_eq!(map.get_by_shortcut(None, "b").map(Link::id), Some(1));
+
+        map.remove(1);
+        assert!(map.get_by_shortcut(None, "b").is_none());
+        map.insert(2, link(2, "b")).unwrap();
+        assert_eq!(map.ordered().map(Link::id).collect::<Vec<_>>(), [2]);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut map = LinkMap::default();
+        map.insert(1, link(1, "a")).unwrap();
+        map.insert(2, link(2, "b")).unwrap();
+        assert!(map.commit().is_err());
+
+        map.begin().unwrap();
+        assert!(map.begin().is_err());
+        // swap the shortcuts and add a link
+        map.insert(1, link(1, "c")).unwrap();
+        map.insert(2, link(2, "a")).unwrap();
+        map.insert(1, link(1, "b")).unwrap();
+        map.remove(3);
+        map.insert(3, link(3, "d")).unwrap();
+        map.hit(&link(3, "d")).unwrap();
+        let mut changed = map.changed().unwrap();
+        changed.sort_unstable();
+        assert_eq!(changed, [1, 2, 3]);
+        map.rollback().unwrap();
+
+        assert!(!map.in_transaction());
+        assert_eq!(map.get_by_shortcut(None, "a").map(Link::id), Some(1));
+        assert_eq!(map.get_by_shortcut(None, "b").map(Link::id), Some(2));
+        assert!(map.get(3).is_none());
+        assert!(map.get_by_shortcut(None, "d").is_none());
+        assert_eq!(map.ordered().count(), 2);
+    }
+}
@@ -0,0 +1,888 @@
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use super::journal::{self, Journal};
+use super::map::LinkMap;
+use super::pages::{Pages, PAGE};
+use super::{LinkQuery, LinkStore, Transactional};
+use crate::{sync, Link, Namespace, Result, UrlManagerError};
+
+/// A [`LinkStore`] keeping every link in a shared in-process map.
+///
+/// Shortcuts are indexed, so [`LinkStore::get_by_shortcut_in`] doesn't scan.
+/// The map sits behind a `RwLock`, so lookups from several threads don't
+/// wait for each other, only for writes. Counting a hit is a write, and
+/// the HTTP server keeps its store behind a `Mutex` of its own, so its
+/// redirects still take turns, see `Server`.
+///
+/// With [`InMemoryLinkStore::with_journal`] every change is also appended
+/// to a log on disk, in the format of [`FileLinkStore`](super::FileLinkStore),
+/// before anyone can see it, and the log is replayed on startup.
+#[derive(Debug, Default)]
+pub struct InMemoryLinkStore {
+    links: Arc<RwLock<LinkMap>>,
+    journal: Option<Journal>,
+}
+
+impl InMemoryLinkStore {
+    pub fn new() -> Self {
+        InMemoryLinkStore {
+            links: Arc::new(RwLock::new(LinkMap::default())),
+            journal: None,
+        }
+    }
+
+    /// A store journaling to the log at `path`, starting with the links
+    /// replayed from it; the log is created if it doesn't exist.
+    ///
+    /// A change that can't be journaled fails and is undone, so the map
+    /// never holds what a restart would lose.
+    pub fn with_journal(path: impl AsRef<Path>) -> Result<Self> {
+        let (journal, links) = Journal::open(path)?;
+        Ok(InMemoryLinkStore {
+            links: Arc::new(RwLock::new(links)),
+            journal: Some(journal),
+        })
+    }
+
+    /// Rewrites the journal so it only holds the current links; does
+    /// nothing without one.
+    /// Fails while a transaction is open.
+    pub fn compact_journal(&mut self) -> Result<()> {
+        let links = sync::read(&self.links);
+        match &mut self.journal {
+            Some(_) if links.in_transaction() => Err(journal::compacting_in_transaction()),
+            Some(journal) => journal.compact(&links),
+            None => Ok(()),
+        }
+    }
+
+    // The link under `id` before a change, if there is a journal to undo it for.
+    fn previous(&self, links: &LinkMap, id: u64) -> Option<Link> {
+        self.journal.as_ref().and(links.get(id)).cloned()
+    }
+}
+
+impl LinkStore for InMemoryLinkStore {
+    fn get(&self, id: u64) -> Result<Option<Link>> {
+        Ok(sync::read(&self.links).get(id).cloned())
+    }
+
+    fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        let links = sync::read(&self.links);
+        Ok(links.get_by_shortcut(namespace, shortcut).cloned())
+    }
+
+    fn create(&mut self, link: Link) -> Result<()> {
+        let id = link.id();
+        let mut links = sync::write(&self.links);
+        links.insert_new(link)?;
+        journal::log(self.journal.as_mut(), &mut links, id, None)
+    }
+
+    fn create_batch(&mut self, batch: Vec<Link>) -> Vec<Result<Link>> {
+        let mut links = sync::write(&self.links);
+        journal::create_batch(self.journal.as_mut(), &mut links, batch)
+    }
+
+    fn resolve_batch_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcuts: &[&str],
+    ) -> Vec<Result<Link>> {
+        let links = sync::read(&self.links);
+        shortcuts
+            .iter()
+            .map(|shortcut| {
+                let link = links
+                    .get_by_shortcut(namespace, shortcut)
+                    .ok_or(UrlManagerError::NotFound)?;
+                link.check_access(None)?;
+                Ok(link.clone())
+            })
+            .collect()
+    }
+
+    fn upsert(&mut self, link: Link) -> Result<()> {
+        let id = link.id();
+        let mut links = sync::write(&self.links);
+        let previous = self.previous(&links, id);
+        links.insert(id, link)?;
+        journal::log(self.journal.as_mut(), &mut links, id, previous)
+    }
+
+    fn update(&mut self, id: u64, link: Link) -> Result<()> {
+        let mut links = sync::write(&self.links);
+        let previous = links.get(id).ok_or(UrlManagerError::NotFound)?;
+        let link = link.updated_from(previous);
+        let previous = self.previous(&links, id);
+        links.insert(id, link)?;
+        journal::log(self.journal.as_mut(), &mut links, id, previous)
+    }
+
+    fn update_if_version(&mut self, id: u64, version: u64, link: Link) -> Result<()> {
+        let mut links = sync::write(&self.links);
+        let previous = links.get(id).ok_or(UrlManagerError::NotFound)?;
+        if previous.version() != version {
+            return Err(UrlManagerError::Conflict(previous.version()));
+        }
+        let link = link.updated_from(previous);
+        let previous = self.previous(&links, id);
+        links.insert(id, link)?;
+        journal::log(self.journal.as_mut(), &mut links, id, previous)
+    }
+
+    fn update_with(&mut self, id: u64, change: impl FnOnce(&mut Link)) -> Result<Link> {
+        let mut links = sync::write(&self.links);
+        let previous = self.previous(&links, id);
+        let link = links.modify(id, change)?.clone();
+        journal::log(self.journal.as_mut(), &mut links, id, previous)?;
+        Ok(link)
+    }
+
+    fn record_hit_with_password_in(
+        &mut self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        let checked = sync::read(&self.links)
+            .get_by_shortcut(namespace, shortcut)
+            .cloned()
+            .ok_or(UrlManagerError::NotFound)?;
+        // without the lock, as checking a password takes a while
+        checked.check_access(password)?;
+        self.record_resolved_hit(&checked)
+    }
+
+    fn record_resolved_hit(&mut self, link: &Link) -> Result<Link> {
+        let mut links = sync::write(&self.links);
+        let previous = self.previous(&links, link.id());
+        let link = links.hit(link)?.clone();
+        journal::log(self.journal.as_mut(), &mut links, link.id(), previous)?;
+        Ok(link)
+    }
+
+    fn record_variant_hit(&mut self, id: u64, variant: usize) -> Result<Link> {
+        let mut links = sync::write(&self.links);
+        let previous = self.previous(&links, id);
+        let link = links.hit_variant(id, variant)?.clone();
+        journal::log(self.journal.as_mut(), &mut links, id, previous)?;
+        Ok(link.resolved_to(variant))
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        let links = sync::read(&self.links);
+        Ok(links.live().skip(offset).take(limit).cloned().collect())
+    }
+
+    fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        let links = sync::read(&self.links);
+        Ok(links.trashed().skip(offset).take(limit).cloned().collect())
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Link>> + '_> {
+        // resumes after the last link read, so writes in between don't
+        // shift the pages
+        let mut last = None;
+        Box::new(Pages::new(move || {
+            let links = sync::read(&self.links);
+            let page: Vec<Link> = match last {
+                Some(last) => links.live_after(last).take(PAGE).cloned().collect(),
+                None => links.live().take(PAGE).cloned().collect(),
+            };
+            if let Some(link) = page.last() {
+                last = Some((link.created_at(), link.id()));
+            }
+            Ok(page)
+        }))
+    }
+
+    fn find(&self, query: &LinkQuery) -> Result<Vec<Link>> {
+        Ok(sync::read(&self.links).find(query))
+    }
+
+    fn count(&self) -> Result<usize> {
+        Ok(sync::read(&self.links).live().count())
+    }
+
+    fn purge_expired(&mut self) -> Result<usize> {
+        let mut links = sync::write(&self.links);
+        let expired = links.purge_expired();
+        // not undone on failure: the links are expired either way, and a
+        // replay brings back only what the next purge removes again
+        if let Some(journal) = self.journal.as_mut().filter(|_| !links.in_transaction()) {
+            for &id in &expired {
+                journal.delete(id, &links)?;
+            }
+        }
+        Ok(expired.len())
+    }
+
+    fn delete(&mut self, id: u64) -> Result<()> {
+        let mut links = sync::write(&self.links);
+        let previous = links.remove(id).ok_or(UrlManagerError::NotFound)?;
+        journal::log(self.journal.as_mut(), &mut links, id, Some(previous))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        match &self.journal {
+            Some(journal) => journal.sync(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Transactional for InMemoryLinkStore {
+    fn begin(&mut self) -> Result<()> {
+        sync::write(&self.links).begin()
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let mut links = sync::write(&self.links);
+        let changed = links.changed()?;
+        if let Some(journal) = &mut self.journal {
+            if let Err(e) = journal.commit(&changed, &links) {
+                links.rollback()?;
+                return Err(e);
+            }
+        }
+        links.commit()
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        sync::write(&self.links).rollback()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::{Any, TypeId};
+
+    #[test]
+    fn test_link_store() {
+        // this can be done and should be done thorugh the factory
+        let mut linkstore = InMemoryLinkStore::new();
+        assert_eq!(
+            TypeId::of::<InMemoryLinkStore>(),
+            linkstore.type_id(),
+            "InMemoryLinkStore type does not match"
+        );
+
+        let link = Link::default();
+        let id = link.id();
+        linkstore.create(link).unwrap();
+        assert!(
+            linkstore.get(id).unwrap().is_some(),
+            "{id} not in {:#?}",
+            linkstore
+        );
+    }
+
+    #[test]
+    fn test_poisoned_lock() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let link = Link::default();
+        linkstore.create(link.clone()).unwrap();
+
+        let links = Arc::clone(&linkstore.links);
+        let _ = std::thread::spawn(move || {
+            let _guard = links.write().unwrap();
+            panic!("poisoning the store");
+        })
+        .join();
+        assert!(linkstore.links.is_poisoned());
+
+        assert!(linkstore.get(link.id()).unwrap().is_some());
+        assert!(!linkstore.links.is_poisoned());
+        linkstore.delete(link.id()).unwrap();
+        assert_eq!(linkstore.count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_update_and_delete() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let link = Link::default();
+        let id = link.id();
+
+        assert!(matches!(
+            linkstore.update(id, link.clone()),
+            Err(UrlManagerError::NotFound)
+        ));
+        linkstore.create(link.clone()).unwrap();
+        assert!(linkstore.update(id, link).is_ok());
+
+        assert!(linkstore.delete(id).is_ok());
+        assert!(matches!(
+            linkstore.delete(id),
+            Err(UrlManagerError::NotFound)
+        ));
+        assert!(linkstore.get(id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_duplicate_id() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let link = |target: &str, shortcut: &str| {
+            Link::builder()
+                .id(1)
+                .target(target)
+                .shortcut(shortcut)
+                .build()
+                .unwrap()
+        };
+        linkstore
+            .create(link("https://example.com/a", "a"))
+            .unwrap();
+        linkstore.record_hit("a").unwrap();
+
+        assert!(matches!(
+            linkstore.create(link("https://example.com/b", "b")),
+            Err(UrlManagerError::DuplicateId(1))
+        ));
+        assert_eq!(linkstore.get(1).unwrap().unwrap().shortcut(), Some("a"));
+        assert!(linkstore.get_by_shortcut("b").unwrap().is_none());
+
+        linkstore
+            .upsert(link("https://example.com/b", "b"))
+            .unwrap();
+        let stored = linkstore.get(1).unwrap().unwrap();
+        assert_eq!(stored.target().as_str(), "https://example.com/b");
+        assert_eq!(stored.hit_count(), 0, "upsert replaces the whole link");
+        assert!(linkstore.get_by_shortcut("a").unwrap().is_none());
+        assert_eq!(linkstore.count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_update_if_version() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let link = Link::default();
+        linkstore.create(link.clone()).unwrap();
+        linkstore
+            .update_if_version(link.id(), 1, link.clone())
+            .unwrap();
+        assert!(matches!(
+            linkstore.update_if_version(link.id(), 1, link.clone()),
+            Err(UrlManagerError::Conflict(2))
+        ));
+        linkstore
+            .update_with(link.id(), |link| link.set_interstitial(true))
+            .unwrap();
+        let stored = linkstore.get(link.id()).unwrap().unwrap();
+        assert_eq!(stored.version(), 3);
+        assert!(matches!(
+            linkstore.update_if_version(7, 1, link),
+            Err(UrlManagerError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_update_with() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let link = linkstore
+            .create_with_slug(
+                "docs",
+                crate::UrlType::parse("https://example.com").unwrap(),
+            )
+            .unwrap();
+        linkstore.record_hit("docs").unwrap();
+
+        let updated = linkstore
+            .update_with(link.id(), |link| {
+                link.add_tag("spring");
+            })
+            .unwrap();
+        assert!(updated.has_tag("spring"));
+        assert_eq!(updated.hit_count(), 1, "hits should survive the change");
+        assert!(updated.updated_at() >= link.updated_at());
+        assert_eq!(
+            linkstore
+                .find(&LinkQuery::new().tag("spring"))
+                .unwrap()
+                .len(),
+            1
+        );
+
+        assert!(matches!(
+            linkstore.update_with(link.id() ^ 1, |_| {}),
+            Err(UrlManagerError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_create_new() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let builder = || Link::builder().target("https://example.com");
+        linkstore.create(builder().id(1).build().unwrap()).unwrap();
+
+        let ids = crate::SequentialIds::new(1);
+        let link = linkstore.create_new(builder().id(7), &ids).unwrap();
+        assert_eq!(link.id(), 2, "taken ids should be skipped");
+        assert_eq!(linkstore.get(2).unwrap().unwrap().shortcut(), Some("2"));
+
+        let link = linkstore
+            .create_new(builder(), &crate::Snowflake::new(1).unwrap())
+            .unwrap();
+        assert!(linkstore.get(link.id()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_create_with_slug() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let target = crate::UrlType::parse("https://www.example.com/docs").unwrap();
+
+        let link = linkstore.create_with_slug("docs", target.clone()).unwrap();
+        assert_eq!(link.shortcut(), Some("docs"));
+        assert!(linkstore.get(link.id()).unwrap().is_some());
+        let found = linkstore.get_by_shortcut("docs").unwrap().unwrap();
+        assert_eq!(found.id(), link.id());
+        assert!(linkstore.get_by_shortcut("nope").unwrap().is_none());
+
+        assert!(matches!(
+            linkstore.create_with_slug("docs", target.clone()),
+            Err(UrlManagerError::ShortcutCollision(slug)) if slug == "docs"
+        ));
+        assert!(matches!(
+            linkstore.create_with_slug("admin", target.clone()),
+            Err(UrlManagerError::InvalidSlug(_))
+        ));
+
+        let other = linkstore.create_with_slug("other", target).unwrap();
+        let found = linkstore
+            .find(&LinkQuery::new().shortcut_prefix("doc"))
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id(), link.id());
+        let renamed = Link::builder()
+            .id(other.id())
+            .target(other.target().clone())
+            .shortcut("docs")
+            .build()
+            .unwrap();
+        assert!(matches!(
+            linkstore.update(other.id(), renamed),
+            Err(UrlManagerError::ShortcutCollision(_))
+        ));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let link = Link::builder()
+            .target(crate::UrlType::parse("https://example.com").unwrap())
+            .shortcut("soon")
+            .expires_in(std::time::Duration::from_millis(10))
+            .build()
+            .unwrap();
+        linkstore.create(link.clone()).unwrap();
+        linkstore.create(Link::default()).unwrap();
+        assert!(linkstore.resolve("soon").is_ok());
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(matches!(
+            linkstore.resolve("soon"),
+            Err(UrlManagerError::Expired)
+        ));
+        assert!(matches!(
+            linkstore.record_hit("soon"),
+            Err(UrlManagerError::Expired)
+        ));
+        assert_eq!(linkstore.purge_expired().unwrap(), 1);
+        assert!(linkstore.get(link.id()).unwrap().is_none());
+        assert_eq!(linkstore.purge_expired().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_max_uses() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let link = Link::builder()
+            .target(crate::UrlType::parse("https://example.com/download").unwrap())
+            .shortcut("once")
+            .max_uses(1)
+            .build()
+            .unwrap();
+        linkstore.create(link.clone()).unwrap();
+
+        assert_eq!(linkstore.record_hit("once").unwrap().hit_count(), 1);
+        assert!(matches!(
+            linkstore.record_hit("once"),
+            Err(UrlManagerError::UsesExhausted)
+        ));
+        assert!(matches!(
+            linkstore.resolve("once"),
+            Err(UrlManagerError::UsesExhausted)
+        ));
+        assert_eq!(linkstore.purge_expired().unwrap(), 1);
+        assert!(Link::builder()
+            .target(link.target().clone())
+            .max_uses(0)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_resolved_hit() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let link = Link::builder()
+            .target(crate::UrlType::parse("https://example.com/secret").unwrap())
+            .shortcut("secret")
+            .password("hunter2")
+            .build()
+            .unwrap();
+        linkstore.create(link).unwrap();
+
+        let resolved = linkstore
+            .resolve_with_password("secret", "hunter2")
+            .unwrap();
+        assert_eq!(
+            linkstore
+                .record_resolved_hit(&resolved)
+                .unwrap()
+                .hit_count(),
+            1
+        );
+        // the password changed after it was checked
+        linkstore
+            .update_with(resolved.id(), |link| link.set_password("hunter3"))
+            .unwrap();
+        assert!(matches!(
+            linkstore.record_resolved_hit(&resolved),
+            Err(UrlManagerError::PasswordRequired)
+        ));
+        assert_eq!(
+            linkstore
+                .record_hit_with_password_in(None, "secret", Some("hunter3"))
+                .unwrap()
+                .hit_count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_variant_not_stored() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let mut link = Link::builder()
+            .target("https://example.com/a")
+            .shortcut("ab")
+            .build()
+            .unwrap();
+        link.add_variant(crate::UrlType::parse("https://example.com/b").unwrap(), 1);
+        linkstore.create(link.clone()).unwrap();
+
+        let resolved = linkstore.record_variant_hit(link.id(), 0).unwrap();
+        assert_eq!(resolved.variant(), Some(0));
+        linkstore.update(link.id(), resolved.clone()).unwrap();
+        assert_eq!(linkstore.get(link.id()).unwrap().unwrap().variant(), None);
+
+        let mut plain = resolved;
+        plain.clear_variants();
+        linkstore.update(link.id(), plain).unwrap();
+        let stored = linkstore.get(link.id()).unwrap().unwrap();
+        assert_eq!(stored.target().as_str(), "https://example.com/a");
+    }
+
+    #[test]
+    fn test_rule_not_stored() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let mut link = Link::builder()
+            .target("https://example.com/web")
+            .shortcut("app")
+            .build()
+            .unwrap();
+        let ios = crate::UrlType::parse("https://example.com/ios").unwrap();
+        link.add_rule(crate::RedirectRule::new(ios).platform(crate::Platform::Ios));
+        linkstore.create(link.clone()).unwrap();
+
+        let resolved = link.matched_rule(0);
+        assert_eq!(resolved.target().as_str(), "https://example.com/ios");
+        linkstore.update(resolved.id(), resolved.clone()).unwrap();
+        let stored = linkstore.get(resolved.id()).unwrap().unwrap();
+        assert_eq!(stored.rule(), None);
+        assert_eq!(stored.target().as_str(), "https://example.com/web");
+
+        let mut plain = resolved;
+        plain.clear_rules();
+        linkstore.update(plain.id(), plain).unwrap();
+        let stored = linkstore.get(stored.id()).unwrap().unwrap();
+        assert_eq!(stored.target().as_str(), "https://example.com/web");
+    }
+
+    #[test]
+    fn test_list() {
+        let mut linkstore = InMemoryLinkStore::new();
+        for id in [3, 1, 2] {
+            linkstore
+                .create(
+                    Link::builder()
+                        .id(id)
+                        .target("https://example.com")
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+        assert_eq!(linkstore.count().unwrap(), 3);
+
+        let all: Vec<u64> = linkstore
+            .list(0, 10)
+            .unwrap()
+            .iter()
+            .map(Link::id)
+            .collect();
+        let mut sorted = all.clone();
+        sorted.sort_by_key(|&id| (linkstore.get(id).unwrap().unwrap().created_at(), id));
+        assert_eq!(all, sorted);
+
+        let page: Vec<u64> = linkstore.list(1, 1).unwrap().iter().map(Link::id).collect();
+        assert_eq!(page, all[1..2]);
+        assert!(linkstore.list(3, 10).unwrap().is_empty());
+
+        // updates keep their place
+        linkstore
+            .update(all[0], linkstore.get(all[0]).unwrap().unwrap())
+            .unwrap();
+        assert_eq!(linkstore.list(0, 1).unwrap()[0].id(), all[0]);
+    }
+
+    #[test]
+    fn test_tags() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let tagged = Link::builder()
+            .target("https://example.com")
+            .tag("spring")
+            .build()
+            .unwrap();
+        linkstore.create(tagged.clone()).unwrap();
+        let mut other = Link::default();
+        linkstore.create(other.clone()).unwrap();
+
+        assert!(other.add_tag("spring"));
+        assert!(!other.add_tag("spring"));
+        linkstore.update(other.id(), other.clone()).unwrap();
+        let spring = LinkQuery::new().tag("spring");
+        assert_eq!(linkstore.find(&spring).unwrap().len(), 2);
+
+        assert!(other.remove_tag("spring"));
+        linkstore.update(other.id(), other.clone()).unwrap();
+        assert_eq!(linkstore.delete_by_tag("spring").unwrap(), 1);
+        assert!(linkstore.get(tagged.id()).unwrap().is_none());
+        assert!(linkstore.get(other.id()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let team = Namespace::new("team").unwrap();
+        let shared = linkstore
+            .create_with_slug(
+                "docs",
+                crate::UrlType::parse("https://example.com").unwrap(),
+            )
+            .unwrap();
+        let scoped = Link::builder()
+            .target("https://team.example.com")
+            .namespace(team.clone())
+            .slug("docs")
+            .build()
+            .unwrap();
+        linkstore.create(scoped.clone()).unwrap();
+
+        assert_eq!(linkstore.resolve("docs").unwrap().id(), shared.id());
+        assert_eq!(
+            linkstore.resolve_in(Some(&team), "docs").unwrap().id(),
+            scoped.id()
+        );
+        let other = Namespace::new("other").unwrap();
+        assert!(linkstore
+            .get_by_shortcut_in(Some(&other), "docs")
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            linkstore
+                .record_hit_in(Some(&team), "docs")
+                .unwrap()
+                .hit_count(),
+            1
+        );
+        assert_eq!(
+            linkstore
+                .find(&LinkQuery::new().namespace(team.clone()))
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let duplicate = Link::builder()
+            .target("https://example.com")
+            .namespace(team)
+            .slug("docs")
+            .build()
+            .unwrap();
+        assert!(matches!(
+            linkstore.create(duplicate),
+            Err(UrlManagerError::ShortcutCollision(_))
+        ));
+        linkstore.delete(shared.id()).unwrap();
+        assert!(linkstore
+            .resolve_in(Some(&Namespace::new("team").unwrap()), "docs")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_create_or_get() {
+        let mut linkstore = InMemoryLinkStore::new();
+        let target = |url: &str| crate::UrlType::parse(url).unwrap();
+
+        let link = linkstore
+            .create_or_get(target("https://Example.com:443/a/../b?b=2&a=1"))
+            .unwrap();
+        assert_eq!(link.target().as_str(), "https://example.com/b?a=1&b=2");
+        let again = linkstore
+            .create_or_get(target("https://example.com/b?a=1&b=2"))
+            .unwrap();
+        assert_eq!(again.id(), link.id());
+        assert_eq!(linkstore.count().unwrap(), 1);
+
+        let other = linkstore
+            .create_or_get(target("https://example.com/c"))
+            .unwrap();
+        assert_ne!(other.id(), link.id());
+
+        // the index follows updates
+        let moved = Link::builder()
+            .id(link.id())
+            .target("https://example.com/d")
+            .build()
+            .unwrap();
+        linkstore.update(link.id(), moved).unwrap();
+        let query = LinkQuery::new().target(&target("https://example.com/b?a=1&b=2"));
+        assert!(linkstore.find(&query).unwrap().is_empty());
+        let moved = linkstore
+            .create_or_get(target("https://example.com/d"))
+            .unwrap();
+        assert_eq!(moved.id(), link.id());
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut store = InMemoryLinkStore::new();
+        for _ in 0..2 * PAGE + 10 {
+            store.create(Link::default()).unwrap();
+        }
+        let trashed = store.list(5, 1).unwrap()[0].id();
+        store.soft_delete(trashed).unwrap();
+
+        let mut links = store.iter();
+        let first: Vec<u64> = links.by_ref().take(PAGE).map(|l| l.unwrap().id()).collect();
+        // created while iterating, after every link read so far
+        let late = Link::default();
+        sync::write(&store.links).insert_new(late.clone()).unwrap();
+        let rest: Vec<u64> = links.map(|l| l.unwrap().id()).collect();
+        assert_eq!(first.len() + rest.len(), 2 * PAGE + 10);
+        assert!(rest.contains(&late.id()));
+        assert!(!first.contains(&trashed));
+        let listed: Vec<u64> = store.list(0, PAGE).unwrap().iter().map(Link::id).collect();
+        assert_eq!(first, listed);
+    }
+
+    #[test]
+    fn test_transaction() {
+        let path = std::env::temp_dir().join(format!(
+            "url-manager-transaction-{}.jsonl",
+            rand::random::<u32>()
+        ));
+        let link = |slug: &str| {
+            Link::builder()
+                .target("https://example.com")
+                .slug(slug)
+                .build()
+                .unwrap()
+        };
+        let (docs, blog) = (link("docs"), link("blog"));
+        let mut store = InMemoryLinkStore::with_journal(&path).unwrap();
+        store.create(docs.clone()).unwrap();
+
+        let records = || std::fs::read_to_string(&path).unwrap().lines().count();
+        store
+            .transaction(|store| {
+                store.delete(docs.id())?;
+                store.create(blog.clone())?;
+                store.record_hit("blog")?;
+                store.create_batch(vec![link("news")]).remove(0).map(drop)
+            })
+            .unwrap();
+        assert_eq!(records(), 2);
+
+        store.begin().unwrap();
+        assert!(store.begin().is_err());
+        store.delete(blog.id()).unwrap();
+        store.create(link("docs")).unwrap();
+        assert!(store.compact_journal().is_err());
+        store.rollback().unwrap();
+        assert_eq!(records(), 2);
+        assert_eq!(store.resolve("blog").unwrap().hit_count(), 1);
+        assert!(store.resolve("docs").is_err());
+        drop(store);
+
+        let store = InMemoryLinkStore::with_journal(&path).unwrap();
+        assert!(store.get(docs.id()).unwrap().is_none());
+        assert_eq!(store.resolve("blog").unwrap().hit_count(), 1);
+        assert_eq!(store.count().unwrap(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_journal() {
+        let path = std::env::temp_dir().join(format!(
+            "url-manager-journal-{}.jsonl",
+            rand::random::<u32>()
+        ));
+        let link = |slug: &str| {
+            Link::builder()
+                .target("https://example.com")
+                .slug(slug)
+                .build()
+                .unwrap()
+        };
+        let (docs, blog) = (link("docs"), link("blog"));
+        {
+            let mut store = InMemoryLinkStore::with_journal(&path).unwrap();
+            store.create(docs.clone()).unwrap();
+            store.create(blog.clone()).unwrap();
+            assert!(store.create(link("docs")).is_err());
+            store.record_hit("docs").unwrap();
+            store
+                .update_with(docs.id(), |link| {
+                    link.add_tag("beta");
+                })
+                .unwrap();
+            store.delete(blog.id()).unwrap();
+            store.close().unwrap();
+        }
+
+        let mut store = InMemoryLinkStore::with_journal(&path).unwrap();
+        let restored = store.resolve("docs").unwrap();
+        assert_eq!(restored.hit_count(), 1);
+        assert!(restored.has_tag("beta"));
+        assert!(store.get(blog.id()).unwrap().is_none());
+        let created = store.create_batch(vec![link("news"), link("docs"), link("shop")]);
+        assert!(created[0].is_ok() && created[1].is_err() && created[2].is_ok());
+        let resolved = store.resolve_batch(&["news", "shop", "blog"]);
+        assert!(resolved[0].is_ok() && resolved[1].is_ok());
+        assert!(matches!(resolved[2], Err(UrlManagerError::NotFound)));
+        drop(store);
+
+        let mut store = InMemoryLinkStore::with_journal(&path).unwrap();
+        assert_eq!(store.count().unwrap(), 3);
+        store.compact_journal().unwrap();
+        drop(store);
+        let file = crate::FileLinkStore::open(&path).unwrap();
+        assert_eq!(file.count().unwrap(), 3);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::LinkStore;
+use crate::{Link, Result, UrlManagerError};
+
+/// What [`migrate`] does with a link whose id or shortcut is already taken
+/// in the destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Conflict {
+    /// Leaves the destination's link alone and counts the source link as skipped.
+    #[default]
+    Skip,
+    /// Deletes the destination's link and copies the source link.
+    Overwrite,
+    /// Stops the migration with `DuplicateId` or `ShortcutCollision`.
+    Error,
+}
+
+impl FromStr for Conflict {
+    type Err = UrlManagerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(Conflict::Skip),
+            "overwrite" => Ok(Conflict::Overwrite),
+            "error" => Ok(Conflict::Error),
+            _ => Err(UrlManagerError::InvalidConfig(format!(
+                "unknown conflict policy '{s}', expected skip, overwrite or error"
+            ))),
+        }
+    }
+}
+
+/// How far a [`migrate`] got, handed to the progress callback after every
+/// batch and returned at the end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrateReport {
+    /// How many links the source held when the migration started.
+    pub total: usize,
+    /// Links copied, including overwritten ones.
+    pub copied: usize,
+    /// Links that replaced a conflicting one in the destination.
+    pub overwritten: usize,
+    pub skipped: usize,
+}
+
+type Progress<'a> = Box<dyn FnMut(&MigrateReport) + 'a>;
+
+/// Settings for [`migrate`].
+pub struct MigrateOptions<'a> {
+    conflict: Conflict,
+    batch_size: usize,
+    progress: Option<Progress<'a>>,
+}
+
+impl Default for MigrateOptions<'_> {
+    fn default() -> Self {
+        MigrateOptions {
+            conflict: Conflict::default(),
+            batch_size: 1000,
+            progress: None,
+        }
+    }
+}
+
+impl fmt::Debug for MigrateOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MigrateOptions")
+            .field("conflict", &self.conflict)
+            .field("batch_size", &self.batch_size)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+impl<'a> MigrateOptions<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_conflict(mut self, conflict: Conflict) -> Self {
+        self.conflict = conflict;
+        self
+    }
+
+    /// How many links are read from the source at a time, 1000 by default.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Calls `progress` after every batch.
+    pub fn progress(mut self, progress: impl FnMut(&MigrateReport) + 'a) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+}
+
+/// Copies every link from `from` to `to`, keeping ids, timestamps and hit
+/// counts.
+///
+/// Links are read in [`LinkStore::list`] order, a batch at a time, so the
+/// source is never loaded as a whole. A link conflicts when the destination
+/// already holds its id or its shortcut in the same namespace; `opts`
+/// decides what happens then.
+pub fn migrate(
+    from: &dyn LinkStore,
+    to: &mut dyn LinkStore,
+    mut opts: MigrateOptions<'_>,
+) -> Result<MigrateReport> {
+    let mut report = MigrateReport {
+        total: from.count()?,
+        ..MigrateReport::default()
+    };
+    let mut offset = 0;
+    loop {
+        let links = from.list(offset, opts.batch_size)?;
+        for link in &links {
+            copy(to, link, opts.conflict, &mut report)?;
+        }
+        offset += links.len();
+        if let Some(progress) = &mut opts.progress {
+            progress(&report);
+        }
+        if links.len() < opts.batch_size {
+            return Ok(report);
+        }
+    }
+}
+
+fn copy(
+    to: &mut dyn LinkStore,
+    link: &Link,
+    conflict: Conflict,
+    report: &mut MigrateReport,
+) -> Result<()> {
+    let mut conflicting = Vec::new();
+    if to.get(link.id())?.is_some() {
+        conflicting.push(link.id());
+    }
+    if let Some(shortcut) = link.shortcut() {
+        if let Some(owner) = to.get_by_shortcut_in(link.namespace(), shortcut)? {
+            if owner.id() != link.id() {
+                conflicting.push(owner.id());
+            }
+        }
+    }
+
+    if let Some(&first) = conflicting.first() {
+        match conflict {
+            Conflict::Skip => {
+                report.skipped += 1;
+                return Ok(());
+            }
+            Conflict::Error if first == link.id() => {
+                return Err(UrlManagerError::DuplicateId(first));
+            }
+            Conflict::Error => {
+                return Err(UrlManagerError::ShortcutCollision(
+                    link.shortcut().unwrap_or_default().to_string(),
+                ));
+            }
+            Conflict::Overwrite => {
+                for id in conflicting {
+                    to.delete(id)?;
+                }
+                report.overwritten += 1;
+            }
+        }
+    }
+    to.create(link.clone())?;
+    report.copied += 1;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryLinkStore;
+
+    fn link(id: u64, shortcut: &str) -> Link {
+        Link::builder()
+            .id(id)
+            .target(format!("https://example.com/{id}").as_str())
+            .shortcut(shortcut)
+            .build()
+            .unwrap()
+    }
+
+    fn stores() -> (InMemoryLinkStore, InMemoryLinkStore) {
+        let mut from = InMemoryLinkStore::new();
+        for (id, shortcut) in [(1, "a"), (2, "b"), (3, "c")] {
+            from.create(link(id, shortcut)).unwrap();
+        }
+        from.record_hit("a").unwrap();
+        let mut to = InMemoryLinkStore::new();
+        to.create(link(2, "x")).unwrap();
+        to.create(link(9, "c")).unwrap();
+        (from, to)
+    }
+
+    #[test]
+    fn test_migrate_skip() {
+        let (from, mut to) = stores();
+        let mut batches = Vec::new();
+        let report = migrate(
+            &from,
+            &mut to,
+            MigrateOptions::new()
+                .batch_size(2)
+                .progress(|report| batches.push(report.copied + report.skipped)),
+        )
+        .unwrap();
+        assert_eq!(batches, [2, 3]);
+        assert_eq!(
+            report,
+            MigrateReport {
+                total: 3,
+                copied: 1,
+                overwritten: 0,
+                skipped: 2
+            }
+        );
+        assert_eq!(to.get_by_shortcut("a").unwrap().unwrap().hit_count(), 1);
+        assert_eq!(to.get(2).unwrap().unwrap().shortcut(), Some("x"));
+        assert_eq!(to.get_by_shortcut("c").unwrap().unwrap().id(), 9);
+    }
+
+    #[test]
+    fn test_migrate_overwrite_and_error() {
+        let (from, mut to) = stores();
+        let report = migrate(
+            &from,
+            &mut to,
+            MigrateOptions::new().on_conflict(Conflict::Overwrite),
+        )
+        .unwrap();
+        assert_eq!((report.copied, report.overwritten), (3, 2));
+        assert_eq!(to.count().unwrap(), 3);
+        assert!(to.get(9).unwrap().is_none());
+        assert_eq!(to.get(2).unwrap().unwrap().shortcut(), Some("b"));
+
+        let (from, mut to) = stores();
+        assert!(matches!(
+            migrate(
+                &from,
+                &mut to,
+                MigrateOptions::new().on_conflict(Conflict::Error)
+            ),
+            Err(UrlManagerError::DuplicateId(2))
+        ));
+        assert_eq!(
+            "Overwrite".parse::<Conflict>().unwrap(),
+            Conflict::Overwrite
+        );
+    }
+}
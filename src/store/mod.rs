@@ -0,0 +1,578 @@
+mod archive;
+mod async_store;
+mod cached;
+mod file;
+mod history;
+mod journal;
+mod kv;
+mod map;
+mod memory;
+mod migrate;
+mod pages;
+mod purge;
+mod query;
+#[cfg(feature = "redis")]
+mod redis;
+mod sharded;
+mod tiered;
+mod transaction;
+
+pub use archive::{ArchiveStore, LocalObjectStore, ObjectStore};
+#[cfg(test)]
+pub(crate) use async_store::block_on;
+pub use async_store::{AsyncLinkStore, SyncStoreAdapter};
+pub use cached::{CacheStats, CachedLinkStore};
+pub use file::FileLinkStore;
+pub use history::{AuditedLinkStore, Change, Revision};
+pub use kv::{InMemoryKv, KvLinkStore, KvPage, KvStore};
+pub use memory::InMemoryLinkStore;
+pub use migrate::{migrate, Conflict, MigrateOptions, MigrateReport};
+pub(crate) use purge::spawn_every;
+pub use purge::{spawn_purger, Purger};
+pub use query::LinkQuery;
+#[cfg(feature = "redis")]
+pub use redis::RedisLinkStore;
+pub use sharded::ShardedLinkStore;
+pub use tiered::TieredLinkStore;
+pub use transaction::Transactional;
+
+use std::io::{BufRead, Write};
+use std::time::{Duration, SystemTime};
+
+use pages::{Pages, PAGE};
+
+use crate::link::now;
+use crate::snapshot::Snapshot;
+use crate::transfer::{self, Format, ImportReport, RowError};
+use crate::{
+    unique_id, IdGenerator, Link, LinkBuilder, Namespace, Normalizer, Result, UrlManagerError,
+    UrlType,
+};
+
+/// Storage for links.
+///
+/// The trait is open so other crates can provide their own backends;
+/// [`InMemoryLinkStore`] is the reference implementation.
+///
+/// # Unique shortcuts
+///
+/// A shortcut names at most one link per namespace, trashed links
+/// included. Every method storing a link that would break this fails with
+/// `ShortcutCollision` carrying the shortcut and leaves the store as it
+/// was. Backends enforcing it with a unique index, like SQL databases,
+/// translate the violation into `ShortcutCollision` instead of passing it
+/// on as a `StorageBackend` error, so callers can tell a taken shortcut
+/// from a failing backend. The `testing` feature's
+/// `conformance::unique_shortcuts` checks this.
+pub trait LinkStore {
+    /// Returns the link stored under `id`, if any.
+    fn get(&self, id: u64) -> Result<Option<Link>>;
+    /// Returns the link reachable under `shortcut` in `namespace`, if any.
+    fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>>;
+    /// Stores `link`, failing with `DuplicateId` if a link with its id is
+    /// already stored and with `ShortcutCollision` if another link in its
+    /// namespace has its shortcut.
+    fn create(&mut self, link: Link) -> Result<()>;
+    /// Replaces the link stored under `id`, failing with `NotFound` if there is none
+    /// and with `ShortcutCollision` if another link has the new shortcut.
+    fn update(&mut self, id: u64, link: Link) -> Result<()>;
+    /// Removes the link stored under `id`, failing with `NotFound` if there is none.
+    fn delete(&mut self, id: u64) -> Result<()>;
+    /// Returns up to `limit` links outside the trash after skipping
+    /// `offset`, ordered by creation time and then id so pages stay stable.
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>>;
+    /// Returns up to `limit` links in the trash after skipping `offset`, in
+    /// [`LinkStore::list`] order.
+    fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>>;
+    /// Returns how many links are stored, not counting those in the trash.
+    fn count(&self) -> Result<usize>;
+    /// Removes every expired or used up link, returning how many were removed.
+    fn purge_expired(&mut self) -> Result<usize>;
+
+    /// Stores `link`, replacing any link stored under its id as a whole,
+    /// creation time and hits included. Fails with `ShortcutCollision` if
+    /// another link in its namespace has its shortcut.
+    ///
+    /// The default checks the shortcut, then deletes and creates, so the old
+    /// link is gone if the creation still fails; stores should override it
+    /// to replace in one step.
+    fn upsert(&mut self, link: Link) -> Result<()> {
+        if let Some(shortcut) = link.shortcut() {
+            let owner = self.get_by_shortcut_in(link.namespace(), shortcut)?;
+            if owner.is_some_and(|owner| owner.id() != link.id()) {
+                return Err(UrlManagerError::ShortcutCollision(shortcut.to_string()));
+            }
+        }
+        match self.delete(link.id()) {
+            Ok(()) | Err(UrlManagerError::NotFound) => self.create(link),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the link reachable under `shortcut` without a namespace, if any.
+    fn get_by_shortcut(&self, shortcut: &str) -> Result<Option<Link>> {
+        self.get_by_shortcut_in(None, shortcut)
+    }
+
+    /// Every link outside the trash in [`LinkStore::list`] order, read a
+    /// page at a time so the whole store is never in memory at once. The
+    /// iterator ends after the first error.
+    ///
+    /// The default pages through `list` by offset; stores should override it
+    /// to resume after the last link instead, as offsets get slow deep into
+    /// large tables.
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Link>> + '_> {
+        let mut offset = 0;
+        Box::new(Pages::new(move || {
+            let page = self.list(offset, PAGE)?;
+            offset += page.len();
+            Ok(page)
+        }))
+    }
+
+    /// Returns the links matching `query`, in [`LinkStore::list`] order.
+    ///
+    /// The default filters every link in or outside the trash, as the query
+    /// asks; database backends should translate the query instead.
+    fn find(&self, query: &LinkQuery) -> Result<Vec<Link>> {
+        let mut links = if query.get_deleted() {
+            self.list_deleted(0, usize::MAX)?
+        } else {
+            self.list(0, usize::MAX)?
+        };
+        links.retain(|link| query.matches(link));
+        Ok(links)
+    }
+
+    /// Looks up the link behind `shortcut` in `namespace` for following it,
+    /// failing with `NotFound` if there is none, with `Expired` if it has
+    /// expired, with `UsesExhausted` if it has no uses left and with
+    /// `PasswordRequired` if it is password-protected.
+    fn resolve_in(&self, namespace: Option<&Namespace>, shortcut: &str) -> Result<Link> {
+        self.resolve_with_password_in(namespace, shortcut, None)
+    }
+
+    /// [`LinkStore::resolve_in`] without a namespace.
+    fn resolve(&self, shortcut: &str) -> Result<Link> {
+        self.resolve_in(None, shortcut)
+    }
+
+    /// Like [`LinkStore::resolve_in`], but a password-protected link
+    /// resolves if `password` matches.
+    fn resolve_with_password_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        let link = self
+            .get_by_shortcut_in(namespace, shortcut)?
+            .ok_or(UrlManagerError::NotFound)?;
+        link.check_access(password)?;
+        Ok(link)
+    }
+
+    /// Resolves `shortcut` without a namespace, giving `password` for a
+    /// protected link; unprotected links resolve regardless.
+    fn resolve_with_password(&self, shortcut: &str, password: &str) -> Result<Link> {
+        self.resolve_with_password_in(None, shortcut, Some(password))
+    }
+
+    /// Creates every link of `links`, returning the outcome of each in
+    /// order; a link that fails, e.g. with `ShortcutCollision`, doesn't stop
+    /// the others.
+    ///
+    /// The default creates one link at a time; stores should override it to
+    /// write them together, e.g. in a multi-row insert.
+    fn create_batch(&mut self, links: Vec<Link>) -> Vec<Result<Link>> {
+        links
+            .into_iter()
+            .map(|link| {
+                self.create(link.clone())?;
+                Ok(link)
+            })
+            .collect()
+    }
+
+    /// Resolves every one of `shortcuts` in `namespace` like
+    /// [`LinkStore::resolve_in`], returning the outcome of each in order.
+    ///
+    /// The default looks up one shortcut at a time; stores should override
+    /// it to look them up together, e.g. with `MGET`.
+    fn resolve_batch_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcuts: &[&str],
+    ) -> Vec<Result<Link>> {
+        shortcuts
+            .iter()
+            .map(|shortcut| self.resolve_in(namespace, shortcut))
+            .collect()
+    }
+
+    /// [`LinkStore::resolve_batch_in`] without a namespace.
+    fn resolve_batch(&self, shortcuts: &[&str]) -> Vec<Result<Link>> {
+        self.resolve_batch_in(None, shortcuts)
+    }
+
+    /// Creates a link to `target` under the human-chosen `slug`.
+    ///
+    /// The slug is checked with [`validate_slug`](crate::validate_slug) first.
+    fn create_with_slug(&mut self, slug: &str, target: UrlType) -> Result<Link> {
+        let link = Link::builder().target(target).slug(slug).build()?;
+        self.create(link.clone())?;
+        Ok(link)
+    }
+
+    /// Builds `builder` with an id from `ids` that no stored link has yet,
+    /// stores the link and returns it. An id set on the builder is replaced.
+    fn create_new(&mut self, builder: LinkBuilder, ids: &dyn IdGenerator) -> Result<Link> {
+        let id = unique_id(ids, |id| Ok(self.get(id)?.is_some()))?;
+        let link = builder.id(id).build()?;
+        self.create(link.clone())?;
+        Ok(link)
+    }
+
+    /// Returns a resolvable link without a namespace whose target normalizes
+    /// like `target`, or creates one with a generated shortcut.
+    ///
+    /// Stores answer the lookup from a target index, see [`LinkQuery::target`].
+    fn create_or_get(&mut self, target: UrlType) -> Result<Link> {
+        let existing = self.find(&LinkQuery::new().target(&target))?;
+        if let Some(link) = existing
+            .into_iter()
+            .find(|link| link.namespace().is_none() && link.check_resolvable().is_ok())
+        {
+            return Ok(link);
+        }
+        let link = Link::builder()
+            .target(target)
+            .normalize(Normalizer::default())
+            .build()?;
+        self.create(link.clone())?;
+        Ok(link)
+    }
+
+    /// [`LinkStore::update`] if the link stored under `id` is still at
+    /// `version`, see [`Link::version`], failing with `Conflict` otherwise,
+    /// so two people editing the same link can't overwrite each other
+    /// unnoticed.
+    ///
+    /// The default checks and updates in two steps; stores should override
+    /// it to do both in one critical section.
+    fn update_if_version(&mut self, id: u64, version: u64, link: Link) -> Result<()> {
+        let current = self.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        if current.version() != version {
+            return Err(UrlManagerError::Conflict(current.version()));
+        }
+        self.update(id, link)
+    }
+
+    /// Applies `change` to the link stored under `id` and returns the stored
+    /// result, failing like [`LinkStore::update`].
+    ///
+    /// Unlike `update`, the change starts from the stored link, so edits made
+    /// in between aren't lost. The default reads and writes in two steps;
+    /// stores should override it to do both in one critical section.
+    fn update_with(&mut self, id: u64, change: impl FnOnce(&mut Link)) -> Result<Link>
+    where
+        Self: Sized,
+    {
+        let mut link = self.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        change(&mut link);
+        self.update(id, link)?;
+        self.get(id)?.ok_or(UrlManagerError::NotFound)
+    }
+
+    /// Counts a resolution of `shortcut` in `namespace` and returns the link
+    /// it resolved to, failing like [`LinkStore::resolve_in`].
+    fn record_hit_in(&mut self, namespace: Option<&Namespace>, shortcut: &str) -> Result<Link> {
+        self.record_hit_with_password_in(namespace, shortcut, None)
+    }
+
+    /// [`LinkStore::record_hit_in`] with `password` for protected links, see
+    /// [`LinkStore::resolve_with_password_in`].
+    ///
+    /// The default replaces the link through [`LinkStore::upsert`], as
+    /// `update` keeps the stored hits, and isn't atomic, so concurrent hits
+    /// can exceed `max_uses`; stores should override it to check and count
+    /// in one step.
+    fn record_hit_with_password_in(
+        &mut self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        let mut link = self.resolve_with_password_in(namespace, shortcut, password)?;
+        link.hit();
+        self.upsert(link.clone())?;
+        Ok(link)
+    }
+
+    /// Counts a resolution of `link`, as returned by
+    /// [`LinkStore::resolve_with_password_in`], and returns it counted,
+    /// failing like [`LinkStore::record_hit_in`] if it can't be followed
+    /// anymore and with `PasswordRequired` if its password changed since.
+    ///
+    /// Checking a password is slow on purpose, so callers sharing a store
+    /// behind a lock resolve first and count with this, holding the lock
+    /// only for each step. The default goes through [`LinkStore::upsert`],
+    /// see [`LinkStore::record_hit_with_password_in`].
+    fn record_resolved_hit(&mut self, link: &Link) -> Result<Link> {
+        let mut current = self.get(link.id())?.ok_or(UrlManagerError::NotFound)?;
+        current.check_access_as(link)?;
+        current.hit();
+        self.upsert(current.clone())?;
+        Ok(current)
+    }
+
+    /// [`LinkStore::record_hit_in`] without a namespace.
+    fn record_hit(&mut self, shortcut: &str) -> Result<Link> {
+        self.record_hit_in(None, shortcut)
+    }
+
+    /// Counts a resolution of the link stored under `id` that picked its
+    /// variant `variant`, see [`Link::pick_variant`], and returns the link
+    /// with [`Link::target`] pointing at that variant. Fails with `NotFound`
+    /// if there is no link and with `InvalidLink` if there is no such variant.
+    ///
+    /// This only counts per variant; [`record_hit`](crate::record_hit) also
+    /// counts the resolution itself. The default replaces the link through
+    /// [`LinkStore::upsert`]; stores should override it to count in one step.
+    fn record_variant_hit(&mut self, id: u64, variant: usize) -> Result<Link> {
+        let mut link = self.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        link.hit_variant(variant)?;
+        self.upsert(link.clone())?;
+        Ok(link.resolved_to(variant))
+    }
+
+    /// Deletes every link carrying `tag`, returning how many were deleted.
+    fn delete_by_tag(&mut self, tag: &str) -> Result<usize> {
+        let links = self.find(&LinkQuery::new().tag(tag))?;
+        for link in &links {
+            self.delete(link.id())?;
+        }
+        Ok(links.len())
+    }
+
+    /// Moves the link stored under `id` to the trash, failing with
+    /// `NotFound` if there is none or it is already there.
+    ///
+    /// A trashed link stops resolving and is left out of `list`, `count` and
+    /// `find` unless asked for with [`LinkQuery::deleted`], but keeps its
+    /// shortcut until [`LinkStore::purge`]d; `get` still returns it.
+    fn soft_delete(&mut self, id: u64) -> Result<()> {
+        let mut link = self.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        if link.is_deleted() {
+            return Err(UrlManagerError::NotFound);
+        }
+        link.trash();
+        self.update(id, link)
+    }
+
+    /// Takes the link stored under `id` out of the trash, failing with
+    /// `NotFound` if it isn't there.
+    fn restore(&mut self, id: u64) -> Result<()> {
+        let mut link = self.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        if !link.is_deleted() {
+            return Err(UrlManagerError::NotFound);
+        }
+        link.restore();
+        self.update(id, link)
+    }
+
+    /// Deletes every link that has been in the trash for at least
+    /// `older_than`, returning how many were deleted.
+    fn purge(&mut self, older_than: Duration) -> Result<usize> {
+        let cutoff = now()
+            .checked_sub(older_than)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut purged = 0;
+        for link in self.find(&LinkQuery::new().deleted())? {
+            if link
+                .deleted_at()
+                .is_some_and(|deleted_at| deleted_at <= cutoff)
+            {
+                self.delete(link.id())?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Every namespace holding a live link, in order.
+    fn namespaces(&self) -> Result<Vec<Namespace>> {
+        let namespaces: std::collections::BTreeSet<Namespace> = self
+            .list(0, usize::MAX)?
+            .into_iter()
+            .filter_map(|link| link.namespace().cloned())
+            .collect();
+        Ok(namespaces.into_iter().collect())
+    }
+
+    /// Up to `limit` of the live links owned by the user `owner_id` after
+    /// skipping `offset`, ordered like [`LinkStore::list`].
+    fn list_owned(&self, owner_id: u64, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        Ok(self
+            .find(&LinkQuery::new().owner_id(owner_id))?
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .collect())
+    }
+
+    /// [`LinkStore::update`] on behalf of the user `owner_id`, failing with
+    /// `NotFound` if the stored link isn't theirs and with `Forbidden` if
+    /// `link` would give it to someone else.
+    fn update_owned(&mut self, owner_id: u64, id: u64, link: Link) -> Result<()> {
+        self.get_owned(owner_id, id)?;
+        if link.owner_id() != Some(owner_id) {
+            return Err(UrlManagerError::Forbidden(format!(
+                "link {id} can't be given away"
+            )));
+        }
+        self.update(id, link)
+    }
+
+    /// [`LinkStore::soft_delete`] on behalf of the user `owner_id`, failing
+    /// with `NotFound` if the link isn't theirs.
+    fn delete_owned(&mut self, owner_id: u64, id: u64) -> Result<()> {
+        self.get_owned(owner_id, id)?;
+        self.soft_delete(id)
+    }
+
+    /// The live link stored under `id` if it belongs to the user
+    /// `owner_id`, failing with `NotFound` otherwise so other users' links
+    /// can't be told apart from missing ones.
+    fn get_owned(&self, owner_id: u64, id: u64) -> Result<Link> {
+        self.get(id)?
+            .filter(|link| !link.is_deleted() && link.owner_id() == Some(owner_id))
+            .ok_or(UrlManagerError::NotFound)
+    }
+
+    /// The live links whose target failed its last check, see
+    /// [`healthcheck`](crate::healthcheck); links never checked aren't
+    /// included.
+    fn find_broken(&self) -> Result<Vec<Link>> {
+        self.iter()
+            .filter(|link| {
+                link.as_ref().map_or(true, |link| {
+                    link.health().is_some_and(|health| health.is_broken())
+                })
+            })
+            .collect()
+    }
+
+    /// Every recorded change to the link stored under `id`, oldest first,
+    /// failing with `NotFound` if there is neither a link nor a history.
+    ///
+    /// The built-in stores keep no history and return none; wrap one in an
+    /// [`AuditedLinkStore`] to record it.
+    fn history(&self, id: u64) -> Result<Vec<Revision>> {
+        self.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        Ok(Vec::new())
+    }
+
+    /// The link stored under `id` as it was at `time` according to its
+    /// [`LinkStore::history`], `None` if it didn't exist then.
+    fn link_at(&self, id: u64, time: SystemTime) -> Result<Option<Link>> {
+        Ok(self
+            .history(id)?
+            .into_iter()
+            .take_while(|revision| revision.at <= time)
+            .last()
+            .and_then(|revision| revision.link))
+    }
+
+    /// Creates a link for every record in `reader`, keeping ids and timestamps.
+    ///
+    /// Records that don't parse or can't be stored are listed in the report
+    /// and skipped; only I/O errors abort the import.
+    fn import(&mut self, reader: impl BufRead, format: Format) -> Result<ImportReport>
+    where
+        Self: Sized,
+    {
+        let mut report = ImportReport::default();
+        transfer::read_links(reader, format, |line, link| {
+            match link.and_then(|link| self.create(link)) {
+                Ok(()) => report.imported += 1,
+                Err(error) => report.errors.push(RowError { line, error }),
+            }
+        })?;
+        Ok(report)
+    }
+
+    /// Writes every link to `writer` in [`LinkStore::list`] order, returning
+    /// how many were written.
+    fn export(&self, mut writer: impl Write, format: Format) -> Result<usize>
+    where
+        Self: Sized,
+    {
+        transfer::write_header(&mut writer, format)?;
+        let mut written = 0;
+        for link in self.iter() {
+            transfer::write_link(&mut writer, format, &link?)?;
+            written += 1;
+        }
+        writer.flush()?;
+        Ok(written)
+    }
+
+    /// Writes a [`Snapshot`] of every link, trashed ones included, to
+    /// `writer`, returning how many links it holds. Use
+    /// [`Snapshot::with_clicks`] to keep clicks as well.
+    fn snapshot(&self, writer: impl Write) -> Result<usize>
+    where
+        Self: Sized,
+    {
+        let snapshot = Snapshot::of(self)?;
+        snapshot.write_to(writer)?;
+        Ok(snapshot.links().len())
+    }
+
+    /// Reads a snapshot from `reader` and, only once all of it checked
+    /// out, stores its links, see [`Snapshot::restore_into`]. Returns the
+    /// snapshot, e.g. to [replay its clicks](Snapshot::replay_clicks).
+    fn restore_snapshot(&mut self, reader: impl BufRead) -> Result<Snapshot>
+    where
+        Self: Sized,
+    {
+        let snapshot = Snapshot::read_from(reader)?;
+        snapshot.restore_into(self)?;
+        Ok(snapshot)
+    }
+
+    /// Usage figures for the link stored under `id`.
+    fn stats(&self, id: u64) -> Result<LinkStats> {
+        let link = self.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        Ok(LinkStats {
+            hits: link.hit_count(),
+            last_hit_at: link.last_hit_at(),
+            created_at: link.created_at(),
+        })
+    }
+
+    /// Fails if the backend can't be reached, for health checks. The
+    /// default asks for the [`LinkStore::count`].
+    fn ping(&self) -> Result<()> {
+        self.count().map(drop)
+    }
+
+    /// Writes out anything still buffered before the store is dropped, e.g.
+    /// when a server shuts down. The default does nothing.
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Usage figures of a link, see [`LinkStore::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkStats {
+    pub hits: u64,
+    pub last_hit_at: Option<SystemTime>,
+    pub created_at: SystemTime,
+}
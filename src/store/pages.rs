@@ -0,0 +1,74 @@
+use std::vec;
+
+use crate::{Link, Result};
+
+/// How many links [`Pages`] asks for at a time.
+pub(super) const PAGE: usize = 1000;
+
+/// An iterator over links read a page at a time by `next_page`, which
+/// returns up to [`PAGE`] links following the last page it returned. It
+/// ends after a short page or the first error.
+pub(super) struct Pages<F> {
+    next_page: F,
+    page: vec::IntoIter<Link>,
+    done: bool,
+}
+
+impl<F: FnMut() -> Result<Vec<Link>>> Pages<F> {
+    pub(super) fn new(next_page: F) -> Self {
+        Pages {
+            next_page,
+            page: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+}
+
+impl<F: FnMut() -> Result<Vec<Link>>> Iterator for Pages<F> {
+    type Item = Result<Link>;
+
+    fn next(&mut self) -> Option<Result<Link>> {
+        if let Some(link) = self.page.next() {
+            return Some(Ok(link));
+        }
+        if self.done {
+            return None;
+        }
+        match (self.next_page)() {
+            Ok(page) => {
+                self.done = page.len() < PAGE;
+                self.page = page.into_iter();
+                self.page.next().map(Ok)
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UrlManagerError;
+
+    #[test]
+    fn test_pages() {
+        let mut sizes = vec![PAGE, PAGE, 3].into_iter();
+        let pages = Pages::new(|| Ok(vec![Link::default(); sizes.next().unwrap()]));
+        assert_eq!(pages.count(), 2 * PAGE + 3);
+
+        let mut calls = 0;
+        let mut pages = Pages::new(|| {
+            calls += 1;
+            match calls {
+                1 => Ok(vec![Link::default(); PAGE]),
+                _ => Err(UrlManagerError::backend("gone")),
+            }
+        });
+        assert_eq!(pages.by_ref().take(PAGE).count(), PAGE);
+        assert!(pages.next().unwrap().is_err());
+        assert!(pages.next().is_none());
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::LinkStore;
+use crate::sync;
+
+/// Handle of the thread started by [`spawn_purger`] or
+/// [`spawn_rollup`](crate::analytics::spawn_rollup); dropping it stops the
+/// thread.
+#[derive(Debug)]
+pub struct Purger {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Calls [`LinkStore::purge_expired`] on `store` every `interval` until the
+/// returned [`Purger`] is dropped.
+///
+/// Errors are ignored; the next run tries again.
+pub fn spawn_purger<S>(store: Arc<Mutex<S>>, interval: Duration) -> Purger
+where
+    S: LinkStore + Send + 'static,
+{
+    spawn_every(interval, move || {
+        let _ = sync::lock(&store).purge_expired();
+    })
+}
+
+// Runs `job` every `interval` on a thread until the returned handle is dropped.
+pub(crate) fn spawn_every(interval: Duration, mut job: impl FnMut() + Send + 'static) -> Purger {
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            job();
+        }
+    });
+    Purger {
+        stop: Some(stop),
+        thread: Some(thread),
+    }
+}
+
+impl Drop for Purger {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryLinkStore, Link, UrlType};
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_purger() {
+        let mut store = InMemoryLinkStore::new();
+        let link = Link::builder()
+            .target(UrlType::parse("https://example.com").unwrap())
+            .expires_in(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        store.create(link.clone()).unwrap();
+        let store = Arc::new(Mutex::new(store));
+
+        let purger = spawn_purger(Arc::clone(&store), Duration::from_millis(5));
+        let deadline = SystemTime::now() + Duration::from_secs(5);
+        while store.lock().unwrap().get(link.id()).unwrap().is_some() {
+            assert!(SystemTime::now() < deadline, "link was not purged");
+            thread::sleep(Duration::from_millis(5));
+        }
+        drop(purger);
+    }
+}
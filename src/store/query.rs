@@ -0,0 +1,210 @@
+use std::ops::{Bound, RangeBounds};
+use std::time::SystemTime;
+
+use crate::{normalize, Link, Namespace, UrlType};
+
+/// Filters for [`LinkStore::find`](super::LinkStore::find).
+///
+/// Every filter that is set must match; an empty query matches every link
+/// that isn't in the trash.
+///
+/// ```
+/// # use std::time::{Duration, SystemTime};
+/// # use url_manager::LinkQuery;
+/// let last_week = SystemTime::now() - Duration::from_secs(7 * 24 * 60 * 60);
+/// let query = LinkQuery::new()
+///     .target_host("www.example.com")
+///     .shortcut_prefix("spring-")
+///     .tag("campaign")
+///     .created(last_week..);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkQuery {
+    namespace: Option<Namespace>,
+    owner_id: Option<u64>,
+    target: Option<UrlType>,
+    target_host: Option<String>,
+    shortcut_prefix: Option<String>,
+    created_after: Option<Bound<SystemTime>>,
+    created_before: Option<Bound<SystemTime>>,
+    tag: Option<String>,
+    deleted: bool,
+}
+
+impl LinkQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only links in `namespace`.
+    pub fn namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Only links owned by the user `owner_id`.
+    pub fn owner_id(mut self, owner_id: u64) -> Self {
+        self.owner_id = Some(owner_id);
+        self
+    }
+
+    /// Only links whose target is `target` once both are [`normalize`]d.
+    pub fn target(mut self, target: &UrlType) -> Self {
+        self.target = Some(normalize(target));
+        self
+    }
+
+    /// Only links whose target is on `host`, compared case-insensitively.
+    pub fn target_host(mut self, host: impl Into<String>) -> Self {
+        self.target_host = Some(host.into());
+        self
+    }
+
+    /// Only links whose shortcut starts with `prefix`.
+    pub fn shortcut_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.shortcut_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Only links carrying `tag`.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Only links in the trash, see
+    /// [`LinkStore::soft_delete`](super::LinkStore::soft_delete).
+    pub fn deleted(mut self) -> Self {
+        self.deleted = true;
+        self
+    }
+
+    /// Only links created within `range`.
+    pub fn created(mut self, range: impl RangeBounds<SystemTime>) -> Self {
+        self.created_after = Some(range.start_bound().cloned());
+        self.created_before = Some(range.end_bound().cloned());
+        self
+    }
+
+    pub fn get_namespace(&self) -> Option<&Namespace> {
+        self.namespace.as_ref()
+    }
+
+    pub fn get_owner_id(&self) -> Option<u64> {
+        self.owner_id
+    }
+
+    /// The normalized target.
+    pub fn get_target(&self) -> Option<&UrlType> {
+        self.target.as_ref()
+    }
+
+    pub fn get_target_host(&self) -> Option<&str> {
+        self.target_host.as_deref()
+    }
+
+    pub fn get_shortcut_prefix(&self) -> Option<&str> {
+        self.shortcut_prefix.as_deref()
+    }
+
+    pub fn get_tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    /// Whether the query is for links in the trash instead of live ones.
+    pub fn get_deleted(&self) -> bool {
+        self.deleted
+    }
+
+    /// The creation time range, for backends that translate the query.
+    pub fn get_created(&self) -> (Bound<SystemTime>, Bound<SystemTime>) {
+        (
+            self.created_after.unwrap_or(Bound::Unbounded),
+            self.created_before.unwrap_or(Bound::Unbounded),
+        )
+    }
+
+    pub fn matches(&self, link: &Link) -> bool {
+        let target = self
+            .target
+            .as_ref()
+            .is_none_or(|target| normalize(link.target()) == *target);
+        let host = self.target_host.as_deref().is_none_or(|host| {
+            link.target()
+                .host_str()
+                .is_some_and(|target| target.eq_ignore_ascii_case(host))
+        });
+        let prefix = self.shortcut_prefix.as_deref().is_none_or(|prefix| {
+            link.shortcut()
+                .is_some_and(|shortcut| shortcut.starts_with(prefix))
+        });
+        let namespace = self
+            .namespace
+            .as_ref()
+            .is_none_or(|namespace| link.namespace() == Some(namespace));
+        let owner = self
+            .owner_id
+            .is_none_or(|owner_id| link.owner_id() == Some(owner_id));
+        let tag = self.tag.as_deref().is_none_or(|tag| link.has_tag(tag));
+        link.is_deleted() == self.deleted
+            && namespace
+            && owner
+            && target
+            && host
+            && prefix
+            && tag
+            && self.get_created().contains(&link.created_at())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_matches() {
+        let link = Link::builder()
+            .target("https://WWW.example.com/docs")
+            .shortcut("spring-sale")
+            .tag("campaign")
+            .build()
+            .unwrap();
+
+        assert!(LinkQuery::new().matches(&link));
+        assert!(LinkQuery::new()
+            .target_host("www.Example.com")
+            .matches(&link));
+        assert!(!LinkQuery::new().target_host("example.com").matches(&link));
+        assert!(LinkQuery::new().shortcut_prefix("spring").matches(&link));
+        assert!(!LinkQuery::new().shortcut_prefix("fall").matches(&link));
+        assert!(LinkQuery::new().tag("campaign").matches(&link));
+        let target = UrlType::parse("https://www.example.com/docs#intro").unwrap();
+        assert!(LinkQuery::new().target(&target).matches(&link));
+        let target = UrlType::parse("https://www.example.com/doc").unwrap();
+        assert!(!LinkQuery::new().target(&target).matches(&link));
+        assert!(!LinkQuery::new().tag("Campaign").matches(&link));
+        assert!(!LinkQuery::new().owner_id(7).matches(&link));
+        let mut owned = link.clone();
+        owned.set_owner_id(Some(7));
+        assert!(LinkQuery::new().owner_id(7).matches(&owned));
+        assert!(!LinkQuery::new().owner_id(8).matches(&owned));
+
+        let created = link.created_at();
+        assert!(LinkQuery::new().created(created..).matches(&link));
+        assert!(!LinkQuery::new().created(..created).matches(&link));
+        assert!(LinkQuery::new()
+            .created(..=created)
+            .shortcut_prefix("spring")
+            .matches(&link));
+        assert!(!LinkQuery::new()
+            .created(created + Duration::from_millis(1)..)
+            .matches(&link));
+
+        let mut trashed = link.clone();
+        trashed.trash();
+        assert!(!LinkQuery::new().matches(&trashed));
+        assert!(LinkQuery::new().deleted().matches(&trashed));
+        assert!(!LinkQuery::new().deleted().matches(&link));
+    }
+}
@@ -0,0 +1,738 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::{LinkQuery, LinkStore, PAGE};
+use crate::json;
+use crate::link::unix_millis;
+use crate::{sync, Link, Namespace, Result, UrlManagerError};
+
+// Ids are zero-padded so they sort as members of the same score in id order.
+const LINK_PREFIX: &str = "link:";
+const SHORTCUT_PREFIX: &str = "shortcut:";
+// A sorted set of the padded ids of all links, scored by creation time.
+const LINKS_KEY: &str = "links";
+
+const KEEP_EXPIRED: Duration = Duration::from_secs(24 * 60 * 60);
+const TIMEOUT: Duration = Duration::from_secs(5);
+// The largest string Redis stores.
+const MAX_BULK_LENGTH: usize = 512 * 1024 * 1024;
+
+/// A [`LinkStore`] keeping links in Redis, e.g. behind a redirect service
+/// running on several nodes.
+///
+/// Each link is stored as JSON under `link:<id>` and its shortcut under
+/// `shortcut:<namespace>:<shortcut>`, pointing at the id; the sorted set
+/// `links` orders them for `list`. Shortcuts are claimed with `SET NX`, so
+/// two nodes can't both take the same one. Links that expire are stored
+/// with a Redis expiry [`RedisLinkStore::keep_expired`] past theirs:
+/// until then they resolve with `Expired` and are removed by
+/// [`LinkStore::purge_expired`], after that Redis drops them itself.
+/// Trashed links are kept until purged.
+///
+/// Counting a hit reads the link and writes it back, so nodes hitting the
+/// same link at once can lose counts and exceed `max_uses`. It speaks RESP
+/// over one connection, reconnecting after I/O errors, and needs Redis 6.2
+/// or later for `SET … PXAT`.
+///
+/// ```no_run
+/// # use url_manager::{LinkStore, RedisLinkStore};
+/// let mut store = RedisLinkStore::connect("127.0.0.1:6379")?;
+/// store.create_with_slug("docs", "https://example.com/docs".parse()?)?;
+/// assert_eq!(store.resolve("docs")?.target().as_str(), "https://example.com/docs");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct RedisLinkStore {
+    addrs: Vec<SocketAddr>,
+    connection: Mutex<Option<Connection>>,
+    keep_expired: Duration,
+}
+
+impl RedisLinkStore {
+    /// Connects to the Redis server at `addr`, failing if it doesn't answer
+    /// a `PING`.
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let store = RedisLinkStore {
+            addrs: addr.to_socket_addrs()?.collect(),
+            connection: Mutex::new(None),
+            keep_expired: KEEP_EXPIRED,
+        };
+        store.ping()?;
+        Ok(store)
+    }
+
+    /// How long expired links are kept before Redis drops them, a day by
+    /// default.
+    pub fn keep_expired(mut self, keep_expired: Duration) -> Self {
+        self.keep_expired = keep_expired;
+        self
+    }
+
+    // Sends `args` as a command, reconnecting first if the last command
+    // failed on the connection. Error replies fail with `StorageBackend`.
+    fn query(&self, args: &[&str]) -> Result<Reply> {
+        let mut connection = sync::lock(&self.connection);
+        let open = match &mut *connection {
+            Some(open) => open,
+            None => connection.insert(Connection::open(&self.addrs)?),
+        };
+        match open.query(args) {
+            Ok(Reply::Error(message)) => Err(UrlManagerError::backend(message)),
+            Ok(reply) => Ok(reply),
+            Err(e) => {
+                // the reply may still be on its way, so the next command
+                // would read it as its own
+                *connection = None;
+                Err(e.into())
+            }
+        }
+    }
+
+    // Stores `value` under `key` if `condition`, `NX` or `XX`, holds,
+    // expiring at `expire_at` in unix milliseconds if given. Returns whether
+    // it was stored.
+    fn set(&self, key: &str, value: &str, condition: &str, expire_at: Option<u64>) -> Result<bool> {
+        let mut args = vec!["SET", key, value];
+        args.extend((!condition.is_empty()).then_some(condition));
+        let expire_at = expire_at.map(|at| at.to_string());
+        if let Some(at) = &expire_at {
+            args.extend(["PXAT", at]);
+        }
+        Ok(!matches!(self.query(&args)?, Reply::Nil))
+    }
+
+    fn put(&self, link: &Link, condition: &str) -> Result<bool> {
+        let value = link.to_json().to_string();
+        self.set(
+            &link_key(link.id()),
+            &value,
+            condition,
+            self.expire_at(link),
+        )
+    }
+
+    // Points the shortcut of `link` at it, failing with `ShortcutCollision`
+    // if another link has it. Returns whether the shortcut was free.
+    fn claim(&self, link: &Link) -> Result<bool> {
+        let Some(shortcut) = link.shortcut() else {
+            return Ok(false);
+        };
+        let key = shortcut_key(link.namespace(), shortcut);
+        let id = link.id().to_string();
+        let expire_at = self.expire_at(link);
+        if self.set(&key, &id, "NX", expire_at)? {
+            return Ok(true);
+        }
+        // the index may lag behind the link it points at
+        let owner = self.get_by_shortcut_in(link.namespace(), shortcut)?;
+        match owner {
+            Some(owner) if owner.id() != link.id() => {
+                Err(UrlManagerError::ShortcutCollision(shortcut.to_string()))
+            }
+            // also moves the expiry along with the link's
+            _ => self.set(&key, &id, "", expire_at).map(|_| owner.is_none()),
+        }
+    }
+
+    fn release(&self, namespace: Option<&Namespace>, shortcut: &str) -> Result<()> {
+        self.query(&["DEL", &shortcut_key(namespace, shortcut)])
+            .map(drop)
+    }
+
+    // When Redis drops `link`, in unix milliseconds, if ever.
+    fn expire_at(&self, link: &Link) -> Option<u64> {
+        if link.is_deleted() {
+            return None;
+        }
+        let expires_at = link.expires_at()?;
+        Some(unix_millis(
+            expires_at
+                .checked_add(self.keep_expired)
+                .unwrap_or(expires_at),
+        ))
+    }
+
+    // Up to `limit` links in or outside the trash after skipping `offset`.
+    fn page(&self, deleted: bool, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        let mut links = Vec::new();
+        let mut skipped = 0;
+        if limit == 0 {
+            return Ok(links);
+        }
+        self.scan(|_, link| {
+            match link.filter(|link| link.is_deleted() == deleted) {
+                Some(_) if skipped < offset => skipped += 1,
+                Some(link) => links.push(link),
+                None => {}
+            }
+            links.len() < limit
+        })?;
+        Ok(links)
+    }
+
+    // Calls `visit` with the id of every link in `list` order, and the link
+    // unless Redis dropped it, until it returns false.
+    fn scan(&self, mut visit: impl FnMut(u64, Option<Link>) -> bool) -> Result<()> {
+        let mut start = 0;
+        loop {
+            let stop = (start + PAGE - 1).to_string();
+            let members = self
+                .query(&["ZRANGE", LINKS_KEY, &start.to_string(), &stop])?
+                .into_strings()?;
+            if members.is_empty() {
+                return Ok(());
+            }
+            let keys: Vec<String> = members
+                .iter()
+                .map(|member| format!("{LINK_PREFIX}{member}"))
+                .collect();
+            let mut args = vec!["MGET"];
+            args.extend(keys.iter().map(String::as_str));
+            let values = self.query(&args)?.into_array()?;
+            for (member, value) in members.iter().zip(values) {
+                let id = member.parse().map_err(|_| {
+                    UrlManagerError::backend(format!("invalid id in links: {member}"))
+                })?;
+                let link = value
+                    .into_string()?
+                    .map(|value| parse_link(&value))
+                    .transpose()?;
+                if !visit(id, link) {
+                    return Ok(());
+                }
+            }
+            if members.len() < PAGE {
+                return Ok(());
+            }
+            start += PAGE;
+        }
+    }
+}
+
+impl LinkStore for RedisLinkStore {
+    fn get(&self, id: u64) -> Result<Option<Link>> {
+        self.query(&["GET", &link_key(id)])?
+            .into_string()?
+            .map(|value| parse_link(&value))
+            .transpose()
+    }
+
+    fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        let key = shortcut_key(namespace, shortcut);
+        let Some(id) = self.query(&["GET", &key])?.into_string()? else {
+            return Ok(None);
+        };
+        let id = id
+            .parse()
+            .map_err(|_| UrlManagerError::backend(format!("invalid id under {key}: {id}")))?;
+        Ok(self
+            .get(id)?
+            .filter(|link| link.namespace() == namespace && link.shortcut() == Some(shortcut)))
+    }
+
+    fn create(&mut self, link: Link) -> Result<()> {
+        let key = link_key(link.id());
+        if self.query(&["EXISTS", &key])?.into_integer()? > 0 {
+            return Err(UrlManagerError::DuplicateId(link.id()));
+        }
+        let claimed = self.claim(&link)?;
+        if !self.put(&link, "NX")? {
+            // created by another node since
+            if let Some(shortcut) = link.shortcut().filter(|_| claimed) {
+                self.release(link.namespace(), shortcut)?;
+            }
+            return Err(UrlManagerError::DuplicateId(link.id()));
+        }
+        let score = unix_millis(link.created_at()).to_string();
+        self.query(&["ZADD", LINKS_KEY, &score, &member(link.id())])?;
+        Ok(())
+    }
+
+    fn update(&mut self, id: u64, link: Link) -> Result<()> {
+        let previous = self.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        let link = link.updated_from(&previous);
+        self.claim(&link)?;
+        if !self.put(&link, "XX")? {
+            return Err(UrlManagerError::NotFound);
+        }
+        if let Some(shortcut) = previous.shortcut() {
+            if (link.namespace(), link.shortcut()) != (previous.namespace(), Some(shortcut)) {
+                self.release(previous.namespace(), shortcut)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, id: u64) -> Result<()> {
+        let link = self.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        if let Some(shortcut) = link.shortcut() {
+            self.release(link.namespace(), shortcut)?;
+        }
+        self.query(&["DEL", &link_key(id)])?;
+        self.query(&["ZREM", LINKS_KEY, &member(id)])?;
+        Ok(())
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.page(false, offset, limit)
+    }
+
+    fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.page(true, offset, limit)
+    }
+
+    fn count(&self) -> Result<usize> {
+        let mut count = 0;
+        self.scan(|_, link| {
+            count += usize::from(link.is_some_and(|link| !link.is_deleted()));
+            true
+        })?;
+        Ok(count)
+    }
+
+    fn purge_expired(&mut self) -> Result<usize> {
+        let mut expired = Vec::new();
+        let mut dropped = Vec::new();
+        self.scan(|id, link| {
+            match link {
+                Some(link) if link.is_expired() || link.is_exhausted() => expired.push(id),
+                Some(_) => {}
+                // dropped by Redis, which leaves the sorted set alone
+                None => dropped.push(member(id)),
+            }
+            true
+        })?;
+        for &id in &expired {
+            match self.delete(id) {
+                Ok(()) | Err(UrlManagerError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if !dropped.is_empty() {
+            let mut args = vec!["ZREM", LINKS_KEY];
+            args.extend(dropped.iter().map(String::as_str));
+            self.query(&args)?;
+        }
+        Ok(expired.len())
+    }
+
+    fn find(&self, query: &LinkQuery) -> Result<Vec<Link>> {
+        let mut links = Vec::new();
+        self.scan(|_, link| {
+            links.extend(link.filter(|link| query.matches(link)));
+            true
+        })?;
+        Ok(links)
+    }
+
+    // Writes back just the link, as its shortcut stays.
+    fn record_hit_with_password_in(
+        &mut self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        let mut link = self.resolve_with_password_in(namespace, shortcut, password)?;
+        link.hit();
+        if !self.put(&link, "XX")? {
+            return Err(UrlManagerError::NotFound);
+        }
+        Ok(link)
+    }
+
+    fn record_resolved_hit(&mut self, link: &Link) -> Result<Link> {
+        let mut current = self.get(link.id())?.ok_or(UrlManagerError::NotFound)?;
+        current.check_access_as(link)?;
+        current.hit();
+        if !self.put(&current, "XX")? {
+            return Err(UrlManagerError::NotFound);
+        }
+        Ok(current)
+    }
+
+    fn record_variant_hit(&mut self, id: u64, variant: usize) -> Result<Link> {
+        let mut link = self.get(id)?.ok_or(UrlManagerError::NotFound)?;
+        link.hit_variant(variant)?;
+        if !self.put(&link, "XX")? {
+            return Err(UrlManagerError::NotFound);
+        }
+        Ok(link.resolved_to(variant))
+    }
+
+    fn ping(&self) -> Result<()> {
+        self.query(&["PING"]).map(drop)
+    }
+}
+
+fn link_key(id: u64) -> String {
+    format!("{LINK_PREFIX}{}", member(id))
+}
+
+fn member(id: u64) -> String {
+    format!("{id:020}")
+}
+
+fn shortcut_key(namespace: Option<&Namespace>, shortcut: &str) -> String {
+    let namespace = namespace.map_or("", Namespace::as_str);
+    format!("{SHORTCUT_PREFIX}{namespace}:{shortcut}")
+}
+
+fn parse_link(value: &str) -> Result<Link> {
+    Link::from_json(&json::parse(value).map_err(UrlManagerError::backend)?)
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: BufReader<TcpStream>,
+}
+
+impl Connection {
+    fn open(addrs: &[SocketAddr]) -> io::Result<Self> {
+        let stream = TcpStream::connect(addrs)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream: BufReader::new(stream),
+        })
+    }
+
+    fn query(&mut self, args: &[&str]) -> io::Result<Reply> {
+        let mut command = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            command.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            command.extend_from_slice(arg.as_bytes());
+            command.extend_from_slice(b"\r\n");
+        }
+        self.stream.get_mut().write_all(&command)?;
+        Reply::read(&mut self.stream)
+    }
+}
+
+// A RESP2 reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(String),
+    Array(Vec<Reply>),
+    Nil,
+}
+
+impl Reply {
+    fn read(reader: &mut impl BufRead) -> io::Result<Reply> {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line
+            .strip_suffix("\r\n")
+            .ok_or_else(|| invalid(format!("unterminated reply {line:?}")))?;
+        let Some(kind) = line.chars().next() else {
+            return Err(invalid("empty reply".to_string()));
+        };
+        let rest = &line[1..];
+        let length = || {
+            rest.parse::<i64>()
+                .map_err(|_| invalid(format!("invalid length in {line:?}")))
+        };
+        match kind {
+            '+' => Ok(Reply::Status(rest.to_string())),
+            '-' => Ok(Reply::Error(rest.to_string())),
+            ':' => Ok(Reply::Integer(length()?)),
+            '$' => match usize::try_from(length()?) {
+                Err(_) => Ok(Reply::Nil),
+                Ok(length) if length > MAX_BULK_LENGTH => {
+                    Err(invalid(format!("{length} byte reply")))
+                }
+                Ok(length) => {
+                    let mut bulk = vec![0; length + 2];
+                    reader.read_exact(&mut bulk)?;
+                    if !bulk.ends_with(b"\r\n") {
+                        return Err(invalid("unterminated bulk string".to_string()));
+                    }
+                    bulk.truncate(length);
+                    String::from_utf8(bulk)
+                        .map(Reply::Bulk)
+                        .map_err(|e| invalid(e.to_string()))
+                }
+            },
+            '*' => match usize::try_from(length()?) {
+                Err(_) => Ok(Reply::Nil),
+                Ok(length) => (0..length)
+                    .map(|_| Reply::read(reader))
+                    .collect::<io::Result<_>>()
+                    .map(Reply::Array),
+            },
+            _ => Err(invalid(format!("unknown reply {line:?}"))),
+        }
+    }
+
+    fn into_string(self) -> Result<Option<String>> {
+        match self {
+            Reply::Bulk(value) => Ok(Some(value)),
+            Reply::Nil => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn into_integer(self) -> Result<i64> {
+        match self {
+            Reply::Integer(value) => Ok(value),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn into_array(self) -> Result<Vec<Reply>> {
+        match self {
+            Reply::Array(values) => Ok(values),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn into_strings(self) -> Result<Vec<String>> {
+        self.into_array()?
+            .into_iter()
+            .map(|value| value.into_string()?.ok_or_else(|| unexpected(Reply::Nil)))
+            .collect()
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn unexpected(reply: Reply) -> UrlManagerError {
+    UrlManagerError::backend(format!("unexpected reply from Redis: {reply:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::link::now;
+
+    // Just enough of Redis for the store, with keys expiring as they are
+    // read.
+    #[derive(Debug, Default)]
+    struct FakeRedis {
+        // values and the unix milliseconds they expire at
+        strings: HashMap<String, (String, Option<u64>)>,
+        // members of `links` and their scores
+        links: BTreeMap<String, u64>,
+    }
+
+    impl FakeRedis {
+        fn value(&mut self, key: &str) -> Option<String> {
+            let now = unix_millis(now());
+            if self.expire_at(key).is_some_and(|at| at <= now) {
+                self.strings.remove(key);
+            }
+            self.strings.get(key).map(|(value, _)| value.clone())
+        }
+
+        fn expire_at(&self, key: &str) -> Option<u64> {
+            self.strings.get(key).and_then(|&(_, at)| at)
+        }
+
+        fn execute(&mut self, args: &[String]) -> Reply {
+            let ok = || Reply::Status("OK".to_string());
+            let bulk = |value: Option<String>| value.map_or(Reply::Nil, Reply::Bulk);
+            match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+                ["PING"] => Reply::Status("PONG".to_string()),
+                ["GET", key] => bulk(self.value(key)),
+                ["EXISTS", key] => Reply::Integer(self.value(key).is_some().into()),
+                ["MGET", ref keys @ ..] => {
+                    Reply::Array(keys.iter().map(|key| bulk(self.value(key))).collect())
+                }
+                ["SET", key, value, ref options @ ..] => {
+                    let exists = self.value(key).is_some();
+                    let expire_at = match options {
+                        [.., "PXAT", at] => Some(at.parse().unwrap()),
+                        _ => None,
+                    };
+                    match options.first() {
+                        Some(&"NX") if exists => return Reply::Nil,
+                        Some(&"XX") if !exists => return Reply::Nil,
+                        _ => {}
+                    }
+                    self.strings
+                        .insert(key.to_string(), (value.to_string(), expire_at));
+                    ok()
+                }
+                ["DEL", ref keys @ ..] => {
+                    let deleted = keys.iter().filter(|key| self.value(key).is_some()).count();
+                    for key in keys {
+                        self.strings.remove(*key);
+                    }
+                    Reply::Integer(deleted as i64)
+                }
+                ["ZADD", LINKS_KEY, score, member] => {
+                    let added = self
+                        .links
+                        .insert(member.to_string(), score.parse().unwrap());
+                    Reply::Integer(added.is_none().into())
+                }
+                ["ZREM", LINKS_KEY, ref members @ ..] => Reply::Integer(
+                    members
+                        .iter()
+                        .filter(|member| self.links.remove(**member).is_some())
+                        .count() as i64,
+                ),
+                ["ZRANGE", LINKS_KEY, start, stop] => {
+                    let mut members: Vec<(u64, String)> = self
+                        .links
+                        .iter()
+                        .map(|(member, &score)| (score, member.clone()))
+                        .collect();
+                    members.sort();
+                    let (start, stop): (usize, usize) =
+                        (start.parse().unwrap(), stop.parse().unwrap());
+                    Reply::Array(
+                        members
+                            .into_iter()
+                            .skip(start)
+                            .take(stop + 1 - start)
+                            .map(|(_, member)| Reply::Bulk(member))
+                            .collect(),
+                    )
+                }
+                _ => Reply::Error(format!("ERR unknown command {args:?}")),
+            }
+        }
+    }
+
+    fn write_reply(out: &mut Vec<u8>, reply: &Reply) {
+        match reply {
+            Reply::Status(status) => out.extend_from_slice(format!("+{status}\r\n").as_bytes()),
+            Reply::Error(error) => out.extend_from_slice(format!("-{error}\r\n").as_bytes()),
+            Reply::Integer(n) => out.extend_from_slice(format!(":{n}\r\n").as_bytes()),
+            Reply::Bulk(value) => {
+                out.extend_from_slice(format!("${}\r\n{value}\r\n", value.len()).as_bytes())
+            }
+            Reply::Array(values) => {
+                out.extend_from_slice(format!("*{}\r\n", values.len()).as_bytes());
+                for value in values {
+                    write_reply(out, value);
+                }
+            }
+            Reply::Nil => out.extend_from_slice(b"$-1\r\n"),
+        }
+    }
+
+    // Serves a `FakeRedis` one connection at a time.
+    fn fake_redis() -> (SocketAddr, Arc<Mutex<FakeRedis>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let redis = Arc::new(Mutex::new(FakeRedis::default()));
+        let served = redis.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = BufReader::new(stream.unwrap());
+                while let Ok(Reply::Array(args)) = Reply::read(&mut stream) {
+                    let args: Vec<String> = args
+                        .into_iter()
+                        .map(|arg| arg.into_string().unwrap().unwrap())
+                        .collect();
+                    let mut out = Vec::new();
+                    write_reply(&mut out, &sync::lock(&served).execute(&args));
+                    if stream.get_mut().write_all(&out).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        (addr, redis)
+    }
+
+    fn fake_store() -> RedisLinkStore {
+        RedisLinkStore::connect(fake_redis().0).unwrap()
+    }
+
+    #[cfg(feature = "testing")]
+    crate::linkstore_conformance!(conformance, fake_store());
+
+    fn link(slug: &str) -> Link {
+        Link::builder()
+            .target(format!("https://example.com/{slug}").as_str())
+            .slug(slug)
+            .expires_in(Duration::from_secs(60))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_expiry() {
+        let (addr, redis) = fake_redis();
+        let mut store = RedisLinkStore::connect(addr)
+            .unwrap()
+            .keep_expired(Duration::from_secs(60));
+        let docs = link("docs");
+        store.create(docs.clone()).unwrap();
+        let drop_at = unix_millis(docs.expires_at().unwrap()) + 60_000;
+        let key = shortcut_key(None, "docs");
+        for key in [link_key(docs.id()), key.clone()] {
+            assert_eq!(sync::lock(&redis).expire_at(&key), Some(drop_at));
+        }
+
+        // trashing keeps the link until it is purged
+        store.soft_delete(docs.id()).unwrap();
+        assert_eq!(sync::lock(&redis).expire_at(&key), None);
+        store.restore(docs.id()).unwrap();
+
+        store.update_with(docs.id(), Link::expire).unwrap();
+        assert!(matches!(
+            store.record_hit("docs"),
+            Err(UrlManagerError::Expired)
+        ));
+        let mut store = store.keep_expired(Duration::ZERO);
+        let blog = link("blog");
+        store.create(blog.clone()).unwrap();
+        let mut expired = blog.clone();
+        expired.expire();
+        store.update(blog.id(), expired).unwrap();
+        // dropped by Redis, and left in `links` until purged
+        assert!(store.get(blog.id()).unwrap().is_none());
+        assert!(store.resolve("blog").is_err());
+        assert_eq!(sync::lock(&redis).links.len(), 2);
+        assert_eq!(store.purge_expired().unwrap(), 1);
+        assert!(sync::lock(&redis).links.is_empty());
+        assert_eq!(store.count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_reply() {
+        let mut replies: &[u8] = b"*3\r\n$5\r\nhello\r\n$-1\r\n:42\r\n-ERR wrong type\r\n$3\r\nabc";
+        assert_eq!(
+            Reply::read(&mut replies).unwrap(),
+            Reply::Array(vec![
+                Reply::Bulk("hello".to_string()),
+                Reply::Nil,
+                Reply::Integer(42)
+            ])
+        );
+        assert_eq!(
+            Reply::read(&mut replies).unwrap(),
+            Reply::Error("ERR wrong type".to_string())
+        );
+        assert!(Reply::read(&mut replies).is_err());
+
+        let store = fake_store();
+        assert!(matches!(
+            store.query(&["FLUSHALL"]),
+            Err(UrlManagerError::StorageBackend(_))
+        ));
+        store.ping().unwrap();
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{LinkQuery, LinkStore, Revision};
+use crate::strategy::fnv1a;
+use crate::{Link, Namespace, Result, UrlManagerError};
+
+// Points each shard takes on the ring; more even out the shares.
+const POINTS: u32 = 64;
+
+/// A [`LinkStore`] partitioning links across inner stores by a hash of
+/// their shortcut, for deployments outgrowing a single node.
+///
+/// Shards sit on a consistent-hash ring by name, so adding one takes about
+/// its share of the links off the others and leaves the rest where they
+/// are. [`ShardedLinkStore::add_shard`] doesn't move anything itself; links
+/// keep resolving from where they are until [`ShardedLinkStore::rebalance`]
+/// moves them to their new shard, though shortcuts then route to the new
+/// one, so run it right after adding.
+///
+/// Resolving and counting hits go to the one shard owning the shortcut,
+/// which also keeps shortcuts unique. Links without a shortcut are placed
+/// by id. Lookups by id ask every shard, and `list`, `count` and `find`
+/// merge what all of them hold.
+///
+/// ```
+/// # use url_manager::{InMemoryLinkStore, LinkStore, ShardedLinkStore};
+/// let mut store = ShardedLinkStore::new("a", InMemoryLinkStore::new());
+/// let link = store.create_with_slug("docs", "https://example.com/docs".parse()?)?;
+/// store.add_shard("b", InMemoryLinkStore::new())?;
+/// store.rebalance()?;
+/// assert_eq!(store.resolve("docs")?.id(), link.id());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct ShardedLinkStore<S> {
+    shards: Vec<Shard<S>>,
+    // points on the ring and the index of the shard owning them
+    ring: BTreeMap<u64, usize>,
+}
+
+struct Shard<S> {
+    name: String,
+    store: S,
+}
+
+impl<S: fmt::Debug> fmt::Debug for ShardedLinkStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.shards.iter().map(|shard| (&shard.name, &shard.store)))
+            .finish()
+    }
+}
+
+impl<S: LinkStore> ShardedLinkStore<S> {
+    /// A store with the one shard `store`, named `name`.
+    pub fn new(name: impl Into<String>, store: S) -> Self {
+        let mut sharded = ShardedLinkStore {
+            shards: Vec::new(),
+            ring: BTreeMap::new(),
+        };
+        sharded.push(name.into(), store);
+        sharded
+    }
+
+    /// Adds `store` as a shard named `name`, failing with `InvalidConfig` if
+    /// the name is taken. Call [`ShardedLinkStore::rebalance`] next.
+    ///
+    /// Placement depends on names alone, so shards have to keep theirs
+    /// across restarts, and be added again under the same ones.
+    pub fn add_shard(&mut self, name: impl Into<String>, store: S) -> Result<()> {
+        let name = name.into();
+        if self.shards.iter().any(|shard| shard.name == name) {
+            return Err(UrlManagerError::InvalidConfig(format!(
+                "shard '{name}' already exists"
+            )));
+        }
+        self.push(name, store);
+        Ok(())
+    }
+
+    /// Moves every link, trashed ones included, to the shard it belongs on,
+    /// returning how many were moved. Each is created on its new shard
+    /// before it is deleted from the old one, so an interrupted rebalance
+    /// can be run again.
+    pub fn rebalance(&mut self) -> Result<usize> {
+        let mut moved = 0;
+        for from in 0..self.shards.len() {
+            let store = &self.shards[from].store;
+            let mut links = store.iter().collect::<Result<Vec<_>>>()?;
+            links.extend(store.find(&LinkQuery::new().deleted())?);
+            for link in links {
+                let to = self.route(&link);
+                if to == from {
+                    continue;
+                }
+                let id = link.id();
+                match self.shards[to].store.create(link) {
+                    // already copied by an interrupted rebalance
+                    Ok(()) | Err(UrlManagerError::DuplicateId(_)) => {}
+                    Err(e) => return Err(e),
+                }
+                self.shards[from].store.delete(id)?;
+                moved += 1;
+            }
+        }
+        Ok(moved)
+    }
+
+    /// The name of the shard `link` belongs on.
+    pub fn shard_of(&self, link: &Link) -> &str {
+        &self.shards[self.route(link)].name
+    }
+
+    /// The shards with their names, in the order they were added.
+    pub fn shards(&self) -> impl Iterator<Item = (&str, &S)> {
+        self.shards
+            .iter()
+            .map(|shard| (shard.name.as_str(), &shard.store))
+    }
+
+    fn push(&mut self, name: String, store: S) {
+        let index = self.shards.len();
+        for point in 0..POINTS {
+            self.ring.insert(hash(&format!("{name}#{point}")), index);
+        }
+        self.shards.push(Shard { name, store });
+    }
+
+    // The shard owning the first point on the ring at or after `key`'s.
+    fn owner(&self, key: &str) -> usize {
+        let hash = hash(key);
+        let (_, &index) = self
+            .ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .expect("a sharded store has a shard");
+        index
+    }
+
+    fn route(&self, link: &Link) -> usize {
+        match link.shortcut() {
+            Some(shortcut) => self.owner(&shortcut_key(link.namespace(), shortcut)),
+            None => self.owner(&format!("#{}", link.id())),
+        }
+    }
+
+    fn shortcut_shard(&self, namespace: Option<&Namespace>, shortcut: &str) -> &S {
+        &self.shards[self.owner(&shortcut_key(namespace, shortcut))].store
+    }
+
+    // The shard holding the link stored under `id`, and the link.
+    fn locate(&self, id: u64) -> Result<Option<(usize, Link)>> {
+        for (index, shard) in self.shards.iter().enumerate() {
+            if let Some(link) = shard.store.get(id)? {
+                return Ok(Some((index, link)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<S: LinkStore> LinkStore for ShardedLinkStore<S> {
+    fn get(&self, id: u64) -> Result<Option<Link>> {
+        Ok(self.locate(id)?.map(|(_, link)| link))
+    }
+
+    fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        self.shortcut_shard(namespace, shortcut)
+            .get_by_shortcut_in(namespace, shortcut)
+    }
+
+    fn create(&mut self, link: Link) -> Result<()> {
+        if self.locate(link.id())?.is_some() {
+            return Err(UrlManagerError::DuplicateId(link.id()));
+        }
+        let to = self.route(&link);
+        self.shards[to].store.create(link)
+    }
+
+    fn update(&mut self, id: u64, link: Link) -> Result<()> {
+        let (from, previous) = self.locate(id)?.ok_or(UrlManagerError::NotFound)?;
+        let to = self.route(&link);
+        if from == to {
+            return self.shards[from].store.update(id, link);
+        }
+        // a new shortcut can belong on another shard
+        self.shards[to].store.create(link.updated_from(&previous))?;
+        self.shards[from].store.delete(id)
+    }
+
+    fn update_if_version(&mut self, id: u64, version: u64, link: Link) -> Result<()> {
+        let (from, previous) = self.locate(id)?.ok_or(UrlManagerError::NotFound)?;
+        if self.route(&link) == from {
+            return self.shards[from].store.update_if_version(id, version, link);
+        }
+        if previous.version() != version {
+            return Err(UrlManagerError::Conflict(previous.version()));
+        }
+        self.update(id, link)
+    }
+
+    fn delete(&mut self, id: u64) -> Result<()> {
+        let (from, _) = self.locate(id)?.ok_or(UrlManagerError::NotFound)?;
+        self.shards[from].store.delete(id)
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        let mut links = Vec::new();
+        for shard in &self.shards {
+            links.extend(shard.store.list(0, offset.saturating_add(limit))?);
+        }
+        links.sort_by_key(|link| (link.created_at(), link.id()));
+        Ok(links.into_iter().skip(offset).take(limit).collect())
+    }
+
+    fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        let mut links = Vec::new();
+        for shard in &self.shards {
+            links.extend(shard.store.list_deleted(0, offset.saturating_add(limit))?);
+        }
+        links.sort_by_key(|link| (link.created_at(), link.id()));
+        Ok(links.into_iter().skip(offset).take(limit).collect())
+    }
+
+    fn count(&self) -> Result<usize> {
+        self.shards.iter().map(|shard| shard.store.count()).sum()
+    }
+
+    fn purge_expired(&mut self) -> Result<usize> {
+        self.shards
+            .iter_mut()
+            .map(|shard| shard.store.purge_expired())
+            .sum()
+    }
+
+    fn find(&self, query: &LinkQuery) -> Result<Vec<Link>> {
+        let mut links = Vec::new();
+        for shard in &self.shards {
+            links.extend(shard.store.find(query)?);
+        }
+        links.sort_by_key(|link| (link.created_at(), link.id()));
+        Ok(links)
+    }
+
+    fn record_hit_with_password_in(
+        &mut self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        let index = self.owner(&shortcut_key(namespace, shortcut));
+        self.shards[index]
+            .store
+            .record_hit_with_password_in(namespace, shortcut, password)
+    }
+
+    fn record_resolved_hit(&mut self, link: &Link) -> Result<Link> {
+        let (from, _) = self.locate(link.id())?.ok_or(UrlManagerError::NotFound)?;
+        self.shards[from].store.record_resolved_hit(link)
+    }
+
+    fn record_variant_hit(&mut self, id: u64, variant: usize) -> Result<Link> {
+        let (from, _) = self.locate(id)?.ok_or(UrlManagerError::NotFound)?;
+        self.shards[from].store.record_variant_hit(id, variant)
+    }
+
+    fn history(&self, id: u64) -> Result<Vec<Revision>> {
+        let (from, _) = self.locate(id)?.ok_or(UrlManagerError::NotFound)?;
+        self.shards[from].store.history(id)
+    }
+}
+
+fn shortcut_key(namespace: Option<&Namespace>, shortcut: &str) -> String {
+    format!("{}/{shortcut}", namespace.map_or("", Namespace::as_str))
+}
+
+// FNV-1a spreads keys differing only in their last bytes, like the points
+// of a shard, poorly; the splitmix64 finalizer scatters them over the ring.
+fn hash(key: &str) -> u64 {
+    let mut hash = fnv1a(key.as_bytes());
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryLinkStore, UrlType};
+
+    fn counts(store: &ShardedLinkStore<InMemoryLinkStore>) -> Vec<usize> {
+        store
+            .shards()
+            .map(|(_, shard)| shard.count().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_rebalance() {
+        let mut store = ShardedLinkStore::new("a", InMemoryLinkStore::new());
+        let target = UrlType::parse("https://example.com/").unwrap();
+        for n in 0..300 {
+            store
+                .create_with_slug(&format!("link-{n}"), target.clone())
+                .unwrap();
+        }
+        let trashed = store.resolve("link-0").unwrap();
+        store.soft_delete(trashed.id()).unwrap();
+        store.add_shard("b", InMemoryLinkStore::new()).unwrap();
+        store.add_shard("c", InMemoryLinkStore::new()).unwrap();
+        assert!(store.add_shard("c", InMemoryLinkStore::new()).is_err());
+
+        let moved = store.rebalance().unwrap();
+        assert!((100..250).contains(&moved), "moved {moved}");
+        assert_eq!(store.rebalance().unwrap(), 0);
+        let before = counts(&store);
+        assert!(before.iter().all(|&count| count > 40), "{before:?}");
+
+        // a new shard only takes links from the others
+        store.add_shard("d", InMemoryLinkStore::new()).unwrap();
+        store.rebalance().unwrap();
+        let after = counts(&store);
+        assert!(after[..3].iter().zip(&before).all(|(a, b)| a <= b));
+        assert_eq!(after.iter().sum::<usize>(), 299);
+
+        for n in 1..300 {
+            let link = store.resolve(&format!("link-{n}")).unwrap();
+            let (_, shard) = store
+                .shards()
+                .find(|(name, _)| *name == store.shard_of(&link))
+                .unwrap();
+            assert!(shard.get(link.id()).unwrap().is_some());
+        }
+        assert!(store.get(trashed.id()).unwrap().unwrap().is_deleted());
+        assert_eq!(store.list(290, 100).unwrap().len(), 9);
+    }
+}
@@ -0,0 +1,437 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use super::{LinkQuery, LinkStore, Revision};
+use crate::{sync, Link, Namespace, Result};
+
+/// A [`LinkStore`] writing to a primary store and reading from replicas and
+/// caches in front of it, e.g. a Redis cache in front of Postgres.
+///
+/// Tiers are asked in the order they were added. A tier that fails or
+/// doesn't have the link is skipped, so lookups fall back to the next tier
+/// and finally to the primary; only the primary's errors are returned.
+///
+/// - A [replica](TieredLinkStore::replica) is kept current by the backend
+///   itself, e.g. a database read replica, and is never written to. It also
+///   answers `list`, `find` and `count`.
+/// - A [cache](TieredLinkStore::cache) only answers `get` and
+///   `get_by_shortcut_in`, and so the `resolve` family. Links read from a
+///   later tier are copied into it, and every write through this store is
+///   copied to it once the primary has taken it.
+///
+/// Counting hits and every other write goes to the primary alone, which can
+/// do it atomically. A cache that can't take a copy drops the link instead.
+///
+/// ```
+/// # use url_manager::{InMemoryLinkStore, LinkStore, TieredLinkStore};
+/// let mut store = TieredLinkStore::new(InMemoryLinkStore::new()).cache(InMemoryLinkStore::new());
+/// let link = store.create_with_slug("docs", "https://example.com/docs".parse()?)?;
+/// assert_eq!(store.resolve("docs")?.id(), link.id());
+/// assert_eq!(store.record_hit("docs")?.hit_count(), 1);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct TieredLinkStore<P> {
+    primary: P,
+    tiers: Vec<Tier>,
+}
+
+struct Tier {
+    store: Mutex<Box<dyn LinkStore + Send>>,
+    cache: bool,
+}
+
+impl<P: fmt::Debug> fmt::Debug for TieredLinkStore<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let caches = self.tiers.iter().filter(|tier| tier.cache).count();
+        f.debug_struct("TieredLinkStore")
+            .field("primary", &self.primary)
+            .field("replicas", &(self.tiers.len() - caches))
+            .field("caches", &caches)
+            .finish()
+    }
+}
+
+impl<P: LinkStore> TieredLinkStore<P> {
+    /// Reads and writes `primary` until tiers are added.
+    pub fn new(primary: P) -> Self {
+        TieredLinkStore {
+            primary,
+            tiers: Vec::new(),
+        }
+    }
+
+    /// Reads from `replica` before the tiers added after it and the primary.
+    pub fn replica(mut self, replica: impl LinkStore + Send + 'static) -> Self {
+        self.tiers.push(Tier {
+            store: Mutex::new(Box::new(replica)),
+            cache: false,
+        });
+        self
+    }
+
+    /// Reads from `cache` before the tiers added after it and the primary,
+    /// and keeps it filled.
+    pub fn cache(mut self, cache: impl LinkStore + Send + 'static) -> Self {
+        self.tiers.push(Tier {
+            store: Mutex::new(Box::new(cache)),
+            cache: true,
+        });
+        self
+    }
+
+    pub fn get_ref(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn into_inner(self) -> P {
+        self.primary
+    }
+
+    fn caches(&self) -> impl Iterator<Item = &Tier> {
+        self.tiers.iter().filter(|tier| tier.cache)
+    }
+
+    fn lookup(
+        &self,
+        find: impl Fn(&dyn LinkStore) -> Result<Option<Link>>,
+    ) -> Result<Option<Link>> {
+        let mut missed = Vec::new();
+        for tier in &self.tiers {
+            let found = find(&**sync::lock(&tier.store));
+            if let Ok(Some(link)) = found {
+                fill(missed, &link);
+                return Ok(Some(link));
+            }
+            if tier.cache {
+                missed.push(tier);
+            }
+        }
+        let link = find(&self.primary)?;
+        if let Some(link) = &link {
+            fill(missed, link);
+        }
+        Ok(link)
+    }
+
+    fn query<T>(&self, query: impl Fn(&dyn LinkStore) -> Result<T>) -> Result<T> {
+        for tier in self.tiers.iter().filter(|tier| !tier.cache) {
+            if let Ok(result) = query(&**sync::lock(&tier.store)) {
+                return Ok(result);
+            }
+        }
+        query(&self.primary)
+    }
+
+    /// Copies the link now stored under `id` to the caches, or drops it
+    /// from them if it is gone.
+    fn refresh(&self, id: u64) {
+        match self.primary.get(id) {
+            Ok(Some(link)) => fill(self.caches(), &link),
+            _ => self.evict(id),
+        }
+    }
+
+    fn evict(&self, id: u64) {
+        for tier in self.caches() {
+            // most caches won't have it
+            let _ = sync::lock(&tier.store).delete(id);
+        }
+    }
+
+    fn written<T>(&self, id: u64, result: Result<T>) -> Result<T> {
+        match &result {
+            Ok(_) => self.refresh(id),
+            // the primary may have changed the link before failing
+            Err(_) => self.evict(id),
+        }
+        result
+    }
+}
+
+fn fill<'a>(caches: impl IntoIterator<Item = &'a Tier>, link: &Link) {
+    for tier in caches {
+        let mut cache = sync::lock(&tier.store);
+        if cache.upsert(link.clone()).is_err() {
+            let _ = cache.delete(link.id());
+        }
+    }
+}
+
+impl<P: LinkStore> LinkStore for TieredLinkStore<P> {
+    fn get(&self, id: u64) -> Result<Option<Link>> {
+        self.lookup(|store| store.get(id))
+    }
+
+    fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        self.lookup(|store| store.get_by_shortcut_in(namespace, shortcut))
+    }
+
+    fn create(&mut self, link: Link) -> Result<()> {
+        let id = link.id();
+        let result = self.primary.create(link);
+        if result.is_ok() {
+            self.refresh(id);
+        }
+        result
+    }
+
+    fn create_batch(&mut self, links: Vec<Link>) -> Vec<Result<Link>> {
+        let results = self.primary.create_batch(links);
+        for link in results.iter().flatten() {
+            self.refresh(link.id());
+        }
+        results
+    }
+
+    fn upsert(&mut self, link: Link) -> Result<()> {
+        let id = link.id();
+        let result = self.primary.upsert(link);
+        self.written(id, result)
+    }
+
+    fn update(&mut self, id: u64, link: Link) -> Result<()> {
+        let result = self.primary.update(id, link);
+        self.written(id, result)
+    }
+
+    fn update_if_version(&mut self, id: u64, version: u64, link: Link) -> Result<()> {
+        let result = self.primary.update_if_version(id, version, link);
+        self.written(id, result)
+    }
+
+    fn update_with(&mut self, id: u64, change: impl FnOnce(&mut Link)) -> Result<Link> {
+        let result = self.primary.update_with(id, change);
+        self.written(id, result)
+    }
+
+    fn record_hit_with_password_in(
+        &mut self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        let result = self
+            .primary
+            .record_hit_with_password_in(namespace, shortcut, password);
+        if let Ok(link) = &result {
+            fill(self.caches(), link);
+        }
+        result
+    }
+
+    fn record_resolved_hit(&mut self, link: &Link) -> Result<Link> {
+        let result = self.primary.record_resolved_hit(link);
+        if let Ok(link) = &result {
+            fill(self.caches(), link);
+        }
+        result
+    }
+
+    fn record_variant_hit(&mut self, id: u64, variant: usize) -> Result<Link> {
+        // returns the link pointing at the variant, not the stored one
+        let result = self.primary.record_variant_hit(id, variant);
+        self.written(id, result)
+    }
+
+    fn delete(&mut self, id: u64) -> Result<()> {
+        let result = self.primary.delete(id);
+        self.evict(id);
+        result
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.query(|store| store.list(offset, limit))
+    }
+
+    fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.query(|store| store.list_deleted(offset, limit))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Link>> + '_> {
+        self.primary.iter()
+    }
+
+    fn find(&self, query: &LinkQuery) -> Result<Vec<Link>> {
+        self.query(|store| store.find(query))
+    }
+
+    fn count(&self) -> Result<usize> {
+        self.query(|store| store.count())
+    }
+
+    fn ping(&self) -> Result<()> {
+        self.primary.ping()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for tier in &self.tiers {
+            if let Err(e) = sync::lock(&tier.store).close() {
+                result = Err(e);
+            }
+        }
+        self.primary.close().and(result)
+    }
+
+    fn purge_expired(&mut self) -> Result<usize> {
+        for tier in self.caches() {
+            let _ = sync::lock(&tier.store).purge_expired();
+        }
+        self.primary.purge_expired()
+    }
+
+    fn history(&self, id: u64) -> Result<Vec<Revision>> {
+        self.primary.history(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryLinkStore, UrlManagerError, UrlType};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // A store the test keeps a handle to, which can be taken down.
+    #[derive(Clone, Default)]
+    struct Shared {
+        store: Arc<Mutex<InMemoryLinkStore>>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl Shared {
+        fn with<T>(&self, f: impl FnOnce(&mut InMemoryLinkStore) -> Result<T>) -> Result<T> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(UrlManagerError::backend("down"));
+            }
+            f(&mut sync::lock(&self.store))
+        }
+    }
+
+    impl LinkStore for Shared {
+        fn get(&self, id: u64) -> Result<Option<Link>> {
+            self.with(|store| store.get(id))
+        }
+
+        fn get_by_shortcut_in(
+            &self,
+            namespace: Option<&Namespace>,
+            shortcut: &str,
+        ) -> Result<Option<Link>> {
+            self.with(|store| store.get_by_shortcut_in(namespace, shortcut))
+        }
+
+        fn create(&mut self, link: Link) -> Result<()> {
+            self.with(|store| store.create(link))
+        }
+
+        fn update(&mut self, id: u64, link: Link) -> Result<()> {
+            self.with(|store| store.update(id, link))
+        }
+
+        fn delete(&mut self, id: u64) -> Result<()> {
+            self.with(|store| store.delete(id))
+        }
+
+        fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+            self.with(|store| store.list(offset, limit))
+        }
+
+        fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+            self.with(|store| store.list_deleted(offset, limit))
+        }
+
+        fn count(&self) -> Result<usize> {
+            self.with(|store| store.count())
+        }
+
+        fn purge_expired(&mut self) -> Result<usize> {
+            self.with(|store| store.purge_expired())
+        }
+    }
+
+    fn link(slug: &str) -> Link {
+        Link::builder()
+            .target(UrlType::parse(&format!("https://example.com/{slug}")).unwrap())
+            .slug(slug)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_replica_fallback() {
+        let mut primary = InMemoryLinkStore::new();
+        let replica = Shared::default();
+        let docs = link("docs");
+        primary.create(docs.clone()).unwrap();
+        sync::lock(&replica.store).create(docs.clone()).unwrap();
+        let mut store = TieredLinkStore::new(primary).replica(replica.clone());
+
+        // not replicated yet
+        let blog = link("blog");
+        store.create(blog.clone()).unwrap();
+        assert!(replica.get(blog.id()).unwrap().is_none());
+        assert_eq!(store.resolve("blog").unwrap().id(), blog.id());
+        assert_eq!(store.count().unwrap(), 1);
+
+        // replicated with a lag
+        sync::lock(&replica.store)
+            .update_with(docs.id(), |link| {
+                link.add_tag("replica");
+            })
+            .unwrap();
+        assert!(store.resolve("docs").unwrap().has_tag("replica"));
+
+        replica.down.store(true, Ordering::SeqCst);
+        assert!(!store.resolve("docs").unwrap().has_tag("replica"));
+        assert_eq!(store.count().unwrap(), 2);
+        assert_eq!(store.record_hit("docs").unwrap().hit_count(), 1);
+    }
+
+    #[test]
+    fn test_cache_write_through() {
+        let mut primary = InMemoryLinkStore::new();
+        let docs = link("docs");
+        primary.create(docs.clone()).unwrap();
+        let cache = Shared::default();
+        let mut store = TieredLinkStore::new(primary).cache(cache.clone());
+
+        // filled on a miss
+        assert!(cache.get(docs.id()).unwrap().is_none());
+        store.resolve("docs").unwrap();
+        assert!(cache.get(docs.id()).unwrap().is_some());
+
+        let blog = link("blog");
+        store.create(blog.clone()).unwrap();
+        store.record_hit("blog").unwrap();
+        assert_eq!(cache.get(blog.id()).unwrap().unwrap().hit_count(), 1);
+        store
+            .update_with(blog.id(), |link| {
+                link.add_tag("changed");
+            })
+            .unwrap();
+        assert!(cache.get(blog.id()).unwrap().unwrap().has_tag("changed"));
+        // caches don't answer queries
+        assert_eq!(
+            store.find(&LinkQuery::new().tag("changed")).unwrap().len(),
+            1
+        );
+
+        store.soft_delete(docs.id()).unwrap();
+        assert!(matches!(
+            store.resolve("docs"),
+            Err(UrlManagerError::NotFound)
+        ));
+        store.delete(blog.id()).unwrap();
+        assert!(cache.get(blog.id()).unwrap().is_none());
+        assert!(store.get(blog.id()).unwrap().is_none());
+
+        cache.down.store(true, Ordering::SeqCst);
+        store.restore(docs.id()).unwrap();
+        assert_eq!(store.resolve("docs").unwrap().id(), docs.id());
+    }
+}
@@ -0,0 +1,54 @@
+use super::LinkStore;
+use crate::Result;
+
+/// A [`LinkStore`] that can make several changes atomically.
+///
+/// Changes made between [`Transactional::begin`] and
+/// [`Transactional::commit`] are kept together or, after
+/// [`Transactional::rollback`], not at all. Only one transaction can be open
+/// at a time; beginning another, or committing or rolling back without one,
+/// fails with `StorageBackend`. [`Transactional::transaction`] wraps a
+/// closure in one.
+///
+/// The in-process stores undo changes in memory and journal them as one
+/// record on commit, so a crash loses all of a transaction or none of it.
+/// They don't isolate transactions: reads see changes before their commit.
+///
+/// ```
+/// # use url_manager::{InMemoryLinkStore, LinkStore, Transactional, UrlManagerError};
+/// let mut store = InMemoryLinkStore::new();
+/// let docs = store.create_with_slug("docs", "https://example.com/docs".parse()?)?;
+/// let result = store.transaction(|store| {
+///     store.delete(docs.id())?;
+///     store.create_with_slug("docs", "https://example.org/docs".parse()?)?;
+///     Err::<(), _>(UrlManagerError::Forbidden("changed my mind".to_string()))
+/// });
+/// assert!(result.is_err());
+/// assert_eq!(store.resolve("docs")?.id(), docs.id());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub trait Transactional: LinkStore {
+    fn begin(&mut self) -> Result<()>;
+    fn commit(&mut self) -> Result<()>;
+    fn rollback(&mut self) -> Result<()>;
+
+    /// Runs `change` in a transaction, committing it if `change` succeeds
+    /// and rolling it back if it fails.
+    fn transaction<T>(&mut self, change: impl FnOnce(&mut Self) -> Result<T>) -> Result<T>
+    where
+        Self: Sized,
+    {
+        self.begin()?;
+        match change(self) {
+            Ok(value) => {
+                self.commit()?;
+                Ok(value)
+            }
+            Err(e) => {
+                // the change's error says more than a failed rollback
+                let _ = self.rollback();
+                Err(e)
+            }
+        }
+    }
+}
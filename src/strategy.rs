@@ -0,0 +1,187 @@
+use rand::Rng;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::code::MAX_ATTEMPTS;
+use crate::{validate_slug, Base62, CodeGenerator, Link, Result, UrlManagerError};
+
+/// How the shortcut of a new link is chosen.
+///
+/// Every strategy goes through [`ShortenStrategy::shortcut`], which checks
+/// the candidates against the store and retries where that makes sense.
+#[derive(Debug, Clone)]
+pub enum ShortenStrategy {
+    /// Encodes the link id.
+    Id(Base62),
+    /// Hashes the target, so the same target always starts from the same code.
+    Hash { encoder: Base62, length: usize },
+    /// Draws every character from a cryptographically secure generator.
+    Random { encoder: Base62, length: usize },
+    /// Encodes a counter that is shared between clones of the strategy.
+    Sequential {
+        encoder: Base62,
+        counter: Arc<AtomicU64>,
+    },
+    /// Uses a slug chosen by the user, failing instead of retrying when it is taken.
+    Vanity(String),
+}
+
+impl Default for ShortenStrategy {
+    fn default() -> Self {
+        ShortenStrategy::Id(Base62::new())
+    }
+}
+
+impl ShortenStrategy {
+    pub fn hash(length: usize) -> Self {
+        ShortenStrategy::Hash {
+            encoder: Base62::new(),
+            length,
+        }
+    }
+
+    pub fn random(length: usize) -> Self {
+        ShortenStrategy::Random {
+            encoder: Base62::new(),
+            length,
+        }
+    }
+
+    /// Counts up from `start`.
+    pub fn sequential(start: u64) -> Self {
+        ShortenStrategy::Sequential {
+            encoder: Base62::new(),
+            counter: Arc::new(AtomicU64::new(start)),
+        }
+    }
+
+    /// Always the human-chosen `slug`, checked with [`validate_slug`].
+    pub fn vanity(slug: impl Into<String>) -> Self {
+        ShortenStrategy::Vanity(slug.into())
+    }
+
+    /// Picks a shortcut for `link` that `is_taken` reports as free.
+    pub fn shortcut<F>(&self, link: &Link, mut is_taken: F) -> Result<String>
+    where
+        F: FnMut(&str) -> Result<bool>,
+    {
+        if let ShortenStrategy::Vanity(slug) = self {
+            validate_slug(slug)?;
+            return if is_taken(slug)? {
+                Err(UrlManagerError::ShortcutCollision(slug.clone()))
+            } else {
+                Ok(slug.clone())
+            };
+        }
+
+        let mut code = String::new();
+        for attempt in 0..MAX_ATTEMPTS {
+            code = self.candidate(link, attempt);
+            if !is_taken(&code)? {
+                return Ok(code);
+            }
+        }
+        Err(UrlManagerError::ShortcutCollision(code))
+    }
+
+    fn candidate(&self, link: &Link, attempt: u32) -> String {
+        match self {
+            ShortenStrategy::Id(encoder) => encoder.generate(link.id(), attempt),
+            ShortenStrategy::Hash { encoder, length } => {
+                let mut input = link.target().as_str().as_bytes().to_vec();
+                if attempt > 0 {
+                    input.extend_from_slice(&attempt.to_be_bytes());
+                }
+                let code = encoder.encode(fnv1a(&input));
+                code.chars().take((*length).max(1)).collect()
+            }
+            ShortenStrategy::Random { encoder, length } => {
+                let alphabet = encoder.chars();
+                let mut rng = rand::thread_rng();
+                (0..(*length).max(1))
+                    .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+                    .collect()
+            }
+            ShortenStrategy::Sequential { encoder, counter } => {
+                encoder.encode(counter.fetch_add(1, Ordering::Relaxed))
+            }
+            ShortenStrategy::Vanity(slug) => slug.clone(),
+        }
+    }
+}
+
+// FNV-1a keeps hashed codes stable across Rust releases, unlike DefaultHasher.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UrlType;
+
+    fn link(target: &str) -> Link {
+        Link::builder()
+            .target(UrlType::parse(target).unwrap())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_hash_is_deterministic() {
+        let strategy = ShortenStrategy::hash(7);
+        let first = strategy
+            .shortcut(&link("https://example.com/a"), |_| Ok(false))
+            .unwrap();
+        let again = strategy
+            .shortcut(&link("https://example.com/a"), |_| Ok(false))
+            .unwrap();
+        let other = strategy
+            .shortcut(&link("https://example.com/b"), |_| Ok(false))
+            .unwrap();
+
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        assert_eq!(first.len(), 7);
+
+        let retried = strategy
+            .shortcut(&link("https://example.com/a"), |code| Ok(code == first))
+            .unwrap();
+        assert_ne!(retried, first);
+    }
+
+    #[test]
+    fn test_random_and_sequential() {
+        let link = link("https://example.com");
+        let random = ShortenStrategy::random(8)
+            .shortcut(&link, |_| Ok(false))
+            .unwrap();
+        assert_eq!(random.len(), 8);
+        assert!(random.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        let sequential = ShortenStrategy::sequential(61);
+        let shared = sequential.clone();
+        assert_eq!(sequential.shortcut(&link, |_| Ok(false)).unwrap(), "z");
+        assert_eq!(shared.shortcut(&link, |_| Ok(false)).unwrap(), "10");
+        // a taken code just moves on to the next number
+        assert_eq!(sequential.shortcut(&link, |c| Ok(c == "11")).unwrap(), "12");
+    }
+
+    #[test]
+    fn test_vanity_does_not_retry() {
+        let link = link("https://example.com");
+        let strategy = ShortenStrategy::vanity("docs");
+
+        assert_eq!(strategy.shortcut(&link, |_| Ok(false)).unwrap(), "docs");
+        assert!(matches!(
+            strategy.shortcut(&link, |_| Ok(true)),
+            Err(UrlManagerError::ShortcutCollision(slug)) if slug == "docs"
+        ));
+        assert!(matches!(
+            ShortenStrategy::vanity("../admin").shortcut(&link, |_| Ok(false)),
+            Err(UrlManagerError::InvalidSlug(_))
+        ));
+    }
+}
@@ -0,0 +1,423 @@
+//! Behavioral tests every [`LinkStore`] should pass.
+//!
+//! Each function takes a fresh, empty store and panics on the first
+//! deviation from the documented [`LinkStore`] contract.
+//! [`linkstore_conformance!`](crate::linkstore_conformance) turns them into
+//! `#[test]`s for a backend:
+//!
+//! ```ignore
+//! url_manager::linkstore_conformance!(my_store, MyStore::connect_test_db());
+//! ```
+
+use std::time::Duration;
+
+use crate::{Link, LinkQuery, LinkStore, Namespace, UrlManagerError, UrlType};
+
+fn link(slug: &str) -> Link {
+    Link::builder()
+        .target(UrlType::parse(&format!("https://example.com/{slug}")).unwrap())
+        .slug(slug)
+        .build()
+        .unwrap()
+}
+
+/// Creating, reading, updating and deleting single links.
+pub fn crud<S: LinkStore>(mut store: S) {
+    let docs = link("docs");
+    store.create(docs.clone()).unwrap();
+    let stored = store
+        .get(docs.id())
+        .unwrap()
+        .expect("created link is stored");
+    assert_eq!(stored.target(), docs.target());
+    assert_eq!(stored.shortcut(), Some("docs"));
+    let by_shortcut = store.get_by_shortcut("docs").unwrap();
+    assert_eq!(by_shortcut.map(|link| link.id()), Some(docs.id()));
+    assert_eq!(store.count().unwrap(), 1);
+    assert!(store.get(docs.id().wrapping_add(1)).unwrap().is_none());
+    assert!(store.get_by_shortcut("nope").unwrap().is_none());
+
+    let moved = Link::builder()
+        .id(docs.id())
+        .target(UrlType::parse("https://example.org/moved").unwrap())
+        .slug("moved")
+        .build()
+        .unwrap();
+    store.update(docs.id(), moved).unwrap();
+    let stored = store.get(docs.id()).unwrap().unwrap();
+    assert_eq!(stored.target().as_str(), "https://example.org/moved");
+    assert_eq!(
+        stored.created_at(),
+        docs.created_at(),
+        "update keeps created_at"
+    );
+    assert!(store.get_by_shortcut("docs").unwrap().is_none());
+    assert_eq!(store.resolve("moved").unwrap().id(), docs.id());
+
+    let changed = store
+        .update_with(docs.id(), |link| {
+            link.add_tag("changed");
+        })
+        .unwrap();
+    assert!(changed.has_tag("changed"));
+    assert!(store.get(docs.id()).unwrap().unwrap().has_tag("changed"));
+
+    store.delete(docs.id()).unwrap();
+    assert!(store.get(docs.id()).unwrap().is_none());
+    assert!(store.get_by_shortcut("moved").unwrap().is_none());
+    assert_eq!(store.count().unwrap(), 0);
+    assert!(matches!(
+        store.delete(docs.id()),
+        Err(UrlManagerError::NotFound)
+    ));
+    assert!(matches!(
+        store.update(docs.id(), docs.clone()),
+        Err(UrlManagerError::NotFound)
+    ));
+}
+
+/// `DuplicateId` and `ShortcutCollision` on create and update.
+pub fn duplicates<S: LinkStore>(mut store: S) {
+    let docs = link("docs");
+    store.create(docs.clone()).unwrap();
+    assert!(matches!(
+        store.create(docs.clone()),
+        Err(UrlManagerError::DuplicateId(id)) if id == docs.id()
+    ));
+    assert!(matches!(
+        store.create(link("docs")),
+        Err(UrlManagerError::ShortcutCollision(_))
+    ));
+
+    let blog = link("blog");
+    store.create(blog.clone()).unwrap();
+    let taken = Link::builder()
+        .id(blog.id())
+        .target(blog.target().clone())
+        .slug("docs")
+        .build()
+        .unwrap();
+    assert!(matches!(
+        store.update(blog.id(), taken),
+        Err(UrlManagerError::ShortcutCollision(_))
+    ));
+    assert_eq!(store.count().unwrap(), 2);
+    assert_eq!(store.resolve("docs").unwrap().id(), docs.id());
+    assert_eq!(store.resolve("blog").unwrap().id(), blog.id());
+}
+
+/// Every way of storing a taken shortcut fails with `ShortcutCollision`
+/// naming it and changes nothing, see [`LinkStore`]'s unique shortcuts.
+pub fn unique_shortcuts<S: LinkStore>(mut store: S) {
+    let docs = link("docs");
+    let blog = link("blog");
+    store.create(docs.clone()).unwrap();
+    store.create(blog.clone()).unwrap();
+    let taken = |link: &Link| {
+        Link::builder()
+            .id(link.id())
+            .target(UrlType::parse("https://example.com/taken").unwrap())
+            .slug("docs")
+            .build()
+            .unwrap()
+    };
+    let collides = |result: crate::Result<()>| matches!(result, Err(UrlManagerError::ShortcutCollision(shortcut)) if shortcut == "docs");
+
+    assert!(collides(store.create(link("docs"))));
+    assert!(collides(store.update(blog.id(), taken(&blog))));
+    assert!(collides(store.upsert(taken(&blog))));
+    let mut results = store
+        .create_batch(vec![link("docs"), link("shop"), link("shop")])
+        .into_iter();
+    assert!(collides(results.next().unwrap().map(drop)));
+    assert!(results.next().unwrap().is_ok());
+    assert!(matches!(
+        results.next(),
+        Some(Err(UrlManagerError::ShortcutCollision(shortcut))) if shortcut == "shop"
+    ));
+
+    assert_eq!(store.count().unwrap(), 3);
+    assert_eq!(store.resolve("docs").unwrap().id(), docs.id());
+    let stored = store.get(blog.id()).unwrap().unwrap();
+    assert_eq!(stored.shortcut(), Some("blog"));
+    assert_eq!(stored.target(), blog.target());
+
+    store.delete(docs.id()).unwrap();
+    store.update(blog.id(), taken(&blog)).unwrap();
+    assert_eq!(store.resolve("docs").unwrap().id(), blog.id());
+}
+
+/// `list` pages in creation order, then by id, without gaps or repeats.
+pub fn pagination<S: LinkStore>(mut store: S) {
+    let mut links = Vec::new();
+    for id in (1..=7).rev() {
+        let link = Link::builder()
+            .id(id)
+            .target(UrlType::parse(&format!("https://example.com/{id}")).unwrap())
+            .build()
+            .unwrap();
+        store.create(link.clone()).unwrap();
+        links.push(link);
+    }
+    links.sort_by_key(|link| (link.created_at(), link.id()));
+    let expected: Vec<u64> = links.iter().map(Link::id).collect();
+
+    let all: Vec<u64> = store.list(0, 100).unwrap().iter().map(Link::id).collect();
+    assert_eq!(all, expected);
+    let mut paged = Vec::new();
+    for offset in (0..7).step_by(3) {
+        let page = store.list(offset, 3).unwrap();
+        assert!(page.len() <= 3);
+        paged.extend(page.iter().map(Link::id));
+    }
+    assert_eq!(paged, expected);
+    assert!(store.list(7, 3).unwrap().is_empty());
+    assert!(store.list(0, 0).unwrap().is_empty());
+}
+
+/// Expired and used-up links stop resolving and are purged.
+pub fn expiry<S: LinkStore>(mut store: S) {
+    let soon = link("soon");
+    let once = Link::builder()
+        .target(UrlType::parse("https://example.com/once").unwrap())
+        .slug("once")
+        .max_uses(1)
+        .build()
+        .unwrap();
+    let kept = link("kept");
+    for link in [&soon, &once, &kept] {
+        store.create(link.clone()).unwrap();
+    }
+
+    store.update_with(soon.id(), Link::expire).unwrap();
+    assert!(matches!(
+        store.resolve("soon"),
+        Err(UrlManagerError::Expired)
+    ));
+    assert!(matches!(
+        store.record_hit("soon"),
+        Err(UrlManagerError::Expired)
+    ));
+
+    assert_eq!(store.record_hit("once").unwrap().hit_count(), 1);
+    assert_eq!(store.get(once.id()).unwrap().unwrap().hit_count(), 1);
+    assert!(matches!(
+        store.record_hit("once"),
+        Err(UrlManagerError::UsesExhausted)
+    ));
+
+    assert_eq!(store.purge_expired().unwrap(), 2);
+    assert!(store.get(soon.id()).unwrap().is_none());
+    assert!(store.get(once.id()).unwrap().is_none());
+    assert_eq!(store.resolve("kept").unwrap().id(), kept.id());
+    assert_eq!(store.purge_expired().unwrap(), 0);
+}
+
+/// The same shortcut in different namespaces names different links.
+pub fn namespaces<S: LinkStore>(mut store: S) {
+    let team = Namespace::new("team").unwrap();
+    let global = link("docs");
+    let scoped = Link::builder()
+        .target(UrlType::parse("https://example.com/team-docs").unwrap())
+        .namespace(team.clone())
+        .slug("docs")
+        .build()
+        .unwrap();
+    store.create(global.clone()).unwrap();
+    store.create(scoped.clone()).unwrap();
+
+    assert_eq!(store.resolve("docs").unwrap().id(), global.id());
+    assert_eq!(
+        store.resolve_in(Some(&team), "docs").unwrap().id(),
+        scoped.id()
+    );
+    let other = Namespace::new("other").unwrap();
+    assert!(store
+        .get_by_shortcut_in(Some(&other), "docs")
+        .unwrap()
+        .is_none());
+}
+
+/// Trashed links stop resolving and listing until restored or purged.
+pub fn trash<S: LinkStore>(mut store: S) {
+    let docs = link("docs");
+    let kept = link("kept");
+    store.create(docs.clone()).unwrap();
+    store.create(kept.clone()).unwrap();
+
+    store.soft_delete(docs.id()).unwrap();
+    assert!(matches!(
+        store.soft_delete(docs.id()),
+        Err(UrlManagerError::NotFound)
+    ));
+    assert!(matches!(
+        store.resolve("docs"),
+        Err(UrlManagerError::NotFound)
+    ));
+    assert!(matches!(
+        store.record_hit("docs"),
+        Err(UrlManagerError::NotFound)
+    ));
+    assert!(store.get(docs.id()).unwrap().unwrap().is_deleted());
+    let listed: Vec<u64> = store.list(0, 10).unwrap().iter().map(Link::id).collect();
+    assert_eq!(listed, [kept.id()]);
+    assert_eq!(store.count().unwrap(), 1);
+    let trashed = store.find(&LinkQuery::new().deleted()).unwrap();
+    assert_eq!(trashed.len(), 1);
+    assert!(matches!(
+        store.create(link("docs")),
+        Err(UrlManagerError::ShortcutCollision(_))
+    ));
+
+    store.restore(docs.id()).unwrap();
+    assert_eq!(store.resolve("docs").unwrap().id(), docs.id());
+    assert_eq!(store.count().unwrap(), 2);
+    assert!(matches!(
+        store.restore(docs.id()),
+        Err(UrlManagerError::NotFound)
+    ));
+
+    store.soft_delete(docs.id()).unwrap();
+    assert_eq!(store.purge(Duration::from_secs(3600)).unwrap(), 0);
+    assert_eq!(store.purge(Duration::ZERO).unwrap(), 1);
+    assert!(store.get(docs.id()).unwrap().is_none());
+    assert_eq!(store.resolve("kept").unwrap().id(), kept.id());
+}
+
+/// Runs every conformance test, each on a store from `new_store`.
+pub fn run_all<S: LinkStore>(mut new_store: impl FnMut() -> S) {
+    crud(new_store());
+    duplicates(new_store());
+    unique_shortcuts(new_store());
+    pagination(new_store());
+    expiry(new_store());
+    namespaces(new_store());
+    trash(new_store());
+}
+
+/// Generates a module `$name` with one `#[test]` per conformance test,
+/// each running on a fresh store from the expression `$new_store`.
+///
+/// Needs the `testing` feature, typically as a dev-dependency feature.
+#[macro_export]
+macro_rules! linkstore_conformance {
+    ($name:ident, $new_store:expr) => {
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[test]
+            fn crud() {
+                $crate::testing::conformance::crud($new_store);
+            }
+
+            #[test]
+            fn duplicates() {
+                $crate::testing::conformance::duplicates($new_store);
+            }
+
+            #[test]
+            fn unique_shortcuts() {
+                $crate::testing::conformance::unique_shortcuts($new_store);
+            }
+
+            #[test]
+            fn pagination() {
+                $crate::testing::conformance::pagination($new_store);
+            }
+
+            #[test]
+            fn expiry() {
+                $crate::testing::conformance::expiry($new_store);
+            }
+
+            #[test]
+            fn namespaces() {
+                $crate::testing::conformance::namespaces($new_store);
+            }
+
+            #[test]
+            fn trash() {
+                $crate::testing::conformance::trash($new_store);
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::MockLinkStore;
+    use crate::{
+        CachedLinkStore, FileLinkStore, InMemoryLinkStore, Link, LinkStore, Namespace, Result,
+        ShardedLinkStore,
+    };
+
+    // Implements just the required methods, so the conformance tests cover
+    // the defaults of everything else.
+    #[derive(Default)]
+    struct Minimal(InMemoryLinkStore);
+
+    impl LinkStore for Minimal {
+        fn get(&self, id: u64) -> Result<Option<Link>> {
+            self.0.get(id)
+        }
+
+        fn get_by_shortcut_in(
+            &self,
+            namespace: Option<&Namespace>,
+            shortcut: &str,
+        ) -> Result<Option<Link>> {
+            self.0.get_by_shortcut_in(namespace, shortcut)
+        }
+
+        fn create(&mut self, link: Link) -> Result<()> {
+            self.0.create(link)
+        }
+
+        fn update(&mut self, id: u64, link: Link) -> Result<()> {
+            self.0.update(id, link)
+        }
+
+        fn delete(&mut self, id: u64) -> Result<()> {
+            self.0.delete(id)
+        }
+
+        fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+            self.0.list(offset, limit)
+        }
+
+        fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+            self.0.list_deleted(offset, limit)
+        }
+
+        fn count(&self) -> Result<usize> {
+            self.0.count()
+        }
+
+        fn purge_expired(&mut self) -> Result<usize> {
+            self.0.purge_expired()
+        }
+    }
+
+    fn file_store() -> FileLinkStore {
+        let path = std::env::temp_dir().join(format!(
+            "url-manager-conformance-{}.jsonl",
+            rand::random::<u64>()
+        ));
+        FileLinkStore::open(path).unwrap()
+    }
+
+    fn sharded_store() -> ShardedLinkStore<InMemoryLinkStore> {
+        let mut store = ShardedLinkStore::new("a", InMemoryLinkStore::new());
+        store.add_shard("b", InMemoryLinkStore::new()).unwrap();
+        store.add_shard("c", InMemoryLinkStore::new()).unwrap();
+        store
+    }
+
+    crate::linkstore_conformance!(memory, InMemoryLinkStore::new());
+    crate::linkstore_conformance!(file, file_store());
+    crate::linkstore_conformance!(cached, CachedLinkStore::new(InMemoryLinkStore::new()));
+    crate::linkstore_conformance!(mock, MockLinkStore::new());
+    crate::linkstore_conformance!(sharded, sharded_store());
+    crate::linkstore_conformance!(minimal, Minimal::default());
+}
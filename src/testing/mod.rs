@@ -0,0 +1,456 @@
+//! Test doubles, enabled by the `testing` feature.
+//!
+//! [`MockLinkStore`] behaves like an [`InMemoryLinkStore`] but records every
+//! call and can be told to fail, so code built on a [`LinkStore`] can be
+//! tested against backend failures without a backend. [`conformance`]
+//! checks that a store implementation keeps the [`LinkStore`] contract.
+//!
+//! ```
+//! # use url_manager::testing::{Failure, Method, MockLinkStore};
+//! # use url_manager::{LinkStore, UrlManagerError};
+//! let mut store = MockLinkStore::new();
+//! store.fail_next(Method::Create, Failure::Timeout);
+//! store.expect(Method::Create, 2);
+//!
+//! let target = "https://example.com".parse()?;
+//! assert!(matches!(
+//!     store.create_with_slug("docs", target),
+//!     Err(UrlManagerError::StorageBackend(_))
+//! ));
+//! store.create_with_slug("docs", "https://example.com".parse()?)?;
+//! store.verify();
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod conformance;
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::{
+    sync, InMemoryLinkStore, Link, LinkQuery, LinkStore, Namespace, Result, UrlManagerError,
+};
+
+/// The [`LinkStore`] methods a [`MockLinkStore`] records and can fail.
+///
+/// Provided methods that are built on these, like `resolve` on
+/// `get_by_shortcut_in`, show up as the calls they make.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    GetByShortcut,
+    Create,
+    Upsert,
+    Update,
+    UpdateWith,
+    RecordHit,
+    RecordResolvedHit,
+    RecordVariantHit,
+    Delete,
+    List,
+    ListDeleted,
+    Find,
+    Count,
+    PurgeExpired,
+}
+
+/// One call made to a [`MockLinkStore`], with its arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    Get(u64),
+    GetByShortcut {
+        namespace: Option<Namespace>,
+        shortcut: String,
+    },
+    Create(u64),
+    Upsert(u64),
+    Update(u64),
+    UpdateWith(u64),
+    RecordHit {
+        namespace: Option<Namespace>,
+        shortcut: String,
+    },
+    RecordResolvedHit(u64),
+    RecordVariantHit {
+        id: u64,
+        variant: usize,
+    },
+    Delete(u64),
+    List {
+        offset: usize,
+        limit: usize,
+    },
+    ListDeleted {
+        offset: usize,
+        limit: usize,
+    },
+    Find,
+    Count,
+    PurgeExpired,
+}
+
+impl Call {
+    pub fn method(&self) -> Method {
+        match self {
+            Call::Get(_) => Method::Get,
+            Call::GetByShortcut { .. } => Method::GetByShortcut,
+            Call::Create(_) => Method::Create,
+            Call::Upsert(_) => Method::Upsert,
+            Call::Update(_) => Method::Update,
+            Call::UpdateWith(_) => Method::UpdateWith,
+            Call::RecordHit { .. } => Method::RecordHit,
+            Call::RecordResolvedHit(_) => Method::RecordResolvedHit,
+            Call::RecordVariantHit { .. } => Method::RecordVariantHit,
+            Call::Delete(_) => Method::Delete,
+            Call::List { .. } => Method::List,
+            Call::ListDeleted { .. } => Method::ListDeleted,
+            Call::Find => Method::Find,
+            Call::Count => Method::Count,
+            Call::PurgeExpired => Method::PurgeExpired,
+        }
+    }
+}
+
+/// How a [`MockLinkStore`] call fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// `NotFound`, as if the link were deleted in between.
+    NotFound,
+    /// A `StorageBackend` error wrapping an `io::ErrorKind::TimedOut`.
+    Timeout,
+    /// A `StorageBackend` error as from a store whose lock was poisoned.
+    Poisoned,
+    /// A `StorageBackend` error with this message.
+    Backend(String),
+}
+
+impl Failure {
+    fn error(&self) -> UrlManagerError {
+        match self {
+            Failure::NotFound => UrlManagerError::NotFound,
+            Failure::Timeout => UrlManagerError::backend(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "mock store timed out",
+            )),
+            Failure::Poisoned => UrlManagerError::backend("mock store lock poisoned"),
+            Failure::Backend(message) => UrlManagerError::backend(message.clone()),
+        }
+    }
+}
+
+/// A [`LinkStore`] for tests that records its calls and fails on demand.
+///
+/// Failures are checked before the call reaches the links: a call fails
+/// with the next [`MockLinkStore::fail_next`] failure queued for its
+/// method, else with its [`MockLinkStore::fail_always`] failure, else with
+/// `Poisoned` after [`MockLinkStore::poison`]. Failed calls are recorded
+/// too.
+#[derive(Debug, Default)]
+pub struct MockLinkStore {
+    links: InMemoryLinkStore,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    calls: Vec<Call>,
+    next: HashMap<Method, Vec<Failure>>,
+    always: HashMap<Method, Failure>,
+    poisoned: bool,
+    delay: Option<Duration>,
+    expected: Vec<(Method, usize)>,
+}
+
+impl MockLinkStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts out holding `links`; storing them isn't recorded as calls.
+    pub fn with_links(links: impl IntoIterator<Item = Link>) -> Result<Self> {
+        let mut store = InMemoryLinkStore::new();
+        for link in links {
+            store.create(link)?;
+        }
+        Ok(MockLinkStore {
+            links: store,
+            state: Mutex::default(),
+        })
+    }
+
+    /// Fails the next call to `method` with `failure`; queued failures are
+    /// used up in order.
+    pub fn fail_next(&self, method: Method, failure: Failure) {
+        let mut state = sync::lock(&self.state);
+        state.next.entry(method).or_default().push(failure);
+    }
+
+    /// Fails every call to `method` with `failure` until
+    /// [`MockLinkStore::clear_failures`].
+    pub fn fail_always(&self, method: Method, failure: Failure) {
+        sync::lock(&self.state).always.insert(method, failure);
+    }
+
+    /// Fails every call with [`Failure::Poisoned`] until
+    /// [`MockLinkStore::clear_failures`].
+    pub fn poison(&self) {
+        sync::lock(&self.state).poisoned = true;
+    }
+
+    /// Forgets all programmed failures.
+    pub fn clear_failures(&self) {
+        let mut state = sync::lock(&self.state);
+        state.next.clear();
+        state.always.clear();
+        state.poisoned = false;
+    }
+
+    /// Sleeps for `delay` in every call, e.g. to test timeouts around the
+    /// store.
+    pub fn delay(&self, delay: Duration) {
+        sync::lock(&self.state).delay = Some(delay);
+    }
+
+    /// Every call made so far, oldest first.
+    pub fn calls(&self) -> Vec<Call> {
+        sync::lock(&self.state).calls.clone()
+    }
+
+    /// How often `method` has been called.
+    pub fn call_count(&self, method: Method) -> usize {
+        let state = sync::lock(&self.state);
+        state
+            .calls
+            .iter()
+            .filter(|call| call.method() == method)
+            .count()
+    }
+
+    pub fn clear_calls(&self) {
+        sync::lock(&self.state).calls.clear();
+    }
+
+    /// Expects `method` to have been called `times` times by the time of
+    /// [`MockLinkStore::verify`].
+    pub fn expect(&self, method: Method, times: usize) {
+        sync::lock(&self.state).expected.push((method, times));
+    }
+
+    /// Panics listing every [`MockLinkStore::expect`]ation that isn't met.
+    #[track_caller]
+    pub fn verify(&self) {
+        let expected = sync::lock(&self.state).expected.clone();
+        let unmet: Vec<String> = expected
+            .into_iter()
+            .filter_map(|(method, times)| {
+                let actual = self.call_count(method);
+                (actual != times)
+                    .then(|| format!("{method:?}: expected {times} calls, got {actual}"))
+            })
+            .collect();
+        assert!(unmet.is_empty(), "unmet expectations: {}", unmet.join("; "));
+    }
+
+    /// Records `call` and returns the failure programmed for it, if any.
+    fn enter(&self, call: Call) -> Result<()> {
+        let method = call.method();
+        let (delay, failure) = {
+            let mut state = sync::lock(&self.state);
+            state.calls.push(call);
+            let next = state
+                .next
+                .get_mut(&method)
+                .and_then(|queued| (!queued.is_empty()).then(|| queued.remove(0)));
+            let failure = next
+                .or_else(|| state.always.get(&method).cloned())
+                .or_else(|| state.poisoned.then_some(Failure::Poisoned));
+            (state.delay, failure)
+        };
+        if let Some(delay) = delay {
+            thread::sleep(delay);
+        }
+        failure.map_or(Ok(()), |failure| Err(failure.error()))
+    }
+}
+
+impl LinkStore for MockLinkStore {
+    fn get(&self, id: u64) -> Result<Option<Link>> {
+        self.enter(Call::Get(id))?;
+        self.links.get(id)
+    }
+
+    fn get_by_shortcut_in(
+        &self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+    ) -> Result<Option<Link>> {
+        self.enter(Call::GetByShortcut {
+            namespace: namespace.cloned(),
+            shortcut: shortcut.to_string(),
+        })?;
+        self.links.get_by_shortcut_in(namespace, shortcut)
+    }
+
+    fn create(&mut self, link: Link) -> Result<()> {
+        self.enter(Call::Create(link.id()))?;
+        self.links.create(link)
+    }
+
+    fn upsert(&mut self, link: Link) -> Result<()> {
+        self.enter(Call::Upsert(link.id()))?;
+        self.links.upsert(link)
+    }
+
+    fn update(&mut self, id: u64, link: Link) -> Result<()> {
+        self.enter(Call::Update(id))?;
+        self.links.update(id, link)
+    }
+
+    fn update_with(&mut self, id: u64, change: impl FnOnce(&mut Link)) -> Result<Link> {
+        self.enter(Call::UpdateWith(id))?;
+        self.links.update_with(id, change)
+    }
+
+    fn record_hit_with_password_in(
+        &mut self,
+        namespace: Option<&Namespace>,
+        shortcut: &str,
+        password: Option<&str>,
+    ) -> Result<Link> {
+        self.enter(Call::RecordHit {
+            namespace: namespace.cloned(),
+            shortcut: shortcut.to_string(),
+        })?;
+        self.links
+            .record_hit_with_password_in(namespace, shortcut, password)
+    }
+
+    fn record_resolved_hit(&mut self, link: &Link) -> Result<Link> {
+        self.enter(Call::RecordResolvedHit(link.id()))?;
+        self.links.record_resolved_hit(link)
+    }
+
+    fn record_variant_hit(&mut self, id: u64, variant: usize) -> Result<Link> {
+        self.enter(Call::RecordVariantHit { id, variant })?;
+        self.links.record_variant_hit(id, variant)
+    }
+
+    fn delete(&mut self, id: u64) -> Result<()> {
+        self.enter(Call::Delete(id))?;
+        self.links.delete(id)
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.enter(Call::List { offset, limit })?;
+        self.links.list(offset, limit)
+    }
+
+    fn list_deleted(&self, offset: usize, limit: usize) -> Result<Vec<Link>> {
+        self.enter(Call::ListDeleted { offset, limit })?;
+        self.links.list_deleted(offset, limit)
+    }
+
+    fn find(&self, query: &LinkQuery) -> Result<Vec<Link>> {
+        self.enter(Call::Find)?;
+        self.links.find(query)
+    }
+
+    fn count(&self) -> Result<usize> {
+        self.enter(Call::Count)?;
+        self.links.count()
+    }
+
+    fn purge_expired(&mut self) -> Result<usize> {
+        self.enter(Call::PurgeExpired)?;
+        self.links.purge_expired()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Base62, LinkService, UrlType};
+
+    fn link(slug: &str) -> Link {
+        Link::builder()
+            .target(UrlType::parse("https://example.com").unwrap())
+            .slug(slug)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_records_calls() {
+        let docs = link("docs");
+        let mut store = MockLinkStore::with_links([docs.clone()]).unwrap();
+        assert!(store.calls().is_empty());
+
+        store.resolve("docs").unwrap();
+        store.delete(docs.id()).unwrap();
+        assert_eq!(
+            store.calls(),
+            [
+                Call::GetByShortcut {
+                    namespace: None,
+                    shortcut: "docs".to_string()
+                },
+                Call::Delete(docs.id()),
+            ]
+        );
+        store.expect(Method::Delete, 1);
+        store.expect(Method::Get, 0);
+        store.verify();
+    }
+
+    #[test]
+    #[should_panic(expected = "Create: expected 1 calls, got 0")]
+    fn test_verify_panics() {
+        let store = MockLinkStore::new();
+        store.expect(Method::Create, 1);
+        store.verify();
+    }
+
+    #[test]
+    fn test_failures() {
+        let mut store = MockLinkStore::with_links([link("docs")]).unwrap();
+        store.fail_next(Method::GetByShortcut, Failure::NotFound);
+        store.fail_next(Method::GetByShortcut, Failure::Timeout);
+        assert!(matches!(
+            store.resolve("docs"),
+            Err(UrlManagerError::NotFound)
+        ));
+        let Err(UrlManagerError::StorageBackend(e)) = store.resolve("docs") else {
+            panic!("expected a timeout");
+        };
+        assert_eq!(
+            e.downcast_ref::<io::Error>().map(io::Error::kind),
+            Some(io::ErrorKind::TimedOut)
+        );
+        assert!(store.resolve("docs").is_ok());
+
+        store.fail_always(Method::Create, Failure::Backend("disk full".to_string()));
+        assert!(store.create(link("new")).is_err());
+        assert!(store.create(link("new")).is_err());
+        store.poison();
+        assert!(store.count().is_err());
+        store.clear_failures();
+        assert!(store.create(link("new")).is_ok());
+        assert_eq!(store.call_count(Method::Create), 3);
+    }
+
+    #[test]
+    fn test_with_service() {
+        let store = MockLinkStore::new();
+        store.fail_next(Method::Create, Failure::Poisoned);
+        let mut service = LinkService::new(store, Base62::new());
+        assert!(service
+            .shorten_with_slug("https://example.com", "docs")
+            .is_err());
+        assert!(service
+            .shorten_with_slug("https://example.com", "docs")
+            .is_ok());
+    }
+}
@@ -0,0 +1,379 @@
+//! Bulk import and export of links, see [`LinkStore::import`](crate::LinkStore::import),
+//! and export of clicks, see [`Analytics::export`](crate::analytics::Analytics::export).
+
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use crate::json::{self, Value};
+use crate::link::{now, unix_millis};
+use crate::{Click, Link, Result, UrlManagerError};
+
+/// Columns of the CSV format, in export order; they match the JSON field names.
+pub const CSV_COLUMNS: [&str; 16] = [
+    "id",
+    "origin",
+    "target",
+    "scheduled_target",
+    "scheduled_at",
+    "namespace",
+    "shortcut",
+    "expires_at",
+    "max_uses",
+    "tags",
+    "hits",
+    "last_hit_at",
+    "password_hash",
+    "verdict",
+    "created_at",
+    "updated_at",
+];
+
+/// Columns of clicks exported as CSV, in order; they match the JSON field
+/// names.
+pub const CLICK_COLUMNS: [&str; 6] = [
+    "link_id",
+    "at",
+    "referrer",
+    "user_agent",
+    "country",
+    "visitor",
+];
+
+const NUMBER_COLUMNS: [&str; 8] = [
+    "id",
+    "scheduled_at",
+    "expires_at",
+    "max_uses",
+    "hits",
+    "last_hit_at",
+    "created_at",
+    "updated_at",
+];
+
+/// Formats for [`LinkStore::import`](crate::LinkStore::import) and
+/// [`LinkStore::export`](crate::LinkStore::export).
+///
+/// `Csv` has a header row naming the columns in [`CSV_COLUMNS`]; tags are
+/// separated by spaces and times are unix milliseconds. A/B test variants,
+/// redirect rules, previews, health checks, owners, versions and the
+/// interstitial flag are only kept by `Json`, which is JSON Lines, one link object per line, so
+/// both can be streamed.
+///
+/// Clicks are written with the columns in [`CLICK_COLUMNS`] and can't be
+/// imported.
+///
+/// On import only `target` is required: a missing id is generated, the
+/// origin defaults to the target and the creation time to now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = UrlManagerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "json" | "jsonl" => Ok(Format::Json),
+            _ => Err(UrlManagerError::InvalidConfig(format!(
+                "unknown format '{s}', expected csv or json"
+            ))),
+        }
+    }
+}
+
+/// A record that could not be imported.
+#[derive(Debug)]
+pub struct RowError {
+    /// The line the record starts on, counting from 1.
+    pub line: usize,
+    pub error: UrlManagerError,
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+/// Outcome of an import; rejected records don't stop the import.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+}
+
+/// Reads `reader` record by record, calling `each` with the line number and
+/// the parsed link. Only I/O errors and a broken CSV header stop reading.
+pub(crate) fn read_links(
+    mut reader: impl BufRead,
+    format: Format,
+    mut each: impl FnMut(usize, Result<Link>),
+) -> Result<()> {
+    match format {
+        Format::Json => {
+            let mut line_number = 0;
+            let mut line = String::new();
+            loop {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 {
+                    return Ok(());
+                }
+                line_number += 1;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let link = json::parse(&line)
+                    .map_err(|e| UrlManagerError::InvalidLink(format!("not JSON: {e}")))
+                    .and_then(|value| Link::from_json(&with_defaults(value)));
+                each(line_number, link);
+            }
+        }
+        Format::Csv => {
+            let mut header: Option<Vec<String>> = None;
+            read_csv(reader, |line, fields| {
+                let Some(columns) = &header else {
+                    if !fields.iter().any(|field| field == "target") {
+                        return Err(UrlManagerError::InvalidConfig(
+                            "CSV header has no 'target' column".to_string(),
+                        ));
+                    }
+                    header = Some(fields);
+                    return Ok(());
+                };
+                each(line, csv_link(columns, fields));
+                Ok(())
+            })
+        }
+    }
+}
+
+/// Reads `reader` as CSV, calling `each` with the line a record starts on
+/// and its fields. Blank lines are skipped; an error from `each` stops reading.
+pub(crate) fn read_csv(
+    mut reader: impl BufRead,
+    mut each: impl FnMut(usize, Vec<String>) -> Result<()>,
+) -> Result<()> {
+    let mut line_number = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        line_number += 1;
+        let start = line_number;
+        // quoted fields may span lines
+        while line.matches('"').count() % 2 == 1 {
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            line_number += 1;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        each(start, parse_csv_record(line.trim_end_matches(['\r', '\n'])))?;
+    }
+}
+
+fn csv_link(columns: &[String], fields: Vec<String>) -> Result<Link> {
+    if fields.len() != columns.len() {
+        return Err(UrlManagerError::InvalidLink(format!(
+            "expected {} fields, found {}",
+            columns.len(),
+            fields.len()
+        )));
+    }
+    let value = Value::object(columns.iter().zip(fields).filter_map(|(column, field)| {
+        let value = if field.is_empty() {
+            return None;
+        } else if column == "tags" {
+            Value::from(field.split_whitespace().collect::<Vec<_>>())
+        } else if NUMBER_COLUMNS.contains(&column.as_str()) {
+            Value::Number(field)
+        } else {
+            Value::from(field)
+        };
+        Some((column.clone(), value))
+    }));
+    Link::from_json(&with_defaults(value))
+}
+
+// Fills in the fields only an export is expected to have.
+pub(crate) fn with_defaults(value: Value) -> Value {
+    let Value::Object(mut fields) = value else {
+        return value;
+    };
+    let has = |fields: &[(String, Value)], key: &str| fields.iter().any(|(k, _)| k == key);
+    if !has(&fields, "id") {
+        fields.push(("id".to_string(), Value::from(rand::random::<u64>())));
+    }
+    if !has(&fields, "origin") {
+        if let Some((_, target)) = fields.iter().find(|(k, _)| k == "target") {
+            fields.push(("origin".to_string(), target.clone()));
+        }
+    }
+    let created_at = match fields.iter().find(|(k, _)| k == "created_at") {
+        Some((_, created_at)) => created_at.clone(),
+        None => {
+            let created_at = Value::from(unix_millis(now()));
+            fields.push(("created_at".to_string(), created_at.clone()));
+            created_at
+        }
+    };
+    if !has(&fields, "updated_at") {
+        fields.push(("updated_at".to_string(), created_at));
+    }
+    Value::Object(fields)
+}
+
+fn parse_csv_record(record: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().unwrap();
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    fields
+}
+
+pub(crate) fn write_header(mut writer: impl Write, format: Format) -> Result<()> {
+    if format == Format::Csv {
+        writeln!(writer, "{}", CSV_COLUMNS.join(","))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_link(writer: impl Write, format: Format, link: &Link) -> Result<()> {
+    write_record(writer, format, &CSV_COLUMNS, &link.to_json())
+}
+
+/// Writes `clicks`, after a header row for CSV.
+pub(crate) fn write_clicks(mut writer: impl Write, format: Format, clicks: &[Click]) -> Result<()> {
+    if format == Format::Csv {
+        writeln!(writer, "{}", CLICK_COLUMNS.join(","))?;
+    }
+    for click in clicks {
+        write_record(&mut writer, format, &CLICK_COLUMNS, &click.to_json())?;
+    }
+    Ok(())
+}
+
+fn write_record(
+    mut writer: impl Write,
+    format: Format,
+    columns: &[&str],
+    value: &Value,
+) -> Result<()> {
+    match format {
+        Format::Json => writeln!(writer, "{value}")?,
+        Format::Csv => {
+            let fields: Vec<String> = columns
+                .iter()
+                .map(|column| match value.get(column) {
+                    None | Some(Value::Null) => String::new(),
+                    Some(Value::String(s)) | Some(Value::Number(s)) => csv_field(s),
+                    Some(Value::Array(tags)) => csv_field(
+                        &tags
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    ),
+                    Some(other) => csv_field(&other.to_string()),
+                })
+                .collect();
+            writeln!(writer, "{}", fields.join(","))?;
+        }
+    }
+    Ok(())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryLinkStore, LinkStore};
+
+    fn sample() -> InMemoryLinkStore {
+        let mut store = InMemoryLinkStore::new();
+        let link = Link::builder()
+            .id(7)
+            .target("https://example.com/a?x=1,2&q=\"hi\"")
+            .shortcut("a")
+            .tag("spring")
+            .tag("mail")
+            .build()
+            .unwrap();
+        store.create(link).unwrap();
+        store.create(Link::default()).unwrap();
+        store
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for format in [Format::Csv, Format::Json] {
+            let source = sample();
+            let mut out = Vec::new();
+            assert_eq!(source.export(&mut out, format).unwrap(), 2);
+
+            let mut target = InMemoryLinkStore::new();
+            let report = target.import(&out[..], format).unwrap();
+            assert!(report.errors.is_empty(), "{format:?}: {:?}", report.errors);
+            assert_eq!(report.imported, 2);
+            for link in source.list(0, 10).unwrap() {
+                let copy = target.get(link.id()).unwrap().unwrap();
+                assert_eq!(copy.target(), link.target());
+                assert_eq!(copy.shortcut(), link.shortcut());
+                assert_eq!(
+                    copy.tags().collect::<Vec<_>>(),
+                    link.tags().collect::<Vec<_>>()
+                );
+                assert_eq!(copy.created_at(), link.created_at());
+            }
+        }
+    }
+
+    #[test]
+    fn test_row_errors() {
+        let csv = "target,shortcut\nhttps://example.com,ok\nnope,bad\n\"https://example.com/x\",\"two\nlines\"\n";
+        let mut store = InMemoryLinkStore::new();
+        let report = store.import(csv.as_bytes(), Format::Csv).unwrap();
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].line, 3);
+        assert!(store.get_by_shortcut("two\nlines").unwrap().is_some());
+
+        let json = "{\"target\": \"https://example.com\", \"shortcut\": \"ok\"}\n{]\n";
+        let report = InMemoryLinkStore::new()
+            .import(json.as_bytes(), Format::Json)
+            .unwrap();
+        assert_eq!(report.imported, 1);
+        assert_eq!(report.errors[0].line, 2);
+
+        assert!(store
+            .import("shortcut\nx\n".as_bytes(), Format::Csv)
+            .is_err());
+        assert_eq!("JSONL".parse::<Format>().unwrap(), Format::Json);
+    }
+}
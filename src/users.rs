@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use crate::{sync, Link, Result, UrlManagerError};
+
+/// What a [`User`] may do. Each role includes the ones before it.
+///
+/// | | `Viewer` | `Editor` | `Admin` |
+/// | --- | --- | --- | --- |
+/// | [`Action::Shorten`] | | ✓ | ✓ |
+/// | [`Action::ChangeOwn`] | | ✓ | ✓ |
+/// | [`Action::ChangeAny`] | | | ✓ |
+/// | [`Action::Purge`] | | | ✓ |
+/// | [`Action::ListNamespaces`] | | | ✓ |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    /// Following and looking at links only.
+    Viewer,
+    /// Creating links and managing their own.
+    #[default]
+    Editor,
+    /// Everything, including other users' links.
+    Admin,
+}
+
+/// The operations [`Role`]s are checked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Creating links.
+    Shorten,
+    /// Changing, expiring or deleting links one owns.
+    ChangeOwn,
+    /// Changing, expiring or deleting anyone's links, or links nobody owns.
+    ChangeAny,
+    /// Purging expired and trashed links.
+    Purge,
+    /// Listing every namespace in use.
+    ListNamespaces,
+}
+
+impl Role {
+    pub fn allows(&self, action: Action) -> bool {
+        let needed = match action {
+            Action::Shorten | Action::ChangeOwn => Role::Editor,
+            Action::ChangeAny | Action::Purge | Action::ListNamespaces => Role::Admin,
+        };
+        *self >= needed
+    }
+}
+
+impl FromStr for Role {
+    type Err = UrlManagerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "admin" => Ok(Role::Admin),
+            _ => Err(UrlManagerError::InvalidConfig(format!(
+                "unknown role '{s}', expected viewer, editor or admin"
+            ))),
+        }
+    }
+}
+
+/// Someone links belong to, see [`Link::owner_id`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    id: u64,
+    name: String,
+    role: Role,
+}
+
+impl User {
+    /// A user with the [`Role::Editor`] role.
+    pub fn new(id: u64, name: impl Into<String>) -> Self {
+        User {
+            id,
+            name: name.into(),
+            role: Role::default(),
+        }
+    }
+
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Shorthand for [`User::role`] with [`Role::Admin`].
+    pub fn admin(self) -> Self {
+        self.role(Role::Admin)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_role(&self) -> Role {
+        self.role
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// Whether the user's role allows `action`.
+    pub fn can(&self, action: Action) -> bool {
+        self.role.allows(action)
+    }
+
+    /// Fails with `Forbidden` unless the user's role allows `action`.
+    pub fn check(&self, action: Action) -> Result<()> {
+        if self.can(action) {
+            Ok(())
+        } else {
+            Err(UrlManagerError::Forbidden(format!(
+                "{} is a {:?} and may not {action:?}",
+                self.name, self.role
+            )))
+        }
+    }
+
+    /// Whether the user may change or delete `link`: their own links as an
+    /// editor, any as an admin.
+    pub fn can_manage(&self, link: &Link) -> bool {
+        self.can(Action::ChangeAny)
+            || (self.can(Action::ChangeOwn) && link.owner_id() == Some(self.id))
+    }
+}
+
+/// Storage for the users of a multi-user deployment.
+///
+/// Like [`LinkStore`](crate::LinkStore) the trait is open for other
+/// backends; [`InMemoryUserStore`] is the reference implementation.
+pub trait UserStore {
+    /// Returns the user with `id`, if any.
+    fn get_user(&self, id: u64) -> Result<Option<User>>;
+    /// Stores `user`, failing with `DuplicateId` if a user with its id is
+    /// already stored.
+    fn create_user(&mut self, user: User) -> Result<()>;
+    /// Removes the user with `id`, failing with `NotFound` if there is none.
+    /// Their links stay, still carrying the id as [`Link::owner_id`].
+    fn delete_user(&mut self, id: u64) -> Result<()>;
+    /// Returns every user, ordered by id.
+    fn list_users(&self) -> Result<Vec<User>>;
+
+    /// Returns the first user called `name`, if any.
+    fn get_user_by_name(&self, name: &str) -> Result<Option<User>> {
+        Ok(self
+            .list_users()?
+            .into_iter()
+            .find(|user| user.name == name))
+    }
+}
+
+/// A [`UserStore`] keeping every user in a shared in-process map.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryUserStore {
+    users: Arc<RwLock<BTreeMap<u64, User>>>,
+}
+
+impl InMemoryUserStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl UserStore for InMemoryUserStore {
+    fn get_user(&self, id: u64) -> Result<Option<User>> {
+        Ok(sync::read(&self.users).get(&id).cloned())
+    }
+
+    fn create_user(&mut self, user: User) -> Result<()> {
+        let mut users = sync::write(&self.users);
+        if users.contains_key(&user.id) {
+            return Err(UrlManagerError::DuplicateId(user.id));
+        }
+        users.insert(user.id, user);
+        Ok(())
+    }
+
+    fn delete_user(&mut self, id: u64) -> Result<()> {
+        sync::write(&self.users)
+            .remove(&id)
+            .map(drop)
+            .ok_or(UrlManagerError::NotFound)
+    }
+
+    fn list_users(&self) -> Result<Vec<User>> {
+        Ok(sync::read(&self.users).values().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemoryLinkStore, LinkStore};
+
+    #[test]
+    fn test_user_store() {
+        let mut users = InMemoryUserStore::new();
+        users.create_user(User::new(2, "bob")).unwrap();
+        users.create_user(User::new(1, "alice").admin()).unwrap();
+        assert!(matches!(
+            users.create_user(User::new(2, "carol")),
+            Err(UrlManagerError::DuplicateId(2))
+        ));
+        let names: Vec<String> = users
+            .list_users()
+            .unwrap()
+            .iter()
+            .map(|user| user.name().to_string())
+            .collect();
+        assert_eq!(names, ["alice", "bob"]);
+        assert!(users.get_user_by_name("alice").unwrap().unwrap().is_admin());
+        users.delete_user(2).unwrap();
+        assert!(users.get_user(2).unwrap().is_none());
+        assert!(matches!(
+            users.delete_user(2),
+            Err(UrlManagerError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_roles() {
+        let matrix = [
+            (Action::Shorten, [false, true, true]),
+            (Action::ChangeOwn, [false, true, true]),
+            (Action::ChangeAny, [false, false, true]),
+            (Action::Purge, [false, false, true]),
+            (Action::ListNamespaces, [false, false, true]),
+        ];
+        for (action, allowed) in matrix {
+            for (role, allowed) in [Role::Viewer, Role::Editor, Role::Admin]
+                .into_iter()
+                .zip(allowed)
+            {
+                assert_eq!(role.allows(action), allowed, "{role:?} {action:?}");
+                let user = User::new(1, "someone").role(role);
+                assert_eq!(user.check(action).is_ok(), allowed, "{role:?} {action:?}");
+            }
+        }
+
+        let own = Link::builder()
+            .target("https://example.com")
+            .owner_id(1)
+            .build()
+            .unwrap();
+        let unowned = Link::builder()
+            .target("https://example.com")
+            .build()
+            .unwrap();
+        let user = |role| User::new(1, "someone").role(role);
+        assert!(!user(Role::Viewer).can_manage(&own));
+        assert!(user(Role::Editor).can_manage(&own));
+        assert!(!user(Role::Editor).can_manage(&unowned));
+        assert!(user(Role::Admin).can_manage(&unowned));
+        assert_eq!("Admin".parse::<Role>().unwrap(), Role::Admin);
+        assert!("owner".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn test_owned_links() {
+        let mut store = InMemoryLinkStore::new();
+        let link = |owner_id: u64, slug: &str| {
+            Link::builder()
+                .target("https://example.com")
+                .slug(slug)
+                .owner_id(owner_id)
+                .build()
+                .unwrap()
+        };
+        let alice = link(1, "alice");
+        store.create(alice.clone()).unwrap();
+        store.create(link(2, "bob")).unwrap();
+        store
+            .create_with_slug("shared", "https://example.com".parse().unwrap())
+            .unwrap();
+
+        let owned = store.list_owned(1, 0, 10).unwrap();
+        assert_eq!(owned.len(), 1);
+        assert_eq!(owned[0].shortcut(), Some("alice"));
+        assert!(store.list_owned(1, 1, 10).unwrap().is_empty());
+        assert!(User::new(1, "alice").can_manage(&alice));
+        assert!(!User::new(2, "bob").can_manage(&alice));
+        assert!(User::new(2, "bob").admin().can_manage(&alice));
+
+        let mut retagged = alice.clone();
+        retagged.add_tag("mine");
+        assert!(matches!(
+            store.update_owned(2, alice.id(), retagged.clone()),
+            Err(UrlManagerError::NotFound)
+        ));
+        let mut given_away = retagged.clone();
+        given_away.set_owner_id(Some(2));
+        assert!(matches!(
+            store.update_owned(1, alice.id(), given_away),
+            Err(UrlManagerError::Forbidden(_))
+        ));
+        store.update_owned(1, alice.id(), retagged).unwrap();
+        assert!(store.get(alice.id()).unwrap().unwrap().has_tag("mine"));
+
+        assert!(matches!(
+            store.delete_owned(2, alice.id()),
+            Err(UrlManagerError::NotFound)
+        ));
+        store.delete_owned(1, alice.id()).unwrap();
+        assert!(store.list_owned(1, 0, 10).unwrap().is_empty());
+        assert!(matches!(
+            store.delete_owned(1, alice.id()),
+            Err(UrlManagerError::NotFound)
+        ));
+    }
+}
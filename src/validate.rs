@@ -0,0 +1,150 @@
+use std::future::Future;
+use std::net::ToSocketAddrs;
+
+use url::Host;
+
+use crate::events::PlainHttp;
+use crate::healthcheck::HttpHead;
+use crate::{Result, UrlManagerError, UrlType};
+
+/// How thoroughly a [`Validator`] checks a target, each level including
+/// the ones before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Validation {
+    /// The URL parses, which is all a link needs.
+    #[default]
+    Syntax,
+    /// The host is an address or a name that resolves.
+    Dns,
+    /// The target answers a HEAD request with a status below 400.
+    Reachable,
+}
+
+/// Sends a HEAD request for [`Validation::Reachable`] and returns the
+/// response status.
+///
+/// [`PlainHttp`] covers `http://` targets and fails with `InvalidConfig`
+/// for others; for `https://` pass a client of your own.
+pub trait HttpProbe {
+    fn probe(&self, url: &UrlType) -> impl Future<Output = Result<u16>> + Send;
+}
+
+impl HttpProbe for PlainHttp {
+    async fn probe(&self, url: &UrlType) -> Result<u16> {
+        self.head(url)
+    }
+}
+
+/// Checks that targets exist before they are shortened, as thoroughly as
+/// the [`Validation`] asked for on each call.
+///
+/// ```
+/// # use url_manager::{UrlType, Validation, Validator};
+/// # async fn run() -> url_manager::Result<()> {
+/// let validator = Validator::new();
+/// let target = UrlType::parse("https://example.invalid/")?;
+/// assert!(validator.validate(&target, Validation::Syntax).await.is_ok());
+/// assert!(validator.validate(&target, Validation::Dns).await.is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Validator<H = PlainHttp> {
+    http: H,
+}
+
+impl Validator {
+    /// A validator probing targets with [`PlainHttp`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<H: HttpProbe> Validator<H> {
+    /// A validator probing targets with `http`.
+    pub fn with_probe(http: H) -> Self {
+        Validator { http }
+    }
+
+    /// Fails with `InvalidLink` if `target` doesn't pass `level`; errors
+    /// of the probe itself, like `InvalidConfig` for a target it can't
+    /// reach, are passed on.
+    ///
+    /// Resolving the host and probing the target block the calling thread.
+    pub async fn validate(&self, target: &UrlType, level: Validation) -> Result<()> {
+        let invalid = |reason: String| Err(UrlManagerError::InvalidLink(reason));
+        if level < Validation::Dns {
+            return Ok(());
+        }
+        match target.host() {
+            None => return invalid(format!("'{target}' has no host")),
+            Some(Host::Domain(domain)) => {
+                let port = target.port_or_known_default().unwrap_or(0);
+                let resolved = (domain, port)
+                    .to_socket_addrs()
+                    .is_ok_and(|mut addrs| addrs.next().is_some());
+                if !resolved {
+                    return invalid(format!("'{domain}' does not resolve"));
+                }
+            }
+            Some(Host::Ipv4(_) | Host::Ipv6(_)) => {}
+        }
+        if level < Validation::Reachable {
+            return Ok(());
+        }
+        match self.http.probe(target).await {
+            Ok(status) if status < 400 => Ok(()),
+            Ok(status) => invalid(format!("'{target}' answered with {status}")),
+            Err(UrlManagerError::InvalidConfig(reason)) => {
+                Err(UrlManagerError::InvalidConfig(reason))
+            }
+            Err(e) => invalid(format!("'{target}' is unreachable: {e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::block_on;
+
+    struct Statuses;
+
+    impl HttpProbe for Statuses {
+        async fn probe(&self, url: &UrlType) -> Result<u16> {
+            match url.path() {
+                "/ok" => Ok(200),
+                "/gone" => Ok(404),
+                "/secure" => Err(UrlManagerError::InvalidConfig("no TLS".to_string())),
+                _ => Err(UrlManagerError::backend("connection refused")),
+            }
+        }
+    }
+
+    fn validate(url: &str, level: Validation) -> Result<()> {
+        let target = UrlType::parse(url).unwrap();
+        block_on(Validator::with_probe(Statuses).validate(&target, level))
+    }
+
+    #[test]
+    fn test_levels() {
+        assert!(validate("https://nowhere.invalid/", Validation::Syntax).is_ok());
+        assert!(matches!(
+            validate("https://nowhere.invalid/", Validation::Dns),
+            Err(UrlManagerError::InvalidLink(_))
+        ));
+        assert!(validate("mailto:a@example.com", Validation::Dns).is_err());
+        assert!(validate("http://127.0.0.1/", Validation::Dns).is_ok());
+        assert!(validate("http://[::1]:8080/", Validation::Dns).is_ok());
+        assert!(validate("http://localhost/", Validation::Dns).is_ok());
+
+        assert!(validate("http://127.0.0.1/ok", Validation::Reachable).is_ok());
+        assert!(validate("http://127.0.0.1/gone", Validation::Reachable).is_err());
+        assert!(validate("http://127.0.0.1/down", Validation::Reachable).is_err());
+        assert!(matches!(
+            validate("http://127.0.0.1/secure", Validation::Reachable),
+            Err(UrlManagerError::InvalidConfig(_))
+        ));
+        assert!(validate("http://nowhere.invalid/ok", Validation::Reachable).is_err());
+    }
+}
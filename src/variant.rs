@@ -0,0 +1,153 @@
+use crate::crypto::sha256;
+use crate::json::Value;
+use crate::{Result, UrlManagerError, UrlType};
+
+/// One of the targets of an A/B tested link, see [`Link::variants`](crate::Link::variants).
+#[derive(Debug, Clone)]
+pub struct Variant {
+    target: UrlType,
+    weight: u32,
+    hits: u64,
+}
+
+impl Variant {
+    pub(crate) fn new(target: UrlType, weight: u32) -> Self {
+        Variant {
+            target,
+            weight,
+            hits: 0,
+        }
+    }
+
+    pub fn target(&self) -> &UrlType {
+        &self.target
+    }
+
+    /// The share of resolutions sent here, relative to the other variants.
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    /// How often resolving the link picked this variant.
+    pub fn hit_count(&self) -> u64 {
+        self.hits
+    }
+
+    pub(crate) fn hit(&mut self) {
+        self.hits += 1;
+    }
+
+    // Keeps the hits of the variant of `previous` with the same target.
+    pub(crate) fn keep_hits(&mut self, previous: &[Variant]) {
+        if let Some(same) = previous.iter().find(|old| old.target == self.target) {
+            self.hits = same.hits;
+        }
+    }
+
+    // Whether `other` sends the same share to the same target.
+    pub(crate) fn same_as(&self, other: &Variant) -> bool {
+        self.target == other.target && self.weight == other.weight
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        Value::object([
+            ("target", Value::from(self.target.as_str())),
+            ("weight", Value::from(self.weight)),
+            ("hits", Value::from(self.hits)),
+        ])
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Variant> {
+        let invalid = |what: &str| UrlManagerError::InvalidLink(format!("variant {what}"));
+        let target = value
+            .get("target")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("without target"))?;
+        let weight = value
+            .get("weight")
+            .and_then(Value::as_u64)
+            .and_then(|weight| u32::try_from(weight).ok())
+            .ok_or_else(|| invalid("without a valid weight"))?;
+        Ok(Variant {
+            target: UrlType::parse(target)?,
+            weight,
+            hits: value.get("hits").and_then(Value::as_u64).unwrap_or(0),
+        })
+    }
+}
+
+/// Picks a variant by weight, `None` if all weights are 0.
+///
+/// With a `visitor` key the pick only depends on it and `link_id`, so a
+/// visitor keeps seeing the same variant as long as the weights stay the
+/// same; without one it is random.
+pub(crate) fn pick(variants: &[Variant], link_id: u64, visitor: Option<&str>) -> Option<usize> {
+    let total: u64 = variants
+        .iter()
+        .map(|variant| u64::from(variant.weight))
+        .sum();
+    if total == 0 {
+        return None;
+    }
+    let roll = match visitor {
+        Some(visitor) => {
+            let digest = sha256(format!("{link_id}:{visitor}").as_bytes());
+            u64::from_be_bytes(digest[..8].try_into().unwrap())
+        }
+        None => rand::random(),
+    };
+    let mut left = roll % total;
+    variants.iter().position(|variant| {
+        let weight = u64::from(variant.weight);
+        if left < weight {
+            true
+        } else {
+            left -= weight;
+            false
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variants(weights: &[u32]) -> Vec<Variant> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| {
+                Variant::new(
+                    UrlType::parse(&format!("https://example.com/{i}")).unwrap(),
+                    weight,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pick() {
+        assert_eq!(pick(&variants(&[0, 0]), 1, None), None);
+        assert_eq!(pick(&variants(&[]), 1, None), None);
+        assert_eq!(pick(&variants(&[0, 3, 0]), 1, None), Some(1));
+
+        let split = variants(&[1, 1]);
+        let mut picked = [0; 2];
+        for _ in 0..1000 {
+            picked[pick(&split, 1, None).unwrap()] += 1;
+        }
+        assert!(picked.iter().all(|&count| count > 400), "{picked:?}");
+
+        let mut by_visitor = [0; 2];
+        for visitor in 0..1000 {
+            let visitor = visitor.to_string();
+            let first = pick(&split, 1, Some(&visitor)).unwrap();
+            assert_eq!(pick(&split, 1, Some(&visitor)), Some(first));
+            by_visitor[first] += 1;
+        }
+        assert!(
+            by_visitor.iter().all(|&count| count > 400),
+            "{by_visitor:?}"
+        );
+    }
+}
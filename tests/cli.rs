@@ -0,0 +1,202 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn store_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "url-manager-cli-{}-{}.jsonl",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn url_manager(store: &PathBuf, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_url-manager"))
+        .arg("--store")
+        .arg(store)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+#[test]
+fn test_add_resolve_delete() {
+    let store = store_path("crud");
+
+    let added = url_manager(
+        &store,
+        &["add", "https://example.com/docs", "--slug", "docs"],
+    );
+    assert!(added.status.success(), "{added:?}");
+    let id = stdout(&added).split('\t').next().unwrap().to_string();
+
+    let resolved = url_manager(&store, &["resolve", "docs"]);
+    assert_eq!(stdout(&resolved), "https://example.com/docs");
+
+    let listed = url_manager(&store, &["list"]);
+    assert_eq!(stdout(&listed).lines().count(), 1);
+
+    assert!(url_manager(&store, &["delete", &id]).status.success());
+    assert!(!url_manager(&store, &["get", &id]).status.success());
+    assert!(url_manager(&store, &["restore", &id]).status.success());
+    assert!(url_manager(&store, &["get", &id]).status.success());
+    assert!(url_manager(&store, &["delete", &id]).status.success());
+    let purged = url_manager(&store, &["purge"]);
+    assert_eq!(stdout(&purged), "purged 1 links");
+    assert!(!url_manager(&store, &["restore", &id]).status.success());
+
+    let usage = url_manager(&store, &["frobnicate"]);
+    assert_eq!(usage.status.code(), Some(2));
+    std::fs::remove_file(store).unwrap();
+}
+
+#[test]
+fn test_migrate() {
+    let source = store_path("migrate-from");
+    let destination = store_path("migrate-to");
+    for slug in ["a", "b"] {
+        let target = format!("https://example.com/{slug}");
+        assert!(url_manager(&source, &["add", &target, "--slug", slug])
+            .status
+            .success());
+    }
+    url_manager(
+        &destination,
+        &["add", "https://example.com/x", "--slug", "b"],
+    );
+
+    let migrated = url_manager(&source, &["migrate", "--to", destination.to_str().unwrap()]);
+    assert!(migrated.status.success(), "{migrated:?}");
+    assert_eq!(
+        stdout(&migrated),
+        "copied 1 links (0 overwritten), skipped 1"
+    );
+    let resolved = url_manager(&destination, &["resolve", "b"]);
+    assert_eq!(stdout(&resolved), "https://example.com/x");
+
+    let unsupported = url_manager(&source, &["migrate", "--to", "postgres://localhost/links"]);
+    assert!(!unsupported.status.success());
+    std::fs::remove_file(source).unwrap();
+    std::fs::remove_file(destination).unwrap();
+}
+
+#[test]
+fn test_rollup() {
+    let store = store_path("rollup-store");
+    let clicks = store_path("rollup-clicks");
+    let click = |at: u64| {
+        format!(
+            "{{\"click\":{{\"link_id\":1,\"at\":{at},\"referrer\":null,\"user_agent\":null,\"country\":null,\"visitor\":null}}}}\n"
+        )
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let old = 1_700_000_000_000;
+    std::fs::write(&clicks, [click(old), click(old + 1), click(now)].concat()).unwrap();
+
+    let args = [
+        "rollup",
+        "--clicks",
+        clicks.to_str().unwrap(),
+        "--older-than",
+        "7",
+    ];
+    let rolled_up = url_manager(&store, &args);
+    assert!(rolled_up.status.success(), "{rolled_up:?}");
+    assert_eq!(stdout(&rolled_up), "rolled up 2 clicks");
+    assert_eq!(stdout(&url_manager(&store, &args)), "rolled up 0 clicks");
+    let stored = std::fs::read_to_string(&clicks).unwrap();
+    assert!(stored.contains("\"hits\":2"), "{stored}");
+
+    assert_eq!(url_manager(&store, &["rollup"]).status.code(), Some(2));
+    std::fs::remove_file(clicks).unwrap();
+}
+
+#[test]
+fn test_manifest() {
+    let store = store_path("manifest");
+    assert!(
+        url_manager(&store, &["add", "https://example.com/a", "--slug", "a"])
+            .status
+            .success()
+    );
+
+    let sitemap = url_manager(
+        &store,
+        &[
+            "manifest",
+            "--format",
+            "sitemap",
+            "--base-url",
+            "https://sho.rt/",
+        ],
+    );
+    assert!(sitemap.status.success(), "{sitemap:?}");
+    assert!(stdout(&sitemap).contains("<loc>https://sho.rt/a</loc>"));
+    let json = url_manager(&store, &["manifest", "--base-url", "https://sho.rt/"]);
+    assert!(stdout(&json).contains(r#""target":"https://example.com/a""#));
+    assert_eq!(url_manager(&store, &["manifest"]).status.code(), Some(2));
+    std::fs::remove_file(store).unwrap();
+}
+
+#[test]
+fn test_export_static() {
+    let store = store_path("static");
+    let dir = store.with_extension("site");
+    assert!(
+        url_manager(&store, &["add", "https://example.com/a", "--slug", "a"])
+            .status
+            .success()
+    );
+
+    let args = [
+        "export-static",
+        dir.to_str().unwrap(),
+        "--base-url",
+        "https://sho.rt/",
+    ];
+    let exported = url_manager(&store, &args);
+    assert!(exported.status.success(), "{exported:?}");
+    assert_eq!(stdout(&exported), "wrote 1 pages");
+    let page = std::fs::read_to_string(dir.join("a/index.html")).unwrap();
+    assert!(page.contains("url=https://example.com/a"), "{page}");
+    std::fs::remove_dir_all(dir).unwrap();
+    std::fs::remove_file(store).unwrap();
+}
+
+#[cfg(feature = "qr")]
+#[test]
+fn test_qr() {
+    let store = store_path("qr");
+    let png = store.with_extension("png");
+    assert!(
+        url_manager(&store, &["add", "https://example.com/a", "--slug", "a"])
+            .status
+            .success()
+    );
+
+    let args = ["qr", "a", "--base-url", "https://sho.rt/"];
+    let svg = url_manager(&store, &args);
+    assert!(svg.status.success(), "{svg:?}");
+    assert!(stdout(&svg).starts_with("<svg"));
+    let written = url_manager(
+        &store,
+        &[&args[..], &["--out", png.to_str().unwrap()]].concat(),
+    );
+    assert!(written.status.success(), "{written:?}");
+    assert!(std::fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+    assert!(
+        !url_manager(&store, &["qr", "b", "--base-url", "https://sho.rt/"])
+            .status
+            .success()
+    );
+    std::fs::remove_file(png).unwrap();
+    std::fs::remove_file(store).unwrap();
+}